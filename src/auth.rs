@@ -0,0 +1,1086 @@
+use anyhow::{Context, Result};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+
+use crate::clock::Clock;
+use crate::error::AuthCodeError;
+
+/// How long `HttpClient::authorization_code_flow` waits for the user to sign in.
+pub const DEFAULT_AUTH_CODE_TIMEOUT_SECS: u64 = 300;
+pub const DEFAULT_REDIRECT_PATH: &str = "/callback";
+
+/// How long the redirect listener waits for a connection to send its
+/// request line; browsers open idle speculative connections.
+const REDIRECT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait before retrying a failed pre-refresh, doubling with each failure up
+/// to `PRE_REFRESH_MAX_BACKOFF`.
+const PRE_REFRESH_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const PRE_REFRESH_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+pub type TokenFuture = Pin<Box<dyn Future<Output = Result<String>> + Send>>;
+
+type ProviderFuture = Pin<Box<dyn Future<Output = Result<OAuthToken>> + Send>>;
+
+type TokenProvider = Arc<dyn Fn() -> ProviderFuture + Send + Sync>;
+
+type RefreshFailureHook = Arc<dyn Fn(&AuthStatus, &anyhow::Error) + Send + Sync>;
+
+#[derive(Clone)]
+enum RecoveryKind {
+    Provider(TokenProvider),
+    ClientCredentials {
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        scope: Option<String>,
+    },
+    RefreshToken {
+        token_url: String,
+        client_id: String,
+        client_secret: Option<String>,
+        /// Replaced when the server rotates refresh tokens.
+        refresh_token: Arc<Mutex<String>>,
+    },
+}
+
+/// How the client gets fresh credentials after a `401`. The new bearer token
+/// replaces any `Authorization` header from `RequestConfig` on later
+/// requests to the token's origins (see `with_origins`), and the rejected
+/// request is retried exactly once.
+#[derive(Clone)]
+pub struct AuthRecovery {
+    kind: RecoveryKind,
+    recover_forbidden: bool,
+    origins: Vec<String>,
+    initial_token: Option<String>,
+    initial_expires_in: Option<Duration>,
+    pre_refresh: Option<Duration>,
+    on_refresh_failure: Option<RefreshFailureHook>,
+}
+
+impl AuthRecovery {
+    fn new(kind: RecoveryKind) -> Self {
+        Self {
+            kind,
+            recover_forbidden: false,
+            origins: Vec::new(),
+            initial_token: None,
+            initial_expires_in: None,
+            pre_refresh: None,
+            on_refresh_failure: None,
+        }
+    }
+
+    /// Calls `refresh` for a new bearer token, e.g. to use a refresh token or
+    /// ask a token provider.
+    pub fn callback<F, Fut>(refresh: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        let refresh = Arc::new(refresh);
+        Self::new(RecoveryKind::Provider(Arc::new(move || {
+            let refresh = Arc::clone(&refresh);
+            Box::pin(async move { Ok(OAuthToken::bearer(refresh().await?)) })
+        })))
+    }
+
+    /// Like `callback`, for providers that know the token's lifetime
+    /// (`OAuthToken::expires_in`), which `with_pre_refresh` needs.
+    pub fn token_provider<F, Fut>(provide: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<OAuthToken>> + Send + 'static,
+    {
+        Self::new(RecoveryKind::Provider(Arc::new(move || Box::pin(provide()))))
+    }
+
+    /// Runs the OAuth 2.0 client-credentials grant against `token_url` and
+    /// uses the returned `access_token`.
+    pub fn client_credentials(token_url: &str, client_id: &str, client_secret: &str, scope: Option<&str>) -> Self {
+        Self::new(RecoveryKind::ClientCredentials {
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            scope: scope.map(str::to_string),
+        })
+    }
+
+    /// Runs the OAuth 2.0 refresh-token grant, keeping any rotated refresh
+    /// token for the next refresh.
+    pub fn refresh_token(token_url: &str, client_id: &str, client_secret: Option<&str>, refresh_token: &str) -> Self {
+        Self::new(RecoveryKind::RefreshToken {
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.map(str::to_string),
+            refresh_token: Arc::new(Mutex::new(refresh_token.to_string())),
+        })
+    }
+
+    /// Sends `token`'s access token from the first request and refreshes it
+    /// with its refresh token after a `401`. Fails if the server issued no
+    /// refresh token.
+    pub fn from_oauth_token(options: &AuthCodeOptions, token: &OAuthToken) -> Result<Self> {
+        let refresh_token = token
+            .refresh_token
+            .as_deref()
+            .context("Token response has no refresh_token")?;
+        let recovery = Self::refresh_token(
+            &options.token_url,
+            &options.client_id,
+            options.client_secret.as_deref(),
+            refresh_token,
+        )
+        .with_initial_token(token.access_token.clone());
+        Ok(match token.expires_in {
+            Some(seconds) => recovery.with_initial_expiry(Duration::from_secs(seconds)),
+            None => recovery,
+        })
+    }
+
+    /// Uses `token` until the first rejection instead of starting without
+    /// credentials.
+    pub fn with_initial_token(mut self, token: impl Into<String>) -> Self {
+        self.initial_token = Some(token.into());
+        self
+    }
+
+    /// The initial token expires `expires_in` from now, so `with_pre_refresh`
+    /// can replace it in time.
+    pub fn with_initial_expiry(mut self, expires_in: Duration) -> Self {
+        self.initial_expires_in = Some(expires_in);
+        self
+    }
+
+    /// Refreshes the token in the background `margin` before it expires, so
+    /// requests do not wait on a `401` first. Tokens without a known expiry
+    /// are only replaced after a `401`; without any token the first one is
+    /// fetched right away. The task starts with the client's first request
+    /// and stops on `HttpClient::shutdown` or when the client is dropped.
+    pub fn with_pre_refresh(mut self, margin: Duration) -> Self {
+        self.pre_refresh = Some(margin);
+        self
+    }
+
+    /// Called each time a background refresh fails, e.g. to raise an alert.
+    /// The task retries after 1s, doubling up to a minute.
+    pub fn on_refresh_failure<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AuthStatus, &anyhow::Error) + Send + Sync + 'static,
+    {
+        self.on_refresh_failure = Some(Arc::new(hook));
+        self
+    }
+
+    /// Also recover on `403`. Off by default: a 403 usually means the
+    /// credentials are valid but not allowed, which new ones will not fix.
+    pub fn with_forbidden(mut self, recover: bool) -> Self {
+        self.recover_forbidden = recover;
+        self
+    }
+
+    /// Sends the token only to these origins (`https://api.example.com`,
+    /// paths are ignored), plus any origin whose `401` led to a refresh.
+    /// Without it the token is bound to the origin of the first request
+    /// it is sent with. Other hosts get the request's own `Authorization`.
+    pub fn with_origins<I, S>(mut self, origins: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for origin in origins {
+            let origin = origin.as_ref();
+            let url = crate::utils::parse_url(origin).with_context(|| format!("Invalid auth origin: {}", origin))?;
+            self.origins.push(url.origin().ascii_serialization());
+        }
+        Ok(self)
+    }
+
+    pub(crate) fn should_recover(&self, status: u16) -> bool {
+        status == 401 || (status == 403 && self.recover_forbidden)
+    }
+
+    async fn fetch_token(&self, client: &reqwest::Client) -> Result<OAuthToken> {
+        match &self.kind {
+            RecoveryKind::Provider(provide) => provide().await,
+            RecoveryKind::ClientCredentials {
+                token_url,
+                client_id,
+                client_secret,
+                scope,
+            } => {
+                let mut form = vec![
+                    ("grant_type", "client_credentials"),
+                    ("client_id", client_id.as_str()),
+                    ("client_secret", client_secret.as_str()),
+                ];
+                if let Some(scope) = scope {
+                    form.push(("scope", scope.as_str()));
+                }
+
+                let reply: Value = client
+                    .post(token_url)
+                    .form(&form)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| anyhow::Error::new(e.without_url()))
+                    .context("Client-credentials token request failed")?
+                    .json()
+                    .await
+                    .context("Invalid client-credentials token response")?;
+
+                let access_token = reply
+                    .get("access_token")
+                    .and_then(Value::as_str)
+                    .context("Token response has no access_token")?;
+                Ok(OAuthToken {
+                    expires_in: reply.get("expires_in").and_then(Value::as_u64),
+                    ..OAuthToken::bearer(access_token)
+                })
+            }
+            RecoveryKind::RefreshToken {
+                token_url,
+                client_id,
+                client_secret,
+                refresh_token,
+            } => {
+                let current = refresh_token.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                let mut form = vec![
+                    ("grant_type", "refresh_token"),
+                    ("refresh_token", current.as_str()),
+                    ("client_id", client_id.as_str()),
+                ];
+                if let Some(secret) = client_secret {
+                    form.push(("client_secret", secret.as_str()));
+                }
+
+                let token = request_token(client, token_url, &form)
+                    .await
+                    .context("Refresh-token request failed")?;
+                if let Some(rotated) = &token.refresh_token {
+                    *refresh_token.lock().unwrap_or_else(|e| e.into_inner()) = rotated.clone();
+                }
+                Ok(token)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for AuthRecovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match &self.kind {
+            RecoveryKind::Provider(_) => "callback".to_string(),
+            RecoveryKind::ClientCredentials { token_url, .. } => format!("client_credentials({})", token_url),
+            RecoveryKind::RefreshToken { token_url, .. } => format!("refresh_token({})", token_url),
+        };
+        f.debug_struct("AuthRecovery")
+            .field("kind", &kind)
+            .field("recover_forbidden", &self.recover_forbidden)
+            .field("origins", &self.origins)
+            .field("pre_refresh", &self.pre_refresh)
+            .finish()
+    }
+}
+
+/// Where the client's credentials stand, see `HttpClient::auth_status`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthStatus {
+    pub has_token: bool,
+    /// When the current token expires, if its lifetime is known.
+    pub valid_until: Option<SystemTime>,
+    pub last_refresh_at: Option<SystemTime>,
+    /// Why the latest refresh failed; cleared when one succeeds.
+    pub last_error: Option<String>,
+    /// Refreshes that failed since the last success.
+    pub consecutive_failures: u32,
+}
+
+/// The current recovered token. Refreshes are serialized: requests rejected
+/// with a token that has since been replaced reuse the new one instead of
+/// refreshing again.
+pub(crate) struct AuthState {
+    recovery: AuthRecovery,
+    token: RwLock<Option<String>>,
+    /// ASCII-serialized origins the token is sent to.
+    origins: Mutex<Vec<String>>,
+    generation: AtomicU64,
+    refreshes: AtomicU64,
+    refresh_lock: tokio::sync::Mutex<()>,
+    status: Mutex<AuthStatus>,
+    /// Signalled when a refresh replaces the token.
+    refreshed: Notify,
+    pre_refresh_started: AtomicBool,
+    pre_refresh_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AuthState {
+    /// `now` stamps the initial token's expiry.
+    pub(crate) fn new(recovery: AuthRecovery, now: SystemTime) -> Self {
+        let valid_until = recovery
+            .initial_token
+            .as_ref()
+            .and(recovery.initial_expires_in)
+            .map(|expires_in| now + expires_in);
+        Self {
+            token: RwLock::new(recovery.initial_token.clone()),
+            origins: Mutex::new(recovery.origins.clone()),
+            recovery,
+            generation: AtomicU64::new(0),
+            refreshes: AtomicU64::new(0),
+            refresh_lock: tokio::sync::Mutex::new(()),
+            status: Mutex::new(AuthStatus {
+                valid_until,
+                ..AuthStatus::default()
+            }),
+            refreshed: Notify::new(),
+            pre_refresh_started: AtomicBool::new(false),
+            pre_refresh_task: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn should_recover(&self, status: u16) -> bool {
+        self.recovery.should_recover(status)
+    }
+
+    pub(crate) fn token(&self) -> Option<String> {
+        self.token.read().ok().and_then(|token| token.clone())
+    }
+
+    /// The token, when `url` is on one of its origins. With no origin
+    /// configured or learned yet, `url`'s origin becomes the first.
+    pub(crate) fn token_for(&self, url: &str) -> Option<String> {
+        let token = self.token()?;
+        let origin = url::Url::parse(url).ok()?.origin().ascii_serialization();
+        let mut origins = self.origins.lock().unwrap_or_else(|e| e.into_inner());
+        if origins.is_empty() {
+            origins.push(origin.clone());
+        }
+        origins.contains(&origin).then_some(token)
+    }
+
+    fn add_origin(&self, url: &str) {
+        let Ok(url) = url::Url::parse(url) else {
+            return;
+        };
+        let origin = url.origin().ascii_serialization();
+        let mut origins = self.origins.lock().unwrap_or_else(|e| e.into_inner());
+        if !origins.contains(&origin) {
+            origins.push(origin);
+        }
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn refreshes(&self) -> u64 {
+        self.refreshes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn status(&self) -> AuthStatus {
+        AuthStatus {
+            has_token: self.token().is_some(),
+            ..self.status.lock().unwrap_or_else(|e| e.into_inner()).clone()
+        }
+    }
+
+    /// Refreshes the token unless another request already did so after
+    /// `seen_generation` was read. `rejected_by`, the URL that answered
+    /// `401`, is added to the token's origins once a token is available.
+    pub(crate) async fn recover(
+        &self,
+        client: &reqwest::Client,
+        clock: &dyn Clock,
+        seen_generation: u64,
+        rejected_by: Option<&str>,
+    ) -> Result<()> {
+        let _guard = self.refresh_lock.lock().await;
+        if self.generation() != seen_generation {
+            if let Some(url) = rejected_by {
+                self.add_origin(url);
+            }
+            return Ok(());
+        }
+
+        let fetched = self.recovery.fetch_token(client).await;
+        let now = clock.system_now();
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        status.last_refresh_at = Some(now);
+        let token = match fetched {
+            Ok(token) => token,
+            Err(e) => {
+                status.last_error = Some(format!("{:#}", e));
+                status.consecutive_failures += 1;
+                return Err(e);
+            }
+        };
+        status.valid_until = token.expires_in.map(|seconds| now + Duration::from_secs(seconds));
+        status.last_error = None;
+        status.consecutive_failures = 0;
+        drop(status);
+
+        if let Ok(mut current) = self.token.write() {
+            *current = Some(token.access_token);
+        }
+        if let Some(url) = rejected_by {
+            self.add_origin(url);
+        }
+        self.refreshes.fetch_add(1, Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.refreshed.notify_one();
+        Ok(())
+    }
+
+    /// Starts the `AuthRecovery::with_pre_refresh` task, once.
+    pub(crate) fn start_pre_refresh(self: &Arc<Self>, client: &reqwest::Client, clock: Arc<dyn Clock>, closing: watch::Receiver<bool>) {
+        let Some(margin) = self.recovery.pre_refresh else {
+            return;
+        };
+        if self.pre_refresh_started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let task = tokio::spawn(pre_refresh(Arc::clone(self), client.clone(), clock, margin, closing));
+        *self.pre_refresh_task.lock().unwrap_or_else(|e| e.into_inner()) = Some(task);
+    }
+
+    /// Waits for the pre-refresh task to exit after the client began closing.
+    pub(crate) async fn stop_pre_refresh(&self) {
+        let task = self.pre_refresh_task.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+    }
+
+    pub(crate) fn wants_pre_refresh(&self) -> bool {
+        self.recovery.pre_refresh.is_some() && !self.pre_refresh_started.load(Ordering::SeqCst)
+    }
+}
+
+/// Refreshes the token `margin` before it expires until `closing` is set
+/// or the client is dropped, backing off after failures. Waits run on
+/// `clock`, so a `MockClock` has to be advanced for a refresh to happen.
+async fn pre_refresh(
+    state: Arc<AuthState>,
+    client: reqwest::Client,
+    clock: Arc<dyn Clock>,
+    margin: Duration,
+    mut closing: watch::Receiver<bool>,
+) {
+    let mut backoff = Duration::ZERO;
+    let mut retry_at: Option<Instant> = None;
+    loop {
+        let generation = state.generation();
+        let due_at = match (retry_at, state.token(), state.status().valid_until) {
+            (Some(at), _, _) => Some(at),
+            (None, None, _) => Some(clock.now()),
+            (None, Some(_), Some(valid_until)) => Some(
+                clock.now()
+                    + valid_until
+                        .checked_sub(margin)
+                        .and_then(|due| due.duration_since(clock.system_now()).ok())
+                        .unwrap_or(Duration::ZERO),
+            ),
+            // Nothing to schedule until a refresh reveals the lifetime.
+            (None, Some(_), None) => None,
+        };
+        let due = async {
+            match due_at {
+                Some(at) => clock.sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = closing.wait_for(|closed| *closed) => return,
+            _ = state.refreshed.notified() => {
+                // Someone else refreshed; schedule from the new token.
+                (backoff, retry_at) = (Duration::ZERO, None);
+                continue;
+            }
+            _ = due => {}
+        }
+
+        let refreshed = tokio::select! {
+            _ = closing.wait_for(|closed| *closed) => return,
+            refreshed = state.recover(&client, clock.as_ref(), generation, None) => refreshed,
+        };
+        match refreshed {
+            Ok(()) => (backoff, retry_at) = (Duration::ZERO, None),
+            Err(e) => {
+                backoff = (backoff * 2).clamp(PRE_REFRESH_MIN_BACKOFF, PRE_REFRESH_MAX_BACKOFF);
+                retry_at = Some(clock.now() + backoff);
+                let e = e.context("Failed to refresh credentials before expiry");
+                if let Some(hook) = &state.recovery.on_refresh_failure {
+                    hook(&state.status(), &e);
+                }
+            }
+        }
+    }
+}
+
+/// Tokens returned by an OAuth 2.0 token endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OAuthToken {
+    pub access_token: String,
+    #[serde(default)]
+    pub token_type: String,
+    /// Lifetime of the access token in seconds.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+impl OAuthToken {
+    /// A bare access token, e.g. for `AuthRecovery::token_provider`; set
+    /// `expires_in` when the lifetime is known.
+    pub fn bearer(access_token: impl Into<String>) -> Self {
+        Self {
+            access_token: access_token.into(),
+            token_type: "Bearer".to_string(),
+            expires_in: None,
+            refresh_token: None,
+            scope: None,
+        }
+    }
+}
+
+/// A PKCE (RFC 7636) verifier and its `S256` challenge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PkceChallenge {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl PkceChallenge {
+    /// A fresh random verifier of 43 characters.
+    pub fn generate() -> Result<Self> {
+        Ok(Self::from_verifier(&random_token(32)?))
+    }
+
+    pub fn from_verifier(verifier: &str) -> Self {
+        Self {
+            verifier: verifier.to_string(),
+            challenge: base64_url(&crate::utils::sha256(verifier.as_bytes())),
+        }
+    }
+}
+
+type UrlHandler = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Settings for `HttpClient::authorization_code_flow`.
+#[derive(Clone)]
+pub struct AuthCodeOptions {
+    pub authorize_url: String,
+    pub token_url: String,
+    pub client_id: String,
+    /// Only for confidential clients; public clients rely on PKCE alone.
+    pub client_secret: Option<String>,
+    pub scopes: Vec<String>,
+    /// Port of the local redirect listener; `0` picks a free one.
+    pub redirect_port: u16,
+    pub redirect_path: String,
+    /// How long to wait for the user to finish signing in.
+    pub timeout: Duration,
+    on_authorize_url: UrlHandler,
+}
+
+impl AuthCodeOptions {
+    /// `on_authorize_url` is called with the URL the user has to open, e.g.
+    /// to launch a browser or show it in the application's own UI; the
+    /// library never prints it itself.
+    pub fn new<F>(authorize_url: &str, token_url: &str, client_id: &str, on_authorize_url: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        Self {
+            authorize_url: authorize_url.to_string(),
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: None,
+            scopes: Vec::new(),
+            redirect_port: 0,
+            redirect_path: DEFAULT_REDIRECT_PATH.to_string(),
+            timeout: Duration::from_secs(DEFAULT_AUTH_CODE_TIMEOUT_SECS),
+            on_authorize_url: Arc::new(on_authorize_url),
+        }
+    }
+
+    pub fn with_client_secret(mut self, secret: impl Into<String>) -> Self {
+        self.client_secret = Some(secret.into());
+        self
+    }
+
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scopes.push(scope.into());
+        self
+    }
+
+    /// Providers that require an exact redirect URI need a fixed port.
+    pub fn with_redirect_port(mut self, port: u16) -> Self {
+        self.redirect_port = port;
+        self
+    }
+
+    pub fn with_redirect_path(mut self, path: impl Into<String>) -> Self {
+        self.redirect_path = path.into();
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn authorization_url(&self, redirect_uri: &str, state: &str, pkce: &PkceChallenge) -> Result<String> {
+        let mut url = url::Url::parse(&self.authorize_url).context("Invalid authorization URL")?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &self.client_id)
+                .append_pair("redirect_uri", redirect_uri)
+                .append_pair("state", state)
+                .append_pair("code_challenge", &pkce.challenge)
+                .append_pair("code_challenge_method", "S256");
+            if !self.scopes.is_empty() {
+                query.append_pair("scope", &self.scopes.join(" "));
+            }
+        }
+        Ok(url.to_string())
+    }
+}
+
+impl fmt::Debug for AuthCodeOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthCodeOptions")
+            .field("authorize_url", &self.authorize_url)
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("scopes", &self.scopes)
+            .field("redirect_port", &self.redirect_port)
+            .field("redirect_path", &self.redirect_path)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// See `HttpClient::authorization_code_flow`; the code is exchanged through
+/// `client`.
+pub(crate) async fn authorization_code_flow(client: &reqwest::Client, options: AuthCodeOptions) -> Result<OAuthToken> {
+    let listener = TcpListener::bind(("127.0.0.1", options.redirect_port))
+        .await
+        .context("Failed to start the redirect listener")?;
+    let port = listener.local_addr()?.port();
+    let redirect_uri = format!("http://127.0.0.1:{}{}", port, options.redirect_path);
+
+    let pkce = PkceChallenge::generate()?;
+    let state = random_token(16)?;
+    (options.on_authorize_url)(&options.authorization_url(&redirect_uri, &state, &pkce)?);
+
+    let params = tokio::time::timeout(options.timeout, wait_for_redirect(&listener, &options.redirect_path))
+        .await
+        .map_err(|_| AuthCodeError::Timeout {
+            waited_ms: options.timeout.as_millis() as u64,
+        })??;
+
+    // An error redirect is only believed once it is known to answer this
+    // login.
+    if params.get("state") != Some(&state) {
+        return Err(AuthCodeError::StateMismatch.into());
+    }
+    if let Some(error) = params.get("error") {
+        return Err(AuthCodeError::Denied {
+            error: error.clone(),
+            description: params.get("error_description").cloned(),
+        }
+        .into());
+    }
+    let code = params.get("code").context("Authorization redirect has no code")?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code.as_str()),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("client_id", options.client_id.as_str()),
+        ("code_verifier", pkce.verifier.as_str()),
+    ];
+    if let Some(secret) = &options.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+    request_token(client, &options.token_url, &form).await
+}
+
+/// Accepts connections until one requests `path`, answers it with a short
+/// page and returns its query parameters. Other paths get a `404`.
+/// Connections are read concurrently so an idle one cannot hold up the
+/// redirect.
+async fn wait_for_redirect(listener: &TcpListener, path: &str) -> Result<HashMap<String, String>> {
+    let mut reading = FuturesUnordered::new();
+    loop {
+        let (mut stream, target) = tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted.context("Redirect listener failed")?;
+                reading.push(read_request_target(stream));
+                continue;
+            }
+            Some(read) = reading.next(), if !reading.is_empty() => read,
+        };
+        let Some(url) = target.and_then(|target| url::Url::parse(&format!("http://127.0.0.1{}", target)).ok()) else {
+            continue;
+        };
+        if url.path() != path {
+            respond(&mut stream, "404 Not Found", "Not found.").await;
+            continue;
+        }
+
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let message = if params.contains_key("error") {
+            "Sign-in did not complete. You can close this window."
+        } else {
+            "Sign-in complete. You can close this window."
+        };
+        respond(&mut stream, "200 OK", message).await;
+        return Ok(params);
+    }
+}
+
+async fn read_request_target(mut stream: TcpStream) -> (TcpStream, Option<String>) {
+    let target = tokio::time::timeout(REDIRECT_READ_TIMEOUT, request_target(&mut stream))
+        .await
+        .ok()
+        .flatten();
+    (stream, target)
+}
+
+/// The target of a `GET` request line, e.g. `/callback?code=...`.
+async fn request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") && head.len() < 16 * 1024 {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&chunk[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut parts = head.lines().next()?.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    parts.next().map(str::to_string)
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) {
+    let body = format!("<!DOCTYPE html><html><body><p>{}</p></body></html>", message);
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Posts `form` to a token endpoint; a non-2xx reply is
+/// `AuthCodeError::ExchangeFailed`.
+async fn request_token(client: &reqwest::Client, token_url: &str, form: &[(&str, &str)]) -> Result<OAuthToken> {
+    let response = client
+        .post(token_url)
+        .form(form)
+        .send()
+        .await
+        .map_err(|e| anyhow::Error::new(e.without_url()))
+        .context("Token request failed")?;
+    let status = response.status();
+    let body = response.text().await.context("Failed to read token response")?;
+    if !status.is_success() {
+        return Err(AuthCodeError::ExchangeFailed {
+            status: status.as_u16(),
+            body,
+        }
+        .into());
+    }
+    serde_json::from_str(&body).context("Invalid token response")
+}
+
+/// `bytes` random bytes from the OS, base64url-encoded without padding.
+fn random_token(bytes: usize) -> Result<String> {
+    let mut buf = vec![0u8; bytes];
+    getrandom::fill(&mut buf).map_err(|e| anyhow::anyhow!("Failed to gather randomness: {}", e))?;
+    Ok(base64_url(&buf))
+}
+
+fn base64_url(bytes: &[u8]) -> String {
+    crate::models::base64_encode_bytes(bytes)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+
+    /// Answers `401` unless the request carries `Bearer fresh`.
+    fn protected(server: &MockServer) {
+        server.when(HttpMethod::Get, "/*").respond_with(|request| match request.header("authorization") {
+            Some("Bearer fresh") => MockResponse::text(200, "ok"),
+            _ => MockResponse::new(401),
+        });
+    }
+
+    fn recovery() -> AuthRecovery {
+        AuthRecovery::callback(|| async { Ok("fresh".to_string()) })
+    }
+
+    #[tokio::test]
+    async fn recovered_token_stays_on_the_rejecting_origin() -> Result<()> {
+        let (api, other) = (MockServer::start().await?, MockServer::start().await?);
+        protected(&api);
+        other.when(HttpMethod::Get, "/*").respond(MockResponse::new(204));
+        let client = HttpClient::new().with_auth_recovery(recovery());
+
+        let response = client.get(&api.url("/me"), RequestConfig::new()).await?;
+        assert_eq!(response.status, 200);
+        assert_eq!(client.auth_refreshes(), 1);
+
+        client.get(&other.url("/plain"), RequestConfig::new()).await?;
+        client.get(&other.url("/basic"), RequestConfig::new().with_basic_auth("user", "pass")).await?;
+        let requests = other.requests();
+        assert_eq!(requests[0].header("authorization"), None);
+        assert_eq!(requests[1].header("authorization"), Some("Basic dXNlcjpwYXNz"));
+
+        let response = client.get(&api.url("/again"), RequestConfig::new().with_bearer_token("stale")).await?;
+        assert_eq!(response.status, 200);
+        assert_eq!(api.requests().last().and_then(|request| request.header("authorization")), Some("Bearer fresh"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn initial_token_is_limited_to_configured_origins() -> Result<()> {
+        let (api, other) = (MockServer::start().await?, MockServer::start().await?);
+        protected(&api);
+        other.when(HttpMethod::Get, "/*").respond(MockResponse::new(204));
+        let recovery = recovery().with_initial_token("fresh").with_origins([api.base_url()])?;
+        let client = HttpClient::new().with_auth_recovery(recovery);
+
+        client.get(&other.url("/first"), RequestConfig::new()).await?;
+        assert_eq!(other.requests()[0].header("authorization"), None);
+        let response = client.get(&api.url("/me"), RequestConfig::new()).await?;
+        assert_eq!(response.status, 200);
+        assert_eq!(client.auth_refreshes(), 0);
+        assert!(AuthRecovery::callback(|| async { Ok(String::new()) }).with_origins(["not a url"]).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn initial_token_binds_to_the_first_origin_without_configuration() -> Result<()> {
+        let (api, other) = (MockServer::start().await?, MockServer::start().await?);
+        protected(&api);
+        other.when(HttpMethod::Get, "/*").respond(MockResponse::new(204));
+        let client = HttpClient::new().with_auth_recovery(recovery().with_initial_token("fresh"));
+
+        assert_eq!(client.get(&api.url("/me"), RequestConfig::new()).await?.status, 200);
+        client.get(&other.url("/elsewhere"), RequestConfig::new()).await?;
+        assert_eq!(other.requests()[0].header("authorization"), None);
+        Ok(())
+    }
+
+    /// Options that answer the authorization URL like a browser would,
+    /// redirecting back with the query `reply` builds from the URL's
+    /// parameters, which are also kept in `seen`.
+    fn signing_in<F>(token_url: &str, seen: Arc<Mutex<HashMap<String, String>>>, reply: F) -> AuthCodeOptions
+    where
+        F: Fn(&HashMap<String, String>) -> String + Send + Sync + 'static,
+    {
+        AuthCodeOptions::new("https://auth.test/authorize", token_url, "cli", move |url| {
+            let params: HashMap<String, String> = url::Url::parse(url).unwrap().query_pairs().into_owned().collect();
+            let redirect = format!("{}?{}", params["redirect_uri"], reply(&params));
+            *seen.lock().unwrap() = params;
+            tokio::spawn(async move {
+                let _ = reqwest::get(redirect).await;
+            });
+        })
+        .with_timeout(Duration::from_secs(5))
+    }
+
+    #[tokio::test]
+    async fn authorization_code_flow_exchanges_the_code_through_the_client() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Post, "/token").respond(MockResponse::json(
+            200,
+            serde_json::json!({"access_token": "access", "token_type": "Bearer", "refresh_token": "refresh"}),
+        ));
+        // `auth.test` only resolves through the client's own overrides.
+        let client = HttpClient::new().with_resolve("auth.test", &[server.addr()]);
+        let token_url = format!("http://auth.test:{}/token", server.addr().port());
+        let seen = Arc::new(Mutex::new(HashMap::new()));
+        let options = signing_in(&token_url, Arc::clone(&seen), |params| format!("code=the-code&state={}", params["state"]));
+
+        let token = client.authorization_code_flow(options).await?;
+        assert_eq!(token.access_token, "access");
+        assert_eq!(token.refresh_token.as_deref(), Some("refresh"));
+
+        let authorize = seen.lock().unwrap().clone();
+        assert_eq!(authorize["code_challenge_method"], "S256");
+        let exchange = server.requests_to(HttpMethod::Post, "/token").pop().context("no token request")?;
+        let form: HashMap<String, String> = url::form_urlencoded::parse(&exchange.body).into_owned().collect();
+        assert_eq!(form["grant_type"], "authorization_code");
+        assert_eq!(form["code"], "the-code");
+        assert_eq!(form["redirect_uri"], authorize["redirect_uri"]);
+        assert_eq!(PkceChallenge::from_verifier(&form["code_verifier"]).challenge, authorize["code_challenge"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authorization_errors_only_count_with_the_right_state() -> Result<()> {
+        let client = HttpClient::new();
+        let seen = Arc::new(Mutex::new(HashMap::new()));
+
+        let forged = signing_in("http://127.0.0.1:9/token", Arc::clone(&seen), |_| {
+            "error=access_denied&state=forged".to_string()
+        });
+        let error = client.authorization_code_flow(forged).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<AuthCodeError>(), Some(&AuthCodeError::StateMismatch));
+
+        let declined = signing_in("http://127.0.0.1:9/token", seen, |params| {
+            format!("error=access_denied&error_description=No+thanks&state={}", params["state"])
+        });
+        let error = client.authorization_code_flow(declined).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<AuthCodeError>(),
+            Some(&AuthCodeError::Denied {
+                error: "access_denied".to_string(),
+                description: Some("No thanks".to_string()),
+            })
+        );
+        Ok(())
+    }
+
+    /// Polls `done` until it holds; background refreshes run on their own task.
+    async fn eventually(done: impl Fn() -> bool) {
+        for _ in 0..400 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("condition not reached in 2s");
+    }
+
+    #[tokio::test]
+    async fn pre_refresh_replaces_the_token_before_any_request_sees_a_401() -> Result<()> {
+        let (api, issuer) = (MockServer::start().await?, MockServer::start().await?);
+        let current = Arc::new(Mutex::new("t0".to_string()));
+        let issued = Arc::clone(&current);
+        let counter = AtomicU64::new(0);
+        issuer.when(HttpMethod::Post, "/token").respond_with(move |_| {
+            let token = format!("t{}", counter.fetch_add(1, Ordering::SeqCst) + 1);
+            *issued.lock().unwrap() = token.clone();
+            MockResponse::json(200, serde_json::json!({"access_token": token, "expires_in": 2, "token_type": "bearer"}))
+        });
+        let accepted = Arc::clone(&current);
+        let statuses = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&statuses);
+        api.when(HttpMethod::Get, "/*").respond_with(move |request| {
+            let expected = format!("Bearer {}", accepted.lock().unwrap());
+            let status = if request.header("authorization") == Some(expected.as_str()) { 200 } else { 401 };
+            seen.lock().unwrap().push(status);
+            MockResponse::new(status)
+        });
+
+        let clock = Arc::new(MockClock::new());
+        let recovery = AuthRecovery::client_credentials(&issuer.url("/token"), "cli", "secret", None)
+            .with_initial_token("t0")
+            .with_initial_expiry(Duration::from_secs(2))
+            .with_pre_refresh(Duration::from_secs(1));
+        let client = HttpClient::new().with_clock(clock.clone()).with_auth_recovery(recovery);
+
+        client.get(&api.url("/a"), RequestConfig::new()).await?;
+        for round in 1..=2 {
+            clock.advance(Duration::from_secs(1));
+            eventually(|| client.auth_refreshes() == round).await;
+            client.get(&api.url("/b"), RequestConfig::new()).await?;
+        }
+
+        assert_eq!(*statuses.lock().unwrap(), vec![200, 200, 200]);
+        assert_eq!(issuer.received(HttpMethod::Post, "/token"), 2);
+        let status = client.auth_status().context("no auth status")?;
+        assert!(status.has_token);
+        assert_eq!(status.last_refresh_at, Some(clock.system_now()));
+        assert_eq!(status.valid_until, Some(clock.system_now() + Duration::from_secs(2)));
+        client.shutdown(Duration::from_secs(1)).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_pre_refreshes_are_reported_and_backed_off() -> Result<()> {
+        let (api, issuer) = (MockServer::start().await?, MockServer::start().await?);
+        api.when(HttpMethod::Get, "/*").respond(MockResponse::new(204));
+        issuer.when(HttpMethod::Post, "/token").respond_sequence(vec![
+            MockResponse::new(500),
+            MockResponse::new(500),
+            MockResponse::json(200, serde_json::json!({"access_token": "t1", "expires_in": 60})),
+        ]);
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let reported = Arc::clone(&failures);
+        let clock = Arc::new(MockClock::new());
+        let recovery = AuthRecovery::client_credentials(&issuer.url("/token"), "cli", "secret", None)
+            .with_initial_token("t0")
+            .with_initial_expiry(Duration::from_secs(2))
+            .with_pre_refresh(Duration::from_secs(1))
+            .on_refresh_failure(move |status, _| reported.lock().unwrap().push(status.consecutive_failures));
+        let client = HttpClient::new().with_clock(clock.clone()).with_auth_recovery(recovery);
+        client.get(&api.url("/a"), RequestConfig::new()).await?;
+
+        clock.advance(Duration::from_secs(1));
+        eventually(|| failures.lock().unwrap().len() == 1).await;
+        // The first retry waits the minimum backoff of 1s, the next 2s.
+        clock.advance(Duration::from_millis(900));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(issuer.received(HttpMethod::Post, "/token"), 1);
+        clock.advance(Duration::from_millis(100));
+        eventually(|| failures.lock().unwrap().len() == 2).await;
+
+        let status = client.auth_status().context("no auth status")?;
+        assert_eq!(status.consecutive_failures, 2);
+        assert!(status.last_error.is_some());
+        assert_eq!(*failures.lock().unwrap(), vec![1, 2]);
+
+        clock.advance(Duration::from_secs(2));
+        eventually(|| client.auth_refreshes() == 1).await;
+        let status = client.auth_status().context("no auth status")?;
+        assert_eq!((status.consecutive_failures, status.last_error), (0, None));
+        assert_eq!(issuer.received(HttpMethod::Post, "/token"), 3);
+        client.shutdown(Duration::from_secs(1)).await;
+        Ok(())
+    }
+
+    #[test]
+    fn pkce_challenge_matches_rfc_7636() {
+        let pkce = PkceChallenge::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+        assert_eq!(pkce.challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+}
@@ -0,0 +1,320 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::clock::Clock;
+
+pub const DEFAULT_TARGET_COOLDOWN_SECS: u64 = 30;
+
+/// How a request picks one of several base URLs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Cycle through healthy targets in order.
+    RoundRobin,
+    /// Pick healthy targets at random, proportionally to their weight.
+    WeightedRandom,
+    /// Always use the first healthy target in the list.
+    Failover,
+}
+
+/// Health and latency of one load-balanced base URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetStats {
+    pub url: String,
+    pub weight: u32,
+    pub requests: u64,
+    pub failures: u64,
+    pub total_latency_ms: u64,
+    pub healthy: bool,
+}
+
+impl TargetStats {
+    pub fn average_latency_ms(&self) -> Option<u64> {
+        (self.requests > 0).then(|| self.total_latency_ms / self.requests)
+    }
+}
+
+#[derive(Debug)]
+struct TargetState {
+    url: String,
+    weight: u32,
+    ejected_until: Option<Instant>,
+    requests: u64,
+    failures: u64,
+    total_latency_ms: u64,
+}
+
+impl TargetState {
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.ejected_until.is_none_or(|until| until <= now)
+    }
+}
+
+/// Spreads relative request paths over a set of base URLs and temporarily
+/// ejects targets that fail.
+#[derive(Debug)]
+pub(crate) struct LoadBalancer {
+    strategy: Strategy,
+    cooldown: Duration,
+    targets: Mutex<Vec<TargetState>>,
+    next: AtomicUsize,
+    seed: AtomicU64,
+    clock: Arc<dyn Clock>,
+}
+
+impl LoadBalancer {
+    pub(crate) fn new(targets: Vec<(String, u32)>, strategy: Strategy, clock: Arc<dyn Clock>) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x9E37_79B9_7F4A_7C15)
+            | 1;
+
+        Self {
+            strategy,
+            cooldown: Duration::from_secs(DEFAULT_TARGET_COOLDOWN_SECS),
+            targets: Mutex::new(
+                targets
+                    .into_iter()
+                    .map(|(url, weight)| TargetState {
+                        url,
+                        weight: weight.max(1),
+                        ejected_until: None,
+                        requests: 0,
+                        failures: 0,
+                        total_latency_ms: 0,
+                    })
+                    .collect(),
+            ),
+            next: AtomicUsize::new(0),
+            seed: AtomicU64::new(seed),
+            clock,
+        }
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub(crate) fn set_cooldown(&mut self, cooldown: Duration) {
+        self.cooldown = cooldown;
+    }
+
+    /// Candidate `(target index, full URL)` pairs for `path`, in the order they
+    /// should be tried: the strategy's pick, the other healthy targets, and
+    /// finally ejected targets as a last resort.
+    pub(crate) fn plan(&self, path: &str) -> Vec<(usize, String)> {
+        let Ok(targets) = self.targets.lock() else {
+            return Vec::new();
+        };
+        let now = self.clock.now();
+
+        let healthy: Vec<usize> = (0..targets.len())
+            .filter(|&i| targets[i].is_healthy(now))
+            .collect();
+        let ejected = (0..targets.len()).filter(|&i| !targets[i].is_healthy(now));
+
+        let mut order = Vec::with_capacity(targets.len());
+        if !healthy.is_empty() {
+            let first = match self.strategy {
+                Strategy::Failover => 0,
+                Strategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % healthy.len(),
+                Strategy::WeightedRandom => {
+                    let total: u64 = healthy.iter().map(|&i| targets[i].weight as u64).sum();
+                    let mut roll = self.next_random() % total.max(1);
+                    healthy
+                        .iter()
+                        .position(|&i| {
+                            let weight = targets[i].weight as u64;
+                            if roll < weight {
+                                true
+                            } else {
+                                roll -= weight;
+                                false
+                            }
+                        })
+                        .unwrap_or(0)
+                }
+            };
+            order.extend(healthy.iter().cycle().skip(first).take(healthy.len()));
+        }
+        order.extend(ejected);
+
+        order
+            .into_iter()
+            .map(|i| (i, join_base(&targets[i].url, path)))
+            .collect()
+    }
+
+    pub(crate) fn target_url(&self, index: usize) -> Option<String> {
+        let targets = self.targets.lock().ok()?;
+        targets.get(index).map(|t| t.url.clone())
+    }
+
+    pub(crate) fn record(&self, index: usize, success: bool, latency: Duration) {
+        let Ok(mut targets) = self.targets.lock() else {
+            return;
+        };
+        if let Some(target) = targets.get_mut(index) {
+            target.requests += 1;
+            target.total_latency_ms += latency.as_millis() as u64;
+            if success {
+                target.ejected_until = None;
+            } else {
+                target.failures += 1;
+                target.ejected_until = Some(self.clock.now() + self.cooldown);
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Vec<TargetStats> {
+        let Ok(targets) = self.targets.lock() else {
+            return Vec::new();
+        };
+        let now = self.clock.now();
+
+        targets
+            .iter()
+            .map(|t| TargetStats {
+                url: t.url.clone(),
+                weight: t.weight,
+                requests: t.requests,
+                failures: t.failures,
+                total_latency_ms: t.total_latency_ms,
+                healthy: t.is_healthy(now),
+            })
+            .collect()
+    }
+
+    fn next_random(&self) -> u64 {
+        // xorshift64; good enough for spreading load
+        let mut x = self.seed.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.seed.store(x, Ordering::Relaxed);
+        x
+    }
+}
+
+fn join_base(base: &str, path: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{MockClock, SystemClock};
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+    use anyhow::Result;
+
+    async fn region(name: &'static str) -> Result<MockServer> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/items/:id").respond(MockResponse::text(200, name));
+        Ok(server)
+    }
+
+    /// A base URL nothing listens on.
+    fn down() -> Result<String> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        Ok(format!("http://{}", listener.local_addr()?))
+    }
+
+    fn counts(stats: &[TargetStats]) -> Vec<(u64, u64, bool)> {
+        stats.iter().map(|target| (target.requests, target.failures, target.healthy)).collect()
+    }
+
+    #[tokio::test]
+    async fn round_robin_skips_a_target_that_is_down() -> Result<()> {
+        let (east, west) = (region("east").await?, region("west").await?);
+        let client = HttpClient::new().with_base_urls([east.url(""), west.url(""), down()?], Strategy::RoundRobin);
+
+        let mut served = Vec::new();
+        for id in 0..9 {
+            let response = client.get(&format!("/items/{id}"), RequestConfig::new()).await?;
+            let target = if response.body == "east" { &east } else { &west };
+            assert_eq!(response.target, Some(target.url("")));
+            served.push(response.body);
+        }
+
+        assert_eq!(served, ["east", "west", "east", "west", "east", "west", "east", "west", "east"]);
+        assert_eq!(counts(&client.target_stats()), [(5, 0, true), (4, 0, true), (1, 1, false)]);
+        assert_eq!(east.requests_to(HttpMethod::Get, "/items/:id").len(), 5);
+        assert_eq!(west.requests()[0].path, "/items/1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failover_returns_to_the_primary_after_its_cooldown() -> Result<()> {
+        let backup = region("backup").await?;
+        let clock = Arc::new(MockClock::new());
+        let client = HttpClient::new()
+            .with_clock(clock.clone())
+            .with_base_urls([down()?, backup.url(""), down()?], Strategy::Failover)
+            .with_target_cooldown(Duration::from_secs(10));
+
+        for _ in 0..3 {
+            let response = client.get("/items/1", RequestConfig::new()).await?;
+            assert_eq!(response.body, "backup");
+        }
+        assert_eq!(counts(&client.target_stats()), [(1, 1, false), (3, 0, true), (0, 0, true)]);
+
+        clock.advance(Duration::from_secs(10));
+        assert!(client.target_stats()[0].healthy);
+        let response = client.get("/items/2", RequestConfig::new()).await?;
+        assert_eq!(response.target, Some(backup.url("")));
+        assert_eq!(counts(&client.target_stats()), [(2, 2, false), (4, 0, true), (0, 0, true)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn five_hundreds_eject_a_target_too() -> Result<()> {
+        let (broken, healthy) = (MockServer::start().await?, region("healthy").await?);
+        broken.when(HttpMethod::Get, "/items/:id").respond(MockResponse::text(503, "busy"));
+        let client = HttpClient::new().with_base_urls([broken.url(""), healthy.url("")], Strategy::Failover);
+
+        assert_eq!(client.get("/items/1", RequestConfig::new()).await?.body, "healthy");
+        assert_eq!(client.get("/items/2", RequestConfig::new()).await?.body, "healthy");
+        assert_eq!(counts(&client.target_stats()), [(1, 1, false), (2, 0, true)]);
+        assert!(client.target_stats()[1].average_latency_ms().is_some());
+        assert_eq!(client.target_stats()[0].url, broken.url(""));
+        Ok(())
+    }
+
+    #[test]
+    fn weighted_random_follows_the_weights() {
+        let targets = vec![("http://a".to_string(), 1), ("http://b/".to_string(), 3), ("http://c".to_string(), 0)];
+        let balancer = LoadBalancer::new(targets, Strategy::WeightedRandom, Arc::new(SystemClock));
+
+        let mut picks = [0; 3];
+        for _ in 0..4000 {
+            let plan = balancer.plan("/x");
+            assert_eq!(plan.len(), 3);
+            picks[plan[0].0] += 1;
+        }
+        // Weights 1, 3 and 0 (raised to 1) share 4000 picks as 800, 2400, 800.
+        assert!((650..950).contains(&picks[0]), "{picks:?}");
+        assert!((2200..2600).contains(&picks[1]), "{picks:?}");
+        assert!((650..950).contains(&picks[2]), "{picks:?}");
+    }
+
+    #[test]
+    fn ejected_targets_are_tried_last() {
+        let targets = vec![("http://a/".to_string(), 1), ("http://b".to_string(), 1), ("http://c".to_string(), 1)];
+        let balancer = LoadBalancer::new(targets, Strategy::Failover, Arc::new(SystemClock));
+        balancer.record(0, false, Duration::from_millis(5));
+
+        let plan = balancer.plan("items/1");
+        assert_eq!(
+            plan,
+            [
+                (1, "http://b/items/1".to_string()),
+                (2, "http://c/items/1".to_string()),
+                (0, "http://a/items/1".to_string()),
+            ]
+        );
+        balancer.record(0, true, Duration::from_millis(5));
+        assert_eq!(balancer.plan("/items/1")[0], (0, "http://a/items/1".to_string()));
+    }
+}
@@ -0,0 +1,386 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+
+use crate::clock::Clock;
+use crate::models::ApiResponse;
+
+/// Requests a batch keeps in flight unless `BatchOptions::with_concurrency`
+/// says otherwise.
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 8;
+
+/// How a batch such as `HttpClient::get_many` runs its requests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchOptions {
+    /// Requests in flight at once; the starting point when adaptive.
+    pub concurrency: usize,
+    pub adaptive: Option<AdaptiveOptions>,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: DEFAULT_BATCH_CONCURRENCY,
+            adaptive: None,
+        }
+    }
+}
+
+impl BatchOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tunes concurrency while the batch runs, starting from
+    /// `DEFAULT_BATCH_CONCURRENCY` (or `with_concurrency`) within the bounds.
+    pub fn adaptive(options: AdaptiveOptions) -> Self {
+        Self {
+            concurrency: DEFAULT_BATCH_CONCURRENCY,
+            adaptive: Some(options),
+        }
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    fn starting_concurrency(&self) -> usize {
+        match &self.adaptive {
+            Some(adaptive) => self.concurrency.clamp(adaptive.min, adaptive.max),
+            None => self.concurrency.max(1),
+        }
+    }
+}
+
+/// AIMD limits: concurrency grows by one after each window of requests
+/// (one window is as many requests as the current concurrency) that stays
+/// under the error-rate threshold, and is multiplied by `backoff` on a 429
+/// or 503, a response slower than `latency_threshold`, or a window with too
+/// many failures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveOptions {
+    pub min: usize,
+    pub max: usize,
+    pub latency_threshold: Duration,
+    /// Share of failed requests in a window, in `[0, 1]`.
+    pub max_error_rate: f64,
+    /// Multiplier applied when backing off, in `(0, 1)`.
+    pub backoff: f64,
+}
+
+impl Default for AdaptiveOptions {
+    fn default() -> Self {
+        Self {
+            min: 1,
+            max: 64,
+            latency_threshold: Duration::from_secs(1),
+            max_error_rate: 0.1,
+            backoff: 0.5,
+        }
+    }
+}
+
+impl AdaptiveOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_bounds(mut self, min: usize, max: usize) -> Self {
+        self.min = min.max(1);
+        self.max = max.max(self.min);
+        self
+    }
+
+    pub fn with_latency_threshold(mut self, threshold: Duration) -> Self {
+        self.latency_threshold = threshold;
+        self
+    }
+
+    /// Clamped to `[0, 1]`.
+    pub fn with_max_error_rate(mut self, rate: f64) -> Self {
+        self.max_error_rate = if rate.is_nan() { 0.0 } else { rate.clamp(0.0, 1.0) };
+        self
+    }
+
+    /// Clamped to `[0.1, 0.95]`.
+    pub fn with_backoff(mut self, backoff: f64) -> Self {
+        self.backoff = if backoff.is_nan() { 0.5 } else { backoff.clamp(0.1, 0.95) };
+        self
+    }
+}
+
+/// Why a batch's concurrency changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConcurrencyReason {
+    Start,
+    /// A window finished under both thresholds.
+    Increase,
+    /// The server answered 429 or 503.
+    Overloaded,
+    /// A response took longer than `latency_threshold`.
+    SlowResponse,
+    /// A window went over `max_error_rate`.
+    Errors,
+}
+
+/// One step of a batch's concurrency timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConcurrencyChange {
+    /// Since the batch started.
+    pub at: Duration,
+    /// Requests finished at that point.
+    pub completed: usize,
+    pub concurrency: usize,
+    pub reason: ConcurrencyReason,
+}
+
+/// Outcome of a batch.
+#[derive(Debug)]
+pub struct BatchReport {
+    /// One result per request, in input order.
+    pub results: Vec<Result<ApiResponse>>,
+    pub elapsed: Duration,
+    /// The starting concurrency followed by every change; a single entry
+    /// unless the batch was adaptive.
+    pub concurrency: Vec<ConcurrencyChange>,
+}
+
+impl BatchReport {
+    /// Requests that got a 2xx response.
+    pub fn succeeded(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.as_ref().is_ok_and(ApiResponse::is_success))
+            .count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.succeeded()
+    }
+
+    /// Concurrency averaged over the second half of the batch, weighted by
+    /// the requests that finished under each value; AIMD keeps oscillating
+    /// around this rather than stopping on it.
+    pub fn settled_concurrency(&self) -> usize {
+        let total = self.results.len();
+        let from = total / 2;
+        let (mut weighted, mut counted) = (0, 0);
+        for (i, change) in self.concurrency.iter().enumerate() {
+            let until = self.concurrency.get(i + 1).map_or(total, |next| next.completed);
+            let span = until.saturating_sub(change.completed.max(from));
+            weighted += change.concurrency * span;
+            counted += span;
+        }
+        match counted {
+            0 => self.concurrency.last().map_or(0, |change| change.concurrency),
+            counted => (weighted + counted / 2) / counted,
+        }
+    }
+}
+
+enum Outcome {
+    Success,
+    Overloaded,
+    Failed,
+}
+
+impl Outcome {
+    fn of(result: &Result<ApiResponse>) -> Self {
+        match result {
+            Ok(response) if matches!(response.status, 429 | 503) => Outcome::Overloaded,
+            Ok(response) if response.status < 500 => Outcome::Success,
+            _ => Outcome::Failed,
+        }
+    }
+}
+
+struct AdaptiveController {
+    options: AdaptiveOptions,
+    limit: usize,
+    completed: usize,
+    window: usize,
+    window_errors: usize,
+    /// Requests started before the last backoff finish without causing
+    /// another one.
+    hold_until: usize,
+}
+
+impl AdaptiveController {
+    fn new(options: AdaptiveOptions, limit: usize) -> Self {
+        Self {
+            options,
+            limit,
+            completed: 0,
+            window: 0,
+            window_errors: 0,
+            hold_until: 0,
+        }
+    }
+
+    /// Records a finished request; returns the reason when the limit changed.
+    fn record(&mut self, latency: Duration, outcome: Outcome) -> Option<ConcurrencyReason> {
+        self.completed += 1;
+        let spike = match outcome {
+            Outcome::Overloaded => Some(ConcurrencyReason::Overloaded),
+            _ if latency > self.options.latency_threshold => Some(ConcurrencyReason::SlowResponse),
+            _ => None,
+        };
+        if let Some(reason) = spike {
+            return self.back_off(reason);
+        }
+
+        self.window += 1;
+        if matches!(outcome, Outcome::Failed) {
+            self.window_errors += 1;
+        }
+        if self.window < self.limit {
+            return None;
+        }
+        let error_rate = self.window_errors as f64 / self.window as f64;
+        if error_rate > self.options.max_error_rate {
+            return self.back_off(ConcurrencyReason::Errors);
+        }
+        self.reset_window();
+        (self.limit < self.options.max).then(|| {
+            self.limit += 1;
+            ConcurrencyReason::Increase
+        })
+    }
+
+    fn back_off(&mut self, reason: ConcurrencyReason) -> Option<ConcurrencyReason> {
+        self.reset_window();
+        if self.completed < self.hold_until {
+            return None;
+        }
+        self.hold_until = self.completed + self.limit;
+        let reduced = ((self.limit as f64 * self.options.backoff) as usize).max(self.options.min);
+        (reduced < self.limit).then(|| {
+            self.limit = reduced;
+            reason
+        })
+    }
+
+    fn reset_window(&mut self) {
+        self.window = 0;
+        self.window_errors = 0;
+    }
+}
+
+/// Sends one request per item with at most `options.concurrency` in flight,
+/// adjusting that limit as responses come back when the batch is adaptive.
+pub(crate) async fn run_batch<T, F, Fut>(
+    items: Vec<T>,
+    options: &BatchOptions,
+    clock: &Arc<dyn Clock>,
+    send: F,
+) -> BatchReport
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<ApiResponse>>,
+{
+    let start = clock.now();
+    let mut limit = options.starting_concurrency();
+    let mut controller = options
+        .adaptive
+        .clone()
+        .map(|adaptive| AdaptiveController::new(adaptive, limit));
+    let mut timeline = vec![ConcurrencyChange {
+        at: Duration::ZERO,
+        completed: 0,
+        concurrency: limit,
+        reason: ConcurrencyReason::Start,
+    }];
+
+    let mut results: Vec<Option<Result<ApiResponse>>> = items.iter().map(|_| None).collect();
+    let mut pending = items.into_iter().enumerate();
+    let mut running = FuturesUnordered::new();
+    let mut completed = 0;
+    loop {
+        while running.len() < limit {
+            let Some((index, item)) = pending.next() else {
+                break;
+            };
+            let request = send(item);
+            running.push(async move {
+                let sent = clock.now();
+                let result = request.await;
+                (index, clock.now().saturating_duration_since(sent), result)
+            });
+        }
+        let Some((index, latency, result)) = running.next().await else {
+            break;
+        };
+        completed += 1;
+        if let Some(controller) = controller.as_mut()
+            && let Some(reason) = controller.record(latency, Outcome::of(&result))
+        {
+            limit = controller.limit;
+            timeline.push(ConcurrencyChange {
+                at: clock.now().saturating_duration_since(start),
+                completed,
+                concurrency: limit,
+                reason,
+            });
+        }
+        results[index] = Some(result);
+    }
+
+    BatchReport {
+        results: results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|| Err(anyhow::anyhow!("Batch request was never sent"))))
+            .collect(),
+        elapsed: clock.now().saturating_duration_since(start),
+        concurrency: timeline,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn adaptive_concurrency_settles_where_latency_degrades() -> Result<()> {
+        let server = MockServer::start().await?;
+        // Finish times of the responses still being served; past 8 at once
+        // every new request is six times slower.
+        let serving: Mutex<Vec<Instant>> = Mutex::new(Vec::new());
+        server.when(HttpMethod::Get, "/items/:id").respond_with(move |_| {
+            let now = Instant::now();
+            let mut serving = serving.lock().unwrap();
+            serving.retain(|done| *done > now);
+            let delay = match serving.len() + 1 {
+                0..=8 => Duration::from_millis(20),
+                _ => Duration::from_millis(120),
+            };
+            serving.push(now + delay);
+            MockResponse::new(200).with_delay(delay)
+        });
+        let client = HttpClient::new();
+        let urls: Vec<String> = (0..240).map(|id| server.url(&format!("/items/{id}"))).collect();
+        let adaptive = AdaptiveOptions::new()
+            .with_bounds(1, 32)
+            .with_latency_threshold(Duration::from_millis(60))
+            .with_backoff(0.9);
+
+        let report = client
+            .get_many(&urls, RequestConfig::new(), BatchOptions::adaptive(adaptive).with_concurrency(2))
+            .await;
+
+        assert_eq!(report.succeeded(), 240);
+        assert_eq!(report.concurrency[0].concurrency, 2);
+        assert!(report.concurrency.iter().any(|change| change.reason == ConcurrencyReason::SlowResponse));
+        assert!(report.concurrency.iter().all(|change| change.concurrency <= 12), "{:?}", report.concurrency);
+        let settled = report.settled_concurrency();
+        assert!((7..=9).contains(&settled), "settled on {settled}: {:?}", report.concurrency);
+        Ok(())
+    }
+}
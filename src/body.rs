@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::utils::{canonicalize_json, encode_form_pairs};
+
+pub(crate) const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+static BOUNDARY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A request body together with the content type it is sent as.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    /// `application/json`
+    Json(Value),
+    /// `application/json` in RFC 8785 canonical form, byte for byte what
+    /// `utils::canonicalize_json` returns, so a signature computed over
+    /// that string matches the body sent.
+    CanonicalJson(Value),
+    /// `application/x-www-form-urlencoded`; fields are sent sorted by name.
+    Form(HashMap<String, String>),
+    /// `text/plain; charset=utf-8`
+    Text(String),
+    /// Raw bytes with an explicit MIME type.
+    Bytes(Vec<u8>, String),
+    /// `multipart/form-data`
+    Multipart(Vec<FormPart>),
+}
+
+/// One field of a `multipart/form-data` body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormPart {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl FormPart {
+    pub fn text(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            data: value.as_bytes().to_vec(),
+            filename: None,
+            content_type: None,
+        }
+    }
+
+    pub fn file(name: &str, filename: &str, data: Vec<u8>, content_type: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            data,
+            filename: Some(filename.to_string()),
+            content_type: Some(content_type.to_string()),
+        }
+    }
+}
+
+impl Body {
+    /// Parses `data` as JSON.
+    pub fn json_str(data: &str) -> Result<Self> {
+        let value = serde_json::from_str(data).with_context(|| "Invalid JSON data provided")?;
+        Ok(Body::Json(value))
+    }
+
+    /// Serializes the body once, returning the `Content-Type` and payload.
+    /// Multipart bodies get a fresh boundary on each call.
+    pub fn encode(&self) -> Result<(String, Vec<u8>)> {
+        match self {
+            Body::Json(value) => Ok((
+                "application/json".to_string(),
+                serde_json::to_vec(value).with_context(|| "Failed to serialize JSON body")?,
+            )),
+            Body::CanonicalJson(value) => Ok(("application/json".to_string(), canonicalize_json(value).into_bytes())),
+            Body::Form(fields) => {
+                let mut fields: Vec<_> = fields.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                fields.sort();
+                Ok((FORM_CONTENT_TYPE.to_string(), encode_form_pairs(&fields).into_bytes()))
+            }
+            Body::Text(text) => Ok(("text/plain; charset=utf-8".to_string(), text.clone().into_bytes())),
+            Body::Bytes(data, mime) => Ok((mime.clone(), data.clone())),
+            Body::Multipart(parts) => {
+                let boundary = new_boundary();
+                Ok((
+                    format!("multipart/form-data; boundary={}", boundary),
+                    encode_multipart(parts, &boundary),
+                ))
+            }
+        }
+    }
+}
+
+fn new_boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let count = BOUNDARY_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("----RustHTTPBoundary{:016x}{:04x}", nanos, count & 0xffff)
+}
+
+fn encode_multipart(parts: &[FormPart], boundary: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for part in parts {
+        out.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        let mut disposition = format!("Content-Disposition: form-data; name=\"{}\"", quote(&part.name));
+        if let Some(filename) = &part.filename {
+            disposition.push_str(&format!("; filename=\"{}\"", quote(filename)));
+        }
+        out.extend_from_slice(disposition.as_bytes());
+        out.extend_from_slice(b"\r\n");
+        if let Some(content_type) = &part.content_type {
+            out.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&part.data);
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    out
+}
+
+/// Escapes a quoted-string parameter the way browsers do.
+fn quote(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
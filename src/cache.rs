@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::models::ApiResponse;
+
+pub const DEFAULT_RESPONSE_CACHE_TTL_SECS: u64 = 60;
+pub const DEFAULT_RESPONSE_CACHE_ENTRIES: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseCacheConfig {
+    /// How long a response stays fresh; a shorter `max-age` from the server
+    /// wins.
+    pub ttl: Duration,
+    pub max_entries: usize,
+    /// Writes also invalidate the parent collection, so a `PUT /items/1`
+    /// drops a cached `GET /items`.
+    pub invalidate_related: bool,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(DEFAULT_RESPONSE_CACHE_TTL_SECS),
+            max_entries: DEFAULT_RESPONSE_CACHE_ENTRIES,
+            invalidate_related: false,
+        }
+    }
+}
+
+impl ResponseCacheConfig {
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn with_related_invalidation(mut self, enabled: bool) -> Self {
+        self.invalidate_related = enabled;
+        self
+    }
+}
+
+struct CacheEntry {
+    stored: Instant,
+    fresh_for: Duration,
+    resource: String,
+    response: ApiResponse,
+}
+
+/// What `ResponseCache::lookup` found for a request.
+pub(crate) enum Lookup {
+    Fresh(ApiResponse),
+    /// Expired, or stored `no-cache`, but it can be revalidated with these
+    /// conditional request headers.
+    Stale(ApiResponse, Vec<(&'static str, String)>),
+    Missing,
+}
+
+/// Successful GET responses keyed by request fingerprint.
+pub(crate) struct ResponseCache {
+    config: ResponseCacheConfig,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    /// Bumped by every invalidation, so a GET that started before one
+    /// cannot store what it fetched afterwards.
+    generation: AtomicU64,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(config: ResponseCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Taken before a request goes out and handed back to `insert`.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn lookup(&self, key: &str, now: Instant) -> Lookup {
+        let Ok(mut entries) = self.entries.lock() else {
+            return Lookup::Missing;
+        };
+        let Some(entry) = entries.get(key) else {
+            return Lookup::Missing;
+        };
+        if now.saturating_duration_since(entry.stored) < entry.fresh_for {
+            return Lookup::Fresh(entry.response.clone());
+        }
+        let validators = validators(&entry.response);
+        if validators.is_empty() {
+            entries.remove(key);
+            return Lookup::Missing;
+        }
+        Lookup::Stale(entry.response.clone(), validators)
+    }
+
+    /// Stores a response the server allows a private cache to keep:
+    /// `no-store` and `private` responses are skipped, and `no-cache` ones
+    /// are only kept when they can be revalidated. Does nothing if the
+    /// cache was invalidated after `generation` was taken.
+    pub(crate) fn insert(&self, key: String, url: &str, response: &ApiResponse, now: Instant, generation: u64) {
+        let directives = CacheControl::of(response);
+        if directives.no_store || directives.private || self.config.max_entries == 0 {
+            return;
+        }
+        let fresh_for = self.fresh_for(&directives);
+        if fresh_for.is_zero() && validators(response).is_empty() {
+            return;
+        }
+
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        // Checked under the lock, which `invalidate` also takes.
+        if self.generation() != generation {
+            return;
+        }
+        if entries.len() >= self.config.max_entries
+            && !entries.contains_key(&key)
+            && let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored)
+                .map(|(key, _)| key.clone())
+        {
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            key,
+            CacheEntry {
+                stored: now,
+                fresh_for,
+                resource: resource_of(url),
+                response: response.clone(),
+            },
+        );
+    }
+
+    /// Applies a `304 Not Modified` to the stale `cached` response and, if
+    /// the cache was not invalidated meanwhile, stores it as fresh again.
+    pub(crate) fn revalidate(
+        &self,
+        key: &str,
+        mut cached: ApiResponse,
+        not_modified: &ApiResponse,
+        now: Instant,
+        generation: u64,
+    ) -> ApiResponse {
+        for (name, value) in &not_modified.headers {
+            if !matches!(name.as_str(), "content-length" | "content-encoding" | "transfer-encoding") {
+                cached.headers.insert(name.clone(), value.clone());
+            }
+        }
+        let fresh_for = self.fresh_for(&CacheControl::of(&cached));
+        if let Ok(mut entries) = self.entries.lock()
+            && self.generation() == generation
+            && let Some(entry) = entries.get_mut(key)
+        {
+            entry.stored = now;
+            entry.fresh_for = fresh_for;
+            entry.response = cached.clone();
+        }
+        cached
+    }
+
+    /// The configured TTL, cut short by `max-age` or, for `no-cache`, to
+    /// nothing.
+    fn fresh_for(&self, directives: &CacheControl) -> Duration {
+        if directives.no_cache {
+            return Duration::ZERO;
+        }
+        match directives.max_age {
+            Some(max_age) => self.config.ttl.min(Duration::from_secs(max_age)),
+            None => self.config.ttl,
+        }
+    }
+
+    /// Drops every cached GET of the resource at `url`, whatever its query
+    /// string, plus its parent collection when configured.
+    pub(crate) fn invalidate(&self, url: &str) {
+        let resource = resource_of(url);
+        let parent = self
+            .config
+            .invalidate_related
+            .then(|| resource.rsplit_once('/').map(|(parent, _)| parent.to_string()))
+            .flatten()
+            .filter(|parent| !parent.is_empty() && !parent.ends_with('/'));
+
+        if let Ok(mut entries) = self.entries.lock() {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            entries.retain(|_, entry| entry.resource != resource && Some(&entry.resource) != parent.as_ref());
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            entries.clear();
+        }
+    }
+}
+
+/// The `Cache-Control` directives the cache acts on.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    max_age: Option<u64>,
+}
+
+impl CacheControl {
+    fn of(response: &ApiResponse) -> Self {
+        let mut directives = Self::default();
+        let Some(value) = response.headers.get("cache-control") else {
+            return directives;
+        };
+        for directive in value.split(',') {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name, Some(argument.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => directives.no_store = true,
+                // With a field list these only cover those headers; treat
+                // them as covering the whole response.
+                "no-cache" => directives.no_cache = true,
+                "private" => directives.private = true,
+                "max-age" => directives.max_age = argument.and_then(|age| age.parse().ok()).or(Some(0)),
+                _ => {}
+            }
+        }
+        directives
+    }
+}
+
+/// Conditional request headers that revalidate `response`.
+fn validators(response: &ApiResponse) -> Vec<(&'static str, String)> {
+    let mut validators = Vec::new();
+    if let Some(etag) = response.headers.get("etag") {
+        validators.push(("If-None-Match", etag.clone()));
+    }
+    if let Some(modified) = response.headers.get("last-modified") {
+        validators.push(("If-Modified-Since", modified.clone()));
+    }
+    validators
+}
+
+/// The URL without query, fragment or trailing slash.
+fn resource_of(url: &str) -> String {
+    let end = url.find(['?', '#']).unwrap_or(url.len());
+    url[..end].trim_end_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::models::{CacheStatus, HttpMethod, RequestConfig};
+    use crate::testing::{MockResponse, MockServer};
+    use crate::HttpClient;
+    use anyhow::Result;
+    use std::sync::Arc;
+
+    fn cached_client() -> (HttpClient, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new());
+        let client = HttpClient::new()
+            .with_clock(clock.clone())
+            .with_response_cache(ResponseCacheConfig::default());
+        (client, clock)
+    }
+
+    #[test]
+    fn parses_cache_control_directives() {
+        let response = |value: &str| {
+            let mut response = ApiResponse::synthetic(200, "");
+            response.headers.insert("cache-control".to_string(), value.to_string());
+            CacheControl::of(&response)
+        };
+        assert_eq!(
+            response("Private, max-age=\"30\""),
+            CacheControl { private: true, max_age: Some(30), ..Default::default() }
+        );
+        assert_eq!(
+            response("no-cache=\"Set-Cookie\", no-store"),
+            CacheControl { no_cache: true, no_store: true, ..Default::default() }
+        );
+        assert_eq!(response("max-age=soon").max_age, Some(0));
+    }
+
+    #[tokio::test]
+    async fn max_age_shortens_the_ttl() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/items")
+            .respond(MockResponse::text(200, "[]").with_header("Cache-Control", "max-age=10"));
+        let (client, clock) = cached_client();
+
+        client.get(&server.url("/items"), RequestConfig::new()).await?;
+        clock.advance(Duration::from_secs(5));
+        let second = client.get(&server.url("/items"), RequestConfig::new()).await?;
+        assert_eq!(second.cache_status, CacheStatus::Hit);
+        clock.advance(Duration::from_secs(6));
+        let third = client.get(&server.url("/items"), RequestConfig::new()).await?;
+        assert_eq!(third.cache_status, CacheStatus::Miss);
+        assert_eq!(server.received(HttpMethod::Get, "/items"), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn private_and_unvalidated_no_cache_responses_are_not_stored() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/private")
+            .respond(MockResponse::text(200, "mine").with_header("Cache-Control", "private, max-age=60"));
+        server
+            .when(HttpMethod::Get, "/no-cache")
+            .respond(MockResponse::text(200, "fresh").with_header("Cache-Control", "no-cache"));
+        let (client, _) = cached_client();
+
+        for path in ["/private", "/no-cache"] {
+            client.get(&server.url(path), RequestConfig::new()).await?;
+            let second = client.get(&server.url(path), RequestConfig::new()).await?;
+            assert_eq!(second.cache_status, CacheStatus::Miss, "{}", path);
+            assert_eq!(server.received(HttpMethod::Get, path), 2, "{}", path);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_cache_responses_are_revalidated_with_their_etag() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/items").respond_sequence([
+            MockResponse::text(200, "[1]")
+                .with_header("Cache-Control", "no-cache")
+                .with_header("ETag", "\"v1\""),
+            MockResponse::new(304).with_header("ETag", "\"v1\""),
+        ]);
+        let (client, _) = cached_client();
+
+        client.get(&server.url("/items"), RequestConfig::new()).await?;
+        let second = client.get(&server.url("/items"), RequestConfig::new()).await?;
+        assert_eq!(second.cache_status, CacheStatus::Revalidated);
+        assert_eq!((second.status, second.body.as_str()), (200, "[1]"));
+        assert!(!second.from_cache);
+        let requests = server.requests_to(HttpMethod::Get, "/items");
+        assert_eq!(requests[0].header("if-none-match"), None);
+        assert_eq!(requests[1].header("if-none-match"), Some("\"v1\""));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_get_in_flight_during_a_write_does_not_store_its_response() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/items/1")
+            .respond(MockResponse::text(200, "old").with_delay(Duration::from_millis(200)));
+        server.when(HttpMethod::Put, "/items/1").respond(MockResponse::new(204));
+        let (client, _) = cached_client();
+        let url = server.url("/items/1");
+
+        let write = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            client.put(&url, "{}", RequestConfig::new()).await
+        };
+        let (read, written) = tokio::join!(client.get(&url, RequestConfig::new()), write);
+        assert_eq!(read?.cache_status, CacheStatus::Miss);
+        written?;
+
+        let after = client.get(&url, RequestConfig::new()).await?;
+        assert_eq!(after.cache_status, CacheStatus::Miss);
+        assert_eq!(server.received(HttpMethod::Get, "/items/1"), 2);
+        Ok(())
+    }
+}
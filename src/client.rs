@@ -1,112 +1,1582 @@
 use anyhow::{Context, Result};
-use reqwest::{Client, Response};
+use bytes::Bytes;
+use futures_util::stream::{Stream, StreamExt, TryStream};
+use reqwest::{Client, ClientBuilder, RequestBuilder, Response};
+use std::net::{IpAddr, SocketAddr};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::Value;
-use std::collections::HashMap;
-use std::time::Instant;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
 
-use crate::models::{ApiResponse, RequestConfig};
+use crate::batch::{run_batch, BatchOptions, BatchReport};
+use crate::body::{Body, FORM_CONTENT_TYPE};
+use crate::auth::{AuthCodeOptions, AuthRecovery, AuthState, AuthStatus, OAuthToken};
+use crate::clock::{Clock, SystemClock};
+use crate::codec::Codec;
+use crate::balancer::{LoadBalancer, Strategy, TargetStats};
+use crate::cache::{Lookup, ResponseCache, ResponseCacheConfig};
+use crate::download::{self, DownloadReport, FilenameSource};
+use crate::measure::{MeasureOptions, MeasureReport};
+#[cfg(feature = "archive")]
+use crate::extract::{self, ExtractOptions, ExtractReport};
+use crate::dns::{CachingResolver, DnsCacheConfig, DnsLookup, DnsState, DnsStats};
+use crate::connections::{ConnectionLimiter, ConnectionPermit, ConnectionUsage};
+use crate::cookies::{CookieJar, CookiePolicy, StoredCookie, FIRST_PARTY};
+use crate::faults::{FaultInjection, FaultInjector, FAULT_INJECTED_HEADER};
+use crate::headers::HeaderList;
+use crate::error::{AuthRecoveryError, ClientClosed, JsonRpcError, MissingLink, PartialResponse, PollTimeout, RequestError, RetriesExhausted, TimeoutKind, TlsPolicyViolation, WarningEscalated};
+use crate::quota::{QuotaUsage, TransferQuota};
+use crate::metrics::MetricsRecorder;
+use crate::models::{
+    ApiResponse, AttemptOutcome, AttemptRecord, BodyLimits, CacheStatus, EndpointCapabilities, HttpMethod, IntoRequestUrl, PendingBody,
+    RequestConfig, RequestInfo, RequestStats, TlsInfo, UrlRedactor, REQUEST_ID_HEADER,
+};
+use crate::scope::{ScopeDefaults, ScopedClient};
+use crate::secrets::SecretScanner;
+use crate::signing::{sign_request, RequestSigner};
+use crate::spill::{ReadBody, SpillWriter};
+#[cfg(feature = "tls-inspect")]
+use crate::tls::{inspect_handshake, split_target};
+#[cfg(feature = "tls-inspect")]
+use crate::tls::probe_version;
+use crate::tls::{handshake_error, is_negotiation_failure, CertificateInfo, TlsPolicy, TlsVersion, DEFAULT_CERT_EXPIRY_WARNING_DAYS};
+use crate::shutdown::{track_stream, Lifecycle, ShutdownReport};
+use crate::stream::{json_array_stream, next_chunk, paginate, PageCursor};
+use crate::warnings::{check_request, check_response, RequestWarning, WarningCode};
+use crate::utils::{decode_body, parse_url, decode_content_encoding, is_client_decoded, ACCEPT_ENCODING, generate_request_id, encode_form_pairs, expected_body_length, pretty_print_html, pretty_print_json, to_nested_form};
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_USER_AGENT: &str = "RustHttpClient/0.1.0";
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+pub const DEFAULT_CAPABILITY_TTL_SECS: u64 = 300;
+
+pub type StatusMapper = Arc<dyn Fn(u16) -> u16 + Send + Sync>;
+pub type RequestInterceptor = Arc<dyn Fn(&InterceptedRequest<'_>) -> Option<ApiResponse> + Send + Sync>;
+pub type RedirectHandler = Arc<dyn Fn(&RedirectAttempt<'_>) -> RedirectAction + Send + Sync>;
+/// `RedirectAction::Rewrite` targets by the `(from, to)` of the stopped hop.
+type RedirectRewrites = Arc<Mutex<HashMap<(String, String), url::Url>>>;
+
+/// A redirect the server asked for, as seen by `HttpClient::on_redirect`.
+#[derive(Debug, Clone, Copy)]
+pub struct RedirectAttempt<'a> {
+    pub status: u16,
+    /// The URL that answered with the redirect.
+    pub from: &'a url::Url,
+    pub to: &'a url::Url,
+    /// Redirects already followed for this request.
+    pub followed: usize,
+}
+
+impl RedirectAttempt<'_> {
+    /// Host or port changes; credentials are not sent on such redirects.
+    pub fn is_cross_host(&self) -> bool {
+        self.from.host_str() != self.to.host_str() || self.from.port_or_known_default() != self.to.port_or_known_default()
+    }
+
+    /// From `https` to plain `http`.
+    pub fn is_downgrade(&self) -> bool {
+        self.from.scheme() == "https" && self.to.scheme() == "http"
+    }
+}
+
+/// What to do with a redirect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedirectAction {
+    Follow,
+    /// Return the 3xx response itself.
+    Stop,
+    /// Fail the request with this reason.
+    Fail(String),
+    /// Go to this URL instead. The client follows it itself, with the same
+    /// method and body changes and credential stripping as a plain follow.
+    Rewrite(url::Url),
+}
+
+/// What a `before_request` interceptor sees of an attempt about to be sent.
+#[derive(Debug, Clone, Copy)]
+pub struct InterceptedRequest<'a> {
+    pub method: HttpMethod,
+    /// The full URL, after base URL resolution and `with_query`.
+    pub url: &'a str,
+    pub headers: &'a HeaderList,
+    /// 1 for the first attempt, counting up through retries.
+    pub attempt: u32,
+}
+
+/// A request kept for sending again with `HttpClient::replay`.
+#[derive(Debug, Clone)]
+pub struct PreparedRequest {
+    pub method: HttpMethod,
+    pub url: String,
+    pub body: Option<Body>,
+    pub config: RequestConfig,
+}
+
+impl PreparedRequest {
+    pub fn new(method: HttpMethod, url: impl Into<String>, config: RequestConfig) -> Self {
+        Self {
+            method,
+            url: url.into(),
+            body: None,
+            config,
+        }
+    }
+
+    pub fn with_body(mut self, body: Body) -> Self {
+        self.body = Some(body);
+        self
+    }
+}
 
 pub struct HttpClient {
     client: Client,
+    timeout: Duration,
+    tcp_nodelay: Option<bool>,
+    cert_expiry_warning: Duration,
+    tls_policy: Option<TlsPolicy>,
+    /// Clients bound to a source address, one per `RequestConfig::local_address`.
+    local_clients: Mutex<HashMap<IpAddr, Client>>,
+    dns: Arc<DnsState>,
+    default_headers: HeaderList,
+    coalesce_gets: bool,
+    request_ids: bool,
+    inflight: InFlightMap,
+    status_mapper: Option<StatusMapper>,
+    redirect_handler: Option<RedirectHandler>,
+    redirect_rewrites: RedirectRewrites,
+    cookies: Option<Arc<CookieJar>>,
+    escalated_warnings: HashSet<WarningCode>,
+    interceptors: Vec<RequestInterceptor>,
+    signer: Option<Arc<dyn RequestSigner>>,
+    faults: Mutex<Option<Arc<FaultInjector>>>,
+    balancer: Option<LoadBalancer>,
+    base_url: Option<url::Url>,
+    next_rpc_id: AtomicU64,
+    redactor: UrlRedactor,
+    secrets: SecretScanner,
+    capability_ttl: Duration,
+    capability_cache: Mutex<HashMap<String, (Instant, EndpointCapabilities)>>,
+    response_cache: Option<ResponseCache>,
+    metrics: Option<Arc<MetricsRecorder>>,
+    quota: Option<Arc<TransferQuota>>,
+    connections: Option<Arc<ConnectionLimiter>>,
+    connection_queue_timeout: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    auth: Option<Arc<AuthState>>,
+    lifecycle: Arc<Lifecycle>,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HttpClient {
     pub fn new() -> Self {
-        let client = Client::builder()
-            .user_agent("RustHttpClient/0.1.0")
-            .timeout(std::time::Duration::from_secs(30))
+        let dns = Arc::new(DnsState::default());
+        let client = Self::client_builder(Duration::from_secs(DEFAULT_TIMEOUT_SECS), &dns)
             .build()
             .expect("Failed to create HTTP client");
         
-        Self { client }
+        Self::from_parts(client, dns, Duration::from_secs(DEFAULT_TIMEOUT_SECS))
+    }
+
+    fn client_builder(timeout: Duration, dns: &Arc<DnsState>) -> ClientBuilder {
+        Client::builder()
+            .timeout(timeout)
+            // Under every policy, including `on_redirect` handlers, reqwest
+            // drops Authorization, Cookie and Proxy-Authorization (whether
+            // set per request, by default headers or from URL userinfo) when
+            // a redirect changes host or port, so credentials never follow a
+            // redirect to another origin.
+            .redirect(reqwest::redirect::Policy::limited(DEFAULT_MAX_REDIRECTS))
+            .dns_resolver(Arc::new(CachingResolver::new(Arc::clone(dns))))
+            .tls_info(true)
+    }
+
+    /// `client_builder` with this client's connection options.
+    fn connection_builder(&self) -> ClientBuilder {
+        let mut builder = Self::client_builder(self.timeout, &self.dns);
+        if let Some(enabled) = self.tcp_nodelay {
+            builder = builder.tcp_nodelay(enabled);
+        }
+        if let Some(limiter) = &self.connections {
+            builder = builder.pool_max_idle_per_host(limiter.max_per_host());
+        }
+        if let Some(policy) = self.tls_policy {
+            builder = builder.use_rustls_tls().min_tls_version(policy.min_version().to_reqwest());
+        }
+        if let Some(jar) = &self.cookies {
+            builder = builder.cookie_provider(Arc::clone(jar));
+        }
+        if let Some(handler) = &self.redirect_handler {
+            let handler = Arc::clone(handler);
+            let rewrites = Arc::clone(&self.redirect_rewrites);
+            builder = builder.redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                let followed = attempt.previous().len();
+                if followed > DEFAULT_MAX_REDIRECTS {
+                    return attempt.error(format!("too many redirects (more than {})", DEFAULT_MAX_REDIRECTS));
+                }
+                let Some(from) = attempt.previous().last() else {
+                    return attempt.follow();
+                };
+                let action = handler(&RedirectAttempt {
+                    status: attempt.status().as_u16(),
+                    from,
+                    to: attempt.url(),
+                    // `previous` includes the original URL.
+                    followed: followed - 1,
+                });
+                match action {
+                    RedirectAction::Follow => attempt.follow(),
+                    RedirectAction::Stop => attempt.stop(),
+                    RedirectAction::Fail(reason) => attempt.error(reason),
+                    RedirectAction::Rewrite(target) => {
+                        // reqwest cannot change the target; `dispatch` picks
+                        // the stopped hop up and follows it.
+                        let key = (from.to_string(), attempt.url().to_string());
+                        rewrites.lock().unwrap_or_else(|e| e.into_inner()).insert(key, target);
+                        attempt.stop()
+                    }
+                }
+            }));
+        }
+        builder
+    }
+
+    fn from_parts(client: Client, dns: Arc<DnsState>, timeout: Duration) -> Self {
+        Self {
+            client,
+            timeout,
+            tcp_nodelay: None,
+            cert_expiry_warning: Duration::from_secs(DEFAULT_CERT_EXPIRY_WARNING_DAYS * 24 * 60 * 60),
+            tls_policy: None,
+            local_clients: Mutex::new(HashMap::new()),
+            dns,
+            default_headers: HeaderList::new(),
+            coalesce_gets: false,
+            request_ids: false,
+            inflight: Mutex::new(HashMap::new()),
+            status_mapper: None,
+            redirect_handler: None,
+            redirect_rewrites: Arc::default(),
+            cookies: None,
+            escalated_warnings: HashSet::new(),
+            interceptors: Vec::new(),
+            signer: None,
+            faults: Mutex::new(None),
+            balancer: None,
+            base_url: None,
+            next_rpc_id: AtomicU64::new(1),
+            redactor: UrlRedactor::default(),
+            secrets: SecretScanner::default(),
+            capability_ttl: Duration::from_secs(DEFAULT_CAPABILITY_TTL_SECS),
+            capability_cache: Mutex::new(HashMap::new()),
+            response_cache: None,
+            metrics: None,
+            quota: None,
+            connections: None,
+            connection_queue_timeout: None,
+            clock: Arc::new(SystemClock),
+            auth: None,
+            lifecycle: Arc::default(),
+        }
     }
 
-    pub async fn get(&self, url: &str, config: RequestConfig) -> Result<ApiResponse> {
-        let start_time = Instant::now();
+    pub async fn get(&self, url: impl IntoRequestUrl, config: RequestConfig) -> Result<ApiResponse> {
+        self.lifecycle.check_open()?;
+        let url = &url.into_request_url()?;
+        let Some(cache) = self.response_cache.as_ref().filter(|_| !config.defer_body && !config.discard_body) else {
+            return self.get_uncached(url, config).await;
+        };
         
-        let mut request = self.client.get(url);
+        // Keyed on the absolute URL, so relative and absolute requests for
+        // the same resource share entries and invalidations.
+        let absolute = self.join_base_url(url);
+        let key = request_fingerprint(HttpMethod::Get, &absolute, &config);
+        let generation = cache.generation();
+        let (stale, validators) = match cache.lookup(&key, self.clock.now()) {
+            Lookup::Fresh(mut response) => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_cache_hit("response");
+                }
+                response.from_cache = true;
+                response.cache_status = CacheStatus::Hit;
+                return Ok(response);
+            }
+            Lookup::Stale(response, validators) => (Some(response), validators),
+            Lookup::Missing => (None, Vec::new()),
+        };
         
-        for (key, value) in &config.headers {
-            request = request.header(key, value);
+        let full_url = config.url_with_query(&absolute);
+        // Conditions the caller set are theirs; only ours turn a 304 into
+        // the stored response.
+        let conditional = !validators.is_empty()
+            && !config.headers.keys().any(|name| {
+                name.eq_ignore_ascii_case("if-none-match") || name.eq_ignore_ascii_case("if-modified-since")
+            });
+        let config = if conditional {
+            validators.into_iter().fold(config, |config, (name, value)| config.add_header(name, value))
+        } else {
+            config
+        };
+        let mut response = self.get_uncached(url, config).await?;
+        if let Some(stale) = stale.filter(|_| conditional && response.status == 304) {
+            let mut revalidated = cache.revalidate(&key, stale, &response, self.clock.now(), generation);
+            revalidated.response_time_ms = response.response_time_ms;
+            revalidated.total_time_ms = response.total_time_ms;
+            revalidated.dns_time_ms = response.dns_time_ms;
+            revalidated.attempts = std::mem::take(&mut response.attempts);
+            revalidated.request = response.request.take();
+            revalidated.cache_status = CacheStatus::Revalidated;
+            return Ok(revalidated);
+        }
+        response.cache_status = CacheStatus::Miss;
+        if response.is_success() {
+            cache.insert(key, &full_url, &response, self.clock.now(), generation);
+        }
+        Ok(response)
+    }
+
+    /// GETs every URL with at most `options.concurrency` requests in flight,
+    /// or a self-tuning limit with `BatchOptions::adaptive`. Results come back
+    /// in the order of `urls`, one per URL, with the concurrency timeline.
+    pub async fn get_many<I, S>(&self, urls: I, config: RequestConfig, options: BatchOptions) -> BatchReport
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let urls: Vec<String> = urls.into_iter().map(|url| url.as_ref().to_string()).collect();
+        run_batch(urls, &options, &self.clock, |url| {
+            let config = config.clone();
+            async move { self.get(&url, config).await }
+        })
+        .await
+    }
+
+    async fn get_uncached(&self, url: &str, config: RequestConfig) -> Result<ApiResponse> {
+        if !(self.coalesce_gets || config.coalesce) || config.defer_body || config.discard_body {
+            return self.send(HttpMethod::Get, url, &config, |url| self.client.get(url)).await;
         }
         
-        let response = request
-            .send()
-            .await
-            .with_context(|| format!("Failed to send GET request to {}", url))?;
+        let key = request_fingerprint(HttpMethod::Get, url, &config);
+        let follower = {
+            let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+            match inflight.get(&key) {
+                Some(sender) => Some(sender.subscribe()),
+                None => {
+                    inflight.insert(key.clone(), broadcast::channel(1).0);
+                    None
+                }
+            }
+        };
+        
+        if let Some(mut receiver) = follower {
+            match receiver.recv().await {
+                Ok(Ok(response)) => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.record_cache_hit("coalesce");
+                    }
+                    return Ok(response);
+                }
+                Ok(Err(e)) => return Err(anyhow::anyhow!("{:#}", e)),
+                // The leading request was cancelled; go to the network ourselves.
+                Err(_) => {
+                    return self.send(HttpMethod::Get, url, &config, |url| self.client.get(url)).await;
+                }
+            }
+        }
         
-        self.process_response(response, start_time).await
+        let flight = InFlight {
+            key,
+            inflight: &self.inflight,
+            completed: false,
+        };
+        let result = self.send(HttpMethod::Get, url, &config, |url| self.client.get(url)).await;
+        flight.complete(&result);
+        result
+    }
+
+    pub async fn post(&self, url: impl IntoRequestUrl, data: &str, config: RequestConfig) -> Result<ApiResponse> {
+        self.send_body(HttpMethod::Post, url, Body::json_str(data)?, config).await
+    }
+
+    pub async fn put(&self, url: impl IntoRequestUrl, data: &str, config: RequestConfig) -> Result<ApiResponse> {
+        self.send_body(HttpMethod::Put, url, Body::json_str(data)?, config).await
+    }
+
+    /// POSTs `value` as JSON. Unlike `post`, which parses its string, the
+    /// value is serialized once and sent as is.
+    pub async fn post_value(&self, url: impl IntoRequestUrl, value: &Value, config: RequestConfig) -> Result<ApiResponse> {
+        self.send_value(HttpMethod::Post, url, value, config).await
+    }
+
+    /// `post_value` with `PUT`.
+    pub async fn put_value(&self, url: impl IntoRequestUrl, value: &Value, config: RequestConfig) -> Result<ApiResponse> {
+        self.send_value(HttpMethod::Put, url, value, config).await
+    }
+
+    /// `post_value` with `PATCH`.
+    pub async fn patch_value(&self, url: impl IntoRequestUrl, value: &Value, config: RequestConfig) -> Result<ApiResponse> {
+        self.send_value(HttpMethod::Patch, url, value, config).await
+    }
+
+    async fn send_value(&self, method: HttpMethod, url: impl IntoRequestUrl, value: &Value, config: RequestConfig) -> Result<ApiResponse> {
+        let json = serde_json::to_vec(value).with_context(|| "Failed to serialize JSON body")?;
+        self.send_body(method, url, Body::Bytes(json, "application/json".to_string()), config).await
+    }
+
+    /// POSTs `value` serialized with `codec`, with the matching
+    /// `Content-Type` (`Codec::Auto` sends JSON).
+    pub async fn post_encoded<T>(&self, url: &str, value: &T, codec: Codec, config: RequestConfig) -> Result<ApiResponse>
+    where
+        T: Serialize + ?Sized,
+    {
+        let body = Body::Bytes(codec.encode(value)?, codec.content_type().to_string());
+        self.send_body(HttpMethod::Post, url, body, config).await
+    }
+
+    /// POSTs `value` as a nested form (`user[name]=x&user[tags][]=a`), see
+    /// `utils::to_nested_form` for the encoding rules.
+    pub async fn post_form_nested(&self, url: &str, value: &Value, config: RequestConfig) -> Result<ApiResponse> {
+        if !value.is_object() {
+            anyhow::bail!("Nested form data must be a JSON object");
+        }
+        let encoded = encode_form_pairs(&to_nested_form(value));
+        let body = Body::Bytes(encoded.into_bytes(), FORM_CONTENT_TYPE.to_string());
+        self.send_body(HttpMethod::Post, url, body, config).await
     }
 
-    pub async fn post(&self, url: &str, data: &str, config: RequestConfig) -> Result<ApiResponse> {
-        let start_time = Instant::now();
+    /// Sends `body` with the content type its variant implies. The body is
+    /// encoded once and reused for retries and failover.
+    pub async fn send_body(
+        &self,
+        method: HttpMethod,
+        url: impl IntoRequestUrl,
+        body: Body,
+        config: RequestConfig,
+    ) -> Result<ApiResponse> {
+        let url = &url.into_request_url()?;
+        let (content_type, payload) = body.encode()?;
+        let payload = Bytes::from(payload);
         
-        let json_value: Value = serde_json::from_str(data)
-            .with_context(|| "Invalid JSON data provided")?;
+        self.send(method, url, &config, |url| {
+            self.client
+                .request(method.as_reqwest(), url)
+                .header(reqwest::header::CONTENT_TYPE, content_type.as_str())
+                .body(payload.clone())
+        })
+        .await
+    }
+
+    pub async fn delete(&self, url: impl IntoRequestUrl, config: RequestConfig) -> Result<ApiResponse> {
+        let url = &url.into_request_url()?;
+        self.send(HttpMethod::Delete, url, &config, |url| self.client.delete(url)).await
+    }
+
+    /// Streams an upload of exactly `len` bytes with an explicit
+    /// `Content-Length`, for servers that reject chunked transfer encoding.
+    pub async fn post_sized_stream<S>(
+        &self,
+        url: &str,
+        stream: S,
+        len: u64,
+        content_type: &str,
+        config: RequestConfig,
+    ) -> Result<ApiResponse>
+    where
+        S: TryStream + Send + Sync + 'static,
+        S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+        Bytes: From<S::Ok>,
+    {
+        // A streamed body can only be sent once, so it never fails over.
+        let body = Mutex::new(Some(reqwest::Body::wrap_stream(stream)));
         
-        let mut request = self.client
-            .post(url)
-            .json(&json_value);
+        self.send(HttpMethod::Post, url, &config, |url| {
+            let request = self
+                .client
+                .post(url)
+                .header(reqwest::header::CONTENT_LENGTH, len)
+                .header(reqwest::header::CONTENT_TYPE, content_type);
+            match body.lock().ok().and_then(|mut body| body.take()) {
+                Some(body) => request.body(body),
+                None => request,
+            }
+        })
+        .await
+    }
+
+    /// Sends any method; `body` is JSON like for `post`. With
+    /// `RequestConfig::strict_capabilities` the request fails fast when the
+    /// endpoint's OPTIONS response rules the method out.
+    pub async fn request(
+        &self,
+        method: HttpMethod,
+        url: impl IntoRequestUrl,
+        body: Option<&str>,
+        config: RequestConfig,
+    ) -> Result<ApiResponse> {
+        let url = &url.into_request_url()?;
+        if config.strict_capabilities && method != HttpMethod::Options {
+            let capabilities = self.capabilities(url).await?;
+            if capabilities.is_known() && !capabilities.supports(method) {
+                anyhow::bail!(
+                    "Server does not advertise {} for {}",
+                    method,
+                    self.redactor.redact_for_display(url)
+                );
+            }
+        }
         
-        for (key, value) in &config.headers {
-            request = request.header(key, value);
+        match body {
+            Some(data) => self.send_body(method, url, Body::json_str(data)?, config).await,
+            None => {
+                self.send(method, url, &config, |url| self.client.request(method.as_reqwest(), url))
+                    .await
+            }
         }
+    }
+
+    /// Sends `prepared` again after `modify` has adjusted a copy of its
+    /// config, e.g. to add a header while iterating on a failing call. The
+    /// prepared request itself is left unchanged.
+    pub async fn replay(
+        &self,
+        prepared: &PreparedRequest,
+        modify: impl FnOnce(&mut RequestConfig),
+    ) -> Result<ApiResponse> {
+        let mut config = prepared.config.clone();
+        modify(&mut config);
         
-        let response = request
-            .send()
-            .await
-            .with_context(|| format!("Failed to send POST request to {}", url))?;
+        match &prepared.body {
+            Some(body) => self.send_body(prepared.method, &prepared.url, body.clone(), config).await,
+            None => self.request(prepared.method, &prepared.url, None, config).await,
+        }
+    }
+
+    /// GETs `url` every `interval` until `condition` accepts the response,
+    /// e.g. to wait for a job to finish. Each poll only gets the time left
+    /// before `timeout`. Fails with `PollTimeout` once another poll would go
+    /// past it or one is cut off, or with a `Total` `RequestError::Timeout`
+    /// if even the first poll does not answer in time; other request errors
+    /// end polling right away. Bypasses the response cache and uses the
+    /// client's clock.
+    pub async fn poll_until<F>(
+        &self,
+        url: &str,
+        config: RequestConfig,
+        condition: F,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<ApiResponse>
+    where
+        F: Fn(&ApiResponse) -> bool,
+    {
+        self.lifecycle.check_open()?;
+        let started = self.clock.now();
+        let deadline = started + timeout;
+        let mut attempts = 0;
+        let mut last = None;
+        loop {
+            // Cut off on the client's clock, so a `MockClock` drives it too.
+            let poll = tokio::select! {
+                biased;
+                response = self.get_uncached(url, config.clone()) => Some(response),
+                _ = self.clock.sleep_until(deadline) => None,
+            };
+            attempts += 1;
+            let elapsed_ms = self.elapsed(started).as_millis() as u64;
+            let response = match (poll, last) {
+                (Some(response), _) => response?,
+                (None, Some(last)) => return Err(PollTimeout { attempts, elapsed_ms, last }.into()),
+                (None, None) => {
+                    return Err(RequestError::Timeout {
+                        kind: TimeoutKind::Total,
+                        partial: PartialResponse {
+                            elapsed_ms,
+                            ..PartialResponse::default()
+                        },
+                    }
+                    .into());
+                }
+            };
+            if condition(&response) {
+                return Ok(response);
+            }
+            
+            let elapsed = self.elapsed(started);
+            if elapsed + interval > timeout {
+                return Err(PollTimeout {
+                    attempts,
+                    elapsed_ms: elapsed.as_millis() as u64,
+                    last: Box::new(response),
+                }
+                .into());
+            }
+            last = Some(Box::new(response));
+            self.clock.sleep(interval).await;
+        }
+    }
+
+    /// GETs `url` `options.warmup` times, then `options.runs` times one after
+    /// another, timing each with the client's clock. Bypasses the response
+    /// cache; any failed request fails the measurement, but error statuses
+    /// are timed like any other response.
+    pub async fn measure(&self, url: &str, config: RequestConfig, options: MeasureOptions) -> Result<MeasureReport> {
+        anyhow::ensure!(options.runs > 0, "MeasureOptions::runs must be at least 1");
+        for run in 1..=options.warmup {
+            self.get_uncached(url, config.clone())
+                .await
+                .with_context(|| format!("Warmup request {} failed", run))?;
+        }
         
-        self.process_response(response, start_time).await
+        let mut samples_ms = Vec::with_capacity(options.runs);
+        for run in 1..=options.runs {
+            let started = self.clock.now();
+            self.get_uncached(url, config.clone())
+                .await
+                .with_context(|| format!("Measured request {} failed", run))?;
+            samples_ms.push(self.elapsed(started).as_secs_f64() * 1000.0);
+        }
+        MeasureReport::new(&self.redactor.redact(url), samples_ms, &options)
+    }
+
+    /// GETs the first `rel` link of `response` (HAL or JSON:API, see
+    /// `ApiResponse::links`), resolved against the URL the response came
+    /// from. Templated links are expanded without variables; `follow_with`
+    /// fills them in. A missing relation fails with `MissingLink`.
+    pub async fn follow(&self, response: &ApiResponse, rel: &str, config: RequestConfig) -> Result<ApiResponse> {
+        self.follow_with(response, rel, &HashMap::new(), config).await
     }
 
-    pub async fn put(&self, url: &str, data: &str, config: RequestConfig) -> Result<ApiResponse> {
-        let start_time = Instant::now();
+    /// `follow` with variables for a templated link.
+    pub async fn follow_with(
+        &self,
+        response: &ApiResponse,
+        rel: &str,
+        vars: &HashMap<String, Value>,
+        config: RequestConfig,
+    ) -> Result<ApiResponse> {
+        let links = response.links()?;
+        let Some(link) = links.get(rel).and_then(|links| links.first()) else {
+            return Err(MissingLink {
+                rel: rel.to_string(),
+                available: links.keys().cloned().collect(),
+            }
+            .into());
+        };
         
-        let json_value: Value = serde_json::from_str(data)
-            .with_context(|| "Invalid JSON data provided")?;
+        let href = link.expand(vars)?;
+        let base = response.request.as_ref().and_then(|request| url::Url::parse(&request.final_url).ok());
+        let url = match base {
+            Some(base) => base.join(&href).with_context(|| format!("Invalid '{}' link {}", rel, href))?.to_string(),
+            None => href,
+        };
+        self.get(&url, config).await
+    }
+
+    /// GETs `url`, streaming the body into `sink` while also keeping it for
+    /// the returned response, e.g. to fill a cache file without a second read.
+    /// `max_body_size`, `spill_threshold` and the transfer quota apply as
+    /// they do to `get`; a spilled body ends up in `spilled_body()`.
+    pub async fn get_tee<W>(&self, url: &str, mut sink: W, mut config: RequestConfig) -> Result<ApiResponse>
+    where
+        W: std::io::Write + Send,
+    {
+        config.defer_body = true;
+        let mut response = self.get(url, config).await?;
+        response.read_body_into(&mut sink).await?;
+        Ok(response)
+    }
+
+    /// Downloads `url` to exactly `path`, replacing any existing file. The
+    /// body is streamed to disk rather than buffered. Non-2xx responses are
+    /// returned as errors and nothing is written.
+    pub async fn download_file(
+        &self,
+        url: &str,
+        path: impl AsRef<std::path::Path>,
+        config: RequestConfig,
+    ) -> Result<DownloadReport> {
+        let start_time = self.clock.now();
+        let response = self.download_response(url, config).await?;
+        let path = path.as_ref().to_path_buf();
+        let mut report = DownloadReport::new(path.clone(), FilenameSource::Caller, &response);
+        report.bytes = download::write_file(&path, response).await?;
+        report.elapsed_ms = self.elapsed(start_time).as_millis() as u64;
+        Ok(report)
+    }
+
+    /// Downloads `url` into `dir`, naming the file after the server's
+    /// `Content-Disposition` or the URL's last path segment (sanitized, see
+    /// `download::sanitize_filename`). Unless `overwrite` is set, an existing
+    /// file gets a numbered sibling such as `report (1).pdf` instead.
+    pub async fn download_to_dir(
+        &self,
+        url: &str,
+        dir: impl AsRef<std::path::Path>,
+        overwrite: bool,
+        config: RequestConfig,
+    ) -> Result<DownloadReport> {
+        let start_time = self.clock.now();
+        let response = self.download_response(url, config).await?;
+        let (name, source) = download::filename_for(&response, url);
+        let mut report = DownloadReport::new(dir.as_ref().join(&name), source, &response);
+        (report.path, report.bytes) = download::write_in_dir(dir.as_ref(), &name, response, overwrite).await?;
+        report.elapsed_ms = self.elapsed(start_time).as_millis() as u64;
+        Ok(report)
+    }
+
+    /// Downloads an archive (tar, tar.gz or zip) from `url` and unpacks it
+    /// into `dest_dir`, tar archives as they arrive. Entries that escape
+    /// `dest_dir`, and archives past `options`' size or entry limits, stop
+    /// extraction with an `ExtractError`.
+    #[cfg(feature = "archive")]
+    pub async fn download_and_extract(
+        &self,
+        url: &str,
+        dest_dir: impl AsRef<std::path::Path>,
+        mut config: RequestConfig,
+        options: ExtractOptions,
+    ) -> Result<ExtractReport> {
+        let start_time = self.clock.now();
+        config.defer_body = true;
+        let mut response = self.get(url, config).await?;
+        if !response.is_success() {
+            response.discard_body().await?;
+            anyhow::bail!(
+                "Download of {} failed: {} {}",
+                self.redactor.redact_for_display(url),
+                response.status,
+                response.status_text
+            );
+        }
+        let mut report = extract::extract_response(response, url, dest_dir.as_ref(), &options).await?;
+        report.elapsed_ms = self.elapsed(start_time).as_millis() as u64;
+        Ok(report)
+    }
+
+    async fn download_response(&self, url: &str, mut config: RequestConfig) -> Result<ApiResponse> {
+        config.defer_body = true;
+        let mut response = self.get(url, config).await?;
+        if !response.is_success() {
+            response.discard_body().await?;
+            anyhow::bail!(
+                "Download of {} failed: {} {}",
+                self.redactor.redact_for_display(url),
+                response.status,
+                response.status_text
+            );
+        }
+        Ok(response)
+    }
+
+    /// Discovers the methods an endpoint allows via OPTIONS, reading both
+    /// `Allow` and `Access-Control-Allow-Methods`. Results are cached per URL.
+    /// A 405 or a response without either header yields `Unknown`.
+    pub async fn capabilities(&self, url: &str) -> Result<EndpointCapabilities> {
+        if let Ok(cache) = self.capability_cache.lock()
+            && let Some((fetched_at, capabilities)) = cache.get(url)
+            && self.elapsed(*fetched_at) < self.capability_ttl
+        {
+            if let Some(metrics) = &self.metrics {
+                metrics.record_cache_hit("capabilities");
+            }
+            return Ok(capabilities.clone());
+        }
         
-        let mut request = self.client
-            .put(url)
-            .json(&json_value);
+        let response = self
+            .send(HttpMethod::Options, url, &RequestConfig::new(), |url| {
+                self.client.request(reqwest::Method::OPTIONS, url)
+            })
+            .await?;
         
-        for (key, value) in &config.headers {
-            request = request.header(key, value);
+        let mut methods = HashSet::new();
+        let mut advertised = false;
+        if response.is_success() {
+            for header in ["allow", "access-control-allow-methods"] {
+                if let Some(value) = response.headers.get(header) {
+                    advertised = true;
+                    methods.extend(value.split(',').filter_map(|m| m.trim().parse::<HttpMethod>().ok()));
+                }
+            }
         }
         
-        let response = request
-            .send()
-            .await
-            .with_context(|| format!("Failed to send PUT request to {}", url))?;
+        let capabilities = if advertised {
+            EndpointCapabilities::Known(methods)
+        } else {
+            EndpointCapabilities::Unknown
+        };
         
-        self.process_response(response, start_time).await
+        if let Ok(mut cache) = self.capability_cache.lock() {
+            cache.insert(url.to_string(), (self.clock.now(), capabilities.clone()));
+        }
+        
+        Ok(capabilities)
+    }
+
+    pub fn with_capability_ttl(mut self, ttl: Duration) -> Self {
+        self.capability_ttl = ttl;
+        self
+    }
+
+    pub fn clear_capability_cache(&self) {
+        if let Ok(mut cache) = self.capability_cache.lock() {
+            cache.clear();
+        }
+    }
+
+    /// Calls a JSON-RPC 2.0 method using the client's auto-incrementing id.
+    pub async fn json_rpc<T>(
+        &self,
+        url: &str,
+        method: &str,
+        params: Value,
+        config: RequestConfig,
+    ) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let id = self.next_rpc_id.fetch_add(1, Ordering::Relaxed);
+        self.json_rpc_with_id(url, method, params, Value::from(id), config).await
     }
 
-    pub async fn delete(&self, url: &str, config: RequestConfig) -> Result<ApiResponse> {
-        let start_time = Instant::now();
+    /// Calls a JSON-RPC 2.0 method and returns its `result`; an `error` member
+    /// becomes a `JsonRpcError`.
+    pub async fn json_rpc_with_id<T>(
+        &self,
+        url: &str,
+        method: &str,
+        params: Value,
+        id: Value,
+        config: RequestConfig,
+    ) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let envelope = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        
+        let response = self
+            .send(HttpMethod::Post, url, &config, |url| self.client.post(url).json(&envelope))
+            .await?;
         
-        let mut request = self.client.delete(url);
+        let mut reply: Value = response
+            .as_json_value()
+            .with_context(|| format!("Invalid JSON-RPC response from {}", self.redactor.redact_for_display(url)))?;
         
-        for (key, value) in &config.headers {
-            request = request.header(key, value);
+        if let Some(error) = reply.get_mut("error").map(Value::take)
+            && !error.is_null()
+        {
+            let error: JsonRpcError = serde_json::from_value(error)
+                .with_context(|| "Malformed JSON-RPC error object")?;
+            return Err(error.into());
         }
         
-        let response = request
-            .send()
-            .await
-            .with_context(|| format!("Failed to send DELETE request to {}", url))?;
+        let result = reply
+            .get_mut("result")
+            .map(Value::take)
+            .with_context(|| "JSON-RPC response has neither result nor error")?;
+        
+        serde_json::from_value(result).with_context(|| "Failed to deserialize JSON-RPC result")
+    }
+
+    pub async fn get_json_array_stream<T>(
+        &self,
+        url: &str,
+        config: RequestConfig,
+    ) -> Result<impl Stream<Item = Result<T>>>
+    where
+        T: DeserializeOwned,
+    {
+        let mut guard = self.lifecycle.enter()?;
+        let start_time = self.clock.now();
+        let url = &self.resolve_url(&config.url_with_query(url));
+        let mut config = self.with_request_id_header(&config).into_owned();
+        // Items are parsed straight off the wire, so ask for an unencoded body.
+        if ACCEPT_ENCODING.is_some() && !config.headers.contains_key("accept-encoding") {
+            config = config.add_header("Accept-Encoding", "identity");
+        }
+        let request = self.client.get(url);
+        let (response, permit) = tokio::select! {
+            sent = self.dispatch(HttpMethod::Get, url, request, &config, start_time) => sent?,
+            _ = guard.cancelled() => return Err(ClientClosed { cancelled: true }.into()),
+        };
+        
+        if !response.status().is_success() {
+            anyhow::bail!("GET {} returned {}", self.redactor.redact_for_display(url), response.status());
+        }
+        
+        // The connection slot stays taken, and shutdown waits, until the
+        // stream ends or is dropped.
+        let items = json_array_stream(response, start_time, Arc::clone(&self.clock), config.idle_timeout).inspect(move |_| {
+            let _ = &permit;
+        });
+        Ok(track_stream(items, guard))
+    }
+
+    /// Streams items from an API paged with `?offset=&limit=`. `extract_items`
+    /// picks the items out of each page's JSON body; paging stops at the first
+    /// short page.
+    pub fn paginate_offset<'a, F>(
+        &'a self,
+        url: &str,
+        limit: usize,
+        extract_items: F,
+        config: RequestConfig,
+    ) -> impl Stream<Item = Result<Value>> + 'a
+    where
+        F: Fn(&Value) -> Vec<Value> + 'a,
+    {
+        paginate(self, url, PageCursor::Offset { offset: 0, limit: limit.max(1) }, extract_items, config)
+    }
+
+    /// Streams items from a cursor-paged API: each page's `next_field` (a
+    /// dotted path such as `meta.next_cursor`) is sent back as the
+    /// `cursor_field` query parameter until it is missing or null.
+    pub fn paginate_cursor<'a, F>(
+        &'a self,
+        url: &str,
+        cursor_field: &str,
+        next_field: &str,
+        extract_items: F,
+        config: RequestConfig,
+    ) -> impl Stream<Item = Result<Value>> + 'a
+    where
+        F: Fn(&Value) -> Vec<Value> + 'a,
+    {
+        let cursor = PageCursor::Cursor {
+            param: cursor_field.to_string(),
+            next_field: next_field.to_string(),
+            cursor: None,
+        };
+        paginate(self, url, cursor, extract_items, config)
+    }
+
+    /// Defaults first, then the config's headers, each in the order set.
+    fn merged_headers(&self, config: &RequestConfig) -> HeaderList {
+        self.default_headers
+            .iter()
+            .filter(|(key, _)| !config.headers.contains_key(key))
+            .chain(&config.headers)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// The headers `dispatch` will send, with the auth token standing in for
+    /// any `Authorization` header, and the warnings they raise.
+    fn request_warnings(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        request: &RequestBuilder,
+        config: &RequestConfig,
+    ) -> (HeaderList, Vec<RequestWarning>) {
+        let mut headers = self.merged_headers(config);
+        if let Some(token) = self.auth.as_ref().and_then(|auth| auth.token_for(url)) {
+            headers.insert("Authorization", format!("Bearer {}", token));
+        }
+        // Streaming bodies cannot be cloned, so there is a body.
+        let has_body = request
+            .try_clone()
+            .is_none_or(|request| request.build().is_ok_and(|request| request.body().is_some()));
+        let warnings = check_request(method, url, &headers, has_body, config.verify_ssl);
+        (headers, warnings)
+    }
+
+    /// Fails when any of `warnings` has a code passed to `escalate_warnings`.
+    fn escalate(&self, warnings: &[RequestWarning]) -> Result<()> {
+        let escalated: Vec<RequestWarning> = warnings
+            .iter()
+            .filter(|warning| self.escalated_warnings.contains(&warning.code))
+            .cloned()
+            .collect();
+        if escalated.is_empty() {
+            return Ok(());
+        }
+        Err(WarningEscalated {
+            escalated,
+            warnings: warnings.to_vec(),
+        }
+        .into())
+    }
+
+    async fn dispatch(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        mut request: RequestBuilder,
+        config: &RequestConfig,
+        start_time: Instant,
+    ) -> Result<(Response, Option<ConnectionPermit>)> {
+        let user_agent = self.user_agent_for(config);
+        let mut headers = self.merged_headers(config);
+        if user_agent.is_some() {
+            headers.remove("user-agent");
+        }
+        request = request.headers(headers.to_header_map()?);
+        match &user_agent {
+            Some(user_agent) => request = request.header(reqwest::header::USER_AGENT, user_agent),
+            // Set per request rather than on the reqwest client, which
+            // would add it back after `without_header` removed it.
+            None if !headers.contains_key("user-agent") => {
+                request = request.header(reqwest::header::USER_AGENT, DEFAULT_USER_AGENT);
+            }
+            None => {}
+        }
+        if let Some(encoding) = ACCEPT_ENCODING
+            && !headers.contains_key("accept-encoding")
+        {
+            request = request.header(reqwest::header::ACCEPT_ENCODING, encoding);
+        }
+        if let Some(etag) = &config.if_match {
+            request = request.header(reqwest::header::IF_MATCH, etag);
+        }
+        if let Some(etag) = &config.if_none_match {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        
+        if let Some(timeout) = config.timeout {
+            request = request.timeout(timeout);
+        }
+        
+        let send_error = |e: reqwest::Error| {
+            anyhow::Error::new(e.without_url())
+                .context(format!("Failed to send {} request to {}", method, self.redactor.redact_for_display(url)))
+        };
+        let (client, request) = request.build_split();
+        let mut request = request.map_err(send_error)?;
+        // Decided on the final URL, so base URLs and balancing are covered.
+        if let Some(token) = self.auth.as_ref().and_then(|auth| auth.token_for(request.url().as_str())) {
+            let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+                .context("Recovered token is not a valid header value")?;
+            value.set_sensitive(true);
+            request.headers_mut().insert(reqwest::header::AUTHORIZATION, value);
+        }
+        for name in &config.removed_headers {
+            request.headers_mut().remove(name.as_str());
+        }
+        let upload = request.body().and_then(reqwest::Body::as_bytes).map_or(0, <[u8]>::len) as u64;
+        if let Some(quota) = &self.quota {
+            quota.reserve(upload)?;
+        }
+        if let Some(metrics) = &self.metrics
+            && upload > 0
+        {
+            metrics.record_bytes_sent(request.url().as_str(), upload);
+        }
+        let permit = match (&self.connections, request.url().host_str()) {
+            (Some(limiter), Some(host)) => Some(
+                limiter
+                    .acquire(host, self.connection_queue_timeout, self.metrics.as_ref())
+                    .await?,
+            ),
+            _ => None,
+        };
+        let client = match config.local_address {
+            Some(ip) => self.local_client(ip)?,
+            None => client,
+        };
+        if let Some(signer) = &self.signer {
+            sign_request(signer.as_ref(), &mut request)
+                .await
+                .with_context(|| format!("Failed to sign {} request to {}", method, self.redactor.redact_for_display(url)))?;
+        }
+        let target = request.url().clone();
+        let mut followed = self.redirect_handler.as_ref().and_then(|_| request.try_clone());
+        let mut sent = FIRST_PARTY.scope(target.clone(), client.execute(request)).await;
+        let mut rewrites = 0;
+        while let Some(previous) = &followed
+            && let Ok(response) = &sent
+            && let Some(mut next) = self.rewritten_request(previous, response)
+        {
+            rewrites += 1;
+            if rewrites > DEFAULT_MAX_REDIRECTS {
+                anyhow::bail!("Too many redirect rewrites (more than {})", DEFAULT_MAX_REDIRECTS);
+            }
+            if let Some(signer) = &self.signer {
+                sign_request(signer.as_ref(), &mut next)
+                    .await
+                    .with_context(|| format!("Failed to sign rewritten redirect to {}", self.redactor.redact_for_display(next.url().as_str())))?;
+            }
+            followed = next.try_clone();
+            sent = FIRST_PARTY.scope(target.clone(), client.execute(next)).await;
+        }
+        
+        match sent {
+            Ok(response) => Ok((response, permit)),
+            Err(e) if e.is_timeout() => Err(RequestError::Timeout {
+                kind: TimeoutKind::Total,
+                partial: PartialResponse {
+                    elapsed_ms: self.elapsed(start_time).as_millis() as u64,
+                    ..PartialResponse::default()
+                },
+            }
+            .into()),
+            Err(e) => match self.tls_policy_violation(&e, &target).await {
+                Some(violation) => Err(violation.into()),
+                None => Err(send_error(e)),
+            },
+        }
+    }
+
+    /// The request for a hop `on_redirect` rewrote, built from the one that
+    /// got the redirect. Like reqwest's own follows, 301/302 turn POST into
+    /// GET and 303 turns anything but HEAD into GET, dropping the body, and
+    /// credentials are dropped when the host or port changes.
+    fn rewritten_request(&self, previous: &reqwest::Request, response: &Response) -> Option<reqwest::Request> {
+        if !response.status().is_redirection() {
+            return None;
+        }
+        let location = response.headers().get(reqwest::header::LOCATION)?.to_str().ok()?;
+        let to = response.url().join(location).ok()?;
+        let key = (response.url().to_string(), to.to_string());
+        let target = self.redirect_rewrites.lock().unwrap_or_else(|e| e.into_inner()).remove(&key)?;
+
+        let mut next = previous.try_clone()?;
+        let to_get = match response.status().as_u16() {
+            301 | 302 => next.method() == reqwest::Method::POST,
+            303 => next.method() != reqwest::Method::HEAD,
+            _ => false,
+        };
+        if to_get {
+            *next.method_mut() = reqwest::Method::GET;
+            *next.body_mut() = None;
+            for name in [reqwest::header::CONTENT_TYPE, reqwest::header::CONTENT_LENGTH, reqwest::header::CONTENT_ENCODING] {
+                next.headers_mut().remove(name);
+            }
+        }
+        let same_origin = |url: &url::Url| {
+            url.host_str() == target.host_str() && url.port_or_known_default() == target.port_or_known_default()
+        };
+        if !same_origin(previous.url()) || !same_origin(response.url()) {
+            for name in [reqwest::header::AUTHORIZATION, reqwest::header::COOKIE, reqwest::header::PROXY_AUTHORIZATION] {
+                next.headers_mut().remove(name);
+            }
+        }
+        *next.url_mut() = target;
+        Some(next)
+    }
+
+    /// Adds the `X-Request-Id` header the config asks for, generating the ID
+    /// once per logical request so retries share it.
+    fn with_request_id_header<'c>(&self, config: &'c RequestConfig) -> Cow<'c, RequestConfig> {
+        let already_set = config.headers.contains_key(REQUEST_ID_HEADER);
+        let id = match &config.request_id {
+            _ if already_set => None,
+            Some(id) => Some(id.clone()),
+            None if config.generate_request_id || self.request_ids => Some(generate_request_id()),
+            None => None,
+        };
+        match id {
+            Some(id) => Cow::Owned(config.clone().add_header(REQUEST_ID_HEADER, id)),
+            None => Cow::Borrowed(config),
+        }
+    }
+
+    /// The base agent (the config's, the client default or
+    /// `DEFAULT_USER_AGENT`) followed by the config's product tokens; `None`
+    /// when there are no tokens to append.
+    fn user_agent_for(&self, config: &RequestConfig) -> Option<String> {
+        if config.user_agent_tokens.is_empty() {
+            return None;
+        }
+        let base = config
+            .headers
+            .get("user-agent")
+            .or_else(|| self.default_headers.get("user-agent"))
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+        Some(format!("{} {}", base, config.user_agent_tokens.join(" ")))
+    }
+
+    /// A client with the same settings as the main one whose connections
+    /// are bound to `ip`, built on first use.
+    fn local_client(&self, ip: IpAddr) -> Result<Client> {
+        let mut clients = self.local_clients.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(client) = clients.get(&ip) {
+            return Ok(client.clone());
+        }
+        
+        // Binding up front turns "address not on this host" into a clear
+        // error instead of an opaque connect failure.
+        std::net::UdpSocket::bind(SocketAddr::new(ip, 0))
+            .with_context(|| format!("Cannot send from local address {}: it is not assigned to this host", ip))?;
+        let client = self
+            .connection_builder()
+            .local_address(ip)
+            .build()
+            .with_context(|| format!("Failed to create HTTP client bound to {}", ip))?;
+        clients.insert(ip, client.clone());
+        Ok(client)
+    }
+
+    /// Sends a request built by `build`. Relative URLs are resolved against
+    /// the load-balanced base URLs; a target that fails with a connection error
+    /// or 5xx is ejected and the request moves on to the next one, provided
+    /// its body can be replayed.
+    async fn send<F>(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        config: &RequestConfig,
+        build: F,
+    ) -> Result<ApiResponse>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        let mut guard = self.lifecycle.enter()?;
+        if let Some(auth) = &self.auth
+            && auth.wants_pre_refresh()
+        {
+            auth.start_pre_refresh(&self.client, Arc::clone(&self.clock), self.lifecycle.closing());
+        }
+        let url = &config.url_with_query(url);
+        let config = &self.with_request_id_header(config);
+        let result = tokio::select! {
+            result = self.send_routed(method, url, config, build) => result,
+            _ = guard.cancelled() => Err(ClientClosed { cancelled: true }.into()),
+        };
+        // Shutdown keeps waiting for a deferred body until it is read.
+        if let Ok(ApiResponse { pending_body: Some(pending), .. }) = &result {
+            pending.track(guard);
+        }
+        
+        if let (Some(cache), Ok(response)) = (&self.response_cache, &result)
+            && method.is_write()
+            && response.is_success()
+        {
+            cache.invalidate(&self.join_base_url(url));
+        }
+        result
+    }
+
+    async fn send_routed<F>(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        config: &RequestConfig,
+        build: F,
+    ) -> Result<ApiResponse>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        let candidates = match &self.balancer {
+            Some(balancer) if is_relative_url(url) => balancer
+                .plan(url)
+                .into_iter()
+                .map(|(index, full_url)| (Some(index), full_url))
+                .collect(),
+            _ => vec![(None, self.join_base_url(url))],
+        };
+        let last = candidates.len().saturating_sub(1);
         
-        self.process_response(response, start_time).await
+        for (position, (target, full_url)) in candidates.into_iter().enumerate() {
+            let request = build(&full_url);
+            let replayable = request.try_clone().is_some();
+            let started = self.clock.now();
+            let result = self.send_with_retries(method, &full_url, request, config).await;
+            
+            let (Some(index), Some(balancer)) = (target, &self.balancer) else {
+                return result;
+            };
+            
+            let failed = match &result {
+                Ok(response) => response.status >= 500,
+                Err(e) => matches!(
+                    AttemptOutcome::from_error(e),
+                    AttemptOutcome::Connect | AttemptOutcome::Timeout | AttemptOutcome::Dns
+                ),
+            };
+            balancer.record(index, !failed, self.elapsed(started));
+            
+            if !failed || !replayable || position == last {
+                return result.map(|mut response| {
+                    response.target = balancer.target_url(index);
+                    response
+                });
+            }
+        }
+        
+        anyhow::bail!("No base URL available for {}", self.redactor.redact_for_display(url))
+    }
+
+    async fn send_with_retries(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        request: RequestBuilder,
+        config: &RequestConfig,
+    ) -> Result<ApiResponse> {
+        let start_time = self.clock.now();
+        let started_at = self.clock.system_now();
+        let (headers, mut warnings) = self.request_warnings(method, url, &request, config);
+        self.escalate(&warnings)?;
+        let auth_retry = self.auth.as_ref().and_then(|_| request.try_clone());
+        let generation = self.auth.as_ref().map_or(0, |auth| auth.generation());
+        
+        let (mut result, mut attempts) = self.send_attempts(method, url, request, config).await;
+        
+        if let (Some(auth), Some(retry_request)) = (&self.auth, auth_retry)
+            && result.as_ref().is_ok_and(|response| auth.should_recover(response.status))
+        {
+            let rejected_by = result.as_ref().ok().and_then(|response| response.request.as_ref()).map(|info| info.final_url.as_str());
+            auth.recover(&self.client, self.clock.as_ref(), generation, Some(rejected_by.unwrap_or(url)))
+                .await
+                .context("Failed to refresh credentials after 401")?;
+            let (retry_result, retry_attempts) = self.send_attempts(method, url, retry_request, config).await;
+            attempts.extend(retry_attempts);
+            result = match (result, retry_result) {
+                (Ok(first), Ok(retry)) if auth.should_recover(retry.status) => {
+                    Err(AuthRecoveryError {
+                        first: Box::new(first),
+                        retry: Box::new(retry),
+                    }
+                    .into())
+                }
+                (_, retry_result) => retry_result,
+            };
+        }
+        
+        if let Some(metrics) = &self.metrics {
+            let retries = attempts.len().saturating_sub(1) as u64;
+            let url = self.redactor.redact(url);
+            metrics.record_request(method, &url, result.as_ref().ok(), self.elapsed(start_time), retries);
+        }
+        
+        let mut response = match result {
+            Ok(response) => response,
+            Err(mut err) => {
+                if let Some(error) = err.downcast_mut::<RequestError>() {
+                    error.partial_mut().warnings = warnings;
+                }
+                return Err(err);
+            }
+        };
+        warnings.extend(check_response(&headers, &response));
+        self.escalate(&warnings)?;
+        response.warnings = warnings;
+        response.total_time_ms = self.elapsed(start_time).as_millis() as u64;
+        if let Some(request) = &mut response.request {
+            request.started_at = started_at;
+        }
+        if config.retry.is_some() {
+            response.attempts = attempts;
+        }
+        
+        if response.status == 412 && config.is_conditional() {
+            if response.has_pending_body() {
+                response.discard_body().await?;
+            }
+            return Err(RequestError::PreconditionFailed {
+                current_etag: response.headers.get("etag").cloned(),
+                partial: PartialResponse {
+                    status: Some(response.status),
+                    headers: response.headers.clone(),
+                    bytes_received: response.spilled_body().map_or(response.raw_body.len(), |spilled| spilled.len() as usize),
+                    elapsed_ms: response.total_time_ms,
+                    partial_body: (!response.body.is_empty()).then(|| response.body.clone()),
+                    warnings: response.warnings.clone(),
+                },
+            }
+            .into());
+        }
+        
+        if config.debug && !response.is_success() && response.content_type_missing {
+            // Without a Content-Type only the body can tell what it is.
+            response.read_body().await?;
+        }
+        if config.debug && !response.is_success() && (response.is_json() || response.is_html()) {
+            response.read_body().await?;
+            let body = if response.is_html() {
+                pretty_print_html(&response.body)
+            } else {
+                pretty_print_json(&response.body).unwrap_or_else(|_| response.body.clone())
+            };
+            let body = self.secrets.redact(&body);
+            anyhow::bail!(
+                "{} {} returned {} {}\n{}",
+                method,
+                self.redactor.redact_for_display(url),
+                response.status,
+                response.status_text,
+                body
+            );
+        }
+        
+        Ok(response)
+    }
+
+    /// Runs the attempts of one request, retrying per `config.retry`.
+    async fn send_attempts(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        request: RequestBuilder,
+        config: &RequestConfig,
+    ) -> (Result<ApiResponse>, Vec<AttemptRecord>) {
+        let max_attempts = config.retry.as_ref().map_or(1, |retry| retry.max_retries + 1);
+        let mut attempts = Vec::new();
+        let mut pending = Some(request);
+        let mut backoff = Duration::ZERO;
+        let mut attempt = 1;
+        let first_start = self.clock.now();
+        
+        let result = loop {
+            // Keep the original builder around while a retry is still possible;
+            // bodies that cannot be cloned get a single attempt.
+            let current = match pending.as_ref().and_then(|builder| {
+                (attempt < max_attempts).then(|| builder.try_clone()).flatten()
+            }) {
+                Some(clone) => clone,
+                None => pending.take().expect("request builder consumed before final attempt"),
+            };
+            
+            let attempt_start = self.clock.now();
+            let timestamp = self.clock.system_now();
+            let outcome = match self.intercept(method, url, config, attempt).await {
+                Some(response) => Ok(response),
+                None => match self.dispatch(method, url, current, config, attempt_start).await {
+                    Ok((response, permit)) => self.process_response(method, url, response, permit, attempt_start, config).await,
+                    Err(e) => Err(e),
+                },
+            };
+            
+            let record = AttemptRecord {
+                outcome: match &outcome {
+                    Ok(response) => AttemptOutcome::Status(response.status),
+                    Err(e) => AttemptOutcome::from_error(e),
+                },
+                duration_ms: self.elapsed(attempt_start).as_millis() as u64,
+                backoff_ms: backoff.as_millis() as u64,
+                timestamp,
+            };
+            let retryable = config.retry.as_ref().is_some_and(|retry| match &outcome {
+                Ok(response) => retry.should_retry_response(response),
+                Err(_) => retry.should_retry(&record.outcome),
+            });
+            attempts.push(record);
+            
+            if !retryable {
+                break outcome;
+            }
+            if pending.is_none() {
+                break self.retries_exhausted(outcome, &attempts, first_start);
+            }
+            
+            if let Some(retry) = &config.retry {
+                backoff = retry.delay_for(attempt);
+                if let Some(budget) = retry.max_elapsed
+                    && self.elapsed(first_start) + backoff > budget
+                {
+                    break self.retries_exhausted(outcome, &attempts, first_start);
+                }
+            }
+            self.clock.sleep(backoff).await;
+            attempt += 1;
+        };
+        
+        (result, attempts)
+    }
+
+    /// Attaches `RetriesExhausted` to the error of a final retryable attempt
+    /// that followed at least one retry. Responses are returned as they are.
+    fn retries_exhausted(
+        &self,
+        outcome: Result<ApiResponse>,
+        attempts: &[AttemptRecord],
+        first_start: Instant,
+    ) -> Result<ApiResponse> {
+        if attempts.len() < 2 {
+            return outcome;
+        }
+        outcome.map_err(|e| {
+            let exhausted = RetriesExhausted {
+                attempts: attempts.len() as u32,
+                elapsed_ms: self.elapsed(first_start).as_millis() as u64,
+                last_status: attempts.iter().rev().find_map(|attempt| match attempt.outcome {
+                    AttemptOutcome::Status(status) => Some(status),
+                    _ => None,
+                }),
+                last_error: e.to_string(),
+            };
+            e.context(exhausted)
+        })
+    }
+
+    /// Applies fault injection, then the interceptors; returns the response
+    /// that replaces this attempt, if any.
+    async fn intercept(&self, method: HttpMethod, url: &str, config: &RequestConfig, attempt: u32) -> Option<ApiResponse> {
+        let faults = self.faults.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Some(latency) = faults.as_ref().and_then(|faults| faults.config.latency) {
+            self.clock.sleep(latency).await;
+        }
+        let injected = faults
+            .filter(|faults| faults.should_fail())
+            .map(|faults| ApiResponse::synthetic(faults.config.error_status, "").with_header(FAULT_INJECTED_HEADER, "true"));
+        
+        let request = InterceptedRequest {
+            method,
+            url,
+            headers: &config.headers,
+            attempt,
+        };
+        let mut response = injected.or_else(|| self.interceptors.iter().find_map(|interceptor| interceptor(&request)))?;
+        response.request = Some(RequestInfo {
+            method,
+            url: self.redactor.redact(url),
+            final_url: self.redactor.redact(url),
+            started_at: self.clock.system_now(),
+            local_address: None,
+            request_id: config.headers.get(REQUEST_ID_HEADER).cloned(),
+            resolved_host: None,
+        });
+        Some(response)
     }
 
-    async fn process_response(&self, response: Response, start_time: Instant) -> Result<ApiResponse> {
-        let status = response.status().as_u16();
-        let status_text = response.status().canonical_reason().unwrap_or("Unknown").to_string();
+    async fn process_response(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        response: Response,
+        permit: Option<ConnectionPermit>,
+        start_time: Instant,
+        config: &RequestConfig,
+    ) -> Result<ApiResponse> {
+        let original_status = response.status().as_u16();
+        let status = match &self.status_mapper {
+            Some(mapper) => mapper(original_status),
+            None => original_status,
+        };
+        let status_text = reqwest::StatusCode::from_u16(status)
+            .ok()
+            .and_then(|code| code.canonical_reason())
+            .unwrap_or("Unknown")
+            .to_string();
         
-        let mut headers = HashMap::new();
+        let mut headers: HashMap<String, String> = HashMap::new();
         for (key, value) in response.headers() {
             if let Ok(value_str) = value.to_str() {
-                headers.insert(key.to_string(), value_str.to_string());
+                // Cookies cannot be comma-joined (`Expires` has a comma), so
+                // each keeps its own line.
+                if key == reqwest::header::SET_COOKIE
+                    && let Some(cookies) = headers.get_mut(key.as_str())
+                {
+                    cookies.push('\n');
+                    cookies.push_str(value_str);
+                } else {
+                    headers.insert(key.to_string(), value_str.to_string());
+                }
             }
         }
         
@@ -114,39 +1584,1595 @@ impl HttpClient {
             .headers()
             .get("content-type")
             .and_then(|ct| ct.to_str().ok())
-            .unwrap_or("text/plain")
-            .to_string();
-        
-        let body = response
-            .text()
-            .await
-            .with_context(|| "Failed to read response body")?;
+            .map(str::to_string);
+        let content_type_missing = content_type.is_none();
         
-        let response_time_ms = start_time.elapsed().as_millis() as u64;
+        let tls_info = response.extensions().get::<reqwest::tls::TlsInfo>().map(|info| {
+            let alpn = (response.version() == reqwest::Version::HTTP_2).then(|| "h2".to_string());
+            self.tls_info(info.peer_certificate().map(<[u8]>::to_vec).into_iter().collect(), response.url().host_str(), alpn)
+        });
         
-        Ok(ApiResponse {
+        let mut api_response = ApiResponse {
             status,
+            original_status,
             status_text,
             headers,
-            body,
-            content_type,
-            response_time_ms,
-        })
-    }
-
-    pub fn validate_url(url: &str) -> Result<()> {
-        url::Url::parse(url)
-            .with_context(|| format!("Invalid URL format: {}", url))?;
-        Ok(())
+            body: String::new(),
+            content_type: content_type.unwrap_or_default(),
+            content_type_missing,
+            response_time_ms: 0,
+            total_time_ms: 0,
+            dns_time_ms: None,
+            attempts: Vec::new(),
+            target: None,
+            tls_info,
+            request: Some(RequestInfo {
+                method,
+                url: self.redactor.redact(url),
+                final_url: self.redactor.redact(response.url().as_str()),
+                started_at: self.clock.system_now(),
+                local_address: config.local_address,
+                request_id: config
+                    .headers
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(REQUEST_ID_HEADER))
+                    .map(|(_, id)| id.clone()),
+                resolved_host: response
+                    .url()
+                    .host_str()
+                    .and_then(|host| self.dns.served_by(host, response.remote_addr())),
+            }),
+            length_mismatch: None,
+            wire_bytes: 0,
+            decoded_bytes: 0,
+            raw_body: Vec::new(),
+            cache_status: CacheStatus::Bypass,
+            pending_body: None,
+            spilled: None,
+            from_cache: false,
+            warnings: Vec::new(),
+        };
+        
+        let expected_length = expected_body_length(method == HttpMethod::Head, original_status, &api_response.headers);
+        if let (Some(quota), Some(length)) = (&self.quota, expected_length) {
+            // Refuse before downloading a body that cannot fit; deferred
+            // bodies are only counted here.
+            quota.reserve(length)?;
+        }
+        if config.discard_body {
+            let received = self.drain_body(response, expected_length, config.idle_timeout).await?;
+            api_response.wire_bytes = received;
+            api_response.length_mismatch = expected_length
+                .filter(|&expected| expected != received)
+                .map(|expected| (expected, received));
+        } else if config.defer_body {
+            api_response.pending_body = Some(PendingBody::new(
+                response,
+                expected_length,
+                config.strict_length,
+                config.idle_timeout,
+                BodyLimits::new(config, self.quota.clone()),
+                permit,
+            ));
+        } else {
+            let body = self
+                .read_body(response, config, start_time, status, &api_response.headers, expected_length)
+                .await?;
+            let received = body.len();
+            api_response.length_mismatch = expected_length
+                .filter(|&expected| expected != received)
+                .map(|expected| (expected, received));
+            api_response.wire_bytes = received;
+            match body {
+                ReadBody::Memory(body) => {
+                    let body = decode_content_encoding(&mut api_response.headers, body)?;
+                    api_response.decoded_bytes = body.len() as u64;
+                    api_response.body = decode_body(&body, &api_response.content_type);
+                    api_response.raw_body = body;
+                }
+                ReadBody::Spilled(spilled) => {
+                    api_response.decoded_bytes = spilled.len();
+                    api_response.spilled = Some(spilled);
+                }
+            }
+        }
+        
+        api_response.response_time_ms = self.elapsed(start_time).as_millis() as u64;
+        api_response.total_time_ms = api_response.response_time_ms;
+        api_response.dns_time_ms = self.dns_time(url, api_response.request.as_ref(), start_time);
+        
+        Ok(api_response)
     }
 
-    pub fn with_timeout(timeout_secs: u64) -> Result<Self> {
-        let client = Client::builder()
-            .user_agent("RustHttpClient/0.1.0")
-            .timeout(std::time::Duration::from_secs(timeout_secs))
-            .build()
-            .with_context(|| "Failed to create HTTP client with custom timeout")?;
+    async fn read_body(
+        &self,
+        mut response: Response,
+        config: &RequestConfig,
+        start_time: Instant,
+        status: u16,
+        headers: &HashMap<String, String>,
+        expected_length: Option<u64>,
+    ) -> Result<ReadBody> {
+        // Once spilled, `body` only keeps the head for `PartialResponse`.
+        let mut body = Vec::new();
+        let mut spill: Option<SpillWriter> = None;
+        let mut received = 0;
+        let spill_threshold = config.spill_threshold.filter(|_| !is_client_decoded(headers));
+        
+        let partial = |body: &[u8], received: usize| PartialResponse {
+            status: Some(status),
+            headers: headers.clone(),
+            bytes_received: received,
+            elapsed_ms: self.elapsed(start_time).as_millis() as u64,
+            partial_body: config.partial_body_limit.map(|limit| {
+                String::from_utf8_lossy(&body[..body.len().min(limit)]).into_owned()
+            }),
+            warnings: Vec::new(),
+        };
         
-        Ok(Self { client })
+        loop {
+            let Some(next) = next_chunk(&mut response, config.idle_timeout).await else {
+                return Err(RequestError::Timeout {
+                    kind: TimeoutKind::Read,
+                    partial: partial(&body, received),
+                }
+                .into());
+            };
+            match next {
+                Ok(Some(chunk)) => {
+                    received += chunk.len();
+                    match spill.as_mut() {
+                        Some(writer) => writer.write(&chunk).await?,
+                        None => body.extend_from_slice(&chunk),
+                    }
+                    if let Some(threshold) = spill_threshold
+                        && spill.is_none()
+                        && body.len() > threshold
+                    {
+                        let mut writer = SpillWriter::create(config.spill_dir.as_deref()).await?;
+                        writer.write(&body).await?;
+                        body.truncate(config.partial_body_limit.unwrap_or(0));
+                        body.shrink_to_fit();
+                        spill = Some(writer);
+                    }
+                    // Bodies without a Content-Length are counted as they arrive.
+                    if let Some(quota) = self.quota.as_ref().filter(|_| expected_length.is_none()) {
+                        quota.record(chunk.len() as u64)?;
+                    }
+                    if let Some(limit) = config.max_body_size
+                        && received > limit
+                    {
+                        return Err(RequestError::BodyTooLarge {
+                            limit,
+                            partial: partial(&body, received),
+                        }
+                        .into());
+                    }
+                }
+                Ok(None) => break,
+                Err(e) if e.is_timeout() => {
+                    return Err(RequestError::Timeout {
+                        kind: TimeoutKind::Total,
+                        partial: partial(&body, received),
+                    }
+                    .into());
+                }
+                // The connection closed before Content-Length bytes arrived.
+                Err(_) if expected_length.is_some_and(|n| (received as u64) < n) => break,
+                Err(e) => {
+                    return Err(anyhow::Error::new(e.without_url()).context("Failed to read response body"));
+                }
+            }
+        }
+        
+        if config.strict_length
+            && let Some(expected) = expected_length
+            && expected != received as u64
+        {
+            return Err(RequestError::TruncatedBody {
+                expected,
+                received: received as u64,
+                partial: partial(&body, received),
+            }
+            .into());
+        }
+        
+        match spill {
+            Some(writer) => Ok(ReadBody::Spilled(writer.finish().await?)),
+            None => Ok(ReadBody::Memory(body)),
+        }
     }
-}
\ No newline at end of file
+
+    /// Reads the body to the end chunk by chunk, keeping only its length.
+    async fn drain_body(
+        &self,
+        mut response: Response,
+        expected_length: Option<u64>,
+        idle_timeout: Option<Duration>,
+    ) -> Result<u64> {
+        let mut received = 0;
+        loop {
+            let Some(next) = next_chunk(&mut response, idle_timeout).await else {
+                return Err(RequestError::Timeout {
+                    kind: TimeoutKind::Read,
+                    partial: PartialResponse {
+                        bytes_received: received as usize,
+                        ..PartialResponse::default()
+                    },
+                }
+                .into());
+            };
+            match next {
+                Ok(Some(chunk)) => {
+                    received += chunk.len() as u64;
+                    if let Some(quota) = self.quota.as_ref().filter(|_| expected_length.is_none()) {
+                        quota.record(chunk.len() as u64)?;
+                    }
+                }
+                Ok(None) => break,
+                Err(_) if expected_length.is_some_and(|n| received < n) => break,
+                Err(e) => {
+                    return Err(anyhow::Error::new(e.without_url()).context("Failed to drain response body"));
+                }
+            }
+        }
+        Ok(received)
+    }
+
+    /// `certificates` are DER-encoded, leaf first.
+    fn tls_info(&self, certificates: Vec<Vec<u8>>, host: Option<&str>, alpn: Option<String>) -> TlsInfo {
+        let chain: Vec<CertificateInfo> = certificates.iter().filter_map(|der| CertificateInfo::from_der(der)).collect();
+        let peer_certificate = certificates.into_iter().next();
+        let certificate = peer_certificate.as_deref().and_then(CertificateInfo::from_der);
+        TlsInfo {
+            alpn,
+            sni: host
+                .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+                .filter(|host| host.parse::<IpAddr>().is_err())
+                .map(str::to_string),
+            expiring_soon: certificate
+                .as_ref()
+                .is_some_and(|certificate| certificate.expires_within(self.cert_expiry_warning, self.clock.system_now())),
+            certificate,
+            chain,
+            peer_certificate,
+            ..TlsInfo::default()
+        }
+    }
+
+    /// Replaces the clock behind timings, backoff sleeps, cache TTLs and
+    /// target cooldowns, e.g. with a `MockClock` in tests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.dns.set_clock(Arc::clone(&clock));
+        if let Some(balancer) = &mut self.balancer {
+            balancer.set_clock(Arc::clone(&clock));
+        }
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Stats for a finished request, timestamped with this client's clock
+    /// and redacted with its `UrlRedactor`.
+    pub fn request_stats(&self, method: HttpMethod, url: &str, response: &ApiResponse) -> RequestStats {
+        RequestStats::from_response_at(method, url, response, &self.redactor, self.clock.system_now())
+    }
+
+    /// DNS time for the requested and final hosts of a request that started
+    /// at `since`.
+    fn dns_time(&self, url: &str, request: Option<&RequestInfo>, since: Instant) -> Option<u64> {
+        let final_url = request.map_or(url, |request| request.final_url.as_str());
+        let mut hosts: Vec<String> = [url, final_url]
+            .iter()
+            .filter_map(|url| url::Url::parse(url).ok()?.host_str().map(str::to_ascii_lowercase))
+            .collect();
+        hosts.dedup();
+        hosts
+            .iter()
+            .filter_map(|host| self.dns.lookup_time(host, since))
+            .reduce(|total, took| total + took)
+            .map(|took| took.as_millis() as u64)
+    }
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.clock.now().saturating_duration_since(since)
+    }
+
+    /// Stops the client: new requests fail with `ClientClosed` right away,
+    /// running requests get up to `grace` to finish and are cancelled after
+    /// that. Deferred bodies and JSON array streams count as running until
+    /// they are read or dropped. Safe to call while other tasks are still
+    /// using the client.
+    pub async fn shutdown(&self, grace: Duration) -> ShutdownReport {
+        let report = self.lifecycle.shutdown(grace).await;
+        if let Some(auth) = &self.auth {
+            auth.stop_pre_refresh().await;
+        }
+        report
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.lifecycle.is_closed()
+    }
+
+    /// Runs the OAuth 2.0 authorization-code flow with PKCE: hands the
+    /// authorization URL to `on_authorize_url`, waits on a `127.0.0.1`
+    /// listener for the redirect, checks its `state` and exchanges the code
+    /// for tokens through this client, so its timeout, DNS overrides and TLS
+    /// settings apply. Failures specific to the flow are `AuthCodeError`s.
+    /// Pass the result to `AuthRecovery::from_oauth_token` for automatic
+    /// refresh.
+    pub async fn authorization_code_flow(&self, options: AuthCodeOptions) -> Result<OAuthToken> {
+        self.lifecycle.check_open()?;
+        crate::auth::authorization_code_flow(&self.client, options).await
+    }
+
+    /// Refreshes credentials when a request is rejected with `401` (and
+    /// optionally `403`) and retries it once. Concurrent rejections share a
+    /// single refresh. Set `with_clock` first: refreshes are timed on the
+    /// client's clock.
+    pub fn with_auth_recovery(mut self, recovery: AuthRecovery) -> Self {
+        self.auth = Some(Arc::new(AuthState::new(recovery, self.clock.system_now())));
+        self
+    }
+
+    /// How many times credentials have been refreshed.
+    pub fn auth_refreshes(&self) -> u64 {
+        self.auth.as_ref().map_or(0, |auth| auth.refreshes())
+    }
+
+    /// The token's expiry and the latest refresh outcome, if auth recovery
+    /// is configured.
+    pub fn auth_status(&self) -> Option<AuthStatus> {
+        self.auth.as_ref().map(|auth| auth.status())
+    }
+
+    /// Records every request into `recorder`; render it with
+    /// `metrics::prometheus_text`.
+    pub fn with_metrics(mut self, recorder: Arc<MetricsRecorder>) -> Self {
+        self.metrics = Some(recorder);
+        self
+    }
+
+    pub fn metrics(&self) -> Option<&Arc<MetricsRecorder>> {
+        self.metrics.as_ref()
+    }
+
+    /// Replaces the query-parameter redaction rules used for every URL this
+    /// client records in errors and stats.
+    pub fn with_url_redactor(mut self, redactor: UrlRedactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    /// Replaces the scanner that masks secrets in bodies quoted by `debug`
+    /// errors; `SecretScanner::none()` turns masking off. Response bodies
+    /// themselves are never changed.
+    pub fn with_secret_scanner(mut self, secrets: SecretScanner) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    pub fn secret_scanner(&self) -> &SecretScanner {
+        &self.secrets
+    }
+
+    /// Gives every request an `X-Request-Id` UUID unless its config sets
+    /// one; see `RequestConfig::with_request_id`.
+    pub fn with_request_ids(mut self, enabled: bool) -> Self {
+        self.request_ids = enabled;
+        self
+    }
+
+    /// Sends `value` as `key` on every request unless the request's config or
+    /// scope sets that header itself.
+    pub fn with_default_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.default_headers.insert(key.into(), value.into());
+        self
+    }
+
+    /// A cheap view of this client with its own default headers and timeout,
+    /// e.g. per tenant or per task. Creating and using scopes never changes
+    /// the client, so any number can run concurrently.
+    pub fn scoped<F>(&self, configure: F) -> ScopedClient<'_>
+    where
+        F: FnOnce(&mut ScopeDefaults),
+    {
+        let mut defaults = ScopeDefaults::default();
+        configure(&mut defaults);
+        ScopedClient::new(self, defaults)
+    }
+
+    /// Serves repeated GETs from memory until `config.ttl` or the server's
+    /// `max-age` runs out. `no-store` and `private` responses are not kept;
+    /// stale and `no-cache` ones with an `ETag` or `Last-Modified` are
+    /// revalidated with a conditional GET. A successful POST, PUT, PATCH or
+    /// DELETE to a URL drops its cached GETs.
+    pub fn with_response_cache(mut self, config: ResponseCacheConfig) -> Self {
+        self.response_cache = Some(ResponseCache::new(config));
+        self
+    }
+
+    pub fn clear_response_cache(&self) {
+        if let Some(cache) = &self.response_cache {
+            cache.clear();
+        }
+    }
+
+    /// Limits the body bytes this client may upload and download in total.
+    /// Once `bytes` are used up, requests fail with `QuotaExceeded`: before
+    /// sending when the request body does not fit, and before reading the
+    /// response body when its `Content-Length` does not. Bodies of unknown
+    /// length are counted as they arrive and abort once over the limit.
+    pub fn with_transfer_quota(mut self, bytes: u64) -> Self {
+        self.quota = Some(Arc::new(TransferQuota::new(bytes)));
+        self
+    }
+
+    /// Bytes used against the transfer quota, if one is set.
+    pub fn quota_usage(&self) -> Option<QuotaUsage> {
+        self.quota.as_ref().map(|quota| quota.usage())
+    }
+
+    /// Starts the transfer quota over, e.g. at the start of a billing period.
+    pub fn reset_transfer_quota(&self) {
+        if let Some(quota) = &self.quota {
+            quota.reset();
+        }
+    }
+
+    /// Allows at most `max` requests to one host at a time across the whole
+    /// client; further requests queue until a slot frees up. A slot is held
+    /// from sending until the body has been read, so deferred bodies and
+    /// JSON streams keep theirs until consumed or dropped. Idle pooled
+    /// connections per host are capped to match. Redirects to another host
+    /// count against the original one.
+    pub fn with_max_connections_per_host(mut self, max: usize) -> Result<Self> {
+        self.connections = Some(Arc::new(ConnectionLimiter::new(max)));
+        self.client = self
+            .connection_builder()
+            .build()
+            .context("Failed to create HTTP client with a connection limit")?;
+        self.local_clients.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        Ok(self)
+    }
+
+    /// How long a request may wait for a connection slot before failing
+    /// with `ConnectionLimitTimeout`. Without it requests wait indefinitely.
+    pub fn with_connection_queue_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_queue_timeout = Some(timeout);
+        self
+    }
+
+    /// Requests running and queued for `host`, if a connection limit is set.
+    pub fn connection_usage(&self, host: &str) -> Option<ConnectionUsage> {
+        self.connections.as_ref().map(|limiter| limiter.usage(host))
+    }
+
+    /// Shares one network request between concurrent identical GETs (same URL
+    /// and headers); every caller receives its own clone of the response.
+    pub fn with_coalescing(mut self, enabled: bool) -> Self {
+        self.coalesce_gets = enabled;
+        self
+    }
+
+    /// Serves relative request URLs from several base URLs using `strategy`.
+    /// Failing targets are ejected for a cooldown and the request is retried
+    /// on the next one.
+    pub fn with_base_urls<I, S>(self, urls: I, strategy: Strategy) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.with_weighted_base_urls(urls.into_iter().map(|url| (url.into(), 1)), strategy)
+    }
+
+    /// Like `with_base_urls`, with a weight per target for
+    /// `Strategy::WeightedRandom`.
+    pub fn with_weighted_base_urls<I, S>(mut self, targets: I, strategy: Strategy) -> Self
+    where
+        I: IntoIterator<Item = (S, u32)>,
+        S: Into<String>,
+    {
+        let targets = targets.into_iter().map(|(url, weight)| (url.into(), weight)).collect();
+        self.balancer = Some(LoadBalancer::new(targets, strategy, Arc::clone(&self.clock)));
+        self
+    }
+
+    /// How long a failed target stays ejected. Call after `with_base_urls`.
+    pub fn with_target_cooldown(mut self, cooldown: Duration) -> Self {
+        if let Some(balancer) = &mut self.balancer {
+            balancer.set_cooldown(cooldown);
+        }
+        self
+    }
+
+    pub fn target_stats(&self) -> Vec<TargetStats> {
+        self.balancer.as_ref().map(LoadBalancer::stats).unwrap_or_default()
+    }
+
+    fn resolve_url(&self, url: &str) -> String {
+        match &self.balancer {
+            Some(balancer) if is_relative_url(url) => balancer
+                .plan(url)
+                .into_iter()
+                .next()
+                .map(|(_, full_url)| full_url)
+                .unwrap_or_else(|| url.to_string()),
+            _ => self.join_base_url(url),
+        }
+    }
+
+    fn join_base_url(&self, url: &str) -> String {
+        match &self.base_url {
+            Some(base) if is_relative_url(url) => base
+                .join(url)
+                .map(String::from)
+                .unwrap_or_else(|_| url.to_string()),
+            _ => url.to_string(),
+        }
+    }
+
+    /// Joins relative request URLs against `base` the way `url::Url::join`
+    /// does: `/v1/users` replaces the whole path, while `users` replaces only
+    /// the last segment, so give the base a trailing slash to append to it.
+    /// Absolute request URLs are sent as is. `with_base_urls` takes
+    /// precedence when both are set.
+    pub fn with_base_url(mut self, base: &str) -> Result<Self> {
+        let base = parse_url(base).with_context(|| format!("Invalid base URL: {}", base))?;
+        if base.cannot_be_a_base() {
+            anyhow::bail!("Invalid base URL: {}", base);
+        }
+        self.base_url = Some(base);
+        Ok(self)
+    }
+
+    /// Sets `TCP_NODELAY` on new connections, sending small writes at once
+    /// instead of batching them (Nagle's algorithm). Without this call the
+    /// reqwest default applies. Pooled connections are dropped, so call it
+    /// while building the client.
+    ///
+    /// Other socket options: the source address is chosen per request with
+    /// `RequestConfig::with_local_address`; a source port range is not
+    /// supported, the OS picks the ephemeral port.
+    pub fn with_tcp_nodelay(mut self, enabled: bool) -> Result<Self> {
+        self.tcp_nodelay = Some(enabled);
+        self.client = self
+            .connection_builder()
+            .build()
+            .context("Failed to create HTTP client with TCP_NODELAY")?;
+        self.local_clients.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        Ok(self)
+    }
+
+    /// Flags certificates expiring within `window` via
+    /// `TlsInfo::expiring_soon` (default `DEFAULT_CERT_EXPIRY_WARNING_DAYS`).
+    pub fn with_cert_expiry_warning(mut self, window: Duration) -> Self {
+        self.cert_expiry_warning = window;
+        self
+    }
+
+    /// Completes a TLS handshake with `target` (`host`, `host:port` or an
+    /// `https://` URL) and reports it without sending a request. DNS
+    /// overrides and fallbacks apply. Everything reported, including the
+    /// protocol and cipher, comes from that one handshake. An untrusted
+    /// certificate is still reported, with the reason in `verify_error`.
+    #[cfg(feature = "tls-inspect")]
+    pub async fn inspect_tls(&self, target: &str) -> Result<TlsInfo> {
+        self.lifecycle.check_open()?;
+        let (host, port) = split_target(target)?;
+        let addrs = self.socket_addrs(&host, port).await?;
+        let handshake = tokio::time::timeout(self.timeout, inspect_handshake(&addrs, &host))
+            .await
+            .map_err(|_| anyhow::anyhow!("TLS handshake with {} timed out", host))??;
+        Ok(TlsInfo {
+            protocol: handshake.protocol.map(|version| version.protocol_name().to_string()),
+            cipher: handshake.cipher,
+            verify_error: handshake.verify_error,
+            ..self.tls_info(handshake.certificates, Some(&host), handshake.alpn)
+        })
+    }
+
+    /// Addresses of `host`, going through the client's DNS overrides.
+    #[cfg(feature = "tls-inspect")]
+    async fn socket_addrs(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        Ok(self
+            .dns
+            .resolve_host(host)
+            .await
+            .with_context(|| format!("Failed to resolve {}", host))?
+            .into_iter()
+            .map(|addr| SocketAddr::new(addr.ip(), port))
+            .collect())
+    }
+
+    /// Only accepts servers that meet `policy`. A policy switches the client
+    /// to the rustls backend, which offers nothing but forward-secret AEAD
+    /// cipher suites, so legacy ciphers are ruled out whatever the version.
+    /// Handshakes that fail because of the policy return
+    /// `TlsPolicyViolation`. Roots come from the system store, as without a
+    /// policy.
+    pub fn with_tls_policy(mut self, policy: TlsPolicy) -> Result<Self> {
+        self.tls_policy = Some(policy);
+        self.client = self
+            .connection_builder()
+            .build()
+            .with_context(|| format!("Failed to create HTTP client with TLS policy {}", policy))?;
+        self.local_clients.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        Ok(self)
+    }
+
+    /// `with_tls_policy` for the policy whose minimum is `version`.
+    pub fn min_tls_version(self, version: TlsVersion) -> Result<Self> {
+        match version {
+            TlsVersion::V1_2 => self.with_tls_policy(TlsPolicy::Compatible),
+            TlsVersion::V1_3 => self.with_tls_policy(TlsPolicy::Modern),
+        }
+    }
+
+    pub fn tls13_only(self) -> Result<Self> {
+        self.with_tls_policy(TlsPolicy::Modern)
+    }
+
+    pub fn tls_policy(&self) -> Option<TlsPolicy> {
+        self.tls_policy
+    }
+
+    /// A `TlsPolicyViolation` when `error` is a failed version or cipher
+    /// negotiation and the server's best version, where it can be probed,
+    /// is below the policy's minimum.
+    async fn tls_policy_violation(&self, error: &reqwest::Error, url: &url::Url) -> Option<TlsPolicyViolation> {
+        let policy = self.tls_policy?;
+        if url.scheme() != "https" || !handshake_error(error).is_some_and(is_negotiation_failure) {
+            return None;
+        }
+        let host = url.host_str()?.trim_start_matches('[').trim_end_matches(']');
+        let server_best = self.probe_tls_version(host, url.port_or_known_default()?).await;
+        if server_best.is_some_and(|version| version >= policy.min_version()) {
+            return None;
+        }
+        Some(TlsPolicyViolation {
+            policy,
+            host: host.to_string(),
+            server_best,
+        })
+    }
+
+    /// The newest version `host` negotiates, see `TlsPolicyViolation::server_best`.
+    #[cfg(feature = "tls-inspect")]
+    async fn probe_tls_version(&self, host: &str, port: u16) -> Option<TlsVersion> {
+        let addrs = self.socket_addrs(host, port).await.ok()?;
+        probe_version(&addrs, host).await
+    }
+
+    /// Probing skips certificate checks, so it needs `tls-inspect`.
+    #[cfg(not(feature = "tls-inspect"))]
+    async fn probe_tls_version(&self, _host: &str, _port: u16) -> Option<TlsVersion> {
+        None
+    }
+
+    pub fn base_url(&self) -> Option<&str> {
+        self.base_url.as_ref().map(url::Url::as_str)
+    }
+
+    /// Caches DNS answers in memory, clamped to the configured TTL bounds,
+    /// with short-lived negative entries for hosts that do not resolve.
+    pub fn with_dns_cache(self, config: DnsCacheConfig) -> Self {
+        self.dns.set_cache_config(Some(config));
+        self
+    }
+
+    /// Replaces the lookup behind the DNS cache, e.g. with a stub.
+    pub fn with_dns_lookup<L>(self, lookup: L) -> Self
+    where
+        L: DnsLookup + 'static,
+    {
+        self.dns.set_lookup(Arc::new(lookup));
+        self
+    }
+
+    /// Pins `host` to fixed addresses. Overrides bypass the DNS cache.
+    pub fn with_resolve(self, host: &str, addrs: &[SocketAddr]) -> Self {
+        self.dns.add_override(host, addrs.to_vec());
+        self
+    }
+
+    /// Resolves `fallback` instead when `host` fails to resolve, e.g.
+    /// `api.example.com` to `api-fallback.example.com`. The request keeps
+    /// its URL, `Host` header and TLS name; only the addresses change.
+    /// `RequestInfo::resolved_host` records which name was used.
+    pub fn with_dns_fallback(self, host: &str, fallback: &str) -> Self {
+        self.dns.add_fallback(host, fallback);
+        self
+    }
+
+    /// Fails a single DNS lookup after `timeout` (default
+    /// `DEFAULT_DNS_TIMEOUT_SECS`) so a retry can try again.
+    pub fn with_dns_timeout(self, timeout: Duration) -> Self {
+        self.dns.set_timeout(timeout);
+        self
+    }
+
+    pub fn flush_dns(&self) {
+        self.dns.flush();
+    }
+
+    pub fn dns_stats(&self) -> DnsStats {
+        self.dns.stats()
+    }
+
+    /// Normalizes status codes before success, retry and error classification,
+    /// e.g. treating a gateway's custom 430 as 429. `ApiResponse::status` holds
+    /// the mapped code and `ApiResponse::original_status` the one on the wire.
+    pub fn on_status<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(u16) -> u16 + Send + Sync + 'static,
+    {
+        self.status_mapper = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Decides each redirect: follow it, return the 3xx response, fail, or
+    /// go somewhere else with `RedirectAction::Rewrite`. At most
+    /// `DEFAULT_MAX_REDIRECTS` are followed, and as many rewrites. Whatever
+    /// the handler says, `Authorization`, `Cookie` and `Proxy-Authorization`
+    /// are not sent on to another host or port. A request whose body cannot
+    /// be cloned returns the 3xx response instead of being rewritten.
+    pub fn on_redirect<F>(mut self, handler: F) -> Result<Self>
+    where
+        F: Fn(&RedirectAttempt<'_>) -> RedirectAction + Send + Sync + 'static,
+    {
+        self.redirect_handler = Some(Arc::new(handler));
+        self.client = self
+            .connection_builder()
+            .build()
+            .context("Failed to create HTTP client with a redirect handler")?;
+        self.local_clients.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        Ok(self)
+    }
+
+    /// Stores cookies from responses and sends them back on matching
+    /// requests, keeping only those `policy` allows. Each redirect hop is
+    /// judged on its own, so a third-party hop's cookies can be dropped
+    /// while the first party's are kept.
+    pub fn with_cookie_policy(mut self, policy: CookiePolicy) -> Result<Self> {
+        self.cookies = Some(Arc::new(CookieJar::new(policy)));
+        self.client = self
+            .connection_builder()
+            .build()
+            .context("Failed to create HTTP client with a cookie jar")?;
+        self.local_clients.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        Ok(self)
+    }
+
+    /// Cookies currently stored, with the request that set each one. Empty
+    /// without `with_cookie_policy`.
+    pub fn cookie_report(&self) -> Vec<StoredCookie> {
+        self.cookies.as_ref().map(|jar| jar.report()).unwrap_or_default()
+    }
+
+    /// Removes the cookies of `domain` and its subdomains, returning how
+    /// many were removed.
+    pub fn remove_cookies(&self, domain: &str) -> usize {
+        self.cookies.as_ref().map_or(0, |jar| jar.remove_domain(domain))
+    }
+
+    /// Turns warnings with these codes into `WarningEscalated` errors. Request
+    /// warnings stop the request before anything is sent.
+    pub fn escalate_warnings(mut self, codes: impl IntoIterator<Item = WarningCode>) -> Self {
+        self.escalated_warnings.extend(codes);
+        self
+    }
+
+    /// Runs `interceptor` before every attempt, retries included. When it
+    /// returns a response (see `ApiResponse::synthetic`) that response is
+    /// used and nothing goes over the network, e.g. to inject a `503` or
+    /// stub an endpoint behind a feature flag. Interceptors run in the order
+    /// added and the first response wins; JSON array streams bypass them.
+    pub fn before_request<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&InterceptedRequest<'_>) -> Option<ApiResponse> + Send + Sync + 'static,
+    {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Signs each attempt just before it is sent, after every header
+    /// (the `with_auth_recovery` bearer token included) and the body are final,
+    /// e.g. for AWS SigV4. Plain redirect follows are not signed again, but
+    /// `RedirectAction::Rewrite` targets are. A failed signature fails the
+    /// attempt.
+    pub fn with_signer<S>(mut self, signer: S) -> Self
+    where
+        S: RequestSigner + 'static,
+    {
+        self.signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Delays and fails attempts as `faults` describes, before any
+    /// interceptor runs. Failed attempts come back as a `faults.error_status`
+    /// response carrying `x-fault-injected: true`, and retry policies treat
+    /// them like real ones.
+    pub fn with_fault_injection(self, faults: FaultInjection) -> Self {
+        self.set_fault_injection(Some(faults));
+        self
+    }
+
+    /// Turns fault injection on or off (`None`) for subsequent attempts,
+    /// including those of requests already running.
+    pub fn set_fault_injection(&self, faults: Option<FaultInjection>) {
+        *self.faults.lock().unwrap_or_else(|e| e.into_inner()) = faults.map(|faults| Arc::new(FaultInjector::new(faults)));
+    }
+
+    pub fn fault_injection(&self) -> Option<FaultInjection> {
+        self.faults
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|faults| faults.config.clone())
+    }
+
+    pub fn url_redactor(&self) -> &UrlRedactor {
+        &self.redactor
+    }
+
+    /// Checks that `url` parses. Unicode hosts must pass IDNA validation;
+    /// they are sent as punycode.
+    pub fn validate_url(url: &str) -> Result<()> {
+        parse_url(url)?;
+        Ok(())
+    }
+
+    pub fn with_timeout(timeout_secs: u64) -> Result<Self> {
+        let dns = Arc::new(DnsState::default());
+        let client = Self::client_builder(Duration::from_secs(timeout_secs), &dns)
+            .build()
+            .with_context(|| "Failed to create HTTP client with custom timeout")?;
+        
+        Ok(Self::from_parts(client, dns, Duration::from_secs(timeout_secs)))
+    }
+}
+
+type SharedResult = std::result::Result<ApiResponse, Arc<anyhow::Error>>;
+type InFlightMap = Mutex<HashMap<String, broadcast::Sender<SharedResult>>>;
+
+/// Leader side of a coalesced GET. Removes its entry when done, or when the
+/// leading future is dropped so that waiters fall back to their own request.
+struct InFlight<'a> {
+    key: String,
+    inflight: &'a InFlightMap,
+    completed: bool,
+}
+
+impl InFlight<'_> {
+    fn complete(mut self, result: &Result<ApiResponse>) {
+        self.completed = true;
+        let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(sender) = inflight.remove(&self.key) {
+            let shared = match result {
+                Ok(response) => Ok(response.clone()),
+                Err(e) => Err(Arc::new(anyhow::anyhow!("{:#}", e))),
+            };
+            let _ = sender.send(shared);
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+        let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+        inflight.remove(&self.key);
+    }
+}
+
+fn request_fingerprint(method: HttpMethod, url: &str, config: &RequestConfig) -> String {
+    let mut headers: Vec<_> = config
+        .headers
+        .iter()
+        .map(|(k, v)| format!("{}:{}", k.to_ascii_lowercase(), v))
+        .collect();
+    headers.sort();
+    if !config.user_agent_tokens.is_empty() {
+        headers.push(format!("user-agent+:{}", config.user_agent_tokens.join(" ")));
+    }
+    let local = config.local_address.map(|ip| format!(" from {}", ip)).unwrap_or_default();
+    format!("{} {}{}\n{}", method, config.url_with_query(url), local, headers.join("\n"))
+}
+
+fn is_relative_url(url: &str) -> bool {
+    matches!(url::Url::parse(url), Err(url::ParseError::RelativeUrlWithoutBase))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::ResponseCacheConfig;
+    use crate::testing::{MockResponse, MockServer};
+
+    /// GETs `path` on `host`, pinned to `server`, and returns the request
+    /// the server saw.
+    async fn request_via_host(server: &MockServer, host: &str, path: &str) -> Result<(ApiResponse, crate::testing::RecordedRequest)> {
+        let client = HttpClient::new().with_resolve(host, &[server.addr()]);
+        let url = format!("http://{}:{}{}", host, server.addr().port(), path);
+        let response = client.get(url.as_str(), RequestConfig::new()).await?;
+        let request = server.requests().pop().context("no request recorded")?;
+        Ok((response, request))
+    }
+
+    #[tokio::test]
+    async fn internationalized_hosts_and_paths_go_out_encoded() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/*").respond(MockResponse::text(200, "ok"));
+        let port = server.addr().port();
+        let cases = [
+            ("münchen.example", "/straße?q=größe", "xn--mnchen-3ya.example", "/stra%C3%9Fe?q=gr%C3%B6%C3%9Fe"),
+            ("日本.example", "/写真/東京", "xn--wgv71a.example", "/%E5%86%99%E7%9C%9F/%E6%9D%B1%E4%BA%AC"),
+            ("☕.example", "/🍕?topping=🍍", "xn--53h.example", "/%F0%9F%8D%95?topping=%F0%9F%8D%8D"),
+        ];
+        for (host, path, ascii_host, target) in cases {
+            let (response, request) = request_via_host(&server, host, path).await?;
+            assert_eq!(response.status, 200);
+            assert_eq!(request.header("host"), Some(format!("{}:{}", ascii_host, port).as_str()));
+            let sent = match &request.query {
+                Some(query) => format!("{}?{}", request.path, query),
+                None => request.path.clone(),
+            };
+            assert_eq!(sent, target);
+            let info = response.request.as_ref().context("no request info")?;
+            assert!(!info.was_redirected());
+            assert!(info.final_url.contains(ascii_host));
+            assert!(crate::utils::display_url(&info.final_url).contains(host));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn header_flags_set_empty_and_remove_headers() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/").respond(MockResponse::new(204));
+        let client = HttpClient::new().with_default_header("X-Default", "1");
+
+        client.get(&server.url("/"), RequestConfig::new()).await?;
+        let request = server.requests().pop().context("no request recorded")?;
+        assert_eq!(request.header("user-agent"), Some(DEFAULT_USER_AGENT));
+        assert_eq!(request.header("x-default"), Some("1"));
+
+        let flags = crate::utils::parse_header_flags(["X-Empty;", "User-Agent:", "X-Default:", "X-Trace: abc"]);
+        client.get(&server.url("/"), RequestConfig::new().with_header_flags(&flags)).await?;
+        let request = server.requests().pop().context("no request recorded")?;
+        assert_eq!(request.header("x-empty"), Some(""));
+        assert_eq!(request.header("x-trace"), Some("abc"));
+        assert_eq!(request.header("user-agent"), None);
+        assert_eq!(request.header("x-default"), None);
+
+        let config = RequestConfig::new().without_header("Accept-Encoding").add_header("User-Agent", "mine");
+        client.get(&server.url("/"), config).await?;
+        let request = server.requests().pop().context("no request recorded")?;
+        assert_eq!(request.header("user-agent"), Some("mine"));
+        assert_eq!(request.header("accept-encoding"), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn default_user_agent_header_is_not_overridden() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/").respond(MockResponse::new(204));
+        let client = HttpClient::new().with_default_header("User-Agent", "from-default");
+        client.get(&server.url("/"), RequestConfig::new()).await?;
+        let request = server.requests().pop().context("no request recorded")?;
+        assert_eq!(request.header("user-agent"), Some("from-default"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn json_values_are_sent_as_serialized() -> Result<()> {
+        let server = MockServer::start().await?;
+        for method in [HttpMethod::Post, HttpMethod::Put, HttpMethod::Patch] {
+            server.when(method, "/items/:id").respond_with(|request| {
+                MockResponse::new(200)
+                    .with_header("Content-Type", request.header("content-type").unwrap_or_default())
+                    .with_body(request.body.clone())
+            });
+        }
+        let client = HttpClient::new();
+        let value = serde_json::json!({"name": "Grüße", "tags": ["a", "b"], "price": 0.1, "nested": {"ok": true}});
+
+        let response = client.post_value(&server.url("/items/1"), &value, RequestConfig::new()).await?;
+        assert_eq!(response.content_type, "application/json");
+        assert_eq!(serde_json::from_str::<Value>(&response.body)?, value);
+        assert_eq!(response.body, value.to_string());
+
+        let response = client.put_value(&server.url("/items/1"), &value, RequestConfig::new()).await?;
+        assert_eq!(serde_json::from_str::<Value>(&response.body)?, value);
+        let vars = HashMap::from([("id".to_string(), serde_json::json!(2))]);
+        let response = client.patch_value((server.url("/items{/id}").as_str(), &vars), &value, RequestConfig::new()).await?;
+        assert_eq!(serde_json::from_str::<Value>(&response.body)?, value);
+
+        let methods: Vec<String> = server.requests().into_iter().map(|request| format!("{} {}", request.method, request.path)).collect();
+        assert_eq!(methods, ["POST /items/1", "PUT /items/1", "PATCH /items/2"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_tee_enforces_the_body_size_limit() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/big").respond(MockResponse::text(200, "x".repeat(100)));
+        let mut sink = Vec::new();
+        let config = RequestConfig::new().with_max_body_size(10);
+        let error = HttpClient::new().get_tee(&server.url("/big"), &mut sink, config).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<RequestError>(), Some(RequestError::BodyTooLarge { limit: 10, .. })));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_tee_spills_past_the_threshold() -> Result<()> {
+        let server = MockServer::start().await?;
+        let body = "0123456789".repeat(100);
+        server
+            .when(HttpMethod::Get, "/big")
+            .respond(MockResponse::new(200).chunked(body.as_bytes().chunks(64).map(|chunk| chunk.to_vec()), Duration::ZERO));
+        let mut sink = Vec::new();
+        let config = RequestConfig::new().with_spill_threshold(128);
+        let response = HttpClient::new().get_tee(&server.url("/big"), &mut sink, config).await?;
+        assert_eq!(sink, body.as_bytes());
+        let spilled = response.spilled_body().context("body was not spilled")?;
+        assert_eq!(spilled.read()?, body.as_bytes());
+        assert_eq!(response.wire_bytes, 1000);
+        assert!(response.body.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quota_rejects_the_request_that_would_exceed_it() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/data").respond(MockResponse::text(200, "x".repeat(60)));
+        server.when(HttpMethod::Post, "/data").respond(MockResponse::new(204));
+        let client = HttpClient::new().with_transfer_quota(150);
+
+        client.get(&server.url("/data"), RequestConfig::new()).await?;
+        client.post(&server.url("/data"), r#"{"n":1}"#, RequestConfig::new()).await?;
+        assert_eq!(client.quota_usage().map(|usage| usage.used), Some(67));
+        client.get(&server.url("/data"), RequestConfig::new()).await?;
+        let error = client.get(&server.url("/data"), RequestConfig::new()).await.unwrap_err();
+        let exceeded = error.downcast_ref::<crate::error::QuotaExceeded>().context("not a quota error")?;
+        assert_eq!((exceeded.used, exceeded.requested), (127, 60));
+        assert_eq!(client.quota_usage().map(|usage| usage.remaining()), Some(23));
+
+        client.reset_transfer_quota();
+        client.get(&server.url("/data"), RequestConfig::new()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deferred_bodies_without_length_count_against_the_quota() -> Result<()> {
+        let server = MockServer::start().await?;
+        let chunks = || MockResponse::new(200).chunked(["0123456789"; 4], Duration::ZERO);
+        server.when(HttpMethod::Get, "/stream").respond(chunks());
+        let client = HttpClient::new().with_transfer_quota(100);
+        let config = RequestConfig::new().defer_body(true);
+
+        let mut response = client.get(&server.url("/stream"), config.clone()).await?;
+        assert_eq!(client.quota_usage().map(|usage| usage.used), Some(0));
+        response.read_body().await?;
+        assert_eq!(client.quota_usage().map(|usage| usage.used), Some(40));
+
+        let mut response = client.get(&server.url("/stream"), config.clone()).await?;
+        let mut sink = Vec::new();
+        response.read_body_into(&mut sink).await?;
+        assert_eq!(client.quota_usage().map(|usage| usage.used), Some(80));
+
+        let mut response = client.get(&server.url("/stream"), config).await?;
+        let error = response.read_body().await.unwrap_err();
+        let exceeded = error.downcast_ref::<crate::error::QuotaExceeded>().context("not a quota error")?;
+        assert_eq!((exceeded.limit, exceeded.used, exceeded.requested), (100, 100, 10));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deferred_readers_count_against_the_quota() -> Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/stream")
+            .respond(MockResponse::new(200).chunked(["0123456789"; 4], Duration::ZERO));
+        let client = HttpClient::new().with_transfer_quota(25);
+        let response = client.get(&server.url("/stream"), RequestConfig::new().defer_body(true)).await?;
+        let mut body = Vec::new();
+        assert!(response.into_async_reader().read_to_end(&mut body).await.is_err());
+        assert!(client.quota_usage().is_some_and(|usage| usage.used > 25));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plain_http_responses_have_no_tls_info() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/").respond(MockResponse::new(200));
+        let response = HttpClient::new().get(&server.url("/"), RequestConfig::new()).await?;
+        assert_eq!(response.tls_info, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn response_cache_misses_then_hits() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/users").respond(MockResponse::text(200, "[]"));
+        let client = HttpClient::new().with_response_cache(ResponseCacheConfig::default());
+
+        let first = client.get(&server.url("/users"), RequestConfig::new()).await?;
+        assert_eq!(first.cache_status, CacheStatus::Miss);
+        assert!(!first.from_cache);
+        let second = client.get(&server.url("/users"), RequestConfig::new()).await?;
+        assert_eq!(second.cache_status, CacheStatus::Hit);
+        assert!(second.from_cache);
+        assert_eq!(second.body, "[]");
+        assert_eq!(server.received(HttpMethod::Get, "/users"), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cache_entries_are_shared_by_relative_and_absolute_urls() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/api/items").respond(MockResponse::text(200, "[]"));
+        server.when(HttpMethod::Put, "/api/items").respond(MockResponse::new(204));
+        let client = HttpClient::new()
+            .with_base_url(&server.url("/api/"))?
+            .with_response_cache(ResponseCacheConfig::default());
+        let absolute = server.url("/api/items");
+
+        client.get("items", RequestConfig::new()).await?;
+        assert_eq!(client.get(absolute.as_str(), RequestConfig::new()).await?.cache_status, CacheStatus::Hit);
+        client.put(absolute.as_str(), "{}", RequestConfig::new()).await?;
+        assert_eq!(client.get("items", RequestConfig::new()).await?.cache_status, CacheStatus::Miss);
+
+        client.put("items", "{}", RequestConfig::new()).await?;
+        assert_eq!(client.get(absolute.as_str(), RequestConfig::new()).await?.cache_status, CacheStatus::Miss);
+        assert_eq!(server.received(HttpMethod::Get, "/api/items"), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_cache_bypass_it() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Post, "/users").respond(MockResponse::new(201));
+        server.when(HttpMethod::Get, "/users").respond(MockResponse::text(200, "[]"));
+        let cached = HttpClient::new().with_response_cache(ResponseCacheConfig::default());
+
+        let response = HttpClient::new().get(&server.url("/users"), RequestConfig::new()).await?;
+        assert_eq!(response.cache_status, CacheStatus::Bypass);
+        let response = cached.post(&server.url("/users"), "{}", RequestConfig::new()).await?;
+        assert_eq!(response.cache_status, CacheStatus::Bypass);
+        Ok(())
+    }
+
+    /// `origin` redirects `/start` to `target`'s `/landing`.
+    async fn redirect_between(origin: &MockServer, target: &MockServer) {
+        origin
+            .when(HttpMethod::Get, "/start")
+            .respond(MockResponse::new(302).with_header("Location", target.url("/landing")));
+        target.when(HttpMethod::Get, "/landing").respond(MockResponse::text(200, "landed"));
+    }
+
+    fn credentials() -> RequestConfig {
+        RequestConfig::new()
+            .with_bearer_token("secret-token")
+            .add_header("Cookie", "session=abc")
+            .add_header("Proxy-Authorization", "Basic cHJveHk6cHc=")
+    }
+
+    #[tokio::test]
+    async fn credentials_are_stripped_on_cross_origin_redirects() -> Result<()> {
+        let (origin, other) = (MockServer::start().await?, MockServer::start().await?);
+        redirect_between(&origin, &other).await;
+        let followers = [
+            HttpClient::new(),
+            HttpClient::new().on_redirect(|_: &RedirectAttempt<'_>| RedirectAction::Follow)?,
+        ];
+
+        for client in followers {
+            let response = client.get(&origin.url("/start"), credentials()).await?;
+            assert_eq!(response.body, "landed");
+            let first = origin.requests().pop().context("no request to the origin")?;
+            assert_eq!(first.header("authorization"), Some("Bearer secret-token"));
+            let landed = other.requests().pop().context("no request after the redirect")?;
+            for name in ["authorization", "cookie", "proxy-authorization"] {
+                assert_eq!(landed.header(name), None, "{} followed the redirect", name);
+            }
+            origin.reset();
+            other.reset();
+            redirect_between(&origin, &other).await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn credentials_follow_same_origin_redirects() -> Result<()> {
+        let server = MockServer::start().await?;
+        redirect_between(&server, &server).await;
+        let response = HttpClient::new().get(&server.url("/start"), credentials()).await?;
+        assert_eq!(response.body, "landed");
+        let landed = server.requests_to(HttpMethod::Get, "/landing").pop().context("no request after the redirect")?;
+        assert_eq!(landed.header("authorization"), Some("Bearer secret-token"));
+        assert_eq!(landed.header("cookie"), Some("session=abc"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn json_rpc_unwraps_the_result() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Post, "/rpc").respond_with(|request| {
+            let call: Value = serde_json::from_slice(&request.body).unwrap();
+            let sum = call["params"].as_array().unwrap().iter().filter_map(Value::as_i64).sum::<i64>();
+            MockResponse::json(200, serde_json::json!({"jsonrpc": "2.0", "id": call["id"], "result": sum}))
+        });
+        let client = HttpClient::new();
+
+        let sum: i64 = client.json_rpc(&server.url("/rpc"), "add", serde_json::json!([2, 3]), RequestConfig::new()).await?;
+        assert_eq!(sum, 5);
+        let _: i64 = client.json_rpc(&server.url("/rpc"), "add", serde_json::json!([]), RequestConfig::new()).await?;
+
+        let calls: Vec<Value> = server.requests().iter().map(|request| serde_json::from_slice(&request.body).unwrap()).collect();
+        assert_eq!(calls[0], serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "add", "params": [2, 3]}));
+        assert_eq!(calls[1]["id"], 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn json_rpc_surfaces_the_error_object() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Post, "/rpc").respond(MockResponse::json(
+            200,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": "call-1",
+                "error": {"code": -32601, "message": "Method not found", "data": {"method": "nope"}}
+            }),
+        ));
+
+        let error = HttpClient::new()
+            .json_rpc_with_id::<Value>(&server.url("/rpc"), "nope", Value::Null, Value::from("call-1"), RequestConfig::new())
+            .await
+            .unwrap_err();
+        let error = error.downcast_ref::<JsonRpcError>().context("not a JsonRpcError")?;
+        assert_eq!(error.code, -32601);
+        assert_eq!(error.message, "Method not found");
+        assert_eq!(error.data, Some(serde_json::json!({"method": "nope"})));
+        assert_eq!(error.to_string(), "JSON-RPC error -32601: Method not found");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_stalled_body_times_out_with_what_arrived() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/trickle").respond(
+            MockResponse::new(200)
+                .with_header("X-Stream", "yes")
+                .chunked(["abcd"; 10], Duration::from_millis(100)),
+        );
+        server.when(HttpMethod::Get, "/stall").respond(
+            MockResponse::new(200)
+                .with_header("X-Stream", "yes")
+                .chunked(["abcd"], Duration::from_millis(500)),
+        );
+        let client = HttpClient::new();
+
+        let config = RequestConfig::new()
+            .with_timeout(Duration::from_millis(350))
+            .with_partial_body_limit(64);
+        let error = client.get(&server.url("/trickle"), config).await.unwrap_err();
+        let Some(RequestError::Timeout { kind, partial }) = error.downcast_ref::<RequestError>() else {
+            panic!("expected a timeout, got {:?}", error);
+        };
+        assert_eq!(*kind, TimeoutKind::Total);
+        assert_eq!(partial.status, Some(200));
+        assert_eq!(partial.headers.get("x-stream").map(String::as_str), Some("yes"));
+        assert!(partial.bytes_received > 0 && partial.bytes_received < 40, "{}", partial.bytes_received);
+        assert_eq!(partial.partial_body.as_ref().map(String::len), Some(partial.bytes_received));
+        assert!(partial.elapsed_ms >= 350, "{}", partial.elapsed_ms);
+
+        let config = RequestConfig::new().with_idle_timeout(Duration::from_millis(100));
+        let error = client.get(&server.url("/stall"), config).await.unwrap_err();
+        let Some(RequestError::Timeout { kind, partial }) = error.downcast_ref::<RequestError>() else {
+            panic!("expected a timeout, got {:?}", error);
+        };
+        assert_eq!(*kind, TimeoutKind::Read);
+        assert!(partial.headers_received());
+        assert_eq!(partial.headers.get("x-stream").map(String::as_str), Some("yes"));
+        assert_eq!(partial.bytes_received, 0);
+        assert!((100..500).contains(&partial.elapsed_ms), "{}", partial.elapsed_ms);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_412_under_if_match_is_a_precondition_failure() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Put, "/doc").respond_with(|request| match request.header("if-match") {
+            Some("\"v2\"") | None => MockResponse::new(204),
+            Some(_) => MockResponse::json(412, serde_json::json!({"error": "stale"})).with_header("ETag", "\"v2\""),
+        });
+        let client = HttpClient::new();
+
+        let error = client
+            .put(&server.url("/doc"), "{}", RequestConfig::new().if_match("\"v1\""))
+            .await
+            .unwrap_err();
+        match error.downcast_ref::<RequestError>() {
+            Some(RequestError::PreconditionFailed { current_etag, partial }) => {
+                assert_eq!(current_etag.as_deref(), Some("\"v2\""));
+                assert_eq!(partial.status, Some(412));
+            }
+            other => panic!("expected PreconditionFailed, got {:?}", other),
+        }
+        assert_eq!(server.requests()[0].header("if-match"), Some("\"v1\""));
+
+        let response = client.put(&server.url("/doc"), "{}", RequestConfig::new().if_match("\"v2\"")).await?;
+        assert_eq!(response.status, 204);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_back_off_on_the_client_clock() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/flaky").respond_sequence(vec![
+            MockResponse::new(503),
+            MockResponse::new(502),
+            MockResponse::new(500),
+            MockResponse::text(200, "ok"),
+        ]);
+        let clock = Arc::new(crate::clock::MockClock::new());
+        let client = HttpClient::new().with_clock(clock.clone());
+
+        let retry = crate::models::RetryPolicy::new(5)
+            .with_initial_backoff(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(3));
+        let response = client.get(&server.url("/flaky"), RequestConfig::new().with_retry(retry)).await?;
+        assert_eq!(response.body, "ok");
+        assert_eq!(server.received(HttpMethod::Get, "/flaky"), 4);
+        let recorded: Vec<_> = response.attempts.iter().map(|attempt| (attempt.outcome, attempt.backoff_ms)).collect();
+        assert_eq!(
+            recorded,
+            [
+                (AttemptOutcome::Status(503), 0),
+                (AttemptOutcome::Status(502), 1000),
+                (AttemptOutcome::Status(500), 2000),
+                (AttemptOutcome::Status(200), 3000),
+            ]
+        );
+        assert_eq!(clock.elapsed(), Duration::from_secs(6));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mapped_statuses_are_retried_as_the_status_they_map_to() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/busy").respond(MockResponse::text(430, "slow down"));
+        let client = HttpClient::new()
+            .with_clock(Arc::new(crate::clock::MockClock::new()))
+            .on_status(|status| if status == 430 { 429 } else { status });
+
+        let config = RequestConfig::new().with_retry(crate::models::RetryPolicy::new(2));
+        let response = client.get(&server.url("/busy"), config).await?;
+        assert_eq!((response.status, response.original_status), (429, 430));
+        assert!(!response.is_success());
+        assert_eq!(server.received(HttpMethod::Get, "/busy"), 3);
+        let outcomes: Vec<_> = response.attempts.iter().map(|attempt| attempt.outcome).collect();
+        assert_eq!(outcomes, vec![AttemptOutcome::Status(429); 3]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn debug_errors_are_pretty_and_plain_under_no_color() -> Result<()> {
+        // Colors are decided once per process, so check in a child that
+        // starts with `NO_COLOR` set.
+        if std::env::var_os("NO_COLOR").is_none() {
+            let status = std::process::Command::new(std::env::current_exe()?)
+                .args(["--exact", "client::tests::debug_errors_are_pretty_and_plain_under_no_color"])
+                .env("NO_COLOR", "1")
+                .status()?;
+            assert!(status.success());
+            return Ok(());
+        }
+
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Post, "/users").respond(MockResponse::json(
+            422,
+            serde_json::json!({"error": {"field": "email", "message": "is required"}}),
+        ));
+        let error = HttpClient::new()
+            .post(&server.url("/users"), "{}", RequestConfig::new().debug(true))
+            .await
+            .unwrap_err()
+            .to_string();
+        let expected = format!(
+            "POST {} returned 422 Unprocessable Entity\n{{\n  \"error\": {{\n    \"field\": \"email\",\n    \"message\": \"is required\"\n  }}\n}}",
+            server.url("/users")
+        );
+        assert_eq!(error, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signer_sees_the_canonical_json_that_is_sent() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Post, "/signed").respond(MockResponse::new(204));
+        let signed = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&signed);
+        let client = HttpClient::new().with_signer(move |request: &mut crate::signing::SignableRequest<'_>| {
+            *seen.lock().unwrap() = request.payload().to_vec();
+            Ok(())
+        });
+
+        let value = serde_json::json!({"b": [1.50, 1e21, "\u{20ac}"], "a": {"z": null, "y": true}});
+        client.send_body(HttpMethod::Post, server.url("/signed"), Body::CanonicalJson(value.clone()), RequestConfig::new()).await?;
+        let sent = server.requests().pop().context("no request recorded")?.body;
+        assert_eq!(sent.as_ref(), signed.lock().unwrap().as_slice());
+        assert_eq!(sent.as_ref(), crate::utils::canonicalize_json(&value).as_bytes());
+        assert_eq!(sent.as_ref(), r#"{"a":{"y":true,"z":null},"b":[1.5,1e+21,"€"]}"#.as_bytes());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_gets_share_one_request() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/slow")
+            .respond(MockResponse::text(200, "shared").with_delay(Duration::from_millis(200)));
+        let client = HttpClient::new().with_coalescing(true);
+
+        let url = server.url("/slow");
+        let responses = futures_util::future::join_all((0..8).map(|_| client.get(url.as_str(), RequestConfig::new()))).await;
+        assert_eq!(server.received(HttpMethod::Get, "/slow"), 1);
+        for response in responses {
+            assert_eq!(response?.body, "shared");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rewritten_redirects_keep_the_credential_rules() -> Result<()> {
+        let (origin, other) = (MockServer::start().await?, MockServer::start().await?);
+        for server in [&origin, &other] {
+            server.when(HttpMethod::Get, "/moved").respond(MockResponse::text(200, "rewritten"));
+        }
+        origin.when(HttpMethod::Get, "/start").respond(MockResponse::new(302).with_header("Location", "/landing"));
+        origin.when(HttpMethod::Post, "/submit").respond(MockResponse::new(303).with_header("Location", "/landing"));
+        let targets = [origin.url("/moved"), other.url("/moved")];
+        let same_host = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let choice = Arc::clone(&same_host);
+        let client = HttpClient::new().on_redirect(move |attempt: &RedirectAttempt<'_>| {
+            let target = &targets[usize::from(!choice.load(Ordering::SeqCst))];
+            assert_eq!(attempt.to.path(), "/landing");
+            RedirectAction::Rewrite(url::Url::parse(target).unwrap())
+        })?;
+
+        let response = client.get(&origin.url("/start"), credentials()).await?;
+        assert_eq!(response.body, "rewritten");
+        assert!(response.request.as_ref().context("no request info")?.final_url.ends_with("/moved"));
+        let moved = origin.requests_to(HttpMethod::Get, "/moved").pop().context("no request after the rewrite")?;
+        assert_eq!(moved.header("authorization"), Some("Bearer secret-token"));
+        assert_eq!(origin.received(HttpMethod::Get, "/landing"), 0);
+
+        same_host.store(false, Ordering::SeqCst);
+        let response = client.post(&origin.url("/submit"), r#"{"a":1}"#, credentials()).await?;
+        assert_eq!(response.body, "rewritten");
+        let moved = other.requests_to(HttpMethod::Get, "/moved").pop().context("no request after the rewrite")?;
+        for name in ["authorization", "cookie", "proxy-authorization"] {
+            assert_eq!(moved.header(name), None, "{} followed the rewrite", name);
+        }
+        assert!(moved.body.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_info_urls_are_redacted() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/start")
+            .respond(MockResponse::new(302).with_header("Location", server.url("/landing?token=xyz789&page=2")));
+        server.when(HttpMethod::Get, "/landing").respond(MockResponse::text(200, "landed"));
+
+        let client = HttpClient::new().with_url_redactor(UrlRedactor::default().add_param("tenant"));
+        let response = client.get(&server.url("/start?api_key=abc123&tenant=acme"), RequestConfig::new()).await?;
+        let info = response.request.as_ref().context("no request info")?;
+        assert!(info.url.contains("api_key=***") && info.url.contains("tenant=***"), "{}", info.url);
+        assert!(info.final_url.contains("token=***") && info.final_url.contains("page=2"), "{}", info.final_url);
+        assert!(info.was_redirected());
+
+        let serialized = serde_json::to_string(&response)?;
+        for secret in ["abc123", "acme", "xyz789"] {
+            assert!(!serialized.contains(secret), "{} leaked", secret);
+        }
+        assert_eq!(server.requests_to(HttpMethod::Get, "/landing")[0].query.as_deref(), Some("token=xyz789&page=2"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn every_set_cookie_is_copied_into_one_cookie_header() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/login").respond(
+            MockResponse::text(200, "ok")
+                .with_header("Set-Cookie", "session=abc; Path=/; Expires=Wed, 21 Oct 2037 07:28:00 GMT")
+                .with_header("Set-Cookie", "csrf=xyz; Secure")
+                .with_header("X-Trace-Id", "t-1"),
+        );
+        let client = HttpClient::new();
+        let response = client.get(&server.url("/login"), RequestConfig::new()).await?;
+        assert_eq!(response.headers.get("set-cookie").map(|cookies| cookies.lines().count()), Some(2));
+
+        let config = RequestConfig::new().copy_headers_from(&response, &["set-cookie", "x-trace-*"]);
+        assert_eq!(config.headers.get("Cookie").map(String::as_str), Some("session=abc; csrf=xyz"));
+        assert_eq!(config.headers.get("x-trace-id").map(String::as_str), Some("t-1"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_slow_poll_is_cut_off_at_the_timeout() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/job").respond_sequence(vec![
+            MockResponse::json(200, serde_json::json!({"state": "running"})),
+            MockResponse::json(200, serde_json::json!({"state": "done"})).with_delay(Duration::from_secs(5)),
+        ]);
+        server.when(HttpMethod::Get, "/stuck").respond(MockResponse::text(200, "").with_delay(Duration::from_secs(5)));
+        let client = HttpClient::new();
+        let done = |response: &ApiResponse| response.body.contains("done");
+
+        let started = Instant::now();
+        let error = client
+            .poll_until(&server.url("/job"), RequestConfig::new(), done, Duration::from_millis(50), Duration::from_millis(400))
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let timeout = error.downcast_ref::<PollTimeout>().context("not a PollTimeout")?;
+        assert_eq!(timeout.attempts, 2);
+        assert!(timeout.last.body.contains("running"));
+
+        let error = client
+            .poll_until(&server.url("/stuck"), RequestConfig::new(), done, Duration::from_millis(50), Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RequestError>(),
+            Some(RequestError::Timeout { kind: TimeoutKind::Total, .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn polling_succeeds_on_the_third_call_on_a_mock_clock() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/job").respond_sequence(vec![
+            MockResponse::json(200, serde_json::json!({"state": "pending"})),
+            MockResponse::json(200, serde_json::json!({"state": "pending"})),
+            MockResponse::json(200, serde_json::json!({"state": "ready"})),
+        ]);
+        let clock = Arc::new(crate::clock::MockClock::new());
+        let client = HttpClient::new().with_clock(clock.clone());
+
+        let started = Instant::now();
+        let response = client
+            .poll_until(
+                &server.url("/job"),
+                RequestConfig::new(),
+                |response| response.body.contains("ready"),
+                Duration::from_secs(10),
+                Duration::from_secs(60),
+            )
+            .await?;
+
+        assert!(response.body.contains("ready"));
+        assert_eq!(server.received(HttpMethod::Get, "/job"), 3);
+        assert_eq!(clock.elapsed(), Duration::from_secs(20));
+        assert!(started.elapsed() < Duration::from_secs(5), "the interval was slept for real");
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+pub type SleepFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Source of time for the client: response timings, retry backoff, cache
+/// TTLs, target cooldowns and attempt timestamps. Swap in a `MockClock` to
+/// test time-dependent behavior without sleeping.
+pub trait Clock: Send + Sync {
+    /// Monotonic time, used for durations and expiry.
+    fn now(&self) -> Instant;
+    /// Wall-clock time, used for recorded timestamps.
+    fn system_now(&self) -> SystemTime;
+    fn sleep(&self, duration: Duration) -> SleepFuture;
+    /// Resolves once `now()` reaches `deadline`, to cut work off. Unlike
+    /// `sleep` it never moves the clock itself.
+    fn sleep_until(&self, deadline: Instant) -> SleepFuture {
+        self.sleep(deadline.saturating_duration_since(self.now()))
+    }
+}
+
+impl fmt::Debug for dyn Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Clock").field("system_now", &self.system_now()).finish()
+    }
+}
+
+/// The real clock, backed by `Instant`, `SystemTime` and tokio's timer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> SleepFuture {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> SleepFuture {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+/// A clock that only moves when told to. `sleep` advances it by the
+/// requested duration and returns immediately; `sleep_until` waits for
+/// something else to advance it past the deadline.
+#[derive(Debug)]
+pub struct MockClock {
+    base_instant: Instant,
+    base_system: SystemTime,
+    offset: Arc<Mutex<Duration>>,
+}
+
+/// How often `MockClock::sleep_until` checks whether it has been advanced.
+const MOCK_DEADLINE_CHECK: Duration = Duration::from_millis(5);
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+
+    /// Starts the wall clock at `start`, e.g. a fixed date for stable output.
+    pub fn starting_at(start: SystemTime) -> Self {
+        Self {
+            base_instant: Instant::now(),
+            base_system: start,
+            offset: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        if let Ok(mut offset) = self.offset.lock() {
+            *offset += duration;
+        }
+    }
+
+    /// Total time the clock has been advanced.
+    pub fn elapsed(&self) -> Duration {
+        self.offset.lock().map(|offset| *offset).unwrap_or_default()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.base_instant + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.base_system + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) -> SleepFuture {
+        self.advance(duration);
+        Box::pin(std::future::ready(()))
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> SleepFuture {
+        let (base, offset) = (self.base_instant, Arc::clone(&self.offset));
+        Box::pin(async move {
+            while offset.lock().map_or(true, |offset| base + *offset < deadline) {
+                tokio::time::sleep(MOCK_DEADLINE_CHECK).await;
+            }
+        })
+    }
+}
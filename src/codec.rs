@@ -0,0 +1,202 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+
+/// Serialization format of a typed request or response body. MessagePack
+/// and CBOR need the `msgpack` and `cbor` features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// `application/json`
+    Json,
+    /// `application/msgpack`
+    #[cfg(feature = "msgpack")]
+    MsgPack,
+    /// `application/cbor`
+    #[cfg(feature = "cbor")]
+    Cbor,
+    /// Picked from the response `Content-Type` when decoding; encodes as JSON.
+    Auto,
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::Json => write!(f, "JSON"),
+            #[cfg(feature = "msgpack")]
+            Codec::MsgPack => write!(f, "MessagePack"),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => write!(f, "CBOR"),
+            Codec::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl Codec {
+    /// The `Content-Type` bodies encoded with this codec are sent as.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Codec::Json | Codec::Auto => "application/json",
+            #[cfg(feature = "msgpack")]
+            Codec::MsgPack => "application/msgpack",
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => "application/cbor",
+        }
+    }
+
+    /// The codec for a `Content-Type` such as `application/vnd.api+json` or
+    /// `application/x-msgpack`; `None` when none of the enabled codecs fits.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        let subtype = mime.strip_prefix("application/")?;
+        if subtype == "json" || subtype.ends_with("+json") {
+            return Some(Codec::Json);
+        }
+        #[cfg(feature = "msgpack")]
+        if matches!(subtype, "msgpack" | "x-msgpack" | "vnd.msgpack") || subtype.ends_with("+msgpack") {
+            return Some(Codec::MsgPack);
+        }
+        #[cfg(feature = "cbor")]
+        if subtype == "cbor" || subtype.ends_with("+cbor") {
+            return Some(Codec::Cbor);
+        }
+        None
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Codec::Json | Codec::Auto => serde_json::to_vec(value).with_context(|| "Failed to serialize JSON body"),
+            #[cfg(feature = "msgpack")]
+            Codec::MsgPack => rmp_serde::to_vec_named(value).with_context(|| "Failed to serialize MessagePack body"),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes).with_context(|| "Failed to serialize CBOR body")?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Decodes `bytes` received with `content_type`, which picks the codec
+    /// for `Codec::Auto` and is named in the error when decoding fails.
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8], content_type: &str) -> Result<T> {
+        let codec = match self {
+            Codec::Auto => Self::from_content_type(content_type).with_context(|| {
+                format!(
+                    "Cannot pick a codec for Content-Type '{}'; pass one explicitly",
+                    content_type
+                )
+            })?,
+            codec => *codec,
+        };
+        let decoded = match codec {
+            Codec::Json | Codec::Auto => serde_json::from_slice(bytes).map_err(anyhow::Error::new),
+            #[cfg(feature = "msgpack")]
+            Codec::MsgPack => rmp_serde::from_slice(bytes).map_err(anyhow::Error::new),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => ciborium::from_reader(bytes).map_err(anyhow::Error::new),
+        };
+        decoded.with_context(|| match Self::from_content_type(content_type) {
+            Some(declared) if declared != codec => format!(
+                "Failed to decode body as {}: the response is {} ({}); use Codec::{:?} or Codec::Auto",
+                codec, declared, content_type, declared
+            ),
+            _ => format!("Failed to decode body as {} (Content-Type '{}')", codec, content_type),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u64,
+        item: String,
+        quantities: Vec<i32>,
+        gift: Option<bool>,
+    }
+
+    fn order() -> Order {
+        Order {
+            id: 42,
+            item: "Grüße".to_string(),
+            quantities: vec![1, -2, 300],
+            gift: None,
+        }
+    }
+
+    async fn echo_server() -> Result<MockServer> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Post, "/echo").respond_with(|request| {
+            MockResponse::new(200)
+                .with_header("Content-Type", request.header("content-type").unwrap_or_default())
+                .with_body(request.body.clone())
+        });
+        Ok(server)
+    }
+
+    async fn round_trip(codec: Codec) -> Result<()> {
+        let server = echo_server().await?;
+        let client = HttpClient::new();
+
+        let response = client.post_encoded(&server.url("/echo"), &order(), codec, RequestConfig::new()).await?;
+        assert_eq!(response.content_type, codec.content_type());
+        assert_eq!(response.parse_as::<Order>(codec)?, order());
+        assert_eq!(response.parse_as::<Order>(Codec::Auto)?, order());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn json_round_trips() -> Result<()> {
+        round_trip(Codec::Json).await
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[tokio::test]
+    async fn msgpack_round_trips() -> Result<()> {
+        round_trip(Codec::MsgPack).await
+    }
+
+    #[cfg(feature = "cbor")]
+    #[tokio::test]
+    async fn cbor_round_trips() -> Result<()> {
+        round_trip(Codec::Cbor).await
+    }
+
+    #[test]
+    fn content_types_pick_the_codec() {
+        assert_eq!(Codec::from_content_type("application/json; charset=utf-8"), Some(Codec::Json));
+        assert_eq!(Codec::from_content_type("Application/Problem+JSON"), Some(Codec::Json));
+        assert_eq!(Codec::from_content_type("text/plain"), None);
+        #[cfg(feature = "msgpack")]
+        assert_eq!(Codec::from_content_type("application/x-msgpack"), Some(Codec::MsgPack));
+        #[cfg(feature = "cbor")]
+        assert_eq!(Codec::from_content_type("application/cbor"), Some(Codec::Cbor));
+    }
+
+    #[test]
+    fn decoding_with_the_wrong_codec_names_the_right_one() {
+        let error = Codec::Json.decode::<Order>(b"\x84\xa2id*", "application/x-msgpack").unwrap_err();
+        let expected = match cfg!(feature = "msgpack") {
+            true => "Failed to decode body as JSON: the response is MessagePack (application/x-msgpack); use Codec::MsgPack or Codec::Auto",
+            false => "Failed to decode body as JSON (Content-Type 'application/x-msgpack')",
+        };
+        assert_eq!(error.to_string(), expected);
+
+        let error = Codec::Auto.decode::<Order>(b"id=42", "text/plain").unwrap_err();
+        assert_eq!(error.to_string(), "Cannot pick a codec for Content-Type 'text/plain'; pass one explicitly");
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_bodies_read_as_json_point_at_cbor() {
+        let bytes = Codec::Cbor.encode(&order()).unwrap();
+        let error = Codec::Json.decode::<Order>(&bytes, "application/cbor").unwrap_err();
+        assert!(error.to_string().contains("use Codec::Cbor or Codec::Auto"), "{error}");
+    }
+}
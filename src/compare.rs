@@ -0,0 +1,346 @@
+use colored::*;
+use std::collections::BTreeMap;
+
+use crate::headers::header_glob_match;
+use crate::models::ApiResponse;
+
+/// Headers that differ on every response and are ignored by default.
+pub const DEFAULT_IGNORED_HEADERS: &[&str] = &[
+    "date",
+    "age",
+    "*request-id",
+    "x-amzn-requestid",
+    "*correlation-id",
+    "x-trace-id",
+    "traceparent",
+    "tracestate",
+];
+
+/// Headers whose differences are flagged as security-relevant.
+pub const SECURITY_HEADERS: &[&str] = &[
+    "strict-transport-security",
+    "content-security-policy",
+    "content-security-policy-report-only",
+    "x-frame-options",
+    "x-content-type-options",
+    "referrer-policy",
+    "permissions-policy",
+    "cross-origin-opener-policy",
+    "cross-origin-embedder-policy",
+    "cross-origin-resource-policy",
+    "access-control-allow-origin",
+    "access-control-allow-credentials",
+];
+
+/// Widest value shown in a rendered column before it is cut off.
+const MAX_COLUMN_WIDTH: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderChange {
+    /// Only the right response has the header.
+    MissingLeft,
+    /// Only the left response has the header.
+    MissingRight,
+    Changed,
+    /// Same comma-separated items in a different order.
+    OrderOnly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderDiff {
+    /// Lowercase header name.
+    pub name: String,
+    pub left: Option<String>,
+    pub right: Option<String>,
+    pub change: HeaderChange,
+    pub security: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompareOptions {
+    /// Header names or globs (`x-amz-*`) left out of the diff.
+    pub ignore: Vec<String>,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        Self {
+            ignore: DEFAULT_IGNORED_HEADERS.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+impl CompareOptions {
+    /// Ignores nothing.
+    pub fn none() -> Self {
+        Self { ignore: Vec::new() }
+    }
+
+    pub fn with_ignored(mut self, pattern: impl Into<String>) -> Self {
+        self.ignore.push(pattern.into());
+        self
+    }
+
+    fn is_ignored(&self, name: &str) -> bool {
+        self.ignore.iter().any(|pattern| header_glob_match(pattern, name))
+    }
+}
+
+/// Differences between two responses, e.g. the same request against staging
+/// and production.
+#[derive(Debug, Clone)]
+pub struct CompareReport {
+    pub left_status: u16,
+    pub right_status: u16,
+    pub body_equal: bool,
+    /// Sorted by header name.
+    pub header_diff: Vec<HeaderDiff>,
+}
+
+/// Spilled bodies are read from their files. Equal bytes under the same
+/// `Content-Type` decode to the same text.
+fn bodies_equal(left: &ApiResponse, right: &ApiResponse) -> bool {
+    match (left.bytes(), right.bytes()) {
+        (Ok(l), Ok(r)) if l == r => {
+            left.content_type == right.content_type || matches!((left.text(), right.text()), (Ok(l), Ok(r)) if l == r)
+        }
+        _ => false,
+    }
+}
+
+impl CompareReport {
+    pub fn new(left: &ApiResponse, right: &ApiResponse) -> Self {
+        Self::with_options(left, right, &CompareOptions::default())
+    }
+
+    pub fn with_options(left: &ApiResponse, right: &ApiResponse, options: &CompareOptions) -> Self {
+        Self {
+            left_status: left.status,
+            right_status: right.status,
+            body_equal: bodies_equal(left, right),
+            header_diff: diff_headers(left, right, options),
+        }
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.left_status == self.right_status && self.body_equal && self.header_diff.is_empty()
+    }
+
+    pub fn security_changes(&self) -> impl Iterator<Item = &HeaderDiff> {
+        self.header_diff.iter().filter(|diff| diff.security)
+    }
+
+    /// The header diff as aligned, colored columns: removed headers in red,
+    /// added in green, changed in yellow and reordered dimmed. Security
+    /// headers are marked with `!`.
+    pub fn render_headers(&self) -> String {
+        if self.header_diff.is_empty() {
+            return "Headers are identical\n".to_string();
+        }
+
+        let name_width = self.header_diff.iter().map(|diff| diff.name.len()).max().unwrap_or(0).max(6);
+        let left_width = self
+            .header_diff
+            .iter()
+            .map(|diff| column(diff.left.as_deref()).chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+
+        let mut out = format!("  {:name_width$}  {:left_width$}  {}\n", "Header", "Left", "Right");
+        for diff in &self.header_diff {
+            let marker = if diff.security { "!" } else { " " };
+            let left = format!("{:left_width$}", column(diff.left.as_deref()));
+            let right = column(diff.right.as_deref());
+            let line = format!("{} {:name_width$}  {}  {}", marker, diff.name, left, right);
+            let line = match diff.change {
+                HeaderChange::MissingLeft => line.green(),
+                HeaderChange::MissingRight => line.red(),
+                HeaderChange::Changed => line.yellow(),
+                HeaderChange::OrderOnly => line.dimmed(),
+            };
+            let line = if diff.security { line.bold() } else { line };
+            out.push_str(&line.to_string());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Case-insensitive header comparison of two responses.
+pub fn diff_headers(left: &ApiResponse, right: &ApiResponse, options: &CompareOptions) -> Vec<HeaderDiff> {
+    let lowercase = |response: &ApiResponse| -> BTreeMap<String, String> {
+        response
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.clone()))
+            .collect()
+    };
+    let left = lowercase(left);
+    let right = lowercase(right);
+
+    let mut names: Vec<&String> = left.keys().chain(right.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter(|name| !options.is_ignored(name))
+        .filter_map(|name| {
+            let (left_value, right_value) = (left.get(name), right.get(name));
+            let change = match (left_value, right_value) {
+                (None, Some(_)) => HeaderChange::MissingLeft,
+                (Some(_), None) => HeaderChange::MissingRight,
+                (Some(a), Some(b)) if a == b => return None,
+                (Some(a), Some(b)) if same_items(a, b) => HeaderChange::OrderOnly,
+                _ => HeaderChange::Changed,
+            };
+            Some(HeaderDiff {
+                name: name.clone(),
+                left: left_value.cloned(),
+                right: right_value.cloned(),
+                change,
+                security: SECURITY_HEADERS.contains(&name.as_str()),
+            })
+        })
+        .collect()
+}
+
+fn same_items(a: &str, b: &str) -> bool {
+    let items = |value: &str| {
+        let mut items: Vec<String> = value.split(',').map(|item| item.trim().to_string()).collect();
+        items.sort();
+        items
+    };
+    items(a) == items(b)
+}
+
+fn column(value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return "-".to_string();
+    };
+    if value.chars().count() <= MAX_COLUMN_WIDTH {
+        return value.to_string();
+    }
+    let cut: String = value.chars().take(MAX_COLUMN_WIDTH - 1).collect();
+    format!("{}…", cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, body: &str, headers: &[(&str, &str)]) -> ApiResponse {
+        let mut response = ApiResponse::synthetic(status, body);
+        for (name, value) in headers {
+            response.headers.insert(name.to_string(), value.to_string());
+        }
+        response
+    }
+
+    /// `text` without ANSI color sequences.
+    fn plain(text: &str) -> String {
+        let mut out = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\u{1b}' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn headers_are_classified_case_insensitively() {
+        let left = response(200, "{}", &[
+            ("Cache-Control", "no-cache"),
+            ("Vary", "Accept, Origin"),
+            ("X-Old", "1"),
+            ("ETag", "\"a\""),
+            ("Content-Type", "application/json"),
+        ]);
+        let right = response(200, "{}", &[
+            ("cache-control", "no-cache"),
+            ("vary", "Origin,Accept"),
+            ("x-new", "2"),
+            ("etag", "\"b\""),
+            ("CONTENT-TYPE", "application/json"),
+        ]);
+
+        let report = CompareReport::new(&left, &right);
+        let changes: Vec<(&str, HeaderChange)> =
+            report.header_diff.iter().map(|diff| (diff.name.as_str(), diff.change)).collect();
+        assert_eq!(
+            changes,
+            [
+                ("etag", HeaderChange::Changed),
+                ("vary", HeaderChange::OrderOnly),
+                ("x-new", HeaderChange::MissingLeft),
+                ("x-old", HeaderChange::MissingRight),
+            ]
+        );
+        assert!(report.body_equal && !report.is_identical());
+        assert_eq!(report.security_changes().count(), 0);
+    }
+
+    #[test]
+    fn volatile_headers_are_ignored_unless_asked_for() {
+        let left = response(200, "ok", &[("Date", "Mon, 01 Jan 2024 00:00:00 GMT"), ("X-Request-Id", "a"), ("X-Cost", "1")]);
+        let right = response(200, "ok", &[("Date", "Tue, 02 Jan 2024 00:00:00 GMT"), ("X-Request-Id", "b"), ("X-Cost", "2")]);
+
+        assert!(CompareReport::with_options(&left, &right, &CompareOptions::default().with_ignored("x-*")).is_identical());
+        let names: Vec<String> = CompareReport::new(&left, &right).header_diff.into_iter().map(|diff| diff.name).collect();
+        assert_eq!(names, ["x-cost"]);
+        let names: Vec<String> = CompareReport::with_options(&left, &right, &CompareOptions::none())
+            .header_diff
+            .into_iter()
+            .map(|diff| diff.name)
+            .collect();
+        assert_eq!(names, ["date", "x-cost", "x-request-id"]);
+    }
+
+    #[test]
+    fn security_headers_are_flagged_and_marked() {
+        let left = response(200, "a", &[
+            ("Strict-Transport-Security", "max-age=31536000"),
+            ("Content-Security-Policy", "default-src 'self'"),
+            ("X-Frame-Options", "DENY"),
+            ("Server", "nginx"),
+        ]);
+        let right = response(404, "b", &[
+            ("Content-Security-Policy", "default-src *"),
+            ("X-Frame-Options", "DENY"),
+            ("Server", "envoy"),
+        ]);
+
+        let report = CompareReport::new(&left, &right);
+        let flagged: Vec<&str> = report.security_changes().map(|diff| diff.name.as_str()).collect();
+        assert_eq!(flagged, ["content-security-policy", "strict-transport-security"]);
+        assert!(!report.body_equal && (report.left_status, report.right_status) == (200, 404));
+
+        let rendered = plain(&report.render_headers());
+        assert_eq!(
+            rendered,
+            concat!(
+                "  Header                     Left                Right\n",
+                "! content-security-policy    default-src 'self'  default-src *\n",
+                "  server                     nginx               envoy\n",
+                "! strict-transport-security  max-age=31536000    -\n",
+            )
+        );
+    }
+
+    #[test]
+    fn long_values_are_cut_to_the_column_width() {
+        let long = "a".repeat(100);
+        let left = response(200, "", &[("X-Long", long.as_str())]);
+        let right = response(200, "", &[]);
+
+        let rendered = plain(&CompareReport::new(&left, &right).render_headers());
+        let expected = format!("{}…", "a".repeat(MAX_COLUMN_WIDTH - 1));
+        assert!(rendered.contains(&format!("  x-long  {expected}  -\n")), "{rendered}");
+        assert_eq!(CompareReport::new(&right, &right).render_headers(), "Headers are identical\n");
+    }
+}
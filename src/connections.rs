@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::ConnectionLimitTimeout;
+use crate::metrics::MetricsRecorder;
+use crate::utils::host_key;
+
+/// Requests running and waiting for a slot on one host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionUsage {
+    pub active: usize,
+    pub queued: usize,
+}
+
+#[derive(Debug)]
+struct HostSlots {
+    semaphore: Arc<Semaphore>,
+    queued: AtomicUsize,
+}
+
+/// Caps the requests a client has open to each host at once; the rest wait
+/// in line for a slot.
+#[derive(Debug)]
+pub(crate) struct ConnectionLimiter {
+    max_per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<HostSlots>>>,
+}
+
+impl ConnectionLimiter {
+    pub(crate) fn new(max_per_host: usize) -> Self {
+        Self {
+            max_per_host: max_per_host.max(1),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn max_per_host(&self) -> usize {
+        self.max_per_host
+    }
+
+    pub(crate) fn usage(&self, host: &str) -> ConnectionUsage {
+        self.hosts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&host_key(host))
+            .map(|slots| self.usage_of(slots))
+            .unwrap_or_default()
+    }
+
+    fn usage_of(&self, slots: &HostSlots) -> ConnectionUsage {
+        ConnectionUsage {
+            active: self.max_per_host - slots.semaphore.available_permits(),
+            queued: slots.queued.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Waits for a free slot on `host`, failing with `ConnectionLimitTimeout`
+    /// once `queue_timeout` runs out. The slot is held until the returned
+    /// permit is dropped.
+    pub(crate) async fn acquire(
+        self: &Arc<Self>,
+        host: &str,
+        queue_timeout: Option<Duration>,
+        metrics: Option<&Arc<MetricsRecorder>>,
+    ) -> Result<ConnectionPermit, ConnectionLimitTimeout> {
+        let host = host.to_ascii_lowercase();
+        let slots = Arc::clone(
+            self.hosts
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(host.clone())
+                .or_insert_with(|| {
+                    Arc::new(HostSlots {
+                        semaphore: Arc::new(Semaphore::new(self.max_per_host)),
+                        queued: AtomicUsize::new(0),
+                    })
+                }),
+        );
+        let mut permit = ConnectionPermit {
+            limiter: Arc::clone(self),
+            host,
+            slots: Arc::clone(&slots),
+            metrics: metrics.cloned(),
+            permit: None,
+        };
+
+        if let Ok(acquired) = Arc::clone(&slots.semaphore).try_acquire_owned() {
+            permit.permit = Some(acquired);
+            permit.report();
+            return Ok(permit);
+        }
+
+        // Leaves the queue even when the request is cancelled while waiting.
+        struct Queued<'a>(&'a AtomicUsize);
+        impl Drop for Queued<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+
+        slots.queued.fetch_add(1, Ordering::Relaxed);
+        let queued = Queued(&slots.queued);
+        permit.report();
+        let started = Instant::now();
+        let waiting = Arc::clone(&slots.semaphore).acquire_owned();
+        let acquired = match queue_timeout {
+            Some(limit) => tokio::time::timeout(limit, waiting).await.ok(),
+            None => Some(waiting.await),
+        };
+        drop(queued);
+
+        match acquired {
+            // The semaphore is never closed.
+            Some(acquired) => {
+                permit.permit = acquired.ok();
+                permit.report();
+                Ok(permit)
+            }
+            None => {
+                permit.report();
+                Err(ConnectionLimitTimeout {
+                    host: permit.host.clone(),
+                    limit: self.max_per_host,
+                    waited_ms: started.elapsed().as_millis() as u64,
+                })
+            }
+        }
+    }
+}
+
+/// A request's slot on its host, released on drop.
+#[derive(Debug)]
+pub(crate) struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    host: String,
+    slots: Arc<HostSlots>,
+    metrics: Option<Arc<MetricsRecorder>>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ConnectionPermit {
+    fn report(&self) {
+        if let Some(metrics) = &self.metrics {
+            let usage = self.limiter.usage_of(&self.slots);
+            metrics.record_connections(&self.host, usage.active as u64, usage.queued as u64);
+        }
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if self.permit.take().is_some() {
+            self.report();
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use reqwest::header::HeaderValue;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+use crate::utils::host_key;
+
+tokio::task_local! {
+    /// URL of the request that started the current redirect chain, so each
+    /// hop's cookies can be judged first- or third-party.
+    pub(crate) static FIRST_PARTY: Url;
+}
+
+/// Second-level labels that sit under a two-letter country code, as in
+/// `example.co.uk`. Without a public suffix list this is an approximation.
+const COUNTRY_SECOND_LEVEL: &[&str] = &["co", "com", "net", "org", "ac", "gov", "edu", "ne", "or"];
+
+/// Which cookies `HttpClient::with_cookie_policy` stores. The default stores
+/// every valid cookie.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookiePolicy {
+    /// Drop cookies set by a site other than the one first requested, e.g.
+    /// by a redirect hop to another domain.
+    pub block_third_party: bool,
+    /// When not empty, only cookies for these domains (and their
+    /// subdomains) are stored.
+    pub allowed_domains: Vec<String>,
+    /// Cookies for these domains (and their subdomains) are never stored.
+    pub denied_domains: Vec<String>,
+    /// Keep cookies for the client's lifetime only, ignoring `Expires` and
+    /// `Max-Age` (except to delete a cookie).
+    pub session_only: bool,
+}
+
+impl CookiePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block_third_party(mut self, block: bool) -> Self {
+        self.block_third_party = block;
+        self
+    }
+
+    pub fn allow_domain(mut self, domain: impl Into<String>) -> Self {
+        self.allowed_domains.push(normalize_domain(&domain.into()));
+        self
+    }
+
+    pub fn deny_domain(mut self, domain: impl Into<String>) -> Self {
+        self.denied_domains.push(normalize_domain(&domain.into()));
+        self
+    }
+
+    pub fn session_only(mut self, session_only: bool) -> Self {
+        self.session_only = session_only;
+        self
+    }
+
+    fn permits(&self, domain: &str, third_party: bool) -> bool {
+        let listed = |list: &[String]| list.iter().any(|entry| domain_matches(domain, entry));
+        if self.block_third_party && third_party || listed(&self.denied_domains) {
+            return false;
+        }
+        self.allowed_domains.is_empty() || listed(&self.allowed_domains)
+    }
+}
+
+/// A cookie in the client's jar, see `HttpClient::cookie_report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredCookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot.
+    pub domain: String,
+    /// Sent only to `domain` itself, not its subdomains (no `Domain`
+    /// attribute was given).
+    pub host_only: bool,
+    pub path: String,
+    pub secure: bool,
+    pub http_only: bool,
+    /// `None` for session cookies.
+    pub expires: Option<SystemTime>,
+    /// The response that set the cookie, which is a redirect hop when it
+    /// came from one.
+    pub set_by: String,
+    /// The request that started the chain `set_by` belongs to.
+    pub request_url: String,
+    pub third_party: bool,
+}
+
+impl StoredCookie {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let domain_ok = match self.host_only {
+            true => host == self.domain,
+            false => domain_matches(&host, &self.domain),
+        };
+        domain_ok && path_matches(url.path(), &self.path) && (!self.secure || url.scheme() == "https")
+    }
+}
+
+/// The client's cookie jar, applying `CookiePolicy` as responses arrive.
+#[derive(Debug)]
+pub(crate) struct CookieJar {
+    policy: CookiePolicy,
+    cookies: Mutex<Vec<StoredCookie>>,
+}
+
+impl CookieJar {
+    pub(crate) fn new(policy: CookiePolicy) -> Self {
+        Self {
+            policy,
+            cookies: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Unexpired cookies, oldest first.
+    pub(crate) fn report(&self) -> Vec<StoredCookie> {
+        let now = SystemTime::now();
+        let mut cookies = self.cookies.lock().unwrap_or_else(|e| e.into_inner());
+        cookies.retain(|cookie| !cookie.is_expired(now));
+        cookies.clone()
+    }
+
+    /// Removes cookies for `domain` and its subdomains, returning how many.
+    pub(crate) fn remove_domain(&self, domain: &str) -> usize {
+        let domain = normalize_domain(domain);
+        let mut cookies = self.cookies.lock().unwrap_or_else(|e| e.into_inner());
+        let before = cookies.len();
+        cookies.retain(|cookie| !domain_matches(&cookie.domain, &domain));
+        before - cookies.len()
+    }
+
+    fn store(&self, header: &str, url: &Url, first_party: &Url) {
+        let now = SystemTime::now();
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return;
+        };
+        let Some(parsed) = SetCookie::parse(header, now) else {
+            return;
+        };
+
+        let (domain, host_only) = match parsed.domain {
+            // Hosts may only set cookies for themselves and their parents,
+            // and IP addresses only for themselves.
+            Some(domain) if domain_matches(&host, &domain) && (host == domain || host.parse::<IpAddr>().is_err()) => {
+                (domain, false)
+            }
+            Some(_) => return,
+            None => (host.clone(), true),
+        };
+        let first_host = first_party.host_str().unwrap_or_default().to_ascii_lowercase();
+        let third_party = site(&host) != site(&first_host);
+        if !self.policy.permits(&domain, third_party) {
+            return;
+        }
+
+        let cookie = StoredCookie {
+            name: parsed.name,
+            value: parsed.value,
+            domain,
+            host_only,
+            path: parsed.path.unwrap_or_else(|| default_path(url)),
+            secure: parsed.secure,
+            http_only: parsed.http_only,
+            expires: parsed.expires.filter(|_| !self.policy.session_only),
+            set_by: url.to_string(),
+            request_url: first_party.to_string(),
+            third_party,
+        };
+        let deleted = parsed.expires.is_some_and(|expires| expires <= now);
+
+        let mut cookies = self.cookies.lock().unwrap_or_else(|e| e.into_inner());
+        cookies.retain(|existing| {
+            !(existing.name == cookie.name && existing.domain == cookie.domain && existing.path == cookie.path)
+        });
+        if !deleted {
+            cookies.push(cookie);
+        }
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let first_party = FIRST_PARTY.try_with(Url::clone).unwrap_or_else(|_| url.clone());
+        for header in cookie_headers {
+            if let Ok(header) = header.to_str() {
+                self.store(header, url, &first_party);
+            }
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let now = SystemTime::now();
+        let cookies = self.cookies.lock().unwrap_or_else(|e| e.into_inner());
+        let mut matching: Vec<&StoredCookie> = cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now) && cookie.matches(url))
+            .collect();
+        // Longer paths first, as RFC 6265 recommends.
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        let header = matching
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        (!header.is_empty()).then(|| HeaderValue::from_str(&header).ok()).flatten()
+    }
+}
+
+/// The parts of a `Set-Cookie` header the jar uses.
+struct SetCookie {
+    name: String,
+    value: String,
+    domain: Option<String>,
+    path: Option<String>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+}
+
+impl SetCookie {
+    fn parse(header: &str, now: SystemTime) -> Option<Self> {
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = SetCookie {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: None,
+            path: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+        };
+        let mut max_age = None;
+        for attribute in parts {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => cookie.domain = Some(normalize_domain(value)),
+                "path" if value.starts_with('/') => cookie.path = Some(value.to_string()),
+                "expires" => {
+                    if let Ok(expires) = crate::utils::parse_http_date(value) {
+                        cookie.expires = Some(expires);
+                    }
+                }
+                "max-age" => {
+                    if let Ok(seconds) = value.parse::<i64>() {
+                        max_age = Some(match u64::try_from(seconds) {
+                            Ok(seconds) if seconds > 0 => now + Duration::from_secs(seconds),
+                            _ => SystemTime::UNIX_EPOCH,
+                        });
+                    }
+                }
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires.
+        cookie.expires = max_age.or(cookie.expires);
+        Some(cookie)
+    }
+}
+
+fn normalize_domain(domain: &str) -> String {
+    host_key(domain.trim().trim_start_matches('.'))
+}
+
+/// `host` is `domain` or one of its subdomains.
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// RFC 6265 path matching: `/docs` covers `/docs`, `/docs/` and `/docs/x`
+/// but not `/docsearch`.
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/'))
+}
+
+/// The directory of the request path, the default `Path` of a cookie.
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => url.path()[..end].to_string(),
+    }
+}
+
+/// The registrable part of `host`: the last two labels, or three under
+/// country codes such as `co.uk`. IP addresses are their own site.
+fn site(host: &str) -> &str {
+    if host.parse::<IpAddr>().is_ok() {
+        return host;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    let keep = match labels.as_slice() {
+        [.., second, tld] if labels.len() >= 3 && tld.len() == 2 && COUNTRY_SECOND_LEVEL.contains(second) => 3,
+        _ => 2,
+    };
+    let skip: usize = labels.iter().take(labels.len().saturating_sub(keep)).map(|label| label.len() + 1).sum();
+    &host[skip..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+    use anyhow::Result;
+
+    /// `www.shop.test/login` sets `session` and redirects through a tracker
+    /// on another site, which sets `track`, back to `/home`, which sets
+    /// `pref` for the whole `shop.test` domain.
+    struct Sites {
+        shop: MockServer,
+        tracker: MockServer,
+    }
+
+    impl Sites {
+        async fn start() -> Result<Self> {
+            let (shop, tracker) = (MockServer::start().await?, MockServer::start().await?);
+            let shop_url = format!("http://www.shop.test:{}", shop.addr().port());
+            let tracker_url = format!("http://ads.tracker.test:{}", tracker.addr().port());
+            shop.when(HttpMethod::Get, "/login").respond(
+                MockResponse::new(302)
+                    .with_header("Set-Cookie", "session=1; Path=/; HttpOnly")
+                    .with_header("Location", format!("{}/pixel", tracker_url)),
+            );
+            tracker.when(HttpMethod::Get, "/pixel").respond(
+                MockResponse::new(302)
+                    .with_header("Set-Cookie", "track=abc; Max-Age=3600")
+                    .with_header("Location", format!("{}/home", shop_url)),
+            );
+            shop.when(HttpMethod::Get, "/home").respond(
+                MockResponse::text(200, "home").with_header("Set-Cookie", "pref=dark; Domain=shop.test; Max-Age=3600"),
+            );
+            Ok(Self { shop, tracker })
+        }
+
+        fn client(&self, policy: CookiePolicy) -> Result<HttpClient> {
+            Ok(HttpClient::new()
+                .with_cookie_policy(policy)?
+                .with_resolve("www.shop.test", &[self.shop.addr()])
+                .with_resolve("ads.tracker.test", &[self.tracker.addr()]))
+        }
+
+        fn login_url(&self) -> String {
+            format!("http://www.shop.test:{}/login", self.shop.addr().port())
+        }
+
+        /// Names of the cookies `policy` keeps after logging in.
+        async fn stored(&self, policy: CookiePolicy) -> Result<Vec<String>> {
+            let client = self.client(policy)?;
+            client.get(&self.login_url(), RequestConfig::new()).await?;
+            Ok(client.cookie_report().into_iter().map(|cookie| cookie.name).collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn each_hop_of_a_redirect_is_judged_against_the_first_site() -> Result<()> {
+        let sites = Sites::start().await?;
+        let client = sites.client(CookiePolicy::new())?;
+
+        let response = client.get(&sites.login_url(), RequestConfig::new()).await?;
+        assert_eq!(response.status, 200);
+        let report = client.cookie_report();
+        let summary: Vec<(&str, &str, bool, bool)> = report
+            .iter()
+            .map(|cookie| (cookie.name.as_str(), cookie.domain.as_str(), cookie.host_only, cookie.third_party))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("session", "www.shop.test", true, false),
+                ("track", "ads.tracker.test", true, true),
+                ("pref", "shop.test", false, false),
+            ]
+        );
+        let track = &report[1];
+        assert_eq!(track.set_by, format!("http://ads.tracker.test:{}/pixel", sites.tracker.addr().port()));
+        assert_eq!(track.request_url, sites.login_url());
+        assert!(track.expires.is_some() && report[0].expires.is_none() && report[0].http_only);
+
+        // The session cookie set on the first hop went back on the last.
+        let home = sites.shop.requests_to(HttpMethod::Get, "/home");
+        assert_eq!(home[0].header("cookie"), Some("session=1"));
+        assert!(sites.tracker.requests()[0].header("cookie").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn policies_decide_which_cookies_are_stored() -> Result<()> {
+        let sites = Sites::start().await?;
+
+        assert_eq!(sites.stored(CookiePolicy::new()).await?, ["session", "track", "pref"]);
+        assert_eq!(sites.stored(CookiePolicy::new().block_third_party(true)).await?, ["session", "pref"]);
+        assert_eq!(sites.stored(CookiePolicy::new().allow_domain("shop.test")).await?, ["session", "pref"]);
+        assert_eq!(sites.stored(CookiePolicy::new().allow_domain(".tracker.test")).await?, ["track"]);
+        assert_eq!(sites.stored(CookiePolicy::new().deny_domain("tracker.test")).await?, ["session", "pref"]);
+        assert_eq!(sites.stored(CookiePolicy::new().deny_domain("www.shop.test")).await?, ["track", "pref"]);
+        assert_eq!(
+            sites.stored(CookiePolicy::new().allow_domain("shop.test").deny_domain("www.shop.test")).await?,
+            ["pref"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn session_only_drops_expirations_and_domains_can_be_cleared() -> Result<()> {
+        let sites = Sites::start().await?;
+        let client = sites.client(CookiePolicy::new().session_only(true))?;
+
+        client.get(&sites.login_url(), RequestConfig::new()).await?;
+        assert_eq!(client.cookie_report().len(), 3);
+        assert!(client.cookie_report().iter().all(|cookie| cookie.expires.is_none()));
+
+        assert_eq!(client.remove_cookies("tracker.test"), 1);
+        assert_eq!(client.remove_cookies("shop.test"), 2);
+        assert!(client.cookie_report().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn hosts_only_set_cookies_for_themselves_and_their_parents() {
+        let jar = CookieJar::new(CookiePolicy::new());
+        let url = Url::parse("https://api.shop.co.uk/v1/items").unwrap();
+        for header in [
+            "a=1; Domain=shop.co.uk",
+            "b=2; Domain=other.co.uk",
+            "c=3; Domain=deep.api.shop.co.uk",
+            "d=4",
+            "e=5; Max-Age=0",
+            "=6",
+        ] {
+            jar.store(header, &url, &url);
+        }
+
+        let stored: Vec<(String, String, String)> = jar
+            .report()
+            .into_iter()
+            .map(|cookie| (cookie.name, cookie.domain, cookie.path))
+            .collect();
+        assert_eq!(
+            stored,
+            [
+                ("a".to_string(), "shop.co.uk".to_string(), "/v1".to_string()),
+                ("d".to_string(), "api.shop.co.uk".to_string(), "/v1".to_string()),
+            ]
+        );
+        assert_eq!(site("api.shop.co.uk"), "shop.co.uk");
+        assert_eq!(site("ads.tracker.test"), "tracker.test");
+        assert_eq!(site("127.0.0.1"), "127.0.0.1");
+        assert!(path_matches("/docs/x", "/docs") && !path_matches("/docsearch", "/docs"));
+    }
+}
@@ -0,0 +1,554 @@
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::{DnsError, HostNotFound};
+use crate::utils::host_key;
+
+/// How long one lookup may take before it fails as a timeout, well under
+/// the request timeout so a retry still has time left.
+pub const DEFAULT_DNS_TIMEOUT_SECS: u64 = 5;
+
+pub type LookupFuture = Pin<Box<dyn Future<Output = io::Result<DnsAnswer>> + Send>>;
+
+/// Addresses for a host, with the record TTL when the lookup knows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsAnswer {
+    pub addrs: Vec<SocketAddr>,
+    pub ttl: Option<Duration>,
+}
+
+/// Source of DNS answers behind the cache. Swap it out to stub resolution.
+pub trait DnsLookup: Send + Sync {
+    fn lookup(&self, host: &str) -> LookupFuture;
+}
+
+/// The operating system resolver. It does not report TTLs, so its answers
+/// are cached for `DnsCacheConfig::default_ttl`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemLookup;
+
+impl DnsLookup for SystemLookup {
+    fn lookup(&self, host: &str) -> LookupFuture {
+        let host = host.to_string();
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            Ok(DnsAnswer { addrs, ttl: None })
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsCacheConfig {
+    /// TTL used when the lookup does not report one.
+    pub default_ttl: Duration,
+    pub min_ttl: Option<Duration>,
+    pub max_ttl: Option<Duration>,
+    /// How long definitive failures (NXDOMAIN, no addresses) are
+    /// remembered. Transient failures such as SERVFAIL or timeouts are not
+    /// cached, so a retry asks the resolver again.
+    pub negative_ttl: Duration,
+}
+
+impl Default for DnsCacheConfig {
+    fn default() -> Self {
+        Self {
+            default_ttl: Duration::from_secs(60),
+            min_ttl: None,
+            max_ttl: None,
+            negative_ttl: Duration::from_secs(5),
+        }
+    }
+}
+
+impl DnsCacheConfig {
+    pub fn with_default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = ttl;
+        self
+    }
+
+    pub fn with_min_ttl(mut self, ttl: Duration) -> Self {
+        self.min_ttl = Some(ttl);
+        self
+    }
+
+    pub fn with_max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = Some(ttl);
+        self
+    }
+
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+        self
+    }
+
+    fn effective_ttl(&self, ttl: Option<Duration>) -> Duration {
+        let mut ttl = ttl.unwrap_or(self.default_ttl);
+        if let Some(min) = self.min_ttl {
+            ttl = ttl.max(min);
+        }
+        if let Some(max) = self.max_ttl {
+            ttl = ttl.min(max);
+        }
+        ttl
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DnsStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub negative_hits: u64,
+    pub override_hits: u64,
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone)]
+enum CacheEntry {
+    Found { addrs: Vec<SocketAddr>, expires: Instant },
+    Missing { expires: Instant },
+}
+
+/// Shared resolver state: overrides, the optional cache and its counters.
+pub(crate) struct DnsState {
+    lookup: Mutex<Arc<dyn DnsLookup>>,
+    clock: Mutex<Arc<dyn Clock>>,
+    cache_config: Mutex<Option<DnsCacheConfig>>,
+    overrides: Mutex<HashMap<String, Vec<SocketAddr>>>,
+    fallbacks: Mutex<HashMap<String, String>>,
+    /// The name each address of a host came from, so a connection's peer
+    /// address tells which name served it even while lookups of the same
+    /// host race.
+    served_by: Mutex<HashMap<(String, IpAddr), String>>,
+    /// When each host was last resolved and how long that took.
+    lookup_times: Mutex<HashMap<String, (Instant, Duration)>>,
+    timeout: Mutex<Duration>,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    negative_hits: AtomicU64,
+    override_hits: AtomicU64,
+}
+
+impl Default for DnsState {
+    fn default() -> Self {
+        Self {
+            lookup: Mutex::new(Arc::new(SystemLookup)),
+            clock: Mutex::new(Arc::new(SystemClock)),
+            cache_config: Mutex::new(None),
+            overrides: Mutex::new(HashMap::new()),
+            fallbacks: Mutex::new(HashMap::new()),
+            served_by: Mutex::new(HashMap::new()),
+            lookup_times: Mutex::new(HashMap::new()),
+            timeout: Mutex::new(Duration::from_secs(DEFAULT_DNS_TIMEOUT_SECS)),
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            negative_hits: AtomicU64::new(0),
+            override_hits: AtomicU64::new(0),
+        }
+    }
+}
+
+impl DnsState {
+    pub(crate) fn set_lookup(&self, lookup: Arc<dyn DnsLookup>) {
+        if let Ok(mut current) = self.lookup.lock() {
+            *current = lookup;
+        }
+        self.flush();
+    }
+
+    pub(crate) fn set_clock(&self, clock: Arc<dyn Clock>) {
+        if let Ok(mut current) = self.clock.lock() {
+            *current = clock;
+        }
+        self.flush();
+    }
+
+    pub(crate) fn set_cache_config(&self, config: Option<DnsCacheConfig>) {
+        if let Ok(mut current) = self.cache_config.lock() {
+            *current = config;
+        }
+        self.flush();
+    }
+
+    pub(crate) fn add_override(&self, host: &str, addrs: Vec<SocketAddr>) {
+        if let Ok(mut overrides) = self.overrides.lock() {
+            overrides.insert(host_key(host), addrs);
+        }
+    }
+
+    pub(crate) fn add_fallback(&self, host: &str, fallback: &str) {
+        if let Ok(mut fallbacks) = self.fallbacks.lock() {
+            fallbacks.insert(host_key(host), host_key(fallback));
+        }
+    }
+
+    pub(crate) fn set_timeout(&self, timeout: Duration) {
+        if let Ok(mut current) = self.timeout.lock() {
+            *current = timeout;
+        }
+    }
+
+    /// The name that resolved `host` to `peer`, the address a response
+    /// came from: `host` itself, or its fallback.
+    pub(crate) fn served_by(&self, host: &str, peer: Option<SocketAddr>) -> Option<String> {
+        let key = (host_key(host), peer?.ip());
+        self.served_by.lock().ok()?.get(&key).cloned()
+    }
+
+    /// How long resolving `host` took, if it was last resolved at or after
+    /// `since`. Requests on a pooled connection resolve nothing.
+    pub(crate) fn lookup_time(&self, host: &str, since: Instant) -> Option<Duration> {
+        let lookup_times = self.lookup_times.lock().ok()?;
+        let (finished, took) = lookup_times.get(&host_key(host))?;
+        (*finished >= since).then_some(*took)
+    }
+
+    pub(crate) fn flush(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    pub(crate) fn stats(&self) -> DnsStats {
+        DnsStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            negative_hits: self.negative_hits.load(Ordering::Relaxed),
+            override_hits: self.override_hits.load(Ordering::Relaxed),
+            entries: self.entries.lock().map(|e| e.len()).unwrap_or(0),
+        }
+    }
+
+    /// Resolves `host`, trying its fallback name when that fails.
+    pub(crate) async fn resolve_host(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        let host = host.to_ascii_lowercase();
+        let started = self.now();
+        let (addrs, name) = match self.resolve_name(&host).await {
+            Ok(addrs) => (addrs, host.clone()),
+            Err(e) => {
+                let fallback = self.fallbacks.lock().ok().and_then(|f| f.get(&host).cloned());
+                let Some(fallback) = fallback else {
+                    return Err(e);
+                };
+                // Report the original failure if the fallback fails too.
+                let addrs = self.resolve_name(&fallback).await.map_err(|_| e)?;
+                (addrs, fallback)
+            }
+        };
+
+        let finished = self.now();
+        if let Ok(mut lookup_times) = self.lookup_times.lock() {
+            lookup_times.insert(host.clone(), (finished, finished.saturating_duration_since(started)));
+        }
+        if let Ok(mut served_by) = self.served_by.lock() {
+            for addr in &addrs {
+                served_by.insert((host.clone(), addr.ip()), name.clone());
+            }
+        }
+        Ok(addrs)
+    }
+
+    async fn resolve_name(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        let host = host.to_string();
+
+        // Explicit overrides always win over cached answers.
+        if let Some(addrs) = self.overrides.lock().ok().and_then(|o| o.get(&host).cloned()) {
+            self.override_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(addrs);
+        }
+
+        let config = self.cache_config.lock().ok().and_then(|c| c.clone());
+        let Some(config) = config else {
+            return self.lookup_uncached(&host).await.map(|answer| answer.addrs);
+        };
+
+        let now = self.now();
+        let cached = self.entries.lock().ok().and_then(|e| e.get(&host).cloned());
+        match cached {
+            Some(CacheEntry::Found { addrs, expires }) if expires > now => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(addrs);
+            }
+            Some(CacheEntry::Missing { expires }) if expires > now => {
+                self.negative_hits.fetch_add(1, Ordering::Relaxed);
+                return Err(not_found(&host));
+            }
+            _ => {}
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let (entry, result) = match self.lookup_uncached(&host).await {
+            Ok(answer) if !answer.addrs.is_empty() => {
+                let expires = now + config.effective_ttl(answer.ttl);
+                let entry = CacheEntry::Found { addrs: answer.addrs.clone(), expires };
+                (entry, Ok(answer.addrs))
+            }
+            Ok(_) => {
+                let entry = CacheEntry::Missing { expires: now + config.negative_ttl };
+                (entry, Err(not_found(&host)))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                (CacheEntry::Missing { expires: now + config.negative_ttl }, Err(e))
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(host, entry);
+        }
+        result
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.lock().map(|clock| clock.now()).unwrap_or_else(|_| Instant::now())
+    }
+
+    async fn lookup_uncached(&self, host: &str) -> io::Result<DnsAnswer> {
+        let lookup = self
+            .lookup
+            .lock()
+            .map(|l| Arc::clone(&l))
+            .map_err(|_| io::Error::other("DNS lookup lock poisoned"))?;
+        let timeout = self
+            .timeout
+            .lock()
+            .map(|t| *t)
+            .unwrap_or(Duration::from_secs(DEFAULT_DNS_TIMEOUT_SECS));
+        tokio::time::timeout(timeout, lookup.lookup(host))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("DNS lookup for {} timed out", host)))?
+            .map_err(|e| match is_name_not_found(&e) {
+                true => not_found(host),
+                false => e,
+            })
+    }
+}
+
+fn not_found(host: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, HostNotFound { host: host.to_string() })
+}
+
+/// getaddrinfo's `EAI_NONAME` and `EAI_NODATA`, which std only reports as
+/// `gai_strerror` text (glibc, musl, macOS) or a Winsock code.
+fn is_name_not_found(error: &io::Error) -> bool {
+    const MESSAGES: [&str; 5] = [
+        "Name or service not known",
+        "No address associated with hostname",
+        "Name does not resolve",
+        "nodename nor servname provided, or not known",
+        "No such host is known",
+    ];
+    const WSAHOST_NOT_FOUND: i32 = 11001;
+    const WSANO_DATA: i32 = 11004;
+    if error.kind() == io::ErrorKind::NotFound
+        || (cfg!(windows) && matches!(error.raw_os_error(), Some(WSAHOST_NOT_FOUND | WSANO_DATA)))
+    {
+        return true;
+    }
+    let message = error.to_string();
+    MESSAGES.iter().any(|known| message.contains(known))
+}
+
+/// Adapter that plugs `DnsState` into reqwest.
+pub(crate) struct CachingResolver {
+    state: Arc<DnsState>,
+}
+
+impl CachingResolver {
+    pub(crate) fn new(state: Arc<DnsState>) -> Self {
+        Self { state }
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let state = Arc::clone(&self.state);
+        Box::pin(async move {
+            let addrs = state.resolve_host(name.as_str()).await.map_err(|e| DnsError {
+                host: name.as_str().to_string(),
+                timed_out: e.kind() == io::ErrorKind::TimedOut,
+                not_found: e.kind() == io::ErrorKind::NotFound,
+                message: e.to_string(),
+            })?;
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+
+    /// Answers from a fixed table, failing other names with `error`, and
+    /// counts lookups.
+    struct StubLookup {
+        answers: HashMap<String, Vec<SocketAddr>>,
+        error: fn() -> io::Error,
+        calls: Arc<AtomicU64>,
+    }
+
+    impl StubLookup {
+        fn new(answers: &[(&str, SocketAddr)], error: fn() -> io::Error) -> (Self, Arc<AtomicU64>) {
+            let calls = Arc::new(AtomicU64::new(0));
+            let mut table: HashMap<String, Vec<SocketAddr>> = HashMap::new();
+            for (host, addr) in answers {
+                table.entry(host.to_string()).or_default().push(*addr);
+            }
+            (Self { answers: table, error, calls: Arc::clone(&calls) }, calls)
+        }
+    }
+
+    impl DnsLookup for StubLookup {
+        fn lookup(&self, host: &str) -> LookupFuture {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            let answer = self.answers.get(host).cloned();
+            let error = (self.error)();
+            Box::pin(async move {
+                match answer {
+                    Some(addrs) => Ok(DnsAnswer { addrs, ttl: None }),
+                    None => Err(error),
+                }
+            })
+        }
+    }
+
+    fn eai_noname() -> io::Error {
+        io::Error::other("failed to lookup address information: Name or service not known")
+    }
+
+    fn eai_again() -> io::Error {
+        io::Error::other("failed to lookup address information: Temporary failure in name resolution")
+    }
+
+    #[test]
+    fn getaddrinfo_name_errors_are_not_found() {
+        for message in [
+            "failed to lookup address information: Name or service not known",
+            "failed to lookup address information: No address associated with hostname",
+            "failed to lookup address information: Name does not resolve",
+            "failed to lookup address information: nodename nor servname provided, or not known",
+        ] {
+            assert!(is_name_not_found(&io::Error::other(message)), "{}", message);
+        }
+        assert!(!is_name_not_found(&eai_again()));
+        assert!(!is_name_not_found(&io::Error::new(io::ErrorKind::TimedOut, "timed out")));
+    }
+
+    #[tokio::test]
+    async fn eai_noname_is_cached_negatively_but_eai_again_is_not() {
+        let state = DnsState::default();
+        state.set_cache_config(Some(DnsCacheConfig::default()));
+        let (lookup, calls) = StubLookup::new(&[], eai_noname);
+        state.set_lookup(Arc::new(lookup));
+
+        for _ in 0..2 {
+            let error = state.resolve_host("missing.test").await.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::NotFound);
+            let inner = error.get_ref().and_then(|e| e.downcast_ref::<HostNotFound>());
+            assert_eq!(inner.map(|e| e.host.as_str()), Some("missing.test"));
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(state.stats().negative_hits, 1);
+
+        let (lookup, calls) = StubLookup::new(&[], eai_again);
+        state.set_lookup(Arc::new(lookup));
+        for _ in 0..2 {
+            assert_ne!(state.resolve_host("flaky.test").await.unwrap_err().kind(), io::ErrorKind::NotFound);
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn served_by_follows_the_address_not_the_latest_lookup() -> io::Result<()> {
+        let state = DnsState::default();
+        let fallback_addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let direct_addr = SocketAddr::from(([10, 0, 0, 1], 0));
+        let (lookup, _) = StubLookup::new(&[("fallback.test", fallback_addr)], eai_noname);
+        state.set_lookup(Arc::new(lookup));
+        state.add_fallback("api.test", "fallback.test");
+        assert_eq!(state.resolve_host("api.test").await?, [fallback_addr]);
+
+        // The primary name recovers while a connection from the fallback is
+        // still in use.
+        let (lookup, _) = StubLookup::new(&[("api.test", direct_addr)], eai_noname);
+        state.set_lookup(Arc::new(lookup));
+        assert_eq!(state.resolve_host("api.test").await?, [direct_addr]);
+
+        let peer = |addr: SocketAddr| Some(SocketAddr::new(addr.ip(), 443));
+        assert_eq!(state.served_by("api.test", peer(fallback_addr)).as_deref(), Some("fallback.test"));
+        assert_eq!(state.served_by("API.test", peer(direct_addr)).as_deref(), Some("api.test"));
+        assert_eq!(state.served_by("api.test", None), None);
+        Ok(())
+    }
+
+    /// Fails the first `failures` lookups like a flaky resolver, then
+    /// answers with `addr`.
+    struct FlakyLookup {
+        failures: u64,
+        addr: SocketAddr,
+        calls: AtomicU64,
+    }
+
+    impl DnsLookup for FlakyLookup {
+        fn lookup(&self, _host: &str) -> LookupFuture {
+            let call = self.calls.fetch_add(1, Ordering::Relaxed);
+            let answer = match call < self.failures {
+                true => Err(eai_again()),
+                false => Ok(DnsAnswer { addrs: vec![self.addr], ttl: None }),
+            };
+            Box::pin(async move { answer })
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_dns_failures_are_retried() -> anyhow::Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/").respond(MockResponse::new(204));
+        let lookup = FlakyLookup { failures: 2, addr: server.addr(), calls: AtomicU64::new(0) };
+        let client = HttpClient::new().with_dns_lookup(lookup);
+        let url = format!("http://flaky.test:{}/", server.addr().port());
+        let retry = crate::RetryPolicy::new(3).with_initial_backoff(Duration::from_millis(1));
+
+        let response = client.get(url.as_str(), RequestConfig::new().with_retry(retry)).await?;
+        assert_eq!(response.status, 204);
+        let outcomes: Vec<_> = response.attempts.iter().map(|attempt| attempt.outcome).collect();
+        assert_eq!(outcomes, [crate::AttemptOutcome::Dns, crate::AttemptOutcome::Dns, crate::AttemptOutcome::Status(204)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_fall_back_to_the_alternate_name() -> anyhow::Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/").respond(MockResponse::new(204));
+        let (lookup, _) = StubLookup::new(&[("fallback.test", server.addr())], eai_noname);
+        let client = HttpClient::new()
+            .with_dns_lookup(lookup)
+            .with_dns_fallback("api.test", "fallback.test");
+
+        let url = format!("http://api.test:{}/", server.addr().port());
+        let response = client.get(url.as_str(), RequestConfig::new()).await?;
+        let info = response.request.as_ref().map(|info| info.resolved_host.clone());
+        assert_eq!(info, Some(Some("fallback.test".to_string())));
+        let host = format!("api.test:{}", server.addr().port());
+        assert_eq!(server.requests()[0].header("host"), Some(host.as_str()));
+
+        let url = format!("http://nowhere.test:{}/", server.addr().port());
+        let error = client.get(url.as_str(), RequestConfig::new()).await.unwrap_err();
+        let dns = error.chain().find_map(|cause| cause.downcast_ref::<DnsError>()).cloned();
+        assert!(dns.is_some_and(|dns| dns.not_found && !dns.timed_out), "{:#}", error);
+        Ok(())
+    }
+}
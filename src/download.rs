@@ -0,0 +1,296 @@
+use anyhow::{Context, Result};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+use crate::models::ApiResponse;
+
+/// Name used when neither the server nor the URL suggest one.
+pub const DEFAULT_DOWNLOAD_NAME: &str = "download";
+
+/// Longest file name written, in bytes; most filesystems stop at 255.
+const MAX_FILENAME_BYTES: usize = 255;
+
+/// Where a downloaded file's name came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilenameSource {
+    /// Chosen by the caller.
+    Caller,
+    ContentDisposition,
+    /// The last path segment of the (final) URL.
+    UrlPath,
+    Default,
+}
+
+/// Outcome of `HttpClient::download_file` and `download_to_dir`.
+#[derive(Debug, Clone)]
+pub struct DownloadReport {
+    pub path: PathBuf,
+    pub filename_source: FilenameSource,
+    pub bytes: u64,
+    pub status: u16,
+    pub content_type: String,
+    pub elapsed_ms: u64,
+}
+
+impl DownloadReport {
+    pub(crate) fn new(path: PathBuf, filename_source: FilenameSource, response: &ApiResponse) -> Self {
+        Self {
+            path,
+            filename_source,
+            bytes: 0,
+            status: response.status,
+            content_type: response.content_type.clone(),
+            elapsed_ms: response.total_time_ms,
+        }
+    }
+}
+
+/// Picks a safe file name for `response`: the `Content-Disposition` name,
+/// else the last segment of the URL it came from, else `download`.
+pub fn filename_for(response: &ApiResponse, url: &str) -> (String, FilenameSource) {
+    if let Some(name) = response.suggested_filename().and_then(|name| sanitize_filename(&name)) {
+        return (name, FilenameSource::ContentDisposition);
+    }
+
+    let url = response.request.as_ref().map_or(url, |request| request.final_url.as_str());
+    let segment = url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.path_segments()?.rfind(|segment| !segment.is_empty()).map(str::to_string));
+    if let Some(name) = segment.and_then(|segment| sanitize_filename(&crate::utils::percent_decode(&segment))) {
+        return (name, FilenameSource::UrlPath);
+    }
+
+    (DEFAULT_DOWNLOAD_NAME.to_string(), FilenameSource::Default)
+}
+
+/// Reduces a server-supplied name to a plain file name: directories are
+/// stripped (so `../../etc/passwd` becomes `passwd`), characters the OS
+/// forbids are replaced with `_`, and leading dots are dropped so a download
+/// cannot become a hidden dotfile. Returns `None` if nothing usable is left.
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut clean: String = name
+        .chars()
+        .map(|c| if c.is_control() || is_forbidden_char(c) { '_' } else { c })
+        .collect();
+
+    clean = clean.trim().trim_start_matches('.').to_string();
+    if cfg!(windows) {
+        clean = clean.trim_end_matches(['.', ' ']).to_string();
+        let stem = clean.split('.').next().unwrap_or_default().to_ascii_uppercase();
+        if is_reserved_windows_name(&stem) {
+            clean.insert(0, '_');
+        }
+    }
+
+    if clean.len() > MAX_FILENAME_BYTES {
+        let (stem, extension) = split_extension(&clean);
+        clean = fit_name(stem, "", extension);
+    }
+    (!clean.is_empty()).then_some(clean)
+}
+
+fn is_forbidden_char(c: char) -> bool {
+    if cfg!(windows) {
+        matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*')
+    } else {
+        false
+    }
+}
+
+fn is_reserved_windows_name(stem: &str) -> bool {
+    matches!(stem, "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.as_bytes()[3].is_ascii_digit())
+}
+
+/// Splits off the extension, if the part after the last dot looks like one.
+fn split_extension(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && extension.len() < 16 => (stem, Some(extension)),
+        _ => (name, None),
+    }
+}
+
+/// `stem`, then `suffix` and the extension, shortening the stem so the
+/// whole name fits in `MAX_FILENAME_BYTES`.
+fn fit_name(stem: &str, suffix: &str, extension: Option<&str>) -> String {
+    let budget = MAX_FILENAME_BYTES - suffix.len() - extension.map_or(0, |extension| extension.len() + 1);
+    let mut end = budget.min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    match extension {
+        Some(extension) => format!("{}{}.{}", &stem[..end], suffix, extension),
+        None => format!("{}{}", &stem[..end], suffix),
+    }
+}
+
+/// `report.pdf`, `report (1).pdf`, `report (2).pdf`, ...
+fn numbered(name: &str, n: usize) -> String {
+    if n == 0 {
+        return name.to_string();
+    }
+    let (stem, extension) = split_extension(name);
+    fit_name(stem, &format!(" ({})", n), extension)
+}
+
+/// Streams `response`'s body to `name` inside `dir`. Without `overwrite`, an
+/// existing file is never replaced; a numeric suffix is added instead.
+/// Returns the path and the bytes written.
+pub(crate) async fn write_in_dir(dir: &Path, name: &str, response: ApiResponse, overwrite: bool) -> Result<(PathBuf, u64)> {
+    if overwrite {
+        let path = dir.join(name);
+        let bytes = write_file(&path, response).await?;
+        return Ok((path, bytes));
+    }
+
+    for n in 0.. {
+        let path = dir.join(numbered(name, n));
+        // create_new so two downloads racing for a name never share a file.
+        match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&path).await {
+            Ok(file) => {
+                let bytes = write_body(file, &path, response).await?;
+                return Ok((path, bytes));
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", path.display())),
+        }
+    }
+    unreachable!("ran out of numeric suffixes")
+}
+
+/// Streams `response`'s body to `path`, replacing any existing file.
+pub(crate) async fn write_file(path: &Path, response: ApiResponse) -> Result<u64> {
+    let file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    write_body(file, path, response).await
+}
+
+/// Copies the body into `file` as it arrives, so only one chunk is held in
+/// memory. A body that fails part way (idle timeout, size limit, dropped
+/// connection) leaves no partial file behind.
+async fn write_body(mut file: tokio::fs::File, path: &Path, response: ApiResponse) -> Result<u64> {
+    let mut body = response.into_async_reader();
+    let written = match tokio::io::copy(&mut body, &mut file).await {
+        Ok(bytes) => file.flush().await.map(|_| bytes),
+        Err(e) => Err(e),
+    };
+    drop(file);
+    if written.is_err() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    written.with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+    use std::time::Duration;
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("rusthttp-download-{}", crate::utils::generate_request_id()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn numbered_names_stay_within_the_limit() {
+        let long = format!("{}.pdf", "r".repeat(251));
+        assert_eq!(long.len(), MAX_FILENAME_BYTES);
+
+        let first = numbered(&long, 1);
+        assert_eq!(first.len(), MAX_FILENAME_BYTES);
+        assert!(first.ends_with("r (1).pdf"));
+        assert!(numbered(&long, 12345).ends_with("r (12345).pdf"));
+        assert!(numbered(&long, 12345).len() <= MAX_FILENAME_BYTES);
+
+        let multibyte = "é".repeat(127);
+        let numbered = numbered(&multibyte, 3);
+        assert!(numbered.len() <= MAX_FILENAME_BYTES);
+        assert!(numbered.ends_with("é (3)"));
+        assert_eq!(super::numbered("report.pdf", 2), "report (2).pdf");
+    }
+
+    #[tokio::test]
+    async fn long_names_that_collide_are_numbered_within_the_limit() -> Result<()> {
+        let server = MockServer::start().await?;
+        let name = format!("{}.csv", "x".repeat(300));
+        server.when(HttpMethod::Get, "/export").respond(
+            MockResponse::text(200, "a,b\n").with_header("Content-Disposition", format!("attachment; filename=\"{}\"", name)),
+        );
+        let dir = TempDir::new();
+        let client = HttpClient::new();
+        let url = server.url("/export");
+
+        let first = client.download_to_dir(&url, &dir.0, false, RequestConfig::new()).await?;
+        let second = client.download_to_dir(&url, &dir.0, false, RequestConfig::new()).await?;
+
+        let first_name = first.path.file_name().unwrap().to_string_lossy().into_owned();
+        let second_name = second.path.file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(first_name.len(), MAX_FILENAME_BYTES);
+        assert!(second_name.len() <= MAX_FILENAME_BYTES);
+        assert!(second_name.ends_with(" (1).csv"), "{}", second_name);
+        assert_eq!(std::fs::read(&second.path)?, b"a,b\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn downloads_are_written_as_the_body_arrives() -> Result<()> {
+        let server = MockServer::start().await?;
+        let chunks: Vec<Vec<u8>> = (0..4u8).map(|n| vec![b'a' + n; 1024]).collect();
+        server.when(HttpMethod::Get, "/big.bin").respond(
+            MockResponse::new(200).chunked(chunks.clone(), Duration::from_millis(150)),
+        );
+        let dir = TempDir::new();
+        let path = dir.0.join("big.bin");
+        let client = HttpClient::new();
+
+        let url = server.url("/big.bin");
+
+        // Part of the body is on disk while the rest is still in flight.
+        let (report, partial) = tokio::join!(client.download_file(&url, &path, RequestConfig::new()), async {
+            tokio::time::sleep(Duration::from_millis(400)).await;
+            std::fs::metadata(&path).map(|metadata| metadata.len())
+        });
+        let (report, partial) = (report?, partial?);
+        assert!(partial > 0 && partial < 4096, "{} bytes on disk mid-download", partial);
+        assert_eq!(report.bytes, 4096);
+        assert_eq!(std::fs::read(&path)?, chunks.concat());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_body_that_fails_part_way_leaves_no_file() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/big.bin").respond(
+            MockResponse::new(200).chunked(vec![vec![b'a'; 1024]; 4], Duration::from_millis(10)),
+        );
+        let dir = TempDir::new();
+        let path = dir.0.join("big.bin");
+
+        let result = HttpClient::new()
+            .download_file(&server.url("/big.bin"), &path, RequestConfig::new().with_max_body_size(2000))
+            .await;
+
+        assert!(result.is_err());
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,492 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::models::ApiResponse;
+use crate::tls::{TlsPolicy, TlsVersion};
+use crate::utils::{format_duration, format_size, host_to_unicode};
+use crate::warnings::RequestWarning;
+
+/// Whatever had arrived when a request was aborted.
+#[derive(Debug, Clone, Default)]
+pub struct PartialResponse {
+    pub status: Option<u16>,
+    pub headers: HashMap<String, String>,
+    pub bytes_received: usize,
+    pub elapsed_ms: u64,
+    pub partial_body: Option<String>,
+    /// Warnings raised for the request before it failed.
+    pub warnings: Vec<RequestWarning>,
+}
+
+impl PartialResponse {
+    pub fn headers_received(&self) -> bool {
+        self.status.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    /// The overall request deadline elapsed.
+    Total,
+    /// No body data arrived for longer than `RequestConfig::with_idle_timeout`.
+    Read,
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutKind::Total => write!(f, "total"),
+            TimeoutKind::Read => write!(f, "read"),
+        }
+    }
+}
+
+/// Request failures that carry structured data. They travel inside
+/// `anyhow::Error`; use `downcast_ref::<RequestError>()` to inspect them.
+#[derive(Debug, Clone)]
+pub enum RequestError {
+    Timeout {
+        kind: TimeoutKind,
+        partial: PartialResponse,
+    },
+    BodyTooLarge {
+        limit: usize,
+        partial: PartialResponse,
+    },
+    /// The body ended before the `Content-Length` the server announced.
+    TruncatedBody {
+        expected: u64,
+        received: u64,
+        partial: PartialResponse,
+    },
+    /// A conditional request (`If-Match` / `If-None-Match`) got `412`: the
+    /// resource changed, so re-fetch it and retry. `current_etag` is the
+    /// server's `ETag` when it sent one.
+    PreconditionFailed {
+        current_etag: Option<String>,
+        partial: PartialResponse,
+    },
+}
+
+impl RequestError {
+    pub fn partial(&self) -> &PartialResponse {
+        match self {
+            RequestError::Timeout { partial, .. } => partial,
+            RequestError::BodyTooLarge { partial, .. } => partial,
+            RequestError::TruncatedBody { partial, .. } => partial,
+            RequestError::PreconditionFailed { partial, .. } => partial,
+        }
+    }
+
+    pub(crate) fn partial_mut(&mut self) -> &mut PartialResponse {
+        match self {
+            RequestError::Timeout { partial, .. } => partial,
+            RequestError::BodyTooLarge { partial, .. } => partial,
+            RequestError::TruncatedBody { partial, .. } => partial,
+            RequestError::PreconditionFailed { partial, .. } => partial,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, RequestError::Timeout { .. })
+    }
+
+    pub fn is_precondition_failed(&self) -> bool {
+        matches!(self, RequestError::PreconditionFailed { .. })
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Timeout { kind, partial } => write!(
+                f,
+                "Request timed out ({} timeout) after {}",
+                kind,
+                format_duration(partial.elapsed_ms)
+            )?,
+            RequestError::BodyTooLarge { limit, partial } => write!(
+                f,
+                "Response body exceeded the {} limit after {}",
+                format_size(*limit),
+                format_duration(partial.elapsed_ms)
+            )?,
+            RequestError::TruncatedBody { expected, received, .. } => write!(
+                f,
+                "Response body truncated: Content-Length was {} bytes but {} arrived",
+                expected, received
+            )?,
+            RequestError::PreconditionFailed { current_etag, .. } => {
+                write!(f, "Precondition failed, the resource has changed")?;
+                if let Some(etag) = current_etag {
+                    write!(f, " (current ETag {})", etag)?;
+                }
+                return Ok(());
+            }
+        }
+
+        let partial = self.partial();
+        match partial.status {
+            Some(status) => write!(
+                f,
+                " (status {}, {} received)",
+                status,
+                format_size(partial.bytes_received)
+            ),
+            None => write!(f, " (no response headers received)"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// The `error` member of a JSON-RPC 2.0 response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl fmt::Display for JsonRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JSON-RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for JsonRpcError {}
+
+/// Credentials were refreshed after a `401` (or `403`), but the retried
+/// request was rejected as well. Both responses are kept.
+#[derive(Debug, Clone)]
+pub struct AuthRecoveryError {
+    pub first: Box<ApiResponse>,
+    pub retry: Box<ApiResponse>,
+}
+
+impl fmt::Display for AuthRecoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Request was rejected with {} and again with {} after refreshing credentials",
+            self.first.status, self.retry.status
+        )
+    }
+}
+
+impl std::error::Error for AuthRecoveryError {}
+
+/// Name resolution failed, e.g. NXDOMAIN, SERVFAIL or no answer within
+/// `HttpClient::with_dns_timeout`. Retried like connection failures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsError {
+    /// As looked up, in punycode; displayed in Unicode.
+    pub host: String,
+    pub timed_out: bool,
+    /// The name does not exist or has no addresses, see `HostNotFound`.
+    pub not_found: bool,
+    pub message: String,
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.timed_out {
+            write!(f, "DNS lookup for {} timed out", host_to_unicode(&self.host))
+        } else {
+            write!(f, "DNS lookup for {} failed: {}", host_to_unicode(&self.host), self.message)
+        }
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+/// The resolver answered that a host does not exist or has no addresses:
+/// NXDOMAIN, or getaddrinfo's `EAI_NONAME`/`EAI_NODATA`. Carried in an
+/// `io::Error` of kind `NotFound`, which the DNS cache remembers for
+/// `DnsCacheConfig::negative_ttl`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostNotFound {
+    pub host: String,
+}
+
+impl fmt::Display for HostNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No addresses found for {}", host_to_unicode(&self.host))
+    }
+}
+
+impl std::error::Error for HostNotFound {}
+
+/// Why `HttpClient::authorization_code_flow` failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthCodeError {
+    /// The redirect carried a `state` other than the one sent, so it may
+    /// not belong to this login.
+    StateMismatch,
+    /// The authorization server redirected back with an error, e.g.
+    /// `access_denied` when the user declined.
+    Denied { error: String, description: Option<String> },
+    /// The token endpoint rejected the code exchange or refresh.
+    ExchangeFailed { status: u16, body: String },
+    /// No redirect arrived in time.
+    Timeout { waited_ms: u64 },
+}
+
+impl fmt::Display for AuthCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthCodeError::StateMismatch => write!(f, "Authorization redirect has a mismatched state parameter"),
+            AuthCodeError::Denied { error, description } => {
+                write!(f, "Authorization was denied: {}", error)?;
+                if let Some(description) = description {
+                    write!(f, " ({})", description)?;
+                }
+                Ok(())
+            }
+            AuthCodeError::ExchangeFailed { status, body } => {
+                write!(f, "Token endpoint returned {}", status)?;
+                if !body.is_empty() {
+                    write!(f, ": {}", body)?;
+                }
+                Ok(())
+            }
+            AuthCodeError::Timeout { waited_ms } => {
+                write!(f, "No authorization redirect received after {}", format_duration(*waited_ms))
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthCodeError {}
+
+/// `HttpClient::poll_until` ran out of time before the condition held.
+#[derive(Debug, Clone)]
+pub struct PollTimeout {
+    pub attempts: u32,
+    pub elapsed_ms: u64,
+    /// The last response received, which did not satisfy the condition.
+    pub last: Box<ApiResponse>,
+}
+
+impl fmt::Display for PollTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Condition not met after {} polls in {}ms (last status {})",
+            self.attempts, self.elapsed_ms, self.last.status
+        )
+    }
+}
+
+impl std::error::Error for PollTimeout {}
+
+/// A request kept failing until its `RetryPolicy` ran out of attempts or
+/// time. Attached as context to the last attempt's error, which can still
+/// be downcast from the chain (e.g. a `RequestError` timeout).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetriesExhausted {
+    pub attempts: u32,
+    pub elapsed_ms: u64,
+    /// Status of the last attempt that got a response, if any did.
+    pub last_status: Option<u16>,
+    /// The last attempt's error.
+    pub last_error: String,
+}
+
+impl fmt::Display for RetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Gave up after {} attempts in {}", self.attempts, format_duration(self.elapsed_ms))?;
+        if let Some(status) = self.last_status {
+            write!(f, " (last status {})", status)?;
+        }
+        write!(f, ": {}", self.last_error)
+    }
+}
+
+impl std::error::Error for RetriesExhausted {}
+
+/// `HttpClient::follow` found no link with the requested relation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingLink {
+    pub rel: String,
+    /// Relations the response does have, sorted.
+    pub available: Vec<String>,
+}
+
+impl fmt::Display for MissingLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.available.is_empty() {
+            true => write!(f, "No '{}' link: the response has no links", self.rel),
+            false => write!(f, "No '{}' link (available: {})", self.rel, self.available.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for MissingLink {}
+
+/// A request would have taken the client past its transfer quota, see
+/// `HttpClient::with_transfer_quota`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub limit: u64,
+    /// Bytes counted before this request's transfer.
+    pub used: u64,
+    /// Bytes this transfer needed.
+    pub requested: u64,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Transfer quota of {} bytes exceeded: {} used, {} more needed",
+            self.limit, self.used, self.requested
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// No connection slot on `host` freed up within the queue timeout, see
+/// `HttpClient::with_max_connections_per_host`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionLimitTimeout {
+    pub host: String,
+    pub limit: usize,
+    pub waited_ms: u64,
+}
+
+impl fmt::Display for ConnectionLimitTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {} waiting for one of {} connections to {}",
+            format_duration(self.waited_ms),
+            self.limit,
+            self.host
+        )
+    }
+}
+
+impl std::error::Error for ConnectionLimitTimeout {}
+
+/// A request header that cannot be sent. The value is left out of the
+/// message because it may be a credential.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidHeader {
+    pub name: String,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid header '{}': {}", self.name.escape_debug(), self.reason)
+    }
+}
+
+impl std::error::Error for InvalidHeader {}
+
+/// A server could not complete a handshake within the client's TLS policy,
+/// see `HttpClient::with_tls_policy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsPolicyViolation {
+    pub policy: TlsPolicy,
+    pub host: String,
+    /// The newest version the server negotiates when TLS 1.2 and 1.3 are
+    /// both offered; `None` when it supports neither or could not be probed.
+    /// Probing needs the `tls-inspect` feature.
+    pub server_best: Option<TlsVersion>,
+}
+
+impl fmt::Display for TlsPolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} does not meet TLS policy {}: ", self.host, self.policy)?;
+        match self.server_best {
+            Some(version) => write!(f, "the server offers at best {}", version),
+            None => write!(f, "no TLS 1.2 or 1.3 handshake with the server completed"),
+        }
+    }
+}
+
+impl std::error::Error for TlsPolicyViolation {}
+
+/// The client has been shut down: new requests are refused, and requests
+/// still running when the grace period ran out are cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientClosed {
+    pub cancelled: bool,
+}
+
+impl fmt::Display for ClientClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.cancelled {
+            write!(f, "Request cancelled because the client shut down")
+        } else {
+            write!(f, "Client is shut down and no longer accepts requests")
+        }
+    }
+}
+
+impl std::error::Error for ClientClosed {}
+
+/// A request raised warnings whose codes `HttpClient::escalate_warnings`
+/// turns into errors. Request warnings stop it before it is sent; an
+/// `accept-mismatch` fails it once the response arrives.
+#[derive(Debug, Clone)]
+pub struct WarningEscalated {
+    /// The warnings that were escalated.
+    pub escalated: Vec<RequestWarning>,
+    /// Every warning the request raised, escalated or not.
+    pub warnings: Vec<RequestWarning>,
+}
+
+impl fmt::Display for WarningEscalated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request refused by the warning policy: ")?;
+        for (i, warning) in self.escalated.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WarningEscalated {}
+
+/// `HttpClient::download_and_extract` stopped on an entry that breaks its
+/// `ExtractOptions`. Entries extracted before it are left in place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractError {
+    /// An absolute path or one that climbs out of the destination with `..`.
+    PathTraversal { entry: String },
+    /// More than `max_bytes` would be written.
+    TooLarge { limit: u64 },
+    /// More than `max_files` entries.
+    TooManyFiles { limit: usize },
+    /// A link under `SymlinkPolicy::Reject`, or one pointing outside the
+    /// destination under `SymlinkPolicy::WithinDestination`.
+    LinkRejected { entry: String, target: String },
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractError::PathTraversal { entry } => {
+                write!(f, "Archive entry {:?} would be extracted outside the destination", entry)
+            }
+            ExtractError::TooLarge { limit } => {
+                write!(f, "Archive expands to more than the {} limit", format_size(*limit as usize))
+            }
+            ExtractError::TooManyFiles { limit } => write!(f, "Archive has more than {} entries", limit),
+            ExtractError::LinkRejected { entry, target } => {
+                write!(f, "Archive entry {:?} links to {:?}, which is not allowed", entry, target)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {}
@@ -0,0 +1,760 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::error::ExtractError;
+use crate::models::ApiResponse;
+
+/// Bytes `ExtractOptions` lets an archive expand to unless told otherwise.
+pub const DEFAULT_MAX_EXTRACTED_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Entries `ExtractOptions` allows unless told otherwise.
+pub const DEFAULT_MAX_EXTRACTED_FILES: usize = 10_000;
+
+/// Size of the chunks handed from the download to the extractor.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Longest symlink target read from a zip entry.
+const MAX_LINK_TARGET: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    /// gzip-compressed tar (`.tar.gz`, `.tgz`).
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// From the `Content-Type`, else the URL's extension.
+    fn from_response(response: &ApiResponse, url: &str) -> Option<Self> {
+        let essence = response.content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "application/gzip" | "application/x-gzip" | "application/x-tgz" | "application/x-compressed-tar" => {
+                return Some(ArchiveFormat::TarGz);
+            }
+            "application/x-tar" => return Some(ArchiveFormat::Tar),
+            _ if response.is_zip() => return Some(ArchiveFormat::Zip),
+            _ => {}
+        }
+
+        let url = response.request.as_ref().map_or(url, |request| request.final_url.as_str());
+        let path = url::Url::parse(url).map_or_else(|_| url.to_string(), |url| url.path().to_ascii_lowercase());
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if path.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+
+    /// From the first bytes of the body.
+    fn sniff(head: &[u8]) -> Option<Self> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if head.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// What to do with symbolic and hard links in an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Leave links out and list them in `ExtractReport::skipped`.
+    #[default]
+    Skip,
+    /// Fail with `ExtractError::LinkRejected`.
+    Reject,
+    /// Create links whose target stays inside the destination and reject
+    /// the rest. Symlinks are only created on Unix and skipped elsewhere.
+    WithinDestination,
+}
+
+/// Limits for `HttpClient::download_and_extract`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractOptions {
+    /// Bytes written across all files, counted as they are written rather
+    /// than trusted from the archive's headers.
+    pub max_bytes: u64,
+    /// Files, directories and links.
+    pub max_files: usize,
+    pub symlinks: SymlinkPolicy,
+    /// Detected from the `Content-Type`, URL and first bytes when `None`.
+    pub format: Option<ArchiveFormat>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_EXTRACTED_BYTES,
+            max_files: DEFAULT_MAX_EXTRACTED_FILES,
+            symlinks: SymlinkPolicy::Skip,
+            format: None,
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = bytes;
+        self
+    }
+
+    pub fn with_max_files(mut self, files: usize) -> Self {
+        self.max_files = files;
+        self
+    }
+
+    pub fn with_symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    pub fn with_format(mut self, format: ArchiveFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+}
+
+/// A regular file written by `download_and_extract`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedFile {
+    /// Relative to the destination.
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Outcome of `HttpClient::download_and_extract`.
+#[derive(Debug, Clone)]
+pub struct ExtractReport {
+    pub dest: PathBuf,
+    pub format: ArchiveFormat,
+    /// In archive order.
+    pub files: Vec<ExtractedFile>,
+    /// Links created under `SymlinkPolicy::WithinDestination`.
+    pub links: Vec<PathBuf>,
+    /// Links and special files (devices, FIFOs) left out.
+    pub skipped: Vec<PathBuf>,
+    /// Sum of `files` sizes.
+    pub total_bytes: u64,
+    /// Archive bytes received.
+    pub downloaded_bytes: u64,
+    pub status: u16,
+    pub elapsed_ms: u64,
+}
+
+/// What the extractor hands back; the download side fills in the rest.
+#[derive(Debug, Default)]
+struct Extracted {
+    files: Vec<ExtractedFile>,
+    links: Vec<PathBuf>,
+    skipped: Vec<PathBuf>,
+}
+
+/// Streams `response`'s deferred body into `dest`. Tar archives are
+/// extracted as they arrive; zip archives go through a temporary file
+/// first because their index sits at the end.
+pub(crate) async fn extract_response(
+    response: ApiResponse,
+    url: &str,
+    dest: &Path,
+    options: &ExtractOptions,
+) -> Result<ExtractReport> {
+    let status = response.status;
+    let known = options.format.or_else(|| ArchiveFormat::from_response(&response, url));
+    let mut body = response.into_async_reader();
+    let head = read_head(&mut body).await?;
+    let format = known
+        .or_else(|| ArchiveFormat::sniff(&head))
+        .context("Response is not a recognized archive (tar, tar.gz or zip)")?;
+
+    tokio::fs::create_dir_all(dest)
+        .await
+        .with_context(|| format!("Failed to create {}", dest.display()))?;
+    let dest = dest.to_path_buf();
+    let (extracted, downloaded_bytes) = match format {
+        ArchiveFormat::Tar | ArchiveFormat::TarGz => extract_tar_stream(body, head, format, &dest, options).await?,
+        ArchiveFormat::Zip => extract_zip_download(body, head, &dest, options).await?,
+    };
+
+    Ok(ExtractReport {
+        total_bytes: extracted.files.iter().map(|file| file.size).sum(),
+        files: extracted.files,
+        links: extracted.links,
+        skipped: extracted.skipped,
+        dest,
+        format,
+        downloaded_bytes,
+        status,
+        elapsed_ms: 0,
+    })
+}
+
+/// Enough of the body to sniff the format: a tar header is 512 bytes.
+async fn read_head(body: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(512);
+    (&mut *body)
+        .take(512)
+        .read_to_end(&mut head)
+        .await
+        .context("Failed to read archive")?;
+    Ok(head)
+}
+
+async fn extract_tar_stream(
+    mut body: impl AsyncRead + Unpin,
+    head: Vec<u8>,
+    format: ArchiveFormat,
+    dest: &Path,
+    options: &ExtractOptions,
+) -> Result<(Extracted, u64)> {
+    let (sender, receiver) = mpsc::channel::<io::Result<Bytes>>(8);
+    let extractor = {
+        let (dest, options) = (dest.to_path_buf(), options.clone());
+        tokio::task::spawn_blocking(move || {
+            let reader = ChannelReader::new(receiver);
+            let mut extractor = Extractor::new(&dest, &options);
+            match format {
+                ArchiveFormat::TarGz => extractor.tar(flate2::read::MultiGzDecoder::new(reader))?,
+                _ => extractor.tar(reader)?,
+            }
+            Ok::<_, anyhow::Error>(extractor.done)
+        })
+    };
+
+    let mut downloaded = head.len() as u64;
+    // The extractor hangs up when it is done or has failed; either way the
+    // rest of the body is not needed.
+    if sender.send(Ok(Bytes::from(head))).await.is_ok() {
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            match body.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(n) => {
+                    downloaded += n as u64;
+                    if sender.send(Ok(Bytes::copy_from_slice(&buffer[..n]))).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e)).await;
+                    break;
+                }
+            }
+        }
+    }
+    drop(sender);
+
+    let extracted = extractor.await.context("Archive extraction panicked")??;
+    Ok((extracted, downloaded))
+}
+
+async fn extract_zip_download(
+    mut body: impl AsyncRead + Unpin,
+    head: Vec<u8>,
+    dest: &Path,
+    options: &ExtractOptions,
+) -> Result<(Extracted, u64)> {
+    let temp = TempFile(std::env::temp_dir().join(format!("rusthttp-{}.zip", crate::utils::generate_request_id())));
+    let mut file = tokio::fs::File::create(&temp.0)
+        .await
+        .with_context(|| format!("Failed to create {}", temp.0.display()))?;
+    file.write_all(&head).await?;
+    // Compressed data never legitimately outgrows what it expands to.
+    let limit = options.max_bytes.saturating_sub(head.len() as u64);
+    let downloaded = head.len() as u64
+        + tokio::io::copy(&mut (&mut body).take(limit), &mut file)
+            .await
+            .context("Failed to download archive")?;
+    if body.read(&mut [0]).await? > 0 {
+        return Err(ExtractError::TooLarge { limit: options.max_bytes }.into());
+    }
+    file.flush().await?;
+    drop(file);
+
+    let (dest, options) = (dest.to_path_buf(), options.clone());
+    let extracted = tokio::task::spawn_blocking(move || {
+        let file = File::open(&temp.0).with_context(|| format!("Failed to open {}", temp.0.display()))?;
+        let mut extractor = Extractor::new(&dest, &options);
+        extractor.zip(file)?;
+        Ok::<_, anyhow::Error>(extractor.done)
+    })
+    .await
+    .context("Archive extraction panicked")??;
+    Ok((extracted, downloaded))
+}
+
+/// Removed when dropped, whether extraction succeeded or not.
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// A blocking `Read` over chunks sent from the async side.
+struct ChannelReader {
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+    current: Bytes,
+}
+
+impl ChannelReader {
+    fn new(chunks: mpsc::Receiver<io::Result<Bytes>>) -> Self {
+        Self {
+            chunks,
+            current: Bytes::new(),
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(Ok(chunk)) => self.current = chunk,
+                Some(Err(e)) => return Err(e),
+                None => return Ok(0),
+            }
+        }
+        let n = self.current.len().min(buf.len());
+        buf[..n].copy_from_slice(&self.current.split_to(n));
+        Ok(n)
+    }
+}
+
+enum EntryKind {
+    File,
+    Directory,
+    Symlink(PathBuf),
+    HardLink(PathBuf),
+    Other,
+}
+
+/// Writes entries under `dest` while enforcing `ExtractOptions`.
+struct Extractor<'a> {
+    dest: &'a Path,
+    options: &'a ExtractOptions,
+    entries: usize,
+    written: u64,
+    done: Extracted,
+}
+
+impl<'a> Extractor<'a> {
+    fn new(dest: &'a Path, options: &'a ExtractOptions) -> Self {
+        Self {
+            dest,
+            options,
+            entries: 0,
+            written: 0,
+            done: Extracted::default(),
+        }
+    }
+
+    fn tar(&mut self, reader: impl Read) -> Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries().context("Failed to read tar archive")? {
+            let mut entry = entry.context("Failed to read tar entry")?;
+            let name = entry.path_bytes().into_owned();
+            let name = String::from_utf8_lossy(&name).into_owned();
+            let link = entry
+                .link_name()
+                .context("Failed to read tar entry")?
+                .map(|target| target.into_owned());
+            let kind = match (entry.header().entry_type(), link) {
+                (tar::EntryType::Regular | tar::EntryType::Continuous, _) => EntryKind::File,
+                (tar::EntryType::Directory, _) => EntryKind::Directory,
+                (tar::EntryType::Symlink, Some(target)) => EntryKind::Symlink(target),
+                (tar::EntryType::Link, Some(target)) => EntryKind::HardLink(target),
+                _ => EntryKind::Other,
+            };
+            self.entry(&name, kind, entry.header().size().unwrap_or(0), &mut entry)?;
+        }
+        Ok(())
+    }
+
+    fn zip(&mut self, file: File) -> Result<()> {
+        let mut archive = zip::ZipArchive::new(file).map_err(|e| anyhow::anyhow!("Failed to open zip archive: {}", e))?;
+        for index in 0..archive.len() {
+            let mut entry = archive
+                .by_index(index)
+                .map_err(|e| anyhow::anyhow!("Failed to read zip entry {}: {}", index, e))?;
+            let name = entry.name().to_string();
+            let is_symlink = entry.unix_mode().is_some_and(|mode| mode & 0o170000 == 0o120000);
+            let kind = if entry.is_dir() {
+                EntryKind::Directory
+            } else if is_symlink {
+                let mut target = String::new();
+                (&mut entry)
+                    .take(MAX_LINK_TARGET)
+                    .read_to_string(&mut target)
+                    .with_context(|| format!("Failed to read zip entry {}", name))?;
+                EntryKind::Symlink(PathBuf::from(target))
+            } else {
+                EntryKind::File
+            };
+            let size = entry.size();
+            self.entry(&name, kind, size, &mut entry)?;
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, name: &str, kind: EntryKind, declared_size: u64, contents: &mut impl Read) -> Result<()> {
+        let Some(relative) = normalize(Path::new(name)) else {
+            return Err(ExtractError::PathTraversal { entry: name.to_string() }.into());
+        };
+        if relative.as_os_str().is_empty() {
+            return Ok(());
+        }
+        self.entries += 1;
+        if self.entries > self.options.max_files {
+            return Err(ExtractError::TooManyFiles { limit: self.options.max_files }.into());
+        }
+        // Links extracted earlier must not carry later entries elsewhere.
+        if relative.parent().is_some_and(|parent| self.has_link(parent)) {
+            return Err(ExtractError::PathTraversal { entry: name.to_string() }.into());
+        }
+        let path = self.dest.join(&relative);
+
+        match kind {
+            EntryKind::Directory => {
+                fs::create_dir_all(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+            }
+            EntryKind::File => {
+                let remaining = self.options.max_bytes - self.written;
+                if declared_size > remaining {
+                    return Err(ExtractError::TooLarge { limit: self.options.max_bytes }.into());
+                }
+                let size = self.write_file(&path, contents, remaining)?;
+                self.written += size;
+                self.done.files.push(ExtractedFile { path: relative, size });
+            }
+            EntryKind::Symlink(target) | EntryKind::HardLink(target) if self.options.symlinks == SymlinkPolicy::Reject => {
+                return Err(link_rejected(name, &target));
+            }
+            EntryKind::Symlink(_) | EntryKind::HardLink(_) if self.options.symlinks == SymlinkPolicy::Skip => {
+                self.done.skipped.push(relative);
+            }
+            EntryKind::Other => self.done.skipped.push(relative),
+            EntryKind::Symlink(target) => {
+                // Relative to the link's own directory.
+                let parent = relative.parent().unwrap_or(Path::new(""));
+                if target.is_absolute() || !self.stays_inside(&parent.join(&target)) {
+                    return Err(link_rejected(name, &target));
+                }
+                if !self.symlink(&target, &path)? {
+                    self.done.skipped.push(relative);
+                    return Ok(());
+                }
+                self.done.links.push(relative);
+            }
+            EntryKind::HardLink(target) => {
+                // Relative to the archive root.
+                let Some(target_path) = normalize(&target)
+                    .filter(|target| !target.as_os_str().is_empty() && !self.has_link(target))
+                else {
+                    return Err(link_rejected(name, &target));
+                };
+                let original = self.dest.join(target_path);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+                }
+                remove_link(&path)?;
+                fs::hard_link(&original, &path)
+                    .with_context(|| format!("Failed to link {} to {}", path.display(), original.display()))?;
+                self.done.links.push(relative);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `relative` or any directory above it inside `dest` is a link.
+    fn has_link(&self, relative: &Path) -> bool {
+        let mut path = self.dest.to_path_buf();
+        relative.components().any(|component| {
+            path.push(component);
+            fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+        })
+    }
+
+    /// Lexically inside `dest`, with no `..` stepping back out of a link
+    /// (which would leave from wherever the link points).
+    fn stays_inside(&self, relative: &Path) -> bool {
+        let mut path = PathBuf::new();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                Component::ParentDir if !path.as_os_str().is_empty() && !self.has_link(&path) => {
+                    path.pop();
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Copies at most `remaining` bytes, failing if there are more.
+    fn write_file(&self, path: &Path, contents: &mut impl Read, remaining: u64) -> Result<u64> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        remove_link(path)?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let size = io::copy(&mut contents.take(remaining), &mut file)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        if size == remaining && contents.read(&mut [0])? > 0 {
+            return Err(ExtractError::TooLarge { limit: self.options.max_bytes }.into());
+        }
+        file.flush()?;
+        Ok(size)
+    }
+
+    /// Returns `false` where symlinks are not supported.
+    #[cfg(unix)]
+    fn symlink(&self, target: &Path, path: &Path) -> Result<bool> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        remove_link(path)?;
+        std::os::unix::fs::symlink(target, path).with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(true)
+    }
+
+    #[cfg(not(unix))]
+    fn symlink(&self, _target: &Path, _path: &Path) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// An existing symlink at `path` is replaced rather than written through,
+/// so it cannot redirect a file outside the destination.
+fn remove_link(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        fs::remove_file(path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn link_rejected(name: &str, target: &Path) -> anyhow::Error {
+    ExtractError::LinkRejected {
+        entry: name.to_string(),
+        target: target.display().to_string(),
+    }
+    .into()
+}
+
+/// `path` with `.` removed and `..` applied, or `None` when it is absolute
+/// or climbs above its starting point.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+
+    /// An empty destination inside a scratch directory, so anything that
+    /// escapes it can be spotted next to it. Removed on drop.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("rusthttp-extract-{}", crate::utils::generate_request_id()));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn dest(&self) -> PathBuf {
+            self.0.join("out")
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A tar archive of `entries`. Names are written into the header as
+    /// given, so `..` paths that `tar::Builder` would refuse get through.
+    fn tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, data) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    async fn serve(path: &str, archive: Vec<u8>) -> Result<MockServer> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, path).respond(MockResponse::new(200).with_body(archive));
+        Ok(server)
+    }
+
+    fn extract_error(error: &anyhow::Error) -> Option<&ExtractError> {
+        error.chain().find_map(|cause| cause.downcast_ref::<ExtractError>())
+    }
+
+    #[tokio::test]
+    async fn tar_gz_and_zip_archives_are_extracted_with_sizes() -> Result<()> {
+        let entries: &[(&str, &[u8])] = &[("readme.txt", b"hello"), ("data/rows.csv", b"a,b\n1,2\n")];
+        for (path, archive, format) in [
+            ("/export.tar.gz", gzip(&tar(entries)), ArchiveFormat::TarGz),
+            ("/export.zip", zip(entries), ArchiveFormat::Zip),
+        ] {
+            let server = serve(path, archive).await?;
+            let scratch = Scratch::new();
+            let report = HttpClient::new()
+                .download_and_extract(&server.url(path), scratch.dest(), RequestConfig::new(), ExtractOptions::new())
+                .await?;
+
+            assert_eq!(report.format, format);
+            assert_eq!(
+                report.files,
+                vec![
+                    ExtractedFile { path: "readme.txt".into(), size: 5 },
+                    ExtractedFile { path: "data/rows.csv".into(), size: 8 },
+                ]
+            );
+            assert_eq!(report.total_bytes, 13);
+            assert_eq!(fs::read(scratch.dest().join("data/rows.csv"))?, b"a,b\n1,2\n");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn entries_that_escape_the_destination_are_rejected() -> Result<()> {
+        let entries: &[(&str, &[u8])] = &[("ok.txt", b"fine"), ("../evil.txt", b"pwned")];
+        for (path, archive) in [("/x.tar", tar(entries)), ("/x.tar.gz", gzip(&tar(entries))), ("/x.zip", zip(entries))] {
+            let server = serve(path, archive).await?;
+            let scratch = Scratch::new();
+            let error = HttpClient::new()
+                .download_and_extract(&server.url(path), scratch.dest(), RequestConfig::new(), ExtractOptions::new())
+                .await
+                .unwrap_err();
+
+            assert!(
+                matches!(extract_error(&error), Some(ExtractError::PathTraversal { entry }) if entry == "../evil.txt"),
+                "{}: {:#}",
+                path,
+                error
+            );
+            assert!(!scratch.0.join("evil.txt").exists(), "{} escaped", path);
+        }
+
+        let absolute = tar(&[("/tmp/evil.txt", b"pwned")]);
+        let server = serve("/abs.tar", absolute).await?;
+        let scratch = Scratch::new();
+        let error = HttpClient::new()
+            .download_and_extract(&server.url("/abs.tar"), scratch.dest(), RequestConfig::new(), ExtractOptions::new())
+            .await
+            .unwrap_err();
+        assert!(matches!(extract_error(&error), Some(ExtractError::PathTraversal { .. })), "{:#}", error);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archives_that_expand_past_the_limit_are_stopped() -> Result<()> {
+        // 32 MB of zeros compresses to a few tens of kilobytes.
+        let zeros = vec![0u8; 32 * 1024 * 1024];
+        let entries: &[(&str, &[u8])] = &[("zeros.bin", &zeros)];
+        let options = ExtractOptions::new().with_max_bytes(1024 * 1024);
+        for (path, archive) in [("/bomb.tar.gz", gzip(&tar(entries))), ("/bomb.zip", zip(entries))] {
+            assert!(archive.len() < 1024 * 1024, "{} is {} bytes", path, archive.len());
+            let server = serve(path, archive).await?;
+            let scratch = Scratch::new();
+            let error = HttpClient::new()
+                .download_and_extract(&server.url(path), scratch.dest(), RequestConfig::new(), options.clone())
+                .await
+                .unwrap_err();
+
+            assert!(
+                matches!(extract_error(&error), Some(ExtractError::TooLarge { limit: 1048576 })),
+                "{}: {:#}",
+                path,
+                error
+            );
+            let written = fs::metadata(scratch.dest().join("zeros.bin")).map_or(0, |metadata| metadata.len());
+            assert!(written <= 1024 * 1024, "{} wrote {} bytes", path, written);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archives_with_too_many_entries_are_stopped() -> Result<()> {
+        let names: Vec<String> = (0..5).map(|n| format!("file{}.txt", n)).collect();
+        let entries: Vec<(&str, &[u8])> = names.iter().map(|name| (name.as_str(), &b"x"[..])).collect();
+        let server = serve("/many.zip", zip(&entries)).await?;
+        let scratch = Scratch::new();
+        let error = HttpClient::new()
+            .download_and_extract(
+                &server.url("/many.zip"),
+                scratch.dest(),
+                RequestConfig::new(),
+                ExtractOptions::new().with_max_files(3),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(extract_error(&error), Some(ExtractError::TooManyFiles { limit: 3 })), "{:#}", error);
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header set on responses produced by fault injection.
+pub const FAULT_INJECTED_HEADER: &str = "x-fault-injected";
+
+/// Artificial latency and failures for resilience testing, see
+/// `HttpClient::with_fault_injection`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultInjection {
+    /// Added before every attempt, retries included.
+    pub latency: Option<Duration>,
+    /// Chance in `[0, 1]` that an attempt is answered with `error_status`
+    /// instead of being sent.
+    pub error_rate: f64,
+    pub error_status: u16,
+    /// Makes the failures repeatable across runs; `None` seeds from the time.
+    pub seed: Option<u64>,
+}
+
+impl Default for FaultInjection {
+    fn default() -> Self {
+        Self {
+            latency: None,
+            error_rate: 0.0,
+            error_status: 503,
+            seed: None,
+        }
+    }
+}
+
+impl FaultInjection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Clamped to `[0, 1]`.
+    pub fn with_error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = if rate.is_nan() { 0.0 } else { rate.clamp(0.0, 1.0) };
+        self
+    }
+
+    pub fn with_error_status(mut self, status: u16) -> Self {
+        self.error_status = status;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// A `FaultInjection` with its random state.
+#[derive(Debug)]
+pub(crate) struct FaultInjector {
+    pub(crate) config: FaultInjection,
+    state: AtomicU64,
+}
+
+impl FaultInjector {
+    pub(crate) fn new(config: FaultInjection) -> Self {
+        let seed = config.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+        });
+        Self {
+            config,
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    /// Whether the next attempt should fail.
+    pub(crate) fn should_fail(&self) -> bool {
+        match self.config.error_rate {
+            rate if rate <= 0.0 => false,
+            rate if rate >= 1.0 => true,
+            rate => self.next_fraction() < rate,
+        }
+    }
+
+    /// splitmix64, so every seed (zero included) gives a usable sequence.
+    fn next_fraction(&self) -> f64 {
+        let mut z = self
+            .state
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::compare::DEFAULT_IGNORED_HEADERS;
+use crate::download::sanitize_filename;
+use crate::headers::{header_glob_match, is_hop_by_hop};
+use crate::models::{base64_decode_bytes, base64_encode_bytes, ApiResponse, HttpMethod, UrlRedactor, REDACTED};
+use crate::report::SENSITIVE_HEADERS;
+use crate::secrets::SecretScanner;
+use crate::utils::decode_body;
+
+/// Format version written to new fixtures; newer files are refused.
+pub const FIXTURE_VERSION: u32 = 1;
+
+/// What `ApiResponse::to_fixture` leaves out or masks so fixtures stay
+/// stable between captures and safe to commit.
+#[derive(Debug, Clone)]
+pub struct NormalizeOptions {
+    /// Header globs dropped entirely, `DEFAULT_IGNORED_HEADERS` by default.
+    /// Hop-by-hop headers and `Content-Length` are always dropped.
+    pub ignored_headers: Vec<String>,
+    /// Headers kept with their value replaced by `***`.
+    pub redacted_headers: Vec<String>,
+    /// Applied to text bodies.
+    pub secrets: SecretScanner,
+    pub url_redactor: UrlRedactor,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self {
+            ignored_headers: DEFAULT_IGNORED_HEADERS.iter().map(|name| name.to_string()).collect(),
+            redacted_headers: SENSITIVE_HEADERS.iter().map(|name| name.to_string()).collect(),
+            secrets: SecretScanner::default(),
+            url_redactor: UrlRedactor::default(),
+        }
+    }
+}
+
+impl NormalizeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ignored(mut self, glob: impl Into<String>) -> Self {
+        self.ignored_headers.push(glob.into().to_ascii_lowercase());
+        self
+    }
+
+    pub fn with_redacted(mut self, name: impl Into<String>) -> Self {
+        self.redacted_headers.push(name.into().to_ascii_lowercase());
+        self
+    }
+
+    pub fn with_secret_scanner(mut self, secrets: SecretScanner) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    pub fn with_url_redactor(mut self, redactor: UrlRedactor) -> Self {
+        self.url_redactor = redactor;
+        self
+    }
+}
+
+/// A response saved for tests, stored as pretty-printed JSON with sorted
+/// headers so captures of the same response diff cleanly.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fixture {
+    pub version: u32,
+    pub name: String,
+    pub method: HttpMethod,
+    pub url: String,
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    /// The body when it is UTF-8 ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// ... and base64 otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_base64: Option<String>,
+}
+
+impl Fixture {
+    /// Fails for responses that did not come from a request, such as
+    /// `ApiResponse::synthetic`.
+    pub fn from_response(name: &str, response: &ApiResponse, options: &NormalizeOptions) -> Result<Self> {
+        let request = response
+            .request
+            .as_ref()
+            .context("Response has no request to record in a fixture")?;
+
+        let headers = response
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value))
+            .filter(|(name, _)| {
+                !is_hop_by_hop(name, response) && !options.ignored_headers.iter().any(|glob| header_glob_match(glob, name))
+            })
+            .map(|(name, value)| {
+                let value = match options.redacted_headers.iter().any(|redacted| redacted.eq_ignore_ascii_case(&name)) {
+                    true => REDACTED.to_string(),
+                    false => value.clone(),
+                };
+                (name, value)
+            })
+            .collect();
+
+        let bytes = response.bytes()?;
+        let (body, body_base64) = match std::str::from_utf8(&bytes) {
+            Ok(text) => (Some(options.secrets.redact(text).into_owned()), None),
+            Err(_) => (None, Some(base64_encode_bytes(&bytes))),
+        };
+
+        Ok(Self {
+            version: FIXTURE_VERSION,
+            name: name.to_string(),
+            method: request.method,
+            url: options.url_redactor.redact(&request.url),
+            status: response.status,
+            headers,
+            body,
+            body_base64,
+        })
+    }
+
+    pub fn body_bytes(&self) -> Result<Vec<u8>> {
+        match (&self.body, &self.body_base64) {
+            (_, Some(encoded)) => base64_decode_bytes(encoded)
+                .with_context(|| format!("Fixture {} has an invalid base64 body", self.name)),
+            (Some(text), None) => Ok(text.clone().into_bytes()),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+
+    /// The URL's path, which `MockServer::from_fixtures` routes on.
+    pub fn path(&self) -> String {
+        url::Url::parse(&self.url).map_or_else(|_| self.url.clone(), |url| url.path().to_string())
+    }
+
+    /// Writes `<dir>/<name>.json`, creating `dir` if needed.
+    pub fn save(&self, dir: impl AsRef<Path>) -> Result<PathBuf> {
+        let dir = dir.as_ref();
+        let file = sanitize_filename(&self.name)
+            .with_context(|| format!("{:?} cannot be used as a fixture file name", self.name))?;
+        let path = dir.join(format!("{}.json", file));
+        let mut json = serde_json::to_string_pretty(self).context("Failed to serialize fixture")?;
+        json.push('\n');
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        std::fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let fixture: Fixture =
+            serde_json::from_str(&json).with_context(|| format!("{} is not a valid fixture", path.display()))?;
+        if fixture.version > FIXTURE_VERSION {
+            anyhow::bail!(
+                "{} has fixture version {}, newer than the supported {}",
+                path.display(),
+                fixture.version,
+                FIXTURE_VERSION
+            );
+        }
+        Ok(fixture)
+    }
+
+    /// Every `*.json` fixture in `dir`, in file name order.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let dir = dir.as_ref();
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+        paths.iter().map(Self::load).collect()
+    }
+
+    pub fn to_response(&self) -> Result<ApiResponse> {
+        let bytes = self.body_bytes()?;
+        let mut response = ApiResponse::synthetic(self.status, "");
+        for (name, value) in &self.headers {
+            response = response.with_header(name, value);
+        }
+        response.body = decode_body(&bytes, &response.content_type);
+        response.raw_body = bytes;
+        Ok(response)
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::error::InvalidHeader;
+use crate::models::ApiResponse;
+use crate::utils::parse_http_date;
+
+/// Credentials for `RequestConfig::authorization`. `Debug` shows the
+/// scheme and username but never the secret.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Auth {
+    Bearer(String),
+    Basic { username: String, password: String },
+    /// Sent verbatim, for other schemes.
+    Raw(String),
+}
+
+impl Auth {
+    pub fn bearer(token: impl Into<String>) -> Self {
+        Auth::Bearer(token.into())
+    }
+
+    pub fn basic(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Auth::Basic {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// The `Authorization` header value.
+    pub fn header_value(&self) -> String {
+        match self {
+            Auth::Bearer(token) => format!("Bearer {}", token),
+            Auth::Basic { username, password } => {
+                format!("Basic {}", crate::models::base64_encode(&format!("{}:{}", username, password)))
+            }
+            Auth::Raw(value) => value.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Bearer(_) => f.debug_tuple("Bearer").field(&"***").finish(),
+            Auth::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"***")
+                .finish(),
+            Auth::Raw(value) => {
+                let scheme = value.split_whitespace().next().unwrap_or_default();
+                f.debug_tuple("Raw").field(&format!("{} ***", scheme)).finish()
+            }
+        }
+    }
+}
+
+/// One `Cache-Control` directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheDirective {
+    NoCache,
+    NoStore,
+    NoTransform,
+    OnlyIfCached,
+    MustRevalidate,
+    Public,
+    Private,
+    Immutable,
+    MaxAge(u64),
+    SMaxAge(u64),
+    /// `max-stale` with an optional limit in seconds.
+    MaxStale(Option<u64>),
+    MinFresh(u64),
+    StaleWhileRevalidate(u64),
+    StaleIfError(u64),
+    /// Any other directive, with its value if it has one.
+    Other(String, Option<String>),
+}
+
+impl fmt::Display for CacheDirective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheDirective::NoCache => write!(f, "no-cache"),
+            CacheDirective::NoStore => write!(f, "no-store"),
+            CacheDirective::NoTransform => write!(f, "no-transform"),
+            CacheDirective::OnlyIfCached => write!(f, "only-if-cached"),
+            CacheDirective::MustRevalidate => write!(f, "must-revalidate"),
+            CacheDirective::Public => write!(f, "public"),
+            CacheDirective::Private => write!(f, "private"),
+            CacheDirective::Immutable => write!(f, "immutable"),
+            CacheDirective::MaxAge(secs) => write!(f, "max-age={}", secs),
+            CacheDirective::SMaxAge(secs) => write!(f, "s-maxage={}", secs),
+            CacheDirective::MaxStale(None) => write!(f, "max-stale"),
+            CacheDirective::MaxStale(Some(secs)) => write!(f, "max-stale={}", secs),
+            CacheDirective::MinFresh(secs) => write!(f, "min-fresh={}", secs),
+            CacheDirective::StaleWhileRevalidate(secs) => write!(f, "stale-while-revalidate={}", secs),
+            CacheDirective::StaleIfError(secs) => write!(f, "stale-if-error={}", secs),
+            CacheDirective::Other(name, None) => write!(f, "{}", name),
+            CacheDirective::Other(name, Some(value)) if is_token(value) => write!(f, "{}={}", name, value),
+            CacheDirective::Other(name, Some(value)) => {
+                write!(f, "{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+        }
+    }
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Joins directives into a `Cache-Control` value, e.g. `no-cache, max-age=0`.
+pub fn format_cache_control(directives: &[CacheDirective]) -> String {
+    directives
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parses a `Cache-Control` value. Names are case-insensitive; a known
+/// directive with an unusable value is kept as `Other`.
+pub fn parse_cache_control(value: &str) -> Vec<CacheDirective> {
+    split_unquoted(value, ',')
+        .into_iter()
+        .filter_map(|directive| {
+            let directive = directive.trim();
+            if directive.is_empty() {
+                return None;
+            }
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name.trim().to_ascii_lowercase(), Some(unquote(argument.trim()))),
+                None => (directive.to_ascii_lowercase(), None),
+            };
+            let seconds = argument.as_deref().and_then(|argument| argument.parse::<u64>().ok());
+
+            Some(match (name.as_str(), &argument, seconds) {
+                ("no-cache", None, _) => CacheDirective::NoCache,
+                ("no-store", None, _) => CacheDirective::NoStore,
+                ("no-transform", None, _) => CacheDirective::NoTransform,
+                ("only-if-cached", None, _) => CacheDirective::OnlyIfCached,
+                ("must-revalidate", None, _) => CacheDirective::MustRevalidate,
+                ("public", None, _) => CacheDirective::Public,
+                ("private", None, _) => CacheDirective::Private,
+                ("immutable", None, _) => CacheDirective::Immutable,
+                ("max-age", _, Some(secs)) => CacheDirective::MaxAge(secs),
+                ("s-maxage", _, Some(secs)) => CacheDirective::SMaxAge(secs),
+                ("max-stale", None, _) => CacheDirective::MaxStale(None),
+                ("max-stale", _, Some(secs)) => CacheDirective::MaxStale(Some(secs)),
+                ("min-fresh", _, Some(secs)) => CacheDirective::MinFresh(secs),
+                ("stale-while-revalidate", _, Some(secs)) => CacheDirective::StaleWhileRevalidate(secs),
+                ("stale-if-error", _, Some(secs)) => CacheDirective::StaleIfError(secs),
+                _ => CacheDirective::Other(name, argument),
+            })
+        })
+        .collect()
+}
+
+/// A parsed `Content-Type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    /// `type/subtype`, lowercased.
+    pub essence: String,
+    /// Parameters in order; names lowercased, values unquoted.
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = split_unquoted(value, ';').into_iter();
+        let essence = parts.next()?.trim().to_ascii_lowercase();
+        let (kind, subtype) = essence.split_once('/')?;
+        if !is_token(kind) || !is_token(subtype) {
+            return None;
+        }
+
+        let params = parts
+            .filter_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                Some((name.trim().to_ascii_lowercase(), unquote(value.trim())))
+            })
+            .collect();
+        Some(Self { essence, params })
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+}
+
+/// An entity tag, e.g. `"v1"` or `W/"v1"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    pub weak: bool,
+    /// The tag without quotes or the `W/` prefix.
+    pub tag: String,
+}
+
+impl ETag {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        if tag.contains('"') {
+            return None;
+        }
+        Some(Self {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+/// Request headers in the order they were set, which is the order they go
+/// out on the wire. Names match case-insensitively and setting an existing
+/// name replaces its value in place. Names and values are checked when the
+/// request is built, see `validate`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderList {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `name`, returning the value it replaces.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        let (name, value) = (name.into(), value.into());
+        match self.entries.iter_mut().find(|(existing, _)| existing.eq_ignore_ascii_case(&name)) {
+            Some((_, existing)) => Some(std::mem::replace(existing, value)),
+            None => {
+                self.entries.push((name, value));
+                None
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries
+            .iter()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let index = self.entries.iter().position(|(existing, _)| existing.eq_ignore_ascii_case(name))?;
+        Some(self.entries.remove(index).1)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, &str) -> bool) {
+        self.entries.retain(|(name, value)| keep(name, value));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.into_iter()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(name, _)| name)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        self.entries.iter_mut().map(|(_, value)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Fails on the first header that cannot be sent: a name that is not an
+    /// RFC 9110 token, or a value with control characters such as a line
+    /// break.
+    pub fn validate(&self) -> Result<(), InvalidHeader> {
+        for (name, value) in &self.entries {
+            check_header(name, value)?;
+        }
+        Ok(())
+    }
+
+    /// The headers as a `HeaderMap` with their order kept, after `validate`.
+    pub fn to_header_map(&self) -> Result<HeaderMap, InvalidHeader> {
+        let mut map = HeaderMap::with_capacity(self.entries.len());
+        for (name, value) in &self.entries {
+            check_header(name, value)?;
+            let invalid = |reason: String| InvalidHeader { name: name.clone(), reason };
+            let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(e.to_string()))?;
+            let header_value = HeaderValue::from_str(value).map_err(|e| invalid(e.to_string()))?;
+            map.insert(header_name, header_value);
+        }
+        Ok(map)
+    }
+}
+
+fn check_header(name: &str, value: &str) -> Result<(), InvalidHeader> {
+    let invalid = |reason: String| {
+        Err(InvalidHeader {
+            name: name.to_string(),
+            reason,
+        })
+    };
+    let is_token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() {
+        return invalid("the name is empty".to_string());
+    }
+    if let Some(c) = name.chars().find(|&c| !is_token(c)) {
+        return invalid(format!("the name contains {:?}, which is not allowed in header names", c));
+    }
+    if let Some(c) = value.chars().find(|&c| c.is_ascii_control() && c != '\t') {
+        return invalid(format!("the value contains the control character {:?}", c));
+    }
+    Ok(())
+}
+
+impl IntoIterator for HeaderList {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a HeaderList {
+    type Item = (&'a String, &'a String);
+    type IntoIter = std::iter::Map<std::slice::Iter<'a, (String, String)>, fn(&'a (String, String)) -> (&'a String, &'a String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for HeaderList {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, headers: I) {
+        for (name, value) in headers {
+            self.insert(name, value);
+        }
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for HeaderList {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(headers: I) -> Self {
+        let mut list = Self::new();
+        list.extend(headers);
+        list
+    }
+}
+
+impl From<Vec<(String, String)>> for HeaderList {
+    fn from(headers: Vec<(String, String)>) -> Self {
+        headers.into_iter().collect()
+    }
+}
+
+/// A `HashMap` has no order of its own, so its headers are sorted by name.
+impl From<HashMap<String, String>> for HeaderList {
+    fn from(headers: HashMap<String, String>) -> Self {
+        let mut headers: Vec<_> = headers.into_iter().collect();
+        headers.sort();
+        headers.into_iter().collect()
+    }
+}
+
+/// Headers that describe a single connection and must never be forwarded.
+/// `Content-Length` is included because it belongs to the other message's
+/// body.
+pub const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "trailers",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+/// Whether `name` is hop-by-hop for this response, including any header
+/// its `Connection` header lists.
+pub fn is_hop_by_hop(name: &str, response: &ApiResponse) -> bool {
+    HOP_BY_HOP_HEADERS.iter().any(|header| header.eq_ignore_ascii_case(name))
+        || response.headers.get("connection").is_some_and(|listed| {
+            listed.split(',').any(|header| header.trim().eq_ignore_ascii_case(name))
+        })
+}
+
+/// Case-insensitive match where `*` stands for any run of characters, e.g.
+/// `x-ratelimit-*`.
+pub fn header_glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let name = name.to_ascii_lowercase();
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let pieces: Vec<&str> = pieces.collect();
+    let Some((last, middle)) = pieces.split_last() else {
+        return rest.is_empty();
+    };
+    for piece in middle {
+        match rest.find(piece) {
+            Some(index) => rest = &rest[index + piece.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Common response headers parsed into proper types. A header that is
+/// missing or malformed is `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TypedHeaders {
+    pub content_length: Option<u64>,
+    pub content_type: Option<MediaType>,
+    pub last_modified: Option<SystemTime>,
+    pub etag: Option<ETag>,
+    pub cache_control: Vec<CacheDirective>,
+    /// `Location`, resolved against the final request URL when relative.
+    pub location: Option<url::Url>,
+    pub server: Option<String>,
+}
+
+impl TypedHeaders {
+    pub fn from_response(response: &ApiResponse) -> Self {
+        let header = |name: &str| response.headers.get(name).map(String::as_str);
+        Self {
+            content_length: header("content-length").and_then(|value| value.trim().parse().ok()),
+            content_type: header("content-type").and_then(MediaType::parse),
+            last_modified: header("last-modified").and_then(|value| parse_http_date(value).ok()),
+            etag: header("etag").and_then(ETag::parse),
+            cache_control: header("cache-control").map(parse_cache_control).unwrap_or_default(),
+            location: header("location").and_then(|value| {
+                let base = response.request.as_ref().and_then(|request| url::Url::parse(&request.final_url).ok());
+                url::Url::options().base_url(base.as_ref()).parse(value.trim()).ok()
+            }),
+            server: header("server").map(|value| value.trim().to_string()).filter(|value| !value.is_empty()),
+        }
+    }
+}
+
+/// Splits on `separator` outside double-quoted strings.
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&value[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        Some(inner) => {
+            let mut out = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => out.extend(chars.next()),
+                    c => out.push(c),
+                }
+            }
+            out
+        }
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_debug_hides_secrets() {
+        let bearer = format!("{:?}", Auth::bearer("tok-123"));
+        let basic = format!("{:?}", Auth::basic("alice", "hunter2"));
+        let raw = format!("{:?}", Auth::Raw("Digest nonce=\"abc\"".to_string()));
+
+        assert_eq!(bearer, "Bearer(\"***\")");
+        assert!(basic.contains("alice") && !basic.contains("hunter2"), "{}", basic);
+        assert_eq!(raw, "Raw(\"Digest ***\")");
+        assert_eq!(Auth::bearer("tok-123").header_value(), "Bearer tok-123");
+    }
+}
@@ -0,0 +1,252 @@
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// Links by relation, see `ApiResponse::hal_links` and
+/// `ApiResponse::jsonapi_links`. A relation can have several links.
+pub type LinkMap = BTreeMap<String, Vec<Link>>;
+
+/// One hypermedia link. `href` is as the server sent it: possibly relative,
+/// and an RFC 6570 template when `templated` is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Link {
+    pub href: String,
+    pub templated: bool,
+    /// HAL `name`, which tells apart several links of one relation.
+    pub name: Option<String>,
+    pub title: Option<String>,
+    /// Media type hint (HAL `type`).
+    pub media_type: Option<String>,
+}
+
+impl Link {
+    fn new(href: impl Into<String>) -> Self {
+        Self {
+            href: href.into(),
+            ..Self::default()
+        }
+    }
+
+    /// `href` with a template expanded from `vars` (variables left out are
+    /// dropped, see `utils::expand_uri_template`). Plain links are returned
+    /// unchanged.
+    pub fn expand(&self, vars: &HashMap<String, Value>) -> Result<String> {
+        match self.templated {
+            true => crate::utils::expand_uri_template(&self.href, vars),
+            false => Ok(self.href.clone()),
+        }
+    }
+}
+
+/// HAL `_links`: each relation holds a link object or an array of them.
+/// Plain string links are accepted too.
+pub(crate) fn hal_links(body: &Value) -> LinkMap {
+    let mut links = LinkMap::new();
+    let Some(Value::Object(rels)) = body.get("_links") else {
+        return links;
+    };
+    for (rel, value) in rels {
+        let entries = match value {
+            Value::Array(items) => items.iter().filter_map(hal_link).collect(),
+            other => hal_link(other).into_iter().collect::<Vec<_>>(),
+        };
+        if !entries.is_empty() {
+            links.insert(rel.clone(), entries);
+        }
+    }
+    links
+}
+
+fn hal_link(value: &Value) -> Option<Link> {
+    let text = |object: &Map<String, Value>, key: &str| object.get(key).and_then(Value::as_str).map(str::to_string);
+    match value {
+        Value::String(href) => Some(Link::new(href.as_str())),
+        Value::Object(object) => Some(Link {
+            href: text(object, "href")?,
+            templated: object.get("templated").and_then(Value::as_bool).unwrap_or(false),
+            name: text(object, "name"),
+            title: text(object, "title"),
+            media_type: text(object, "type"),
+        }),
+        _ => None,
+    }
+}
+
+/// JSON:API links: the top-level `links`, the primary resource's `links`
+/// when `data` is a single object, and its relationship links as
+/// `<relationship>.<rel>` (e.g. `author.related`). `null` links are skipped.
+pub(crate) fn jsonapi_links(body: &Value) -> LinkMap {
+    let mut links = LinkMap::new();
+    let mut add = |rel: String, value: &Value| {
+        let link = match value {
+            Value::String(href) => Link::new(href.as_str()),
+            Value::Object(object) => match object.get("href").and_then(Value::as_str) {
+                Some(href) => Link {
+                    title: object.get("title").and_then(Value::as_str).map(str::to_string),
+                    media_type: object.get("type").and_then(Value::as_str).map(str::to_string),
+                    ..Link::new(href)
+                },
+                None => return,
+            },
+            _ => return,
+        };
+        links.entry(rel).or_default().push(link);
+    };
+
+    let resource = body.get("data").filter(|data| data.is_object());
+    for section in [Some(body), resource].into_iter().flatten() {
+        if let Some(Value::Object(rels)) = section.get("links") {
+            for (rel, value) in rels {
+                add(rel.clone(), value);
+            }
+        }
+    }
+    if let Some(Value::Object(relationships)) = resource.and_then(|data| data.get("relationships")) {
+        for (name, relationship) in relationships {
+            if let Some(Value::Object(rels)) = relationship.get("links") {
+                for (rel, value) in rels {
+                    add(format!("{}.{}", name, rel), value);
+                }
+            }
+        }
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, MissingLink, RequestConfig};
+    use serde_json::json;
+
+    fn hal_order() -> Value {
+        json!({
+            "total": 30,
+            "_links": {
+                "self": {"href": "/orders/123"},
+                "find": {"href": "/orders{?id}", "templated": true},
+                "warehouse": "/warehouses/56",
+                "ea:item": [
+                    {"href": "/items/1", "name": "first", "title": "Widget"},
+                    {"href": "/items/2", "name": "second", "type": "application/hal+json"}
+                ],
+                "broken": {"title": "no href"},
+                "curies": []
+            }
+        })
+    }
+
+    fn jsonapi_article() -> Value {
+        json!({
+            "links": {"self": "http://example.com/articles/1", "next": null},
+            "data": {
+                "type": "articles",
+                "id": "1",
+                "links": {"canonical": {"href": "/articles/1", "title": "Canonical"}},
+                "relationships": {
+                    "author": {
+                        "links": {"self": "/articles/1/relationships/author", "related": "/articles/1/author"}
+                    },
+                    "comments": {"data": []}
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn hal_links_accept_objects_arrays_and_strings() {
+        let links = hal_links(&hal_order());
+
+        assert_eq!(links.keys().collect::<Vec<_>>(), ["ea:item", "find", "self", "warehouse"]);
+        assert_eq!(links["warehouse"], [Link::new("/warehouses/56")]);
+        assert!(links["find"][0].templated);
+        assert_eq!(
+            links["ea:item"],
+            [
+                Link {
+                    name: Some("first".into()),
+                    title: Some("Widget".into()),
+                    ..Link::new("/items/1")
+                },
+                Link {
+                    name: Some("second".into()),
+                    media_type: Some("application/hal+json".into()),
+                    ..Link::new("/items/2")
+                },
+            ]
+        );
+        assert!(hal_links(&json!({"total": 30})).is_empty());
+    }
+
+    #[test]
+    fn templated_links_expand_and_plain_ones_do_not() -> Result<()> {
+        let links = hal_links(&hal_order());
+        let vars = HashMap::from([("id".to_string(), json!(7))]);
+
+        assert_eq!(links["find"][0].expand(&vars)?, "/orders?id=7");
+        assert_eq!(links["find"][0].expand(&HashMap::new())?, "/orders");
+        assert_eq!(links["self"][0].expand(&vars)?, "/orders/123");
+        Ok(())
+    }
+
+    #[test]
+    fn jsonapi_links_cover_the_document_resource_and_relationships() {
+        let links = jsonapi_links(&jsonapi_article());
+
+        assert_eq!(
+            links.keys().collect::<Vec<_>>(),
+            ["author.related", "author.self", "canonical", "self"]
+        );
+        assert_eq!(links["self"][0].href, "http://example.com/articles/1");
+        assert_eq!(links["canonical"][0].title.as_deref(), Some("Canonical"));
+        assert_eq!(links["author.related"][0].href, "/articles/1/author");
+        assert!(jsonapi_links(&json!({"data": [{"links": {"self": "/a"}}]})).is_empty());
+    }
+
+    #[tokio::test]
+    async fn follow_resolves_links_against_the_response_url() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/api/orders/123").respond(MockResponse::json(200, hal_order()));
+        server.when(HttpMethod::Get, "/items/1").respond(MockResponse::json(200, json!({"sku": "w-1"})));
+        server
+            .when(HttpMethod::Get, "/orders")
+            .respond_with(|request| MockResponse::json(200, json!({"query": request.query})));
+        server
+            .when(HttpMethod::Get, "/articles/1/author")
+            .respond(MockResponse::json(200, json!({"data": {"id": "9"}})));
+        server.when(HttpMethod::Get, "/articles/1").respond(MockResponse::json(200, jsonapi_article()));
+        let client = HttpClient::new();
+
+        let order = client.get(&server.url("/api/orders/123"), RequestConfig::new()).await?;
+        let item = client.follow(&order, "ea:item", RequestConfig::new()).await?;
+        assert_eq!(item.as_json_value()?, json!({"sku": "w-1"}));
+        let vars = HashMap::from([("id".to_string(), json!("a b"))]);
+        let found = client.follow_with(&order, "find", &vars, RequestConfig::new()).await?;
+        assert_eq!(found.as_json_value()?, json!({"query": "id=a%20b"}));
+
+        let article = client.get(&server.url("/articles/1"), RequestConfig::new()).await?;
+        let author = client.follow(&article, "author.related", RequestConfig::new()).await?;
+        assert_eq!(author.as_json_value()?, json!({"data": {"id": "9"}}));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn following_a_missing_rel_lists_the_available_ones() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/orders/123").respond(MockResponse::json(200, hal_order()));
+        server.when(HttpMethod::Get, "/plain").respond(MockResponse::json(200, json!({"ok": true})));
+        let client = HttpClient::new();
+
+        let order = client.get(&server.url("/orders/123"), RequestConfig::new()).await?;
+        let error = client.follow(&order, "payment", RequestConfig::new()).await.unwrap_err();
+        let missing = error.downcast_ref::<MissingLink>().unwrap();
+        assert_eq!(missing.available, ["ea:item", "find", "self", "warehouse"]);
+        assert_eq!(error.to_string(), "No 'payment' link (available: ea:item, find, self, warehouse)");
+
+        let plain = client.get(&server.url("/plain"), RequestConfig::new()).await?;
+        let error = client.follow(&plain, "next", RequestConfig::new()).await.unwrap_err();
+        assert_eq!(error.to_string(), "No 'next' link: the response has no links");
+        Ok(())
+    }
+}
@@ -1,7 +1,79 @@
+#![allow(non_snake_case)]
+
+pub mod auth;
+pub mod balancer;
+pub mod batch;
+pub mod body;
+pub mod cache;
 pub mod client;
+pub mod clock;
+pub mod codec;
+pub mod compare;
+pub mod connections;
+pub mod cookies;
+pub mod dns;
+pub mod download;
+pub mod error;
+#[cfg(feature = "archive")]
+pub mod extract;
+pub mod faults;
+pub mod fixtures;
+pub mod headers;
+pub mod hypermedia;
+pub mod measure;
+pub mod metrics;
 pub mod models;
+pub mod multipart;
+pub mod quota;
+pub mod report;
+pub mod scope;
+pub mod secrets;
+pub mod shutdown;
+pub mod signing;
+pub mod spill;
+pub mod stream;
+pub mod tls;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod utils;
+pub mod vars;
+pub mod warnings;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
-pub use client::HttpClient;
-pub use models::{ApiResponse, RequestConfig, ApiError, HttpMethod, RequestStats};
-pub use utils::{pretty_print_json, format_duration, format_size, is_valid_json, json_path_extract, parse_headers_string, status_message, status_indicator};
\ No newline at end of file
+pub use auth::{AuthCodeOptions, AuthRecovery, AuthStatus, OAuthToken, PkceChallenge, TokenFuture, DEFAULT_AUTH_CODE_TIMEOUT_SECS, DEFAULT_REDIRECT_PATH};
+pub use balancer::{Strategy, TargetStats, DEFAULT_TARGET_COOLDOWN_SECS};
+pub use batch::{AdaptiveOptions, BatchOptions, BatchReport, ConcurrencyChange, ConcurrencyReason, DEFAULT_BATCH_CONCURRENCY};
+pub use body::{Body, FormPart};
+pub use cache::{ResponseCacheConfig, DEFAULT_RESPONSE_CACHE_ENTRIES, DEFAULT_RESPONSE_CACHE_TTL_SECS};
+pub use compare::{diff_headers, CompareOptions, CompareReport, HeaderChange, HeaderDiff, DEFAULT_IGNORED_HEADERS, SECURITY_HEADERS};
+pub use connections::ConnectionUsage;
+pub use cookies::{CookiePolicy, StoredCookie};
+pub use clock::{Clock, MockClock, SleepFuture, SystemClock};
+pub use codec::Codec;
+pub use download::{DownloadReport, FilenameSource, DEFAULT_DOWNLOAD_NAME};
+pub use dns::{DEFAULT_DNS_TIMEOUT_SECS, DnsAnswer, DnsCacheConfig, DnsLookup, DnsStats, LookupFuture, SystemLookup};
+pub use client::{HttpClient, InterceptedRequest, PreparedRequest, RedirectAction, RedirectAttempt, RedirectHandler, RequestInterceptor, StatusMapper, DEFAULT_CAPABILITY_TTL_SECS, DEFAULT_MAX_REDIRECTS, DEFAULT_TIMEOUT_SECS, DEFAULT_USER_AGENT};
+pub use headers::{format_cache_control, header_glob_match, is_hop_by_hop, parse_cache_control, HOP_BY_HOP_HEADERS, Auth, CacheDirective, ETag, HeaderList, MediaType, TypedHeaders};
+pub use hypermedia::{Link, LinkMap};
+#[cfg(feature = "archive")]
+pub use extract::{ArchiveFormat, ExtractOptions, ExtractReport, ExtractedFile, SymlinkPolicy, DEFAULT_MAX_EXTRACTED_BYTES, DEFAULT_MAX_EXTRACTED_FILES};
+pub use faults::{FaultInjection, FAULT_INJECTED_HEADER};
+pub use fixtures::{Fixture, NormalizeOptions, FIXTURE_VERSION};
+pub use error::{AuthCodeError, AuthRecoveryError, ClientClosed, ConnectionLimitTimeout, DnsError, HostNotFound, InvalidHeader, JsonRpcError, MissingLink, PartialResponse, PollTimeout, QuotaExceeded, RequestError, RetriesExhausted, TimeoutKind, TlsPolicyViolation, WarningEscalated, ExtractError};
+pub use measure::{BaselineComparison, LatencyBaseline, LatencyStats, MeasureOptions, MeasureReport, BASELINE_VERSION, DEFAULT_MEASURE_RUNS, DEFAULT_MEASURE_WARMUP, DEFAULT_REGRESSION_THRESHOLD_PERCENT};
+pub use metrics::{prometheus_text, Histogram, MetricsRecorder, MetricsSnapshot, RequestLabels, SlowRequest, DEFAULT_LATENCY_BUCKETS, DEFAULT_MAX_HOSTS, SLOWEST_REQUESTS};
+pub use multipart::{parse_multipart, multipart_boundary, EmbeddedHttpResponse, MultipartPart};
+pub use quota::QuotaUsage;
+pub use report::{ChainResult, ChainStep, DEFAULT_REPORT_BODY_LIMIT};
+pub use scope::{ScopeDefaults, ScopedClient};
+pub use signing::{RequestSigner, SignFuture, SignableRequest};
+pub use spill::SpilledBody;
+pub use secrets::{SecretKind, SecretScanner, DEFAULT_MIN_BASE64_LENGTH, DEFAULT_MIN_HEX_LENGTH};
+pub use shutdown::ShutdownReport;
+pub use stream::JsonArrayParser;
+pub use tls::{CertificateInfo, TlsPolicy, TlsVersion, DEFAULT_CERT_EXPIRY_WARNING_DAYS};
+pub use models::{ApiResponse, CacheStatus, BodyFormat, StatusClass, RequestConfig, IntoRequestUrl, ApiError, HttpMethod, RequestStats, RequestHistory, RequestInfo, UrlParts, QueryParams, RetryPolicy, RetryPredicate, Preset, AttemptRecord, AttemptOutcome, UrlRedactor, EndpointCapabilities, REQUEST_ID_HEADER, TlsInfo, DEFAULT_BACKOFF_MULTIPLIER, DEFAULT_INITIAL_BACKOFF_MS, DEFAULT_MAX_RETRIES};
+pub use utils::{canonicalize_json, expand_uri_template, expand_uri_template_strict, repair_json, JsonRepair, JsonRepairKind, content_disposition_filename, decode_body, parse_header_flags, parse_headers_string_lenient, HeaderFlags, HeaderParseWarning, display_url, host_to_ascii, host_to_unicode, generate_request_id, encode_form_pairs, to_nested_form, to_nested_form_with, FormArrayStyle, to_query_string, ArrayQueryStyle, format_http_date, format_rfc3339, parse_http_date, parse_rfc3339, pretty_print_html, pretty_print_json, minify_json, sniff_body_format, format_duration, format_size, is_valid_json, json_path_extract, json_merge, merge_values, parse_headers_string, parse_url_parts, percent_decode, set_query_param, status_message, status_indicator};
+pub use vars::{resolve_vars, ResolvedVar, ResolvedVars, VarSource, ENV_VAR_PREFIX};
+pub use warnings::{error_warnings, RequestWarning, WarningCode};
\ No newline at end of file
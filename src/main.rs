@@ -1,35 +1,57 @@
-use anyhow::Result;
-use RustHTTP::{HttpClient, RequestConfig, pretty_print_json, format_duration, status_indicator};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use RustHTTP::{parse_header_flags, error_warnings, ApiResponse, ChainResult, ChainStep, HeaderFlags, HttpClient, HttpMethod, LatencyBaseline, MeasureOptions, MetricsRecorder, RequestConfig, RequestWarning, pretty_print_html, pretty_print_json};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "measure") {
+        return measure(&args[1..]).await;
+    }
+
     println!("RustHTTP API Client Demo");
     println!("{}", "=".repeat(40));
 
-    let client = HttpClient::new();
+    let header_flags = header_flags(header_args(std::env::args().skip(1)));
+
+    // `--summary` prints totals for the whole run at the end.
+    let summary = std::env::args().skip(1).any(|arg| arg == "--summary");
+    // `--report FILE` writes the demo requests out as a Markdown report.
+    let report_path = flag_value(std::env::args().skip(1), "--report");
+    let mut chain = ChainResult::new();
+    let recorder = Arc::new(MetricsRecorder::new());
+    let client = HttpClient::new().with_metrics(Arc::clone(&recorder));
 
     println!("\nGET Request Demo");
-    let config = RequestConfig::new();
+    let config = RequestConfig::new().with_header_flags(&header_flags);
+    let url = "https://jsonplaceholder.typicode.com/posts/1";
+    let started = Instant::now();
+    let result = client.get(url, config.clone()).await;
+    chain.push(chain_step("GET post", HttpMethod::Get, url, &config, None, &result, started));
     
-    match client.get("https://jsonplaceholder.typicode.com/posts/1", config).await {
+    match result {
         Ok(response) => {
-            println!("Status: {} {}", 
-                status_indicator(response.status), 
-                response.status_text
-            );
-            println!("Response Time: {}", format_duration(response.response_time_ms));
+            println!("{}", response.summary_colored());
+            print_warnings(&response.warnings);
             
             if response.is_json() {
                 match pretty_print_json(&response.body) {
                     Ok(pretty_json) => println!("Response:\n{}", pretty_json),
                     Err(_) => println!("Response: {}", response.body),
                 }
+            } else if response.is_html() {
+                println!("Response:\n{}", pretty_print_html(&response.body));
             } else {
                 println!("Response: {}", response.body);
             }
         }
-        Err(e) => println!("❌ Error: {}", e),
+        Err(e) => {
+            println!("❌ Error: {}", e);
+            print_warnings(error_warnings(&e));
+        }
     }
 
     println!("\nPOST Request Demo");
@@ -43,91 +65,242 @@ async fn main() -> Result<()> {
     headers.insert("Content-Type".to_string(), "application/json".to_string());
     headers.insert("User-Agent".to_string(), "RustHTTP-Client/0.1.0".to_string());
     
-    let config = RequestConfig::new().with_headers(headers);
+    let config = RequestConfig::new().with_headers(headers).with_header_flags(&header_flags);
+    let url = "https://jsonplaceholder.typicode.com/posts";
+    let started = Instant::now();
+    let result = client.post(url, post_data, config.clone()).await;
+    chain.push(chain_step("Create post", HttpMethod::Post, url, &config, Some(post_data), &result, started));
 
-    match client.post("https://jsonplaceholder.typicode.com/posts", post_data, config).await {
+    match result {
         Ok(response) => {
-            println!("Status: {} {}", 
-                status_indicator(response.status), 
-                response.status_text
-            );
+            println!("{}", response.summary_colored());
+            print_warnings(&response.warnings);
             
             if response.is_success() {
                 println!("POST Done!");
-                if let Ok(json_value) = response.as_json_value() {
-                    if let Some(id) = json_value.get("id") {
-                        println!("New POST ID: {}", id);
-                    }
+                if let Ok(json_value) = response.as_json_value()
+                    && let Some(id) = json_value.get("id")
+                {
+                    println!("New POST ID: {}", id);
                 }
             }
         }
-        Err(e) => println!("POST Error: {}", e),
+        Err(e) => {
+            println!("POST Error: {}", e);
+            print_warnings(error_warnings(&e));
+        }
     }
 
     println!("\nAuthenticated Request Demo");
     let config = RequestConfig::new()
         .with_bearer_token("test-token-12345")
-        .add_header("Accept", "application/json");
+        .add_header("Accept", "application/json")
+        .with_header_flags(&header_flags);
+    let url = "https://jsonplaceholder.typicode.com/users";
+    let started = Instant::now();
+    let result = client.get(url, config.clone()).await;
+    chain.push(chain_step("List users", HttpMethod::Get, url, &config, None, &result, started));
 
-    match client.get("https://jsonplaceholder.typicode.com/users", config).await {
+    match result {
         Ok(response) => {
-            println!("Status: {} {}", 
-                status_indicator(response.status), 
-                response.status_text
-            );
+            println!("{}", response.summary_colored());
+            print_warnings(&response.warnings);
             println!("Headers count: {}", response.headers.len());
             
-            if let Some(content_type) = response.headers.get("content-type") {
-                println!("Content-Type: {}", content_type);
+            if response.content_type_missing {
+                println!("Content-Type: (not sent, looks like {:?})", response.detected_format());
+            } else {
+                println!("Content-Type: {}", response.content_type);
             }
         }
-        Err(e) => println!("Error: {}", e),
+        Err(e) => {
+            println!("Error: {}", e);
+            print_warnings(error_warnings(&e));
+        }
     }
 
     println!("\nPUT Request Demo");
     let put_data = r#"{"id": 1, "name": "cat", "email": "cat@andrewatan.com"}"#;
-    let config = RequestConfig::new();
+    let config = RequestConfig::new().with_header_flags(&header_flags);
+    let url = "https://jsonplaceholder.typicode.com/users/1";
+    let started = Instant::now();
+    let result = client.put(url, put_data, config.clone()).await;
+    chain.push(chain_step("Update user", HttpMethod::Put, url, &config, Some(put_data), &result, started));
 
-    match client.put("https://jsonplaceholder.typicode.com/users/1", put_data, config).await {
+    match result {
         Ok(response) => {
-            println!("Status: {} {}", 
-                status_indicator(response.status), 
-                response.status_text
-            );
+            println!("{}", response.summary_colored());
+            print_warnings(&response.warnings);
             println!("PUT Req Done");
         }
-        Err(e) => println!("PUT Error: {}", e),
+        Err(e) => {
+            println!("PUT Error: {}", e);
+            print_warnings(error_warnings(&e));
+        }
     }
 
     println!("\nDELETE Request Demo");
-    let config = RequestConfig::new();
+    let config = RequestConfig::new().with_header_flags(&header_flags);
+    let url = "https://jsonplaceholder.typicode.com/posts/1";
+    let started = Instant::now();
+    let result = client.delete(url, config.clone()).await;
+    chain.push(chain_step("Delete post", HttpMethod::Delete, url, &config, None, &result, started));
 
-    match client.delete("https://jsonplaceholder.typicode.com/posts/1", config).await {
+    match result {
         Ok(response) => {
-            println!("Status: {} {}", 
-                status_indicator(response.status), 
-                response.status_text
-            );
+            println!("{}", response.summary_colored());
+            print_warnings(&response.warnings);
             println!("DELETE Req Done");
         }
-        Err(e) => println!("DELETE Error: {}", e),
+        Err(e) => {
+            println!("DELETE Error: {}", e);
+            print_warnings(error_warnings(&e));
+        }
     }
 
     println!("\nCustom Timeout Demo");
     match HttpClient::with_timeout(5) {
         Ok(timeout_client) => {
-            let config = RequestConfig::new();
+            let timeout_client = timeout_client.with_metrics(Arc::clone(&recorder));
+            let config = RequestConfig::new().with_header_flags(&header_flags);
             match timeout_client.get("https://jsonplaceholder.typicode.com/posts", config).await {
                 Ok(response) => {
                     println!("Timeout Req Done with 5 Seconds");
-                    println!("Status: {}", status_indicator(response.status));
+                    println!("{}", response.summary_colored());
+                    print_warnings(&response.warnings);
+                }
+                Err(e) => {
+                    println!("Timeout Error: {}", e);
+                    print_warnings(error_warnings(&e));
                 }
-                Err(e) => println!("Timeout Error: {}", e),
             }
         }
         Err(e) => println!("Client creation error: {}", e),
     }
 
     println!("\nAll Demos Work!");
+    if summary {
+        println!("\nSession Summary");
+        print!("{}", recorder.snapshot().render_summary());
+    }
+    if let Some(path) = report_path {
+        std::fs::write(&path, chain.to_markdown()).with_context(|| format!("Failed to write report to {}", path))?;
+        println!("Report written to {}", path);
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// A demo request as a step of the `--report` chain.
+fn chain_step(
+    name: &str,
+    method: HttpMethod,
+    url: &str,
+    config: &RequestConfig,
+    body: Option<&str>,
+    result: &Result<ApiResponse>,
+    started: Instant,
+) -> ChainStep {
+    match result {
+        Ok(response) => ChainStep::from_response(name, method, url, config, body, response),
+        Err(e) => ChainStep::from_error(name, method, url, config, body, e, started.elapsed().as_millis() as u64),
+    }
+}
+
+/// `measure <url> [-H HEADER]... [--runs N] [--warmup N]
+/// [--discard-outliers] [--baseline FILE [--threshold 20%]
+/// [--update-baseline]]`: times GETs of `url`, sending the `-H` headers. A
+/// missing baseline file is written from this run; an existing one is
+/// compared against and the process exits with status 1 when the median
+/// regressed beyond the threshold.
+async fn measure(args: &[String]) -> Result<()> {
+    let mut options = MeasureOptions::new();
+    let (mut url, mut baseline_path, mut threshold, mut update) = (None, None, None, false);
+    let mut headers = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--runs" => options = options.with_runs(value()?.parse().context("--runs must be a number")?),
+            "--warmup" => options = options.with_warmup(value()?.parse().context("--warmup must be a number")?),
+            "--discard-outliers" => options = options.with_discard_outliers(true),
+            "--baseline" => baseline_path = Some(value()?.clone()),
+            "--threshold" => {
+                let percent = value()?.trim_end_matches('%').parse::<f64>();
+                threshold = Some(percent.context("--threshold must be a percentage such as 20%")?);
+            }
+            "--update-baseline" => update = true,
+            "-H" | "--header" => headers.push(value()?.clone()),
+            other if url.is_none() && !other.starts_with("--") => url = Some(other.to_string()),
+            other => anyhow::bail!("Unexpected argument {}", other),
+        }
+    }
+    let url = url.context("Usage: measure <url> [-H HEADER]... [--runs N] [--warmup N] [--discard-outliers] [--baseline FILE] [--threshold 20%] [--update-baseline]")?;
+
+    let baseline = match &baseline_path {
+        Some(path) if !update && std::path::Path::new(path).exists() => Some(LatencyBaseline::load(path)?),
+        _ => None,
+    };
+    if let Some(baseline) = baseline {
+        let threshold = threshold.unwrap_or(options.threshold_percent);
+        options = options.with_baseline(baseline, threshold);
+    }
+
+    let config = RequestConfig::new().with_header_flags(&header_flags(headers));
+    let report = HttpClient::new().measure(&url, config, options).await?;
+    println!("{}", report.url);
+    print!("{}", report.render());
+    match (&baseline_path, &report.comparison) {
+        (Some(path), None) => {
+            report.to_baseline().save(path)?;
+            println!("Baseline written to {}", path);
+        }
+        (_, Some(comparison)) if !comparison.passed => {
+            println!("{}", "Latency regressed beyond the threshold".red());
+            std::process::exit(1);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Request warnings in yellow, one per line.
+fn print_warnings(warnings: &[RequestWarning]) {
+    for warning in warnings {
+        println!("{}", format!("warning: {}", warning).yellow());
+    }
+}
+
+/// The argument after `flag`, e.g. the path in `--report report.md`.
+fn flag_value(mut args: impl Iterator<Item = String>, flag: &str) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+    }
+    None
+}
+
+/// The values of `-H`/`--header` arguments. Every demo request sends them,
+/// following curl: `Name;` sends an empty value and `Name:` drops the
+/// header; malformed ones are skipped with a warning.
+fn header_args(mut args: impl Iterator<Item = String>) -> Vec<String> {
+    let mut headers = Vec::new();
+    while let Some(arg) = args.next() {
+        if (arg == "-H" || arg == "--header")
+            && let Some(value) = args.next()
+        {
+            headers.push(value);
+        }
+    }
+    headers
+}
+
+/// Parses `-H` values, printing a warning for each one skipped.
+fn header_flags(values: Vec<String>) -> HeaderFlags {
+    let flags = parse_header_flags(values);
+    for warning in &flags.warnings {
+        let message = format!("warning: -H #{} skipped ({}): {}", warning.position, warning.reason, warning.input);
+        println!("{}", message.yellow());
+    }
+    flags
+}
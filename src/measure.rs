@@ -0,0 +1,359 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Format version written to new baselines; newer files are refused.
+pub const BASELINE_VERSION: u32 = 1;
+pub const DEFAULT_MEASURE_RUNS: usize = 20;
+pub const DEFAULT_MEASURE_WARMUP: usize = 2;
+/// Median slowdown, in percent, that `MeasureOptions::with_baseline`
+/// tolerates unless told otherwise.
+pub const DEFAULT_REGRESSION_THRESHOLD_PERCENT: f64 = 10.0;
+
+/// How `HttpClient::measure` samples an endpoint.
+#[derive(Debug, Clone)]
+pub struct MeasureOptions {
+    /// Measured requests.
+    pub runs: usize,
+    /// Requests sent first and not measured, to warm up connections and
+    /// server caches.
+    pub warmup: usize,
+    /// Drop samples outside 1.5 interquartile ranges of the middle half
+    /// before computing statistics.
+    pub discard_outliers: bool,
+    /// Compared against when set, see `with_baseline`.
+    pub baseline: Option<LatencyBaseline>,
+    pub threshold_percent: f64,
+}
+
+impl Default for MeasureOptions {
+    fn default() -> Self {
+        Self {
+            runs: DEFAULT_MEASURE_RUNS,
+            warmup: DEFAULT_MEASURE_WARMUP,
+            discard_outliers: false,
+            baseline: None,
+            threshold_percent: DEFAULT_REGRESSION_THRESHOLD_PERCENT,
+        }
+    }
+}
+
+impl MeasureOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_runs(mut self, runs: usize) -> Self {
+        self.runs = runs;
+        self
+    }
+
+    pub fn with_warmup(mut self, warmup: usize) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    pub fn with_discard_outliers(mut self, discard: bool) -> Self {
+        self.discard_outliers = discard;
+        self
+    }
+
+    /// Fails the report's verdict when the median is more than
+    /// `threshold_percent` slower than `baseline`'s.
+    pub fn with_baseline(mut self, baseline: LatencyBaseline, threshold_percent: f64) -> Self {
+        self.baseline = Some(baseline);
+        self.threshold_percent = threshold_percent;
+        self
+    }
+}
+
+/// Summary of a set of latency samples, in milliseconds. `stddev_ms` is the
+/// sample standard deviation and percentiles interpolate between samples.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub samples: usize,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub mean_ms: f64,
+    pub median_ms: f64,
+    pub p95_ms: f64,
+    pub stddev_ms: f64,
+}
+
+impl LatencyStats {
+    /// `None` for no samples.
+    pub fn from_samples(samples_ms: &[f64]) -> Option<Self> {
+        let mut sorted = samples_ms.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let (&min_ms, &max_ms) = (sorted.first()?, sorted.last()?);
+        let n = sorted.len() as f64;
+        let mean_ms = sorted.iter().sum::<f64>() / n;
+        let variance = match sorted.len() {
+            1 => 0.0,
+            _ => sorted.iter().map(|sample| (sample - mean_ms).powi(2)).sum::<f64>() / (n - 1.0),
+        };
+        Some(Self {
+            samples: sorted.len(),
+            min_ms,
+            max_ms,
+            mean_ms,
+            median_ms: percentile(&sorted, 0.5),
+            p95_ms: percentile(&sorted, 0.95),
+            stddev_ms: variance.sqrt(),
+        })
+    }
+}
+
+/// Measured statistics saved for later runs to compare against.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LatencyBaseline {
+    pub version: u32,
+    pub url: String,
+    #[serde(with = "crate::utils::rfc3339")]
+    pub recorded_at: SystemTime,
+    pub stats: LatencyStats,
+}
+
+impl LatencyBaseline {
+    /// Writes the baseline as pretty JSON, replacing `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut json = serde_json::to_string_pretty(self).context("Failed to serialize baseline")?;
+        json.push('\n');
+        std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let baseline: LatencyBaseline =
+            serde_json::from_str(&json).with_context(|| format!("{} is not a valid baseline", path.display()))?;
+        if baseline.version > BASELINE_VERSION {
+            anyhow::bail!(
+                "{} has baseline version {}, newer than the supported {}",
+                path.display(),
+                baseline.version,
+                BASELINE_VERSION
+            );
+        }
+        Ok(baseline)
+    }
+}
+
+/// Verdict of a run against a baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BaselineComparison {
+    pub baseline_median_ms: f64,
+    pub median_ms: f64,
+    /// Positive when slower than the baseline.
+    pub change_percent: f64,
+    pub threshold_percent: f64,
+    /// The median did not slow down by more than the threshold.
+    pub passed: bool,
+}
+
+impl BaselineComparison {
+    pub fn new(baseline: &LatencyStats, current: &LatencyStats, threshold_percent: f64) -> Self {
+        let change_percent = match baseline.median_ms {
+            base if base > 0.0 => (current.median_ms - base) / base * 100.0,
+            _ if current.median_ms > 0.0 => f64::INFINITY,
+            _ => 0.0,
+        };
+        Self {
+            baseline_median_ms: baseline.median_ms,
+            median_ms: current.median_ms,
+            change_percent,
+            threshold_percent,
+            passed: change_percent <= threshold_percent,
+        }
+    }
+}
+
+/// Outcome of `HttpClient::measure`.
+#[derive(Debug, Clone)]
+pub struct MeasureReport {
+    pub url: String,
+    /// Every measured sample in run order, outliers included.
+    pub samples_ms: Vec<f64>,
+    /// Samples dropped as outliers.
+    pub discarded: usize,
+    pub stats: LatencyStats,
+    pub comparison: Option<BaselineComparison>,
+}
+
+impl MeasureReport {
+    pub(crate) fn new(url: &str, samples_ms: Vec<f64>, options: &MeasureOptions) -> Result<Self> {
+        let kept = match options.discard_outliers {
+            true => without_outliers(&samples_ms),
+            false => samples_ms.clone(),
+        };
+        let stats = LatencyStats::from_samples(&kept).context("No latency samples to summarize")?;
+        Ok(Self {
+            url: url.to_string(),
+            discarded: samples_ms.len() - kept.len(),
+            samples_ms,
+            comparison: options
+                .baseline
+                .as_ref()
+                .map(|baseline| BaselineComparison::new(&baseline.stats, &stats, options.threshold_percent)),
+            stats,
+        })
+    }
+
+    /// These statistics as a baseline for later runs.
+    pub fn to_baseline(&self) -> LatencyBaseline {
+        LatencyBaseline {
+            version: BASELINE_VERSION,
+            url: self.url.clone(),
+            recorded_at: SystemTime::now(),
+            stats: self.stats,
+        }
+    }
+
+    /// `false` only when a baseline comparison failed.
+    pub fn passed(&self) -> bool {
+        self.comparison.is_none_or(|comparison| comparison.passed)
+    }
+
+    /// Plain-text summary, one statistic per line.
+    pub fn render(&self) -> String {
+        let stats = &self.stats;
+        let mut out = format!(
+            "{} samples ({} discarded)\nmin     {:.2} ms\nmean    {:.2} ms\nmedian  {:.2} ms\np95     {:.2} ms\nmax     {:.2} ms\nstddev  {:.2} ms\n",
+            stats.samples, self.discarded, stats.min_ms, stats.mean_ms, stats.median_ms, stats.p95_ms, stats.max_ms, stats.stddev_ms
+        );
+        if let Some(comparison) = &self.comparison {
+            out.push_str(&format!(
+                "median {:+.1}% against baseline {:.2} ms (threshold {}%): {}\n",
+                comparison.change_percent,
+                comparison.baseline_median_ms,
+                comparison.threshold_percent,
+                if comparison.passed { "PASS" } else { "FAIL" }
+            ));
+        }
+        out
+    }
+}
+
+/// Linear interpolation between the closest ranks of `sorted`.
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    let rank = fraction * (sorted.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+}
+
+/// `samples` without those beyond Tukey's fences. Fewer than four samples
+/// are returned as they are.
+fn without_outliers(samples: &[f64]) -> Vec<f64> {
+    if samples.len() < 4 {
+        return samples.to_vec();
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let (q1, q3) = (percentile(&sorted, 0.25), percentile(&sorted, 0.75));
+    let fence = 1.5 * (q3 - q1);
+    samples
+        .iter()
+        .copied()
+        .filter(|sample| (q1 - fence..=q3 + fence).contains(sample))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+    use std::time::Duration;
+
+    fn baseline(median_ms: f64) -> LatencyBaseline {
+        let stats = LatencyStats::from_samples(&[median_ms]).unwrap();
+        LatencyBaseline {
+            version: BASELINE_VERSION,
+            url: "http://example.com/health".to_string(),
+            recorded_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            stats,
+        }
+    }
+
+    #[test]
+    fn statistics_interpolate_and_use_the_sample_deviation() {
+        let stats = LatencyStats::from_samples(&[50.0, 10.0, 40.0, 20.0, 30.0]).unwrap();
+        assert_eq!((stats.samples, stats.min_ms, stats.max_ms), (5, 10.0, 50.0));
+        assert_eq!((stats.mean_ms, stats.median_ms), (30.0, 30.0));
+        assert!((stats.p95_ms - 48.0).abs() < 1e-9);
+        assert!((stats.stddev_ms - 250f64.sqrt()).abs() < 1e-9);
+        assert_eq!(LatencyStats::from_samples(&[7.0]).unwrap().stddev_ms, 0.0);
+        assert!(LatencyStats::from_samples(&[]).is_none());
+    }
+
+    #[test]
+    fn outliers_are_dropped_only_when_asked() -> Result<()> {
+        let samples = vec![10.0, 11.0, 12.0, 13.0, 100.0];
+        let kept = MeasureReport::new("u", samples.clone(), &MeasureOptions::new())?;
+        assert_eq!((kept.discarded, kept.stats.max_ms), (0, 100.0));
+
+        let trimmed = MeasureReport::new("u", samples, &MeasureOptions::new().with_discard_outliers(true))?;
+        assert_eq!((trimmed.discarded, trimmed.stats.max_ms), (1, 13.0));
+        assert_eq!(trimmed.samples_ms.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn baselines_round_trip_and_refuse_newer_versions() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("rusthttp-baseline-{}.json", crate::utils::generate_request_id()));
+        let saved = baseline(25.0);
+        saved.save(&path)?;
+        let loaded = LatencyBaseline::load(&path);
+
+        let newer = LatencyBaseline { version: BASELINE_VERSION + 1, ..saved.clone() };
+        newer.save(&path)?;
+        let refused = LatencyBaseline::load(&path);
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(loaded?, saved);
+        let error = refused.unwrap_err().to_string();
+        assert!(error.contains("newer than the supported 1"), "{error}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn measure_times_the_runs_and_checks_the_baseline() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/slow")
+            .respond(MockResponse::new(200).with_delay(Duration::from_millis(40)));
+        let client = HttpClient::new();
+        let url = server.url("/slow");
+        let options = MeasureOptions::new().with_runs(4).with_warmup(2);
+
+        let report = client.measure(&url, RequestConfig::new(), options.clone()).await?;
+        assert_eq!(server.requests().len(), 6);
+        assert_eq!((report.samples_ms.len(), report.stats.samples), (4, 4));
+        assert!(report.stats.min_ms >= 40.0, "{:?}", report.stats);
+        assert!(report.passed() && report.comparison.is_none());
+
+        let regressed = client
+            .measure(&url, RequestConfig::new(), options.clone().with_baseline(baseline(10.0), 20.0))
+            .await?;
+        let comparison = regressed.comparison.unwrap();
+        assert!(!regressed.passed() && comparison.change_percent > 20.0, "{comparison:?}");
+        assert!(regressed.render().ends_with("(threshold 20%): FAIL\n"), "{}", regressed.render());
+
+        let faster = client
+            .measure(&url, RequestConfig::new(), options.with_baseline(baseline(1000.0), 20.0))
+            .await?;
+        assert!(faster.passed() && faster.comparison.unwrap().change_percent < 0.0);
+        assert!(faster.render().ends_with(": PASS\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn a_threshold_exactly_met_still_passes() {
+        let base = LatencyStats::from_samples(&[100.0]).unwrap();
+        let current = LatencyStats::from_samples(&[120.0]).unwrap();
+        assert!(BaselineComparison::new(&base, &current, 20.0).passed);
+        assert!(!BaselineComparison::new(&base, &current, 19.9).passed);
+    }
+}
@@ -0,0 +1,509 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::models::{ApiResponse, HttpMethod};
+use crate::utils::{format_duration, format_size, host_key};
+
+/// Distinct hosts tracked before further hosts are folded into `other`.
+pub const DEFAULT_MAX_HOSTS: usize = 50;
+
+/// Upper bounds, in seconds, of the request latency histogram.
+pub const DEFAULT_LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Requests kept in `MetricsSnapshot::slowest`.
+pub const SLOWEST_REQUESTS: usize = 5;
+
+const OTHER_HOST: &str = "other";
+
+/// Labels of the `rusthttp_requests_total` counter.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestLabels {
+    pub method: String,
+    /// `2xx`, `4xx`, ... or `error` when no response arrived.
+    pub status_class: String,
+    pub host: String,
+}
+
+/// Cumulative histogram: `buckets[i]` counts observations `<= bounds[i]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    pub bounds: Vec<f64>,
+    pub buckets: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            buckets: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, bucket) in self.bounds.iter().zip(self.buckets.iter_mut()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// One of the slowest requests recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowRequest {
+    pub method: String,
+    /// As passed to `record_request`; the client passes it redacted.
+    pub url: String,
+    /// `None` when no response arrived.
+    pub status: Option<u16>,
+    pub elapsed: Duration,
+}
+
+/// Point-in-time copy of everything a `MetricsRecorder` has counted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub requests: BTreeMap<RequestLabels, u64>,
+    /// Request latency in seconds, by method.
+    pub latency: BTreeMap<String, Histogram>,
+    /// Response body bytes, by host.
+    pub bytes_received: BTreeMap<String, u64>,
+    /// Request body bytes, by host, counted once per attempt. Streamed
+    /// bodies are not counted.
+    pub bytes_sent: BTreeMap<String, u64>,
+    /// Retries beyond the first attempt, by host.
+    pub retries: BTreeMap<String, u64>,
+    /// Requests answered without going to the network, by cache.
+    pub cache_hits: BTreeMap<String, u64>,
+    /// Requests holding a connection slot, by host. Only tracked with
+    /// `HttpClient::with_max_connections_per_host`.
+    pub active_connections: BTreeMap<String, u64>,
+    /// Requests waiting for a connection slot, by host.
+    pub queued_connections: BTreeMap<String, u64>,
+    /// The `SLOWEST_REQUESTS` slowest requests, slowest first.
+    pub slowest: Vec<SlowRequest>,
+}
+
+impl MetricsSnapshot {
+    /// A plain-text closing summary of a session or batch: requests by
+    /// status class, bytes, latency, cache hits, retries and the slowest
+    /// requests.
+    pub fn render_summary(&self) -> String {
+        let mut classes: BTreeMap<&str, u64> = BTreeMap::new();
+        for (labels, count) in &self.requests {
+            *classes.entry(labels.status_class.as_str()).or_default() += count;
+        }
+        let total: u64 = classes.values().sum();
+        let breakdown: Vec<String> = classes
+            .iter()
+            .map(|(class, count)| match *class {
+                "error" => format!("{} failed", count),
+                class => format!("{} {}", count, class),
+            })
+            .collect();
+
+        let mut out = String::new();
+        let _ = write!(out, "Requests:    {}", total);
+        if !breakdown.is_empty() {
+            let _ = write!(out, " ({})", breakdown.join(", "));
+        }
+        out.push('\n');
+
+        let sent: u64 = self.bytes_sent.values().sum();
+        let received: u64 = self.bytes_received.values().sum();
+        let _ = writeln!(
+            out,
+            "Transferred: {} sent, {} received",
+            format_size(sent as usize),
+            format_size(received as usize)
+        );
+
+        let seconds: f64 = self.latency.values().map(|histogram| histogram.sum).sum();
+        let timed: u64 = self.latency.values().map(|histogram| histogram.count).sum();
+        let total_ms = (seconds * 1000.0).round() as u64;
+        let average_ms = total_ms.checked_div(timed).unwrap_or(0);
+        let _ = writeln!(
+            out,
+            "Latency:     {} total, {} average",
+            format_duration(total_ms),
+            format_duration(average_ms)
+        );
+
+        let hits: u64 = self.cache_hits.values().sum();
+        let _ = write!(out, "Cache hits:  {}", hits);
+        if hits > 0 {
+            let caches: Vec<String> = self.cache_hits.iter().map(|(cache, count)| format!("{} {}", cache, count)).collect();
+            let _ = write!(out, " ({})", caches.join(", "));
+        }
+        out.push('\n');
+        let _ = writeln!(out, "Retries:     {}", self.retries.values().sum::<u64>());
+
+        if !self.slowest.is_empty() {
+            let _ = writeln!(out, "Slowest requests:");
+            for request in &self.slowest {
+                let status = request.status.map_or("error".to_string(), |status| status.to_string());
+                let _ = writeln!(
+                    out,
+                    "  {:>8}  {} {} {}",
+                    format_duration(request.elapsed.as_millis() as u64),
+                    request.method,
+                    request.url,
+                    status
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Collects request metrics in memory. Attach one to a client with
+/// `HttpClient::with_metrics` and render it with `prometheus_text`.
+///
+/// Host labels are bounded: with an allow-list only those hosts get their
+/// own series, otherwise the first `max_hosts` distinct hosts do. Everything
+/// else is counted as `other`.
+#[derive(Debug)]
+pub struct MetricsRecorder {
+    max_hosts: usize,
+    allowed_hosts: Option<HashSet<String>>,
+    latency_buckets: Vec<f64>,
+    state: Mutex<RecorderState>,
+}
+
+#[derive(Debug, Default)]
+struct RecorderState {
+    seen_hosts: HashSet<String>,
+    snapshot: MetricsSnapshot,
+}
+
+impl Default for MetricsRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsRecorder {
+    pub fn new() -> Self {
+        Self {
+            max_hosts: DEFAULT_MAX_HOSTS,
+            allowed_hosts: None,
+            latency_buckets: DEFAULT_LATENCY_BUCKETS.to_vec(),
+            state: Mutex::new(RecorderState::default()),
+        }
+    }
+
+    pub fn with_max_hosts(mut self, max_hosts: usize) -> Self {
+        self.max_hosts = max_hosts;
+        self
+    }
+
+    pub fn with_host_allow_list<I, S>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_hosts = Some(hosts.into_iter().map(|h| host_key(&h.into())).collect());
+        self
+    }
+
+    pub fn with_latency_buckets(mut self, bounds: &[f64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        self.latency_buckets = bounds;
+        self
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.state.lock().map(|state| state.snapshot.clone()).unwrap_or_default()
+    }
+
+    pub fn reset(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = RecorderState::default();
+        }
+    }
+
+    /// Records one finished request, `retries` being the attempts after the
+    /// first.
+    pub fn record_request(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        response: Option<&ApiResponse>,
+        elapsed: Duration,
+        retries: u64,
+    ) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let host = self.host_label(&mut state, url);
+        let snapshot = &mut state.snapshot;
+
+        let status_class = match response {
+            Some(response) => format!("{}xx", response.status / 100),
+            None => "error".to_string(),
+        };
+        let labels = RequestLabels {
+            method: method.to_string(),
+            status_class,
+            host: host.clone(),
+        };
+        *snapshot.requests.entry(labels).or_default() += 1;
+
+        snapshot
+            .latency
+            .entry(method.to_string())
+            .or_insert_with(|| Histogram::new(&self.latency_buckets))
+            .observe(elapsed.as_secs_f64());
+
+        if let Some(response) = response {
+            *snapshot.bytes_received.entry(host.clone()).or_default() += response.wire_bytes;
+        }
+        if retries > 0 {
+            *snapshot.retries.entry(host).or_default() += retries;
+        }
+
+        if snapshot.slowest.len() < SLOWEST_REQUESTS || snapshot.slowest.last().is_some_and(|slow| elapsed > slow.elapsed) {
+            snapshot.slowest.push(SlowRequest {
+                method: method.to_string(),
+                url: url.to_string(),
+                status: response.map(|response| response.status),
+                elapsed,
+            });
+            snapshot.slowest.sort_by_key(|slow| std::cmp::Reverse(slow.elapsed));
+            snapshot.slowest.truncate(SLOWEST_REQUESTS);
+        }
+    }
+
+    /// Records `bytes` of request body sent to `url`'s host.
+    pub fn record_bytes_sent(&self, url: &str, bytes: u64) {
+        if let Ok(mut state) = self.state.lock() {
+            let host = self.host_label(&mut state, url);
+            *state.snapshot.bytes_sent.entry(host).or_default() += bytes;
+        }
+    }
+
+    pub fn record_cache_hit(&self, cache: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            *state.snapshot.cache_hits.entry(cache.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Sets the connection gauges of `host`. Hosts folded into `other`
+    /// share one series, which shows the latest host's values.
+    pub fn record_connections(&self, host: &str, active: u64, queued: u64) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let host = self.label_for_host(&mut state, host.to_ascii_lowercase());
+        state.snapshot.active_connections.insert(host.clone(), active);
+        state.snapshot.queued_connections.insert(host, queued);
+    }
+
+    fn host_label(&self, state: &mut RecorderState, url: &str) -> String {
+        match url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+        {
+            Some(host) => self.label_for_host(state, host),
+            None => OTHER_HOST.to_string(),
+        }
+    }
+
+    fn label_for_host(&self, state: &mut RecorderState, host: String) -> String {
+        if let Some(allowed) = &self.allowed_hosts {
+            return if allowed.contains(&host) { host } else { OTHER_HOST.to_string() };
+        }
+        if state.seen_hosts.contains(&host) {
+            return host;
+        }
+        if state.seen_hosts.len() < self.max_hosts {
+            state.seen_hosts.insert(host.clone());
+            return host;
+        }
+        OTHER_HOST.to_string()
+    }
+}
+
+/// Renders a snapshot in the Prometheus text exposition format (0.0.4).
+pub fn prometheus_text(snapshot: &MetricsSnapshot) -> String {
+    let mut out = String::new();
+
+    write_header(&mut out, "rusthttp_requests_total", "counter", "Requests by method, status class and host.");
+    for (labels, value) in &snapshot.requests {
+        let _ = writeln!(
+            out,
+            "rusthttp_requests_total{{method=\"{}\",status_class=\"{}\",host=\"{}\"}} {}",
+            escape_label(&labels.method),
+            escape_label(&labels.status_class),
+            escape_label(&labels.host),
+            value
+        );
+    }
+
+    write_header(&mut out, "rusthttp_request_duration_seconds", "histogram", "Request latency including retries.");
+    for (method, histogram) in &snapshot.latency {
+        let method = escape_label(method);
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.buckets) {
+            let _ = writeln!(
+                out,
+                "rusthttp_request_duration_seconds_bucket{{method=\"{}\",le=\"{}\"}} {}",
+                method, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "rusthttp_request_duration_seconds_bucket{{method=\"{}\",le=\"+Inf\"}} {}",
+            method, histogram.count
+        );
+        let _ = writeln!(out, "rusthttp_request_duration_seconds_sum{{method=\"{}\"}} {}", method, histogram.sum);
+        let _ = writeln!(out, "rusthttp_request_duration_seconds_count{{method=\"{}\"}} {}", method, histogram.count);
+    }
+
+    write_counter_family(&mut out, "rusthttp_response_bytes_total", "Response body bytes received.", "host", &snapshot.bytes_received);
+    write_counter_family(&mut out, "rusthttp_request_bytes_total", "Request body bytes sent.", "host", &snapshot.bytes_sent);
+    write_counter_family(&mut out, "rusthttp_retries_total", "Retry attempts after the first.", "host", &snapshot.retries);
+    write_counter_family(&mut out, "rusthttp_cache_hits_total", "Requests served without a network round trip.", "cache", &snapshot.cache_hits);
+    write_family(&mut out, "rusthttp_connections_active", "gauge", "Requests holding a connection slot.", "host", &snapshot.active_connections);
+    write_family(&mut out, "rusthttp_connections_queued", "gauge", "Requests waiting for a connection slot.", "host", &snapshot.queued_connections);
+
+    out
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help.replace('\\', "\\\\").replace('\n', "\\n"));
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_counter_family(out: &mut String, name: &str, help: &str, label: &str, values: &BTreeMap<String, u64>) {
+    write_family(out, name, "counter", help, label, values);
+}
+
+fn write_family(out: &mut String, name: &str, kind: &str, help: &str, label: &str, values: &BTreeMap<String, u64>) {
+    write_header(out, name, kind, help);
+    for (key, value) in values {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, escape_label(key), value);
+    }
+}
+
+/// Escapes a label value: backslash, double quote and line feed.
+fn escape_label(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            other => escaped.push(other),
+        }
+    }
+    escaped
+}
+
+/// Serves `GET /metrics` on `addr` until the future is dropped.
+#[cfg(feature = "metrics-server")]
+pub async fn serve(addr: std::net::SocketAddr, recorder: std::sync::Arc<MetricsRecorder>) -> anyhow::Result<()> {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use std::convert::Infallible;
+
+    let make_service = make_service_fn(move |_| {
+        let recorder = recorder.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let recorder = recorder.clone();
+                async move {
+                    let response = if request.uri().path() == "/metrics" {
+                        Response::builder()
+                            .header("content-type", "text/plain; version=0.0.4; charset=utf-8")
+                            .body(Body::from(prometheus_text(&recorder.snapshot())))
+                    } else {
+                        Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty())
+                    };
+                    Ok::<_, Infallible>(response.unwrap_or_default())
+                }
+            }))
+        }
+    });
+
+    Server::try_bind(&addr)
+        .map_err(|e| anyhow::anyhow!("Failed to bind metrics server on {}: {}", addr, e))?
+        .serve(make_service)
+        .await
+        .map_err(|e| anyhow::anyhow!("Metrics server failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prometheus_text_renders_every_family() {
+        let recorder = MetricsRecorder::new().with_latency_buckets(&[1.0, 0.1]);
+        let mut response = ApiResponse::synthetic(200, "");
+        response.wire_bytes = 42;
+        recorder.record_request(HttpMethod::Get, "https://API.example.com/items?page=2", Some(&response), Duration::from_millis(50), 1);
+        recorder.record_request(HttpMethod::Post, "https://api.example.com/items", None, Duration::from_secs(2), 0);
+        recorder.record_bytes_sent("https://api.example.com/items", 7);
+        recorder.record_cache_hit("coalesce");
+        recorder.record_connections("api.example.com", 1, 0);
+
+        let expected = r#"# HELP rusthttp_requests_total Requests by method, status class and host.
+# TYPE rusthttp_requests_total counter
+rusthttp_requests_total{method="GET",status_class="2xx",host="api.example.com"} 1
+rusthttp_requests_total{method="POST",status_class="error",host="api.example.com"} 1
+# HELP rusthttp_request_duration_seconds Request latency including retries.
+# TYPE rusthttp_request_duration_seconds histogram
+rusthttp_request_duration_seconds_bucket{method="GET",le="0.1"} 1
+rusthttp_request_duration_seconds_bucket{method="GET",le="1"} 1
+rusthttp_request_duration_seconds_bucket{method="GET",le="+Inf"} 1
+rusthttp_request_duration_seconds_sum{method="GET"} 0.05
+rusthttp_request_duration_seconds_count{method="GET"} 1
+rusthttp_request_duration_seconds_bucket{method="POST",le="0.1"} 0
+rusthttp_request_duration_seconds_bucket{method="POST",le="1"} 0
+rusthttp_request_duration_seconds_bucket{method="POST",le="+Inf"} 1
+rusthttp_request_duration_seconds_sum{method="POST"} 2
+rusthttp_request_duration_seconds_count{method="POST"} 1
+# HELP rusthttp_response_bytes_total Response body bytes received.
+# TYPE rusthttp_response_bytes_total counter
+rusthttp_response_bytes_total{host="api.example.com"} 42
+# HELP rusthttp_request_bytes_total Request body bytes sent.
+# TYPE rusthttp_request_bytes_total counter
+rusthttp_request_bytes_total{host="api.example.com"} 7
+# HELP rusthttp_retries_total Retry attempts after the first.
+# TYPE rusthttp_retries_total counter
+rusthttp_retries_total{host="api.example.com"} 1
+# HELP rusthttp_cache_hits_total Requests served without a network round trip.
+# TYPE rusthttp_cache_hits_total counter
+rusthttp_cache_hits_total{cache="coalesce"} 1
+# HELP rusthttp_connections_active Requests holding a connection slot.
+# TYPE rusthttp_connections_active gauge
+rusthttp_connections_active{host="api.example.com"} 1
+# HELP rusthttp_connections_queued Requests waiting for a connection slot.
+# TYPE rusthttp_connections_queued gauge
+rusthttp_connections_queued{host="api.example.com"} 0
+"#;
+        assert_eq!(prometheus_text(&recorder.snapshot()), expected);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label(r#"C:\tmp "x""#), r#"C:\\tmp \"x\""#);
+        assert_eq!(escape_label("a\nb"), r"a\nb");
+        assert_eq!(escape_label("plain-host.example"), "plain-host.example");
+
+        let mut snapshot = MetricsSnapshot::default();
+        snapshot.cache_hits.insert("odd\\\"\ncache".to_string(), 3);
+        let text = prometheus_text(&snapshot);
+        assert!(text.contains("rusthttp_cache_hits_total{cache=\"odd\\\\\\\"\\ncache\"} 3\n"), "{}", text);
+        assert_eq!(text.lines().filter(|line| line.starts_with("rusthttp_cache_hits_total")).count(), 1);
+    }
+}
@@ -1,90 +1,1974 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Header carrying the request ID, see `RequestConfig::with_request_id`.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiResponse {
     pub status: u16,
+    /// Status as sent by the server, before `HttpClient::on_status` mapping.
+    #[serde(default)]
+    pub original_status: u16,
     pub status_text: String,
+    /// Lowercase names. Repeated `Set-Cookie` headers are joined with
+    /// newlines, one cookie per line.
     pub headers: HashMap<String, String>,
+    /// The body decoded to text. Empty when it was spilled to a file
+    /// (`is_spilled()`); `text()`, `bytes()` and `reader()` work either way.
     pub body: String,
+    /// The `Content-Type` header, or empty when the server sent none.
     pub content_type: String,
+    /// Set when the server sent no `Content-Type`; the format is then
+    /// sniffed from the body.
+    #[serde(default)]
+    pub content_type_missing: bool,
     pub response_time_ms: u64,
+    /// Wall time across every attempt, including backoff sleeps.
+    #[serde(default)]
+    pub total_time_ms: u64,
+    /// Time spent resolving the requested and final hosts during the
+    /// request; `None` when nothing was resolved, e.g. on a pooled
+    /// connection or an IP address.
+    #[serde(default)]
+    pub dns_time_ms: Option<u64>,
+    /// One record per attempt; only filled in when retries are configured.
+    #[serde(default)]
+    pub attempts: Vec<AttemptRecord>,
+    /// Base URL that served the request when load balancing is configured.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// TLS session details; `None` for plain HTTP.
+    #[serde(default)]
+    pub tls_info: Option<TlsInfo>,
+    /// The request this response answers.
+    #[serde(default)]
+    pub request: Option<RequestInfo>,
+    /// `(Content-Length, bytes received)` when the body came up short and
+    /// `RequestConfig::strict_length` is off.
+    #[serde(default)]
+    pub length_mismatch: Option<(u64, u64)>,
+    /// Body bytes read off the connection, counted as they arrive (chunked
+    /// bodies included) and before any `Content-Encoding` is decoded. 0 for
+    /// deferred bodies until they are read.
+    #[serde(default)]
+    pub wire_bytes: u64,
+    /// Body length after content decoding, before the charset decoding that
+    /// fills `body`. 0 for discarded and unread deferred bodies.
+    #[serde(default)]
+    pub decoded_bytes: u64,
+    /// Served from the client's response cache without a network request.
+    #[serde(default)]
+    pub from_cache: bool,
+    /// Suspicious things about the request, see `WarningCode`.
+    #[serde(default)]
+    pub warnings: Vec<crate::warnings::RequestWarning>,
+    /// Whether the response cache answered the request; `Hit` exactly when
+    /// `from_cache` is set.
+    #[serde(default)]
+    pub cache_status: CacheStatus,
+    #[serde(skip)]
+    pub(crate) raw_body: Vec<u8>,
+    #[serde(skip)]
+    pub(crate) pending_body: Option<PendingBody>,
+    /// Serialized as `spilled_bytes`, so a spilled response's empty `body`
+    /// is not mistaken for an empty one. The file itself is not kept.
+    #[serde(
+        rename = "spilled_bytes",
+        serialize_with = "crate::spill::serialize_len",
+        skip_serializing_if = "Option::is_none",
+        skip_deserializing
+    )]
+    pub(crate) spilled: Option<crate::spill::SpilledBody>,
+}
+
+/// The range a status code falls in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StatusClass {
+    /// 1xx
+    Informational,
+    /// 2xx
+    Success,
+    /// 3xx
+    Redirect,
+    /// 4xx
+    ClientError,
+    /// 5xx
+    ServerError,
+    /// Anything outside 100-599.
+    Unknown,
+}
+
+impl StatusClass {
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            100..=199 => StatusClass::Informational,
+            200..=299 => StatusClass::Success,
+            300..=399 => StatusClass::Redirect,
+            400..=499 => StatusClass::ClientError,
+            500..=599 => StatusClass::ServerError,
+            _ => StatusClass::Unknown,
+        }
+    }
+}
+
+/// What a response body contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BodyFormat {
+    Json,
+    Html,
+    Xml,
+    Text,
+    Binary,
+}
+
+impl BodyFormat {
+    /// Maps a `Content-Type` value to a format; unrecognized types count as
+    /// binary.
+    pub fn from_content_type(content_type: &str) -> Self {
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        if mime.ends_with("/json") || mime.ends_with("+json") {
+            BodyFormat::Json
+        } else if mime == "text/html" || mime == "application/xhtml+xml" {
+            BodyFormat::Html
+        } else if mime.ends_with("/xml") || mime.ends_with("+xml") {
+            BodyFormat::Xml
+        } else if mime.starts_with("text/")
+            || mime == "application/javascript"
+            || mime == "application/x-www-form-urlencoded"
+        {
+            BodyFormat::Text
+        } else {
+            BodyFormat::Binary
+        }
+    }
 }
 
-impl ApiResponse {
-    pub fn is_success(&self) -> bool {
-        (200..300).contains(&self.status)
+/// Origin of a response, for correlating it with the call that made it.
+/// Both URLs have been through the client's `UrlRedactor`, so sensitive
+/// query values and passwords read `***`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestInfo {
+    pub method: HttpMethod,
+    /// The URL as requested (resolved against the base URL when load
+    /// balancing).
+    pub url: String,
+    /// Where the response actually came from after following redirects.
+    pub final_url: String,
+    #[serde(with = "crate::utils::rfc3339")]
+    pub started_at: std::time::SystemTime,
+    /// Source address the request was bound to, if one was requested.
+    #[serde(default)]
+    pub local_address: Option<std::net::IpAddr>,
+    /// The `X-Request-Id` sent, for matching server logs.
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// The DNS name whose addresses served the final URL: its own host, or
+    /// the fallback from `HttpClient::with_dns_fallback`.
+    #[serde(default)]
+    pub resolved_host: Option<String>,
+}
+
+impl RequestInfo {
+    /// Compares parsed URLs, so a Unicode host matches its punycode form.
+    pub fn was_redirected(&self) -> bool {
+        match (url::Url::parse(&self.url), url::Url::parse(&self.final_url)) {
+            (Ok(url), Ok(final_url)) => url != final_url,
+            _ => self.url != self.final_url,
+        }
+    }
+}
+
+/// How a response cache took part in producing a response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheStatus {
+    /// Served from the cache without a network request.
+    Hit,
+    /// Fetched from the network and eligible for caching.
+    Miss,
+    /// A stored response was confirmed fresh by the origin (304).
+    Revalidated,
+    /// The cache was not consulted, or no cache is configured.
+    #[default]
+    Bypass,
+}
+
+/// Upper bound on bytes drained from an unread deferred body when it is dropped.
+const DEFERRED_DRAIN_LIMIT: usize = 64 * 1024;
+
+/// Unread response body kept alive by `RequestConfig::defer_body`.
+#[derive(Clone)]
+pub(crate) struct PendingBody {
+    inner: Arc<PendingBodyInner>,
+}
+
+/// What the request allowed its body, applied when a deferred body is read
+/// just as when the client reads it right away.
+#[derive(Default)]
+pub(crate) struct BodyLimits {
+    pub(crate) max_body_size: Option<usize>,
+    pub(crate) spill_threshold: Option<usize>,
+    pub(crate) spill_dir: Option<std::path::PathBuf>,
+    pub(crate) partial_body_limit: Option<usize>,
+    /// Charged for bodies without a `Content-Length`; the others were
+    /// reserved when the headers arrived.
+    pub(crate) quota: Option<Arc<crate::quota::TransferQuota>>,
+}
+
+impl BodyLimits {
+    pub(crate) fn new(config: &RequestConfig, quota: Option<Arc<crate::quota::TransferQuota>>) -> Self {
+        Self {
+            max_body_size: config.max_body_size,
+            spill_threshold: config.spill_threshold,
+            spill_dir: config.spill_dir.clone(),
+            partial_body_limit: config.partial_body_limit,
+            quota,
+        }
+    }
+}
+
+struct PendingBodyInner {
+    response: Mutex<Option<reqwest::Response>>,
+    expected_length: Option<u64>,
+    strict_length: bool,
+    idle_timeout: Option<Duration>,
+    limits: BodyLimits,
+    /// Connection slot held until the body is read, discarded or dropped.
+    permit: Mutex<Option<crate::connections::ConnectionPermit>>,
+    /// Keeps the request counted by `HttpClient::shutdown` for as long.
+    request: Mutex<Option<crate::shutdown::RequestGuard>>,
+}
+
+impl PendingBody {
+    pub(crate) fn new(
+        response: reqwest::Response,
+        expected_length: Option<u64>,
+        strict_length: bool,
+        idle_timeout: Option<Duration>,
+        limits: BodyLimits,
+        permit: Option<crate::connections::ConnectionPermit>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(PendingBodyInner {
+                response: Mutex::new(Some(response)),
+                expected_length,
+                strict_length,
+                idle_timeout,
+                limits,
+                permit: Mutex::new(permit),
+                request: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub(crate) fn track(&self, request: crate::shutdown::RequestGuard) {
+        if let Ok(mut slot) = self.inner.request.lock() {
+            *slot = Some(request);
+        }
+    }
+
+    /// `stream::next_chunk`, or `ClientClosed` once shutdown cancels the
+    /// request.
+    async fn next_chunk(
+        &self,
+        response: &mut reqwest::Response,
+    ) -> Result<Option<reqwest::Result<Option<bytes::Bytes>>>, crate::error::ClientClosed> {
+        let next = crate::stream::next_chunk(response, self.inner.idle_timeout);
+        let cancel = self.inner.request.lock().ok().and_then(|slot| slot.as_ref().map(|request| request.cancel_signal()));
+        let Some(mut cancel) = cancel else {
+            return Ok(next.await);
+        };
+        tokio::select! {
+            biased;
+            _ = crate::shutdown::cancelled(&mut cancel) => Err(crate::error::ClientClosed { cancelled: true }),
+            next = next => Ok(next),
+        }
+    }
+
+    /// Charges `chunk` to the quota and checks the size limit, `received`
+    /// counting the chunk.
+    fn check_chunk(&self, chunk: usize, received: u64) -> Result<(), ChunkRejected> {
+        let limits = &self.inner.limits;
+        if let Some(quota) = limits.quota.as_ref().filter(|_| self.inner.expected_length.is_none()) {
+            quota.record(chunk as u64).map_err(ChunkRejected::Quota)?;
+        }
+        match limits.max_body_size {
+            Some(limit) if received > limit as u64 => Err(ChunkRejected::TooLarge(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    fn take(&self) -> Option<reqwest::Response> {
+        self.inner.response.lock().ok().and_then(|mut guard| guard.take())
+    }
+
+    /// Frees the connection slot once the body has been consumed.
+    fn release(&self) {
+        if let Ok(mut permit) = self.inner.permit.lock() {
+            permit.take();
+        }
+        if let Ok(mut request) = self.inner.request.lock() {
+            request.take();
+        }
+    }
+
+    /// The rest of the body as it arrives, holding the connection slot until
+    /// it ends. Encodings the client decodes itself come as one chunk.
+    fn into_chunks(
+        self,
+        response: reqwest::Response,
+        mut headers: HashMap<String, String>,
+    ) -> futures_util::stream::BoxStream<'static, std::io::Result<bytes::Bytes>> {
+        use futures_util::{StreamExt, TryStreamExt};
+        use std::io::{Error, ErrorKind};
+
+        let chunks = futures_util::stream::unfold(Some((self, response, 0u64)), |state| async move {
+            let (pending, mut response, received) = state?;
+            let short = |received: u64| pending.inner.expected_length.is_some_and(|n| received < n);
+            let next = match pending.next_chunk(&mut response).await {
+                Ok(next) => next,
+                Err(closed) => return Some((Err(Error::other(closed)), None)),
+            };
+            match next {
+                None => Some((
+                    Err(Error::new(ErrorKind::TimedOut, "Response body stalled longer than the idle timeout")),
+                    None,
+                )),
+                Some(Ok(Some(chunk))) => {
+                    let received = received + chunk.len() as u64;
+                    match pending.check_chunk(chunk.len(), received) {
+                        Ok(()) => Some((Ok(chunk), Some((pending, response, received)))),
+                        Err(rejected) => Some((Err(Error::other(rejected.into_error())), None)),
+                    }
+                }
+                Some(Ok(None)) | Some(Err(_)) if short(received) && pending.inner.strict_length => Some((
+                    Err(Error::new(ErrorKind::UnexpectedEof, "Response body ended before its Content-Length")),
+                    None,
+                )),
+                Some(Err(e)) if !short(received) => Some((Err(Error::other(e)), None)),
+                Some(_) => {
+                    pending.release();
+                    None
+                }
+            }
+        });
+
+        if !crate::utils::is_client_decoded(&headers) {
+            return chunks.boxed();
+        }
+        futures_util::stream::once(async move {
+            let bytes = chunks
+                .try_fold(Vec::new(), |mut bytes, chunk| async move {
+                    bytes.extend_from_slice(&chunk);
+                    Ok(bytes)
+                })
+                .await?;
+            crate::utils::decode_content_encoding(&mut headers, bytes)
+                .map(bytes::Bytes::from)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+        })
+        .boxed()
+    }
+}
+
+/// Why `PendingBody::check_chunk` stopped a deferred body.
+enum ChunkRejected {
+    Quota(crate::error::QuotaExceeded),
+    TooLarge(usize),
+}
+
+impl ChunkRejected {
+    fn into_error(self) -> anyhow::Error {
+        match self {
+            ChunkRejected::Quota(e) => e.into(),
+            ChunkRejected::TooLarge(limit) => anyhow::anyhow!("Response body exceeds the {} byte limit", limit),
+        }
+    }
+}
+
+impl std::fmt::Debug for PendingBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unread = self.inner.response.lock().map(|guard| guard.is_some()).unwrap_or(false);
+        f.debug_struct("PendingBody").field("unread", &unread).finish()
+    }
+}
+
+impl Drop for PendingBodyInner {
+    fn drop(&mut self) {
+        let response = self.response.get_mut().ok().and_then(|slot| slot.take());
+        let permit = self.permit.get_mut().ok().and_then(|slot| slot.take());
+        if let (Some(mut response), Ok(handle)) = (response, tokio::runtime::Handle::try_current()) {
+            handle.spawn(async move {
+                let _permit = permit;
+                let mut drained = 0;
+                while drained < DEFERRED_DRAIN_LIMIT {
+                    match response.chunk().await {
+                        Ok(Some(chunk)) => drained += chunk.len(),
+                        _ => break,
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// One-line summary such as `200 OK application/json 4.20 KB in 183ms`,
+/// followed by the final URL after a redirect and `(cached)` for responses
+/// from the response cache. See `ApiResponse::summary_colored`.
+impl std::fmt::Display for ApiResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}{}", self.status, self.status_text, self.summary_details())
+    }
+}
+
+impl ApiResponse {
+    /// A response that did not come from the network, e.g. one returned by
+    /// an `HttpClient::before_request` interceptor.
+    pub fn synthetic(status: u16, body: impl Into<String>) -> Self {
+        let body = body.into();
+        Self {
+            status,
+            original_status: status,
+            status_text: reqwest::StatusCode::from_u16(status)
+                .ok()
+                .and_then(|code| code.canonical_reason())
+                .unwrap_or("Unknown")
+                .to_string(),
+            headers: HashMap::new(),
+            content_type: String::new(),
+            content_type_missing: true,
+            response_time_ms: 0,
+            total_time_ms: 0,
+            dns_time_ms: None,
+            attempts: Vec::new(),
+            target: None,
+            tls_info: None,
+            request: None,
+            length_mismatch: None,
+            wire_bytes: 0,
+            decoded_bytes: body.len() as u64,
+            raw_body: body.clone().into_bytes(),
+            body,
+            pending_body: None,
+            spilled: None,
+            from_cache: false,
+            warnings: Vec::new(),
+            cache_status: CacheStatus::Bypass,
+        }
+    }
+
+    /// Sets a header on a synthetic response; `Content-Type` also sets
+    /// `content_type`.
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let (key, value) = (key.into().to_ascii_lowercase(), value.into());
+        if key == "content-type" {
+            self.content_type = value.clone();
+            self.content_type_missing = false;
+        }
+        self.headers.insert(key, value);
+        self
+    }
+
+    /// The `Display` summary with the status colored by `status_indicator`.
+    pub fn summary_colored(&self) -> String {
+        format!(
+            "{} {}{}",
+            crate::utils::status_indicator(self.status),
+            self.status_text,
+            self.summary_details()
+        )
+    }
+
+    /// Everything in the summary after the status line.
+    fn summary_details(&self) -> String {
+        let mut details = String::new();
+        let mime = self.content_type.split(';').next().unwrap_or("").trim();
+        if !mime.is_empty() {
+            details.push_str(&format!(" {}", mime));
+        }
+        if self.has_pending_body() {
+            details.push_str(" (body not read)");
+        } else {
+            let size = self.spilled.as_ref().map_or(self.raw_body.len(), |spilled| spilled.len() as usize);
+            details.push_str(&format!(" {}", crate::utils::format_size(size)));
+        }
+        details.push_str(&format!(" in {}", crate::utils::format_duration(self.total_time_ms)));
+        if let Some(request) = self.request.as_ref().filter(|request| request.was_redirected()) {
+            details.push_str(&format!(" → {}", UrlRedactor::default().redact_for_display(&request.final_url)));
+        }
+        if self.from_cache {
+            details.push_str(" (cached)");
+        }
+        details
+    }
+
+    /// Returns `true` while a deferred body has not been read or discarded yet.
+    pub fn has_pending_body(&self) -> bool {
+        self.pending_body.is_some()
+    }
+
+    /// Downloads a body deferred via `RequestConfig::defer_body`, storing it in
+    /// `body`. Later calls return the already-read body.
+    pub async fn read_body(&mut self) -> anyhow::Result<&str> {
+        self.read_body_into(&mut std::io::sink()).await
+    }
+
+    /// Like `read_body`, also copying each chunk into `sink` as it arrives,
+    /// so the body is saved and returned in a single pass. A body that was
+    /// already read, or a `zstd` body decoded by the client, is written to
+    /// `sink` in one go. The request's `max_body_size`, `spill_threshold`
+    /// and the client's transfer quota apply as for bodies read right away.
+    pub async fn read_body_into<W: std::io::Write>(&mut self, sink: &mut W) -> anyhow::Result<&str> {
+        use anyhow::Context;
+
+        let pending = self
+            .pending_body
+            .take()
+            .and_then(|pending| pending.take().map(|response| (pending, response)));
+        let Some((pending, mut response)) = pending else {
+            std::io::copy(&mut self.reader(), sink).context("Failed to write response body to sink")?;
+            sink.flush().context("Failed to write response body to sink")?;
+            return Ok(&self.body);
+        };
+
+        let expected = pending.inner.expected_length;
+        // Encoded bodies reach `sink` in one go once decoded.
+        let decoded_later = crate::utils::is_client_decoded(&self.headers);
+        let limits = &pending.inner.limits;
+        let spill_threshold = limits.spill_threshold.filter(|_| !decoded_later);
+        // Once spilled, `bytes` only keeps the head for `PartialResponse`.
+        let mut bytes = Vec::new();
+        let mut spill: Option<crate::spill::SpillWriter> = None;
+        let mut received = 0;
+        let partial = |bytes: &[u8], received: usize| crate::error::PartialResponse {
+            status: Some(self.status),
+            headers: self.headers.clone(),
+            bytes_received: received,
+            partial_body: limits
+                .partial_body_limit
+                .map(|limit| String::from_utf8_lossy(&bytes[..bytes.len().min(limit)]).into_owned()),
+            warnings: self.warnings.clone(),
+            ..Default::default()
+        };
+        loop {
+            let Some(next) = pending.next_chunk(&mut response).await? else {
+                return Err(crate::error::RequestError::Timeout {
+                    kind: crate::error::TimeoutKind::Read,
+                    partial: partial(&bytes, received),
+                }
+                .into());
+            };
+            match next {
+                Ok(Some(chunk)) => {
+                    received += chunk.len();
+                    if !decoded_later {
+                        sink.write_all(&chunk).context("Failed to write response body to sink")?;
+                    }
+                    match spill.as_mut() {
+                        Some(writer) => writer.write(&chunk).await?,
+                        None => bytes.extend_from_slice(&chunk),
+                    }
+                    if let Some(threshold) = spill_threshold
+                        && spill.is_none()
+                        && bytes.len() > threshold
+                    {
+                        let mut writer = crate::spill::SpillWriter::create(limits.spill_dir.as_deref()).await?;
+                        writer.write(&bytes).await?;
+                        bytes.truncate(limits.partial_body_limit.unwrap_or(0));
+                        bytes.shrink_to_fit();
+                        spill = Some(writer);
+                    }
+                    match pending.check_chunk(chunk.len(), received as u64) {
+                        Ok(()) => {}
+                        Err(ChunkRejected::Quota(e)) => return Err(e.into()),
+                        Err(ChunkRejected::TooLarge(limit)) => {
+                            return Err(crate::error::RequestError::BodyTooLarge {
+                                limit,
+                                partial: partial(&bytes, received),
+                            }
+                            .into());
+                        }
+                    }
+                }
+                Ok(None) => break,
+                // An early close is a length mismatch, handled below.
+                Err(_) if expected.is_some_and(|n| (received as u64) < n) => break,
+                Err(e) => anyhow::bail!("Failed to read response body: {}", e),
+            }
+        }
+        pending.release();
+
+        if let Some(expected) = expected
+            && expected != received as u64
+        {
+            if pending.inner.strict_length {
+                return Err(crate::error::RequestError::TruncatedBody {
+                    expected,
+                    received: received as u64,
+                    partial: partial(&bytes, received),
+                }
+                .into());
+            }
+            self.length_mismatch = Some((expected, received as u64));
+        }
+        self.wire_bytes = received as u64;
+        if let Some(writer) = spill {
+            sink.flush().context("Failed to write response body to sink")?;
+            let spilled = writer.finish().await?;
+            self.decoded_bytes = spilled.len();
+            self.spilled = Some(spilled);
+            return Ok(&self.body);
+        }
+        if decoded_later {
+            bytes = crate::utils::decode_content_encoding(&mut self.headers, bytes)?;
+            sink.write_all(&bytes).context("Failed to write response body to sink")?;
+        }
+        sink.flush().context("Failed to write response body to sink")?;
+        self.body = crate::utils::decode_body(&bytes, &self.content_type);
+        self.decoded_bytes = bytes.len() as u64;
+        self.raw_body = bytes;
+        Ok(&self.body)
+    }
+
+    /// The buffered body as a `std::io::Read`, for parsers that take readers;
+    /// a spilled body is read from its file. A deferred body has to be read
+    /// first; `into_async_reader` streams it.
+    pub fn into_reader(self) -> impl std::io::Read {
+        match self.spilled {
+            Some(body) => crate::spill::BodyReader::File { body, file: None },
+            None => crate::spill::BodyReader::Memory(std::io::Cursor::new(std::borrow::Cow::Owned(self.raw_body))),
+        }
+    }
+
+    /// The body as a `tokio::io::AsyncRead`. A deferred body is read from the
+    /// connection as it arrives, failing with `TimedOut` after the idle
+    /// timeout and, under `strict_length`, `UnexpectedEof` when it ends short
+    /// of `Content-Length`. Otherwise the buffered or spilled bytes are read.
+    pub fn into_async_reader(mut self) -> impl tokio::io::AsyncRead + Send + Unpin {
+        use futures_util::StreamExt;
+
+        let pending = self
+            .pending_body
+            .take()
+            .and_then(|pending| pending.take().map(|response| (pending, response)));
+        let chunks = match (pending, self.spilled) {
+            (Some((pending, response)), _) => pending.into_chunks(response, std::mem::take(&mut self.headers)),
+            (None, Some(spilled)) => crate::spill::file_chunks(spilled),
+            (None, None) => futures_util::stream::once(std::future::ready(Ok(bytes::Bytes::from(self.raw_body)))).boxed(),
+        };
+        crate::stream::ChunkReader::new(chunks)
+    }
+
+    /// The body exactly as received, before any charset decoding. Empty
+    /// when it was spilled to a file, see `bytes()`.
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.raw_body
+    }
+
+    /// `body`, or a spilled body read from its file and decoded.
+    pub fn text(&self) -> anyhow::Result<std::borrow::Cow<'_, str>> {
+        match &self.spilled {
+            Some(spilled) => Ok(crate::utils::decode_body(&spilled.read()?, &self.content_type).into()),
+            None => Ok(self.body.as_str().into()),
+        }
+    }
+
+    /// `body_bytes`, or a spilled body loaded from its file.
+    pub fn bytes(&self) -> anyhow::Result<std::borrow::Cow<'_, [u8]>> {
+        match &self.spilled {
+            Some(spilled) => Ok(spilled.read()?.into()),
+            None => Ok(self.raw_body.as_slice().into()),
+        }
+    }
+
+    /// Reads the body; a spilled one streams from its file without being
+    /// loaded into memory.
+    pub fn reader(&self) -> impl std::io::Read + Send + '_ {
+        match &self.spilled {
+            Some(body) => crate::spill::BodyReader::File { body: body.clone(), file: None },
+            None => crate::spill::BodyReader::Memory(std::io::Cursor::new(self.raw_body.as_slice().into())),
+        }
+    }
+
+    /// Whether the body went to a temporary file instead of `body`, see
+    /// `RequestConfig::with_spill_threshold`.
+    pub fn is_spilled(&self) -> bool {
+        self.spilled.is_some()
+    }
+
+    /// The temporary file holding a body over
+    /// `RequestConfig::with_spill_threshold`.
+    pub fn spilled_body(&self) -> Option<&crate::spill::SpilledBody> {
+        self.spilled.as_ref()
+    }
+
+    /// Splits a `multipart/*` body into its parts, parsing embedded
+    /// `application/http` responses along the way.
+    pub fn parse_multipart(&self) -> anyhow::Result<Vec<crate::multipart::MultipartPart>> {
+        crate::multipart::parse_multipart(&self.content_type, &self.bytes()?)
+    }
+
+    /// The filename the server suggests via `Content-Disposition`, with
+    /// RFC 5987 `filename*` values percent-decoded in their charset.
+    pub fn suggested_filename(&self) -> Option<String> {
+        crate::utils::content_disposition_filename(self.headers.get("content-disposition")?)
+    }
+
+    /// Content length, type, last-modified date, ETag and cache directives
+    /// parsed from the headers.
+    pub fn typed_headers(&self) -> crate::headers::TypedHeaders {
+        crate::headers::TypedHeaders::from_response(self)
+    }
+
+    pub fn is_zip(&self) -> bool {
+        let mime = self.content_type.split(';').next().unwrap_or("").trim();
+        mime.eq_ignore_ascii_case("application/zip")
+            || mime.eq_ignore_ascii_case("application/x-zip-compressed")
+    }
+
+    /// Extracts a zip archive body into `(name, contents)` pairs, skipping
+    /// directory entries.
+    #[cfg(feature = "zip")]
+    pub fn unzip_entries(&self) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        use std::io::Read;
+
+        if !self.is_zip() {
+            anyhow::bail!("Response is not a zip archive (content type {})", self.content_type);
+        }
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(self.bytes()?))
+            .map_err(|e| anyhow::anyhow!("Failed to open zip archive: {}", e))?;
+
+        let mut entries = Vec::with_capacity(archive.len());
+        for index in 0..archive.len() {
+            let mut file = archive
+                .by_index(index)
+                .map_err(|e| anyhow::anyhow!("Failed to read zip entry {}: {}", index, e))?;
+            if file.is_dir() {
+                continue;
+            }
+
+            let mut contents = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut contents)
+                .map_err(|e| anyhow::anyhow!("Failed to extract {}: {}", file.name(), e))?;
+            entries.push((file.name().to_string(), contents));
+        }
+
+        Ok(entries)
+    }
+
+    /// Saves the response as `<dir>/<name>.json` with the default
+    /// `NormalizeOptions`, returning the path.
+    pub fn to_fixture(&self, name: &str, dir: impl AsRef<std::path::Path>) -> anyhow::Result<std::path::PathBuf> {
+        self.to_fixture_with(name, dir, &crate::fixtures::NormalizeOptions::default())
+    }
+
+    pub fn to_fixture_with(
+        &self,
+        name: &str,
+        dir: impl AsRef<std::path::Path>,
+        options: &crate::fixtures::NormalizeOptions,
+    ) -> anyhow::Result<std::path::PathBuf> {
+        crate::fixtures::Fixture::from_response(name, self, options)?.save(dir)
+    }
+
+    /// Loads a response saved by `to_fixture`. Only status, headers and body
+    /// are restored.
+    pub fn from_fixture(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        crate::fixtures::Fixture::load(path)?.to_response()
+    }
+
+    /// Drains a deferred body without keeping it so the connection can go
+    /// back to the pool.
+    pub async fn discard_body(&mut self) -> anyhow::Result<()> {
+        if let Some(pending) = self.pending_body.take()
+            && let Some(mut response) = pending.take()
+        {
+            while pending
+                .next_chunk(&mut response)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Response body stalled while draining"))?
+                .map_err(|e| anyhow::anyhow!("Failed to drain response body: {}", e))?
+                .is_some()
+            {}
+            pending.release();
+        }
+        Ok(())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status_class() == StatusClass::Success
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        self.status_class() == StatusClass::Redirect
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        self.status_class() == StatusClass::ClientError
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        self.status_class() == StatusClass::ServerError
+    }
+
+    pub fn status_class(&self) -> StatusClass {
+        StatusClass::from_status(self.status)
+    }
+
+    pub fn is_json(&self) -> bool {
+        if self.content_type_missing {
+            return self.detected_format() == BodyFormat::Json;
+        }
+        self.content_type.contains("application/json")
+    }
+
+    pub fn is_html(&self) -> bool {
+        if self.content_type_missing {
+            return self.detected_format() == BodyFormat::Html;
+        }
+        self.content_type.contains("text/html") || self.content_type.contains("application/xhtml+xml")
+    }
+
+    /// The body format according to `Content-Type`, or sniffed from the
+    /// body when the header is missing.
+    pub fn detected_format(&self) -> BodyFormat {
+        if !self.content_type_missing {
+            return BodyFormat::from_content_type(&self.content_type);
+        }
+        self.sniffed_format()
+    }
+
+    /// Whether the body parses as JSON, whatever `Content-Type` claims.
+    pub fn looks_like_json(&self) -> bool {
+        self.sniffed_format() == BodyFormat::Json
+    }
+
+    fn sniffed_format(&self) -> BodyFormat {
+        if self.raw_body.is_empty() && self.spilled.is_none() {
+            crate::utils::sniff_body_format(self.body.as_bytes())
+        } else {
+            crate::utils::sniff_body_format(&self.bytes().unwrap_or_default())
+        }
+    }
+
+    pub fn parse_json<T>(&self) -> anyhow::Result<T>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        match self.spilled {
+            Some(_) => serde_json::from_reader(self.reader()),
+            None => serde_json::from_str(&self.body),
+        }
+        .map_err(|e| anyhow::anyhow!("Failed to parse JSON: {}", e))
+    }
+
+    /// Deserializes a comma-separated body with a header row, one `T` per
+    /// record. Fields map by header name; the body is read in its declared
+    /// charset, from its file when it was spilled.
+    #[cfg(feature = "csv")]
+    pub fn parse_csv<T>(&self) -> anyhow::Result<Vec<T>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.parse_csv_with(b',')
+    }
+
+    /// `parse_csv` with another delimiter, e.g. `b';'` or `b'\t'`.
+    #[cfg(feature = "csv")]
+    pub fn parse_csv_with<T>(&self, delimiter: u8) -> anyhow::Result<Vec<T>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let text = self.text()?;
+        csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .from_reader(text.as_bytes())
+            .deserialize()
+            .map(|record| record.map_err(|e| anyhow::anyhow!("Failed to parse CSV: {}", e)))
+            .collect()
+    }
+
+    /// Decodes the raw body with `codec`; `Codec::Auto` picks it from the
+    /// `Content-Type`.
+    pub fn parse_as<T>(&self, codec: crate::codec::Codec) -> anyhow::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        codec.decode(&self.bytes()?, &self.content_type)
+    }
+
+    /// The body as a `Value`; objects keep the body's key order with the
+    /// `preserve-order` feature.
+    pub fn as_json_value(&self) -> anyhow::Result<serde_json::Value> {
+        self.parse_json()
+    }
+
+    /// `as_json_value` for almost-valid bodies: trailing commas, comments,
+    /// single quotes and unquoted keys are fixed and reported, see
+    /// `utils::repair_json`. The other parsers never repair.
+    pub fn parse_json_lenient(&self) -> anyhow::Result<(serde_json::Value, Vec<crate::utils::JsonRepair>)> {
+        crate::utils::repair_json(&self.body)
+    }
+
+    /// HAL `_links` by relation; empty when the body has none. Fails when
+    /// the body is not JSON.
+    pub fn hal_links(&self) -> anyhow::Result<crate::hypermedia::LinkMap> {
+        Ok(crate::hypermedia::hal_links(&self.as_json_value()?))
+    }
+
+    /// JSON:API `links` of the document and of a single primary resource,
+    /// with relationship links as `<relationship>.<rel>`.
+    pub fn jsonapi_links(&self) -> anyhow::Result<crate::hypermedia::LinkMap> {
+        Ok(crate::hypermedia::jsonapi_links(&self.as_json_value()?))
+    }
+
+    /// `hal_links` when the body has `_links`, otherwise `jsonapi_links`.
+    pub fn links(&self) -> anyhow::Result<crate::hypermedia::LinkMap> {
+        let body = self.as_json_value()?;
+        Ok(match body.get("_links") {
+            Some(_) => crate::hypermedia::hal_links(&body),
+            None => crate::hypermedia::jsonapi_links(&body),
+        })
+    }
+
+    /// Query parameters of the `Location` header, e.g. the `code` and `state`
+    /// of an OAuth redirect. Relative locations are accepted.
+    pub fn location_params(&self) -> Option<QueryParams> {
+        let location = self.headers.get("location")?;
+        let parts = match crate::utils::parse_url_parts(location) {
+            Ok(parts) => parts,
+            Err(_) => {
+                let base = url::Url::parse("http://localhost/").ok()?;
+                let joined = base.join(location).ok()?;
+                crate::utils::parse_url_parts(joined.as_str()).ok()?
+            }
+        };
+        Some(parts.query)
+    }
+}
+
+/// What the TLS backend reports about an HTTPS connection. For requests
+/// reqwest only exposes the leaf certificate; `HttpClient::inspect_tls`
+/// reports everything its handshake negotiated, including the full chain.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsInfo {
+    /// Negotiated version, e.g. `TLSv1.3`, and cipher suite. reqwest does
+    /// not expose either for requests, so only `inspect_tls` sets them.
+    pub protocol: Option<String>,
+    pub cipher: Option<String>,
+    /// ALPN protocol agreed on, e.g. `h2`.
+    #[serde(default)]
+    pub alpn: Option<String>,
+    /// Server name sent in the SNI extension; `None` for IP addresses.
+    #[serde(default)]
+    pub sni: Option<String>,
+    /// The parsed leaf certificate.
+    #[serde(default)]
+    pub certificate: Option<crate::tls::CertificateInfo>,
+    /// Every certificate the server sent, leaf first.
+    #[serde(default)]
+    pub chain: Vec<crate::tls::CertificateInfo>,
+    /// The certificate expires within the client's warning window, see
+    /// `HttpClient::with_cert_expiry_warning`.
+    #[serde(default)]
+    pub expiring_soon: bool,
+    /// Why verification failed when `HttpClient::inspect_tls` had to accept
+    /// an untrusted certificate to finish the handshake.
+    #[serde(default)]
+    pub verify_error: Option<String>,
+    /// DER-encoded leaf certificate of the server.
+    #[serde(skip)]
+    pub peer_certificate: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlParts {
+    pub scheme: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub path_segments: Vec<String>,
+    pub query: QueryParams,
+    pub fragment: Option<String>,
+}
+
+/// Decoded query string that keeps repeated keys in their original order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    pub fn from_pairs(pairs: Vec<(String, String)>) -> Self {
+        Self { pairs }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn get_parsed<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key).and_then(|v| v.parse().ok())
+    }
+
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        self.get_parsed(key)
+    }
+
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        self.get_parsed(key)
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)?.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Some(true),
+            "false" | "0" | "no" | "off" => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn pairs(&self) -> &[(String, String)] {
+        &self.pairs
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// What the client's request methods take as a URL: a string, or a
+/// `(template, vars)` pair expanded with `utils::expand_uri_template`.
+pub trait IntoRequestUrl {
+    fn into_request_url(self) -> anyhow::Result<String>;
+}
+
+impl IntoRequestUrl for &str {
+    fn into_request_url(self) -> anyhow::Result<String> {
+        Ok(self.to_string())
+    }
+}
+
+impl IntoRequestUrl for &String {
+    fn into_request_url(self) -> anyhow::Result<String> {
+        Ok(self.clone())
+    }
+}
+
+impl IntoRequestUrl for String {
+    fn into_request_url(self) -> anyhow::Result<String> {
+        Ok(self)
+    }
+}
+
+impl<T: AsRef<str>> IntoRequestUrl for (T, &HashMap<String, serde_json::Value>) {
+    fn into_request_url(self) -> anyhow::Result<String> {
+        crate::utils::expand_uri_template(self.0.as_ref(), self.1)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RequestConfig {
+    /// Sent in this order, after any client default headers it does not
+    /// override.
+    pub headers: crate::headers::HeaderList,
+    /// Left off the request even when a client default or the client
+    /// itself would send them, see `without_header`.
+    pub removed_headers: Vec<String>,
+    pub pretty_print: bool,
+    pub follow_redirects: bool,
+    pub verify_ssl: bool,
+    pub debug: bool,
+    pub defer_body: bool,
+    pub discard_body: bool,
+    pub timeout: Option<Duration>,
+    /// Longest gap allowed between body chunks, see `with_idle_timeout`.
+    pub idle_timeout: Option<Duration>,
+    pub max_body_size: Option<usize>,
+    pub partial_body_limit: Option<usize>,
+    /// Bodies larger than this go to a temporary file, see
+    /// `with_spill_threshold`.
+    pub spill_threshold: Option<usize>,
+    /// Where spilled bodies are written; the system temp directory when
+    /// `None`.
+    pub spill_dir: Option<std::path::PathBuf>,
+    pub retry: Option<RetryPolicy>,
+    pub strict_capabilities: bool,
+    pub if_match: Option<String>,
+    pub if_none_match: Option<String>,
+    pub strict_length: bool,
+    pub local_address: Option<std::net::IpAddr>,
+    pub request_id: Option<String>,
+    pub generate_request_id: bool,
+    pub coalesce: bool,
+    pub user_agent_tokens: Vec<String>,
+    /// Parameters appended to the URL's query, see `with_query`.
+    pub query: Option<serde_json::Value>,
+    pub array_query_style: crate::utils::ArrayQueryStyle,
+}
+
+/// Named starting points for `RequestConfig::preset`. Every value a preset
+/// sets can be overridden by later builder calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// 2s timeout and no retries, for interactive calls where a quick error
+    /// beats waiting.
+    FastFail,
+    /// 30s timeout and up to 4 retries with exponential backoff (500ms,
+    /// doubling) and jitter, giving up once 2 minutes have passed.
+    Resilient,
+    /// 5 minute timeout and up to 5 retries on `429` and `503` only, backing
+    /// off from 2s with jitter for up to 10 minutes. Identical concurrent
+    /// GETs are coalesced into one request.
+    Bulk,
+}
+
+impl RequestConfig {
+    pub fn new() -> Self {
+        Self {
+            headers: crate::headers::HeaderList::new(),
+            removed_headers: Vec::new(),
+            pretty_print: false,
+            follow_redirects: true,
+            verify_ssl: true,
+            debug: false,
+            defer_body: false,
+            discard_body: false,
+            timeout: None,
+            idle_timeout: None,
+            max_body_size: None,
+            partial_body_limit: None,
+            spill_threshold: None,
+            spill_dir: None,
+            retry: None,
+            strict_capabilities: false,
+            if_match: None,
+            if_none_match: None,
+            strict_length: true,
+            local_address: None,
+            request_id: None,
+            generate_request_id: false,
+            coalesce: false,
+            user_agent_tokens: Vec::new(),
+            query: None,
+            array_query_style: crate::utils::ArrayQueryStyle::default(),
+        }
+    }
+
+    /// A config starting from `preset`, see `Preset` for the values.
+    pub fn preset(preset: Preset) -> Self {
+        match preset {
+            Preset::FastFail => Self::new().with_timeout(Duration::from_secs(2)),
+            Preset::Resilient => Self::new().with_timeout(Duration::from_secs(30)).with_retry(
+                RetryPolicy::new(4)
+                    .with_initial_backoff(Duration::from_millis(500))
+                    .with_multiplier(2.0)
+                    .with_jitter(true)
+                    .with_max_elapsed(Duration::from_secs(120)),
+            ),
+            Preset::Bulk => Self::new()
+                .with_timeout(Duration::from_secs(300))
+                .with_retry(
+                    RetryPolicy::new(5)
+                        .with_initial_backoff(Duration::from_secs(2))
+                        .with_multiplier(2.0)
+                        .with_retry_statuses(vec![429, 503])
+                        .with_jitter(true)
+                        .with_max_elapsed(Duration::from_secs(600)),
+                )
+                .with_coalescing(true),
+        }
+    }
+
+    /// Replaces all headers. A `HashMap` has no order, so its headers are
+    /// sent sorted by name; pass a `Vec` or `HeaderList` to choose the order.
+    pub fn with_headers(mut self, headers: impl Into<crate::headers::HeaderList>) -> Self {
+        self.headers = headers.into();
+        self
+    }
+
+    pub fn with_pretty_print(mut self, pretty: bool) -> Self {
+        self.pretty_print = pretty;
+        self
+    }
+
+    pub fn with_redirects(mut self, follow: bool) -> Self {
+        self.follow_redirects = follow;
+        self
+    }
+
+    pub fn with_ssl_verification(mut self, verify: bool) -> Self {
+        self.verify_ssl = verify;
+        self
+    }
+
+    /// Turns non-2xx JSON responses into errors whose message carries the
+    /// pretty-printed body. Colors follow the `NO_COLOR` convention.
+    pub fn debug(mut self, enabled: bool) -> Self {
+        self.debug = enabled;
+        self
+    }
+
+    /// Returns as soon as the headers arrive; the body is fetched later with
+    /// `ApiResponse::read_body` or dropped with `ApiResponse::discard_body`.
+    pub fn defer_body(mut self, defer: bool) -> Self {
+        self.defer_body = defer;
+        self
+    }
+
+    /// Reads the body to the end without keeping it, so the connection can be
+    /// reused; the response has status and headers only. Takes precedence
+    /// over `defer_body`, and such GETs bypass the response cache and
+    /// coalescing.
+    pub fn discard_body(mut self, discard: bool) -> Self {
+        self.discard_body = discard;
+        self
+    }
+
+    /// Overrides the client-wide timeout for this request only.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Aborts with a `Read` `RequestError::Timeout` when no body data arrives
+    /// for `idle`. The clock restarts with every chunk, so a stream that keeps
+    /// sending runs until the total timeout, which can be raised with
+    /// `with_timeout` for long-lived streams.
+    pub fn with_idle_timeout(mut self, idle: Duration) -> Self {
+        self.idle_timeout = Some(idle);
+        self
+    }
+
+    /// Aborts with `RequestError::BodyTooLarge` once the body exceeds `bytes`.
+    pub fn with_max_body_size(mut self, bytes: usize) -> Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Keeps up to `bytes` of an aborted body in `PartialResponse::partial_body`.
+    pub fn with_partial_body_limit(mut self, bytes: usize) -> Self {
+        self.partial_body_limit = Some(bytes);
+        self
+    }
+
+    /// Streams bodies larger than `bytes` into a temporary file instead of
+    /// memory. The response then has an empty `body` and is read through
+    /// `text()`, `bytes()` or `reader()`; the file is removed when the last
+    /// copy of the response is dropped. Deferred bodies and bodies the
+    /// client decodes itself (`zstd`) stay in memory.
+    pub fn with_spill_threshold(mut self, bytes: usize) -> Self {
+        self.spill_threshold = Some(bytes);
+        self
+    }
+
+    /// Writes spilled bodies into `dir` instead of the system temp directory.
+    pub fn with_spill_dir(mut self, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.spill_dir = Some(dir.into());
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn without_retry(mut self) -> Self {
+        self.retry = None;
+        self
+    }
+
+    /// Shares one in-flight GET between identical concurrent calls, like
+    /// `HttpClient::with_coalescing` but for this request only.
+    pub fn with_coalescing(mut self, enabled: bool) -> Self {
+        self.coalesce = enabled;
+        self
+    }
+
+    /// Makes `HttpClient::request` check the endpoint's advertised methods first.
+    pub fn with_strict_capabilities(mut self, strict: bool) -> Self {
+        self.strict_capabilities = strict;
+        self
+    }
+
+    /// With `true` (the default) a body shorter than its `Content-Length`
+    /// fails with `RequestError::TruncatedBody`; with `false` the response is
+    /// returned with `ApiResponse::length_mismatch` set instead.
+    pub fn with_strict_length(mut self, strict: bool) -> Self {
+        self.strict_length = strict;
+        self
+    }
+
+    /// Sends `If-Match` so the server only applies the request while the
+    /// resource still has `etag`. A `412` then fails with
+    /// `RequestError::PreconditionFailed`.
+    pub fn if_match(mut self, etag: impl Into<String>) -> Self {
+        self.if_match = Some(etag.into());
+        self
+    }
+
+    /// Sends `If-None-Match`, e.g. `"*"` to create a resource only if it does
+    /// not exist yet. A `412` fails like with `if_match`.
+    pub fn if_none_match(mut self, etag: impl Into<String>) -> Self {
+        self.if_none_match = Some(etag.into());
+        self
+    }
+
+    pub fn is_conditional(&self) -> bool {
+        self.if_match.is_some() || self.if_none_match.is_some()
+    }
+
+    /// Sends the request from `ip`, e.g. to pick the egress interface on a
+    /// multi-homed host. Fails before sending if `ip` is not assigned to
+    /// this host.
+    pub fn with_local_address(mut self, ip: std::net::IpAddr) -> Self {
+        self.local_address = Some(ip);
+        self
+    }
+
+    /// Sends `X-Request-Id` with `id`, or with a fresh UUID for `None`. The
+    /// ID stays the same across retries and is recorded in
+    /// `ApiResponse::request`. An `X-Request-Id` header set directly wins.
+    pub fn with_request_id(mut self, id: Option<String>) -> Self {
+        self.generate_request_id = id.is_none();
+        self.request_id = id;
+        self
+    }
+
+    /// Appends the fields of `params`, a struct or map, to the URL's query.
+    /// Later calls merge into earlier ones. Arrays are written as set by
+    /// `with_array_query_style`; see `utils::to_query_string`.
+    pub fn with_query<T: Serialize + ?Sized>(mut self, params: &T) -> anyhow::Result<Self> {
+        let params = serde_json::to_value(params).map_err(|e| anyhow::anyhow!("Failed to serialize query parameters: {}", e))?;
+        if !params.is_object() {
+            anyhow::bail!("Query parameters must serialize to an object");
+        }
+        match &mut self.query {
+            Some(query) => crate::utils::merge_values(query, params),
+            None => self.query = Some(params),
+        }
+        Ok(self)
+    }
+
+    /// How arrays in `with_query` parameters are encoded; repeated keys
+    /// (`ids=1&ids=2`) by default.
+    pub fn with_array_query_style(mut self, style: crate::utils::ArrayQueryStyle) -> Self {
+        self.array_query_style = style;
+        self
+    }
+
+    /// `url` with the `with_query` parameters appended, keeping any query
+    /// and fragment it already has.
+    pub fn url_with_query(&self, url: &str) -> String {
+        let encoded = self
+            .query
+            .as_ref()
+            .map(|query| crate::utils::to_query_string(query, self.array_query_style))
+            .unwrap_or_default();
+        if encoded.is_empty() {
+            return url.to_string();
+        }
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (url, None),
+        };
+        let separator = match url.split_once('?') {
+            Some((_, "")) => "",
+            Some(_) => "&",
+            None => "?",
+        };
+        let mut result = format!("{}{}{}", url, separator, encoded);
+        if let Some(fragment) = fragment {
+            result.push('#');
+            result.push_str(fragment);
+        }
+        result
+    }
+
+    /// Sets a header, replacing any existing one of the same name in any
+    /// case, so raw and typed setters follow "last call wins". A replaced
+    /// header keeps its place in the sending order.
+    pub fn add_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        self.removed_headers.retain(|name| !name.eq_ignore_ascii_case(&key));
+        self.headers.insert(key, value);
+        self
+    }
+
+    /// Copies the response headers matching any of `patterns` (globs such
+    /// as `x-ratelimit-*`). Hop-by-hop headers are always skipped, headers
+    /// already set on this config are kept, and every `Set-Cookie` is
+    /// folded into a single `Cookie` header.
+    pub fn copy_headers_from(mut self, response: &ApiResponse, patterns: &[&str]) -> Self {
+        let mut names: Vec<&String> = response.headers.keys().collect();
+        names.sort();
+        for name in names {
+            if !patterns.iter().any(|pattern| crate::headers::header_glob_match(pattern, name))
+                || crate::headers::is_hop_by_hop(name, response)
+            {
+                continue;
+            }
+
+            let value = &response.headers[name];
+            let (name, value) = if name.eq_ignore_ascii_case("set-cookie") {
+                let cookies: Vec<&str> = value
+                    .lines()
+                    .map(|cookie| cookie.split(';').next().unwrap_or_default().trim())
+                    .filter(|cookie| !cookie.is_empty())
+                    .collect();
+                ("Cookie", cookies.join("; "))
+            } else {
+                (name.as_str(), value.clone())
+            };
+            if !value.is_empty() && !self.headers.contains_key(name) {
+                self.headers.insert(name, value);
+            }
+        }
+        self
+    }
+
+    /// Makes the request conditional on the response's `ETag` and
+    /// `Last-Modified`, so an unchanged resource comes back as `304`.
+    pub fn with_validators_from(mut self, response: &ApiResponse) -> Self {
+        if let Some(etag) = response.headers.get("etag") {
+            self.if_none_match = Some(etag.clone());
+        }
+        if let Some(modified) = response.headers.get("last-modified") {
+            self = self.add_header("If-Modified-Since", modified.clone());
+        }
+        self
+    }
+
+    pub fn content_type(self, mime: impl Into<String>) -> Self {
+        self.add_header("Content-Type", mime)
+    }
+
+    pub fn accept_json(self) -> Self {
+        self.add_header("Accept", "application/json")
     }
 
-    pub fn is_json(&self) -> bool {
-        self.content_type.contains("application/json")
+    /// Sends `Accept-Language` preferring `tags` in the order given:
+    /// `["en-US", "en", "fr"]` gives `en-US, en;q=0.9, fr;q=0.8`. Weights
+    /// drop by 0.1 per tag, or by less when there are more than ten, so they
+    /// descend for up to 901 tags; any beyond share `q=0.001`. Blank and
+    /// repeated tags are skipped.
+    pub fn accept_language(self, tags: &[&str]) -> Self {
+        let mut seen = HashSet::new();
+        let tags: Vec<&str> = tags
+            .iter()
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty() && seen.insert(tag.to_ascii_lowercase()))
+            .collect();
+        if tags.is_empty() {
+            return self;
+        }
+        // q-values have at most three decimals, so work in thousandths.
+        let step = match tags.len() {
+            0..=10 => 100,
+            n => (900 / (n - 1)).max(1),
+        };
+        let value = tags
+            .iter()
+            .enumerate()
+            .map(|(index, tag)| match 1000usize.saturating_sub(index * step).max(1) {
+                1000 => tag.to_string(),
+                q => format!("{};q={}", tag, format!("0.{:03}", q).trim_end_matches('0')),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.add_header("Accept-Language", value)
     }
 
-    pub fn parse_json<T>(&self) -> anyhow::Result<T>
-    where
-        T: for<'de> serde::Deserialize<'de>,
-    {
-        serde_json::from_str(&self.body)
-            .map_err(|e| anyhow::anyhow!("Failed to parse JSON: {}", e))
+    /// Sends `If-Modified-Since` as an IMF-fixdate.
+    pub fn if_modified_since(self, time: std::time::SystemTime) -> Self {
+        self.add_header("If-Modified-Since", crate::utils::format_http_date(time))
     }
 
-    pub fn as_json_value(&self) -> anyhow::Result<serde_json::Value> {
-        serde_json::from_str(&self.body)
-            .map_err(|e| anyhow::anyhow!("Failed to parse JSON: {}", e))
+    /// Replaces the whole `User-Agent`.
+    pub fn user_agent(self, agent: impl Into<String>) -> Self {
+        self.add_header("User-Agent", agent)
+    }
+
+    /// Appends a product token such as `MyApp/2.3` to the `User-Agent`
+    /// instead of replacing it, giving `RustHttpClient/0.1.0 MyApp/2.3`.
+    /// Tokens follow whatever base agent is in effect, including one set
+    /// with `user_agent`.
+    pub fn append_user_agent(mut self, token: impl Into<String>) -> Self {
+        let token = token.into();
+        let token = token.trim();
+        if !token.is_empty() {
+            self.user_agent_tokens.push(token.to_string());
+        }
+        self
+    }
+
+    pub fn cache_control(self, directives: &[crate::headers::CacheDirective]) -> Self {
+        self.add_header("Cache-Control", crate::headers::format_cache_control(directives))
+    }
+
+    pub fn authorization(self, auth: crate::headers::Auth) -> Self {
+        self.add_header("Authorization", auth.header_value())
+    }
+
+    /// Merges a block of `Key: Value` lines, one header per line, into the
+    /// current headers. Fails on a line without a colon.
+    pub fn with_headers_str(mut self, headers: &str) -> anyhow::Result<Self> {
+        self.headers.extend(crate::utils::parse_headers_string(headers)?);
+        Ok(self)
+    }
+
+    /// Leaves `name` off the request: a header set on this config, a client
+    /// default, or one the client adds itself such as `User-Agent` or
+    /// `Accept-Encoding` (curl's `-H "Name:"`). reqwest's `Accept: */*`
+    /// is the exception; it is added back when no `Accept` is set. A later
+    /// `add_header` of the same name sends it again.
+    pub fn without_header(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        self.headers.remove(&name);
+        if !self.removed_headers.iter().any(|removed| removed.eq_ignore_ascii_case(&name)) {
+            self.removed_headers.push(name);
+        }
+        self
+    }
+
+    /// Applies curl-style `-H` flags parsed by `utils::parse_header_flags`:
+    /// sets its headers and leaves off the ones it unsets.
+    pub fn with_header_flags(self, flags: &crate::utils::HeaderFlags) -> Self {
+        let config = flags.unset.iter().fold(self, |config, name| config.without_header(name.as_str()));
+        flags
+            .headers
+            .iter()
+            .fold(config, |config, (name, value)| config.add_header(name.as_str(), value.as_str()))
+    }
+
+    pub fn with_bearer_token(self, token: impl Into<String>) -> Self {
+        self.authorization(crate::headers::Auth::bearer(token))
+    }
+
+    pub fn with_basic_auth(self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.authorization(crate::headers::Auth::basic(username, password))
+    }
+
+    /// Every effective setting, one `name: value` per line, for logging what
+    /// a config or preset actually does. Credential headers are redacted.
+    pub fn describe(&self) -> String {
+        use std::fmt::Write;
+
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "timeout: {}",
+            self.timeout.map_or("client default".to_string(), |timeout| format!("{:?}", timeout))
+        );
+        if let Some(idle) = self.idle_timeout {
+            let _ = writeln!(out, "idle timeout: {:?}", idle);
+        }
+        match &self.retry {
+            Some(retry) => {
+                let _ = writeln!(
+                    out,
+                    "retry: up to {} retries, backoff {:?} x{}{}, on statuses {:?}",
+                    retry.max_retries,
+                    retry.initial_backoff,
+                    retry.multiplier,
+                    if retry.jitter { " with jitter" } else { "" },
+                    retry.retry_statuses,
+                );
+                if let Some(max_delay) = retry.max_delay {
+                    let _ = writeln!(out, "retry max delay: {:?}", max_delay);
+                }
+                if let Some(budget) = retry.max_elapsed {
+                    let _ = writeln!(out, "retry budget: {:?}", budget);
+                }
+                if retry.predicate.is_some() {
+                    let _ = writeln!(out, "retry predicate: set");
+                }
+            }
+            None => {
+                let _ = writeln!(out, "retry: off");
+            }
+        }
+        let _ = writeln!(out, "coalescing: {}", on_off(self.coalesce));
+        let _ = writeln!(out, "follow redirects: {}", on_off(self.follow_redirects));
+        let _ = writeln!(out, "verify ssl: {}", on_off(self.verify_ssl));
+        let _ = writeln!(out, "defer body: {}", on_off(self.defer_body));
+        let _ = writeln!(out, "discard body: {}", on_off(self.discard_body));
+        if let Some(bytes) = self.max_body_size {
+            let _ = writeln!(out, "max body size: {} bytes", bytes);
+        }
+        if let Some(bytes) = self.partial_body_limit {
+            let _ = writeln!(out, "partial body limit: {} bytes", bytes);
+        }
+        if let Some(bytes) = self.spill_threshold {
+            let dir = self.spill_dir.clone().unwrap_or_else(std::env::temp_dir);
+            let _ = writeln!(out, "spill bodies over: {} bytes to {}", bytes, dir.display());
+        }
+        let _ = writeln!(out, "strict length: {}", on_off(self.strict_length));
+        let _ = writeln!(out, "strict capabilities: {}", on_off(self.strict_capabilities));
+        // Values may be API keys, so only the parameter names are listed.
+        if let Some(serde_json::Value::Object(query)) = &self.query {
+            let names: Vec<&str> = query.keys().map(String::as_str).collect();
+            let _ = writeln!(out, "query: {} (arrays {:?})", names.join(", "), self.array_query_style);
+        }
+        if let Some(etag) = &self.if_match {
+            let _ = writeln!(out, "if-match: {}", etag);
+        }
+        if let Some(etag) = &self.if_none_match {
+            let _ = writeln!(out, "if-none-match: {}", etag);
+        }
+        if let Some(ip) = self.local_address {
+            let _ = writeln!(out, "local address: {}", ip);
+        }
+        match (&self.request_id, self.generate_request_id) {
+            (Some(id), _) => {
+                let _ = writeln!(out, "request id: {}", id);
+            }
+            (None, true) => {
+                let _ = writeln!(out, "request id: generated");
+            }
+            (None, false) => {}
+        }
+        if !self.user_agent_tokens.is_empty() {
+            let _ = writeln!(out, "user agent tokens: {}", self.user_agent_tokens.join(" "));
+        }
+        if !self.removed_headers.is_empty() {
+            let _ = writeln!(out, "removed headers: {}", self.removed_headers.join(", "));
+        }
+        let _ = writeln!(out, "pretty print: {}", on_off(self.pretty_print));
+        let _ = writeln!(out, "debug: {}", on_off(self.debug));
+
+        let mut headers: Vec<_> = self.headers.iter().collect();
+        headers.sort();
+        for (name, value) in headers {
+            let value = if crate::report::SENSITIVE_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                REDACTED
+            } else {
+                value.as_str()
+            };
+            let _ = writeln!(out, "header {}: {}", name, value);
+        }
+        out
     }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct RequestConfig {
-    pub headers: HashMap<String, String>,
-    pub pretty_print: bool,
-    pub follow_redirects: bool,
-    pub verify_ssl: bool,
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_INITIAL_BACKOFF_MS: u64 = 200;
+pub const DEFAULT_BACKOFF_MULTIPLIER: f64 = 2.0;
+
+/// Retries connection failures, timeouts and retryable statuses with
+/// exponential backoff.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub multiplier: f64,
+    pub retry_statuses: Vec<u16>,
+    pub predicate: Option<RetryPredicate>,
+    /// Randomizes each delay to between half and all of its nominal value.
+    pub jitter: bool,
+    /// Stops retrying once this much time has passed since the first
+    /// attempt, or would have by the end of the next backoff.
+    pub max_elapsed: Option<Duration>,
+    /// Upper bound on a single backoff, however many attempts came before.
+    pub max_delay: Option<Duration>,
 }
 
-impl RequestConfig {
-    pub fn new() -> Self {
+/// Decides from a received response whether to try again, see
+/// `RetryPolicy::with_retry_predicate`.
+#[derive(Clone)]
+pub struct RetryPredicate(Arc<dyn Fn(&ApiResponse) -> bool + Send + Sync>);
+
+impl RetryPredicate {
+    pub fn matches(&self, response: &ApiResponse) -> bool {
+        (self.0)(response)
+    }
+}
+
+impl std::fmt::Debug for RetryPredicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RetryPredicate")
+    }
+}
+
+impl PartialEq for RetryPredicate {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
         Self {
-            headers: HashMap::new(),
-            pretty_print: false,
-            follow_redirects: true,
-            verify_ssl: true,
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: Duration::from_millis(DEFAULT_INITIAL_BACKOFF_MS),
+            multiplier: DEFAULT_BACKOFF_MULTIPLIER,
+            retry_statuses: vec![429, 500, 502, 503, 504],
+            predicate: None,
+            jitter: false,
+            max_elapsed: None,
+            max_delay: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::default()
         }
     }
 
-    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
-        self.headers = headers;
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
         self
     }
 
-    pub fn with_pretty_print(mut self, pretty: bool) -> Self {
-        self.pretty_print = pretty;
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
         self
     }
 
-    pub fn with_redirects(mut self, follow: bool) -> Self {
-        self.follow_redirects = follow;
+    pub fn with_retry_statuses(mut self, statuses: Vec<u16>) -> Self {
+        self.retry_statuses = statuses;
         self
     }
 
-    pub fn with_ssl_verification(mut self, verify: bool) -> Self {
-        self.verify_ssl = verify;
+    /// Also retries responses for which `predicate` returns `true`, e.g. a
+    /// `{"status":"pending"}` body, within the same attempt budget and
+    /// backoff. Deferred bodies are not read before the check.
+    pub fn with_retry_predicate<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&ApiResponse) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Some(RetryPredicate(Arc::new(predicate)));
         self
     }
 
-    pub fn add_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
-        self.headers.insert(key.into(), value.into());
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
         self
     }
 
-    pub fn with_bearer_token(self, token: impl Into<String>) -> Self {
-        self.add_header("Authorization", format!("Bearer {}", token.into()))
+    /// Caps the total time spent retrying, see `max_elapsed`.
+    pub fn with_max_elapsed(mut self, budget: Duration) -> Self {
+        self.max_elapsed = Some(budget);
+        self
     }
 
-    pub fn with_basic_auth(self, username: impl Into<String>, password: impl Into<String>) -> Self {
-        let credentials = format!("{}:{}", username.into(), password.into());
-        let encoded = base64_encode(&credentials);
-        self.add_header("Authorization", format!("Basic {}", encoded))
+    /// Caps each backoff at `max_delay`; jitter only shortens delays, so it
+    /// never goes past it either.
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// Delay to sleep after the given (1-based) failed attempt, before
+    /// jitter.
+    pub fn backoff_for(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let factor = self.multiplier.powi(exponent);
+        let mut secs = self.initial_backoff.as_secs_f64() * factor.max(0.0);
+        if let Some(max_delay) = self.max_delay {
+            secs = secs.min(max_delay.as_secs_f64());
+        }
+        if self.initial_backoff.is_zero() {
+            return Duration::ZERO;
+        }
+        // Overflows past `Duration::MAX`.
+        Duration::try_from_secs_f64(secs).unwrap_or(self.max_delay.unwrap_or(Duration::MAX))
+    }
+
+    /// `backoff_for` with jitter applied when enabled.
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff_for(attempt);
+        if !self.jitter {
+            return backoff;
+        }
+        backoff.mul_f64(0.5 + crate::utils::random_fraction() / 2.0)
+    }
+
+    pub fn should_retry(&self, outcome: &AttemptOutcome) -> bool {
+        match outcome {
+            AttemptOutcome::Status(status) => self.retry_statuses.contains(status),
+            AttemptOutcome::Timeout | AttemptOutcome::Connect | AttemptOutcome::Dns => true,
+            AttemptOutcome::Other => false,
+        }
+    }
+
+    pub fn should_retry_response(&self, response: &ApiResponse) -> bool {
+        self.retry_statuses.contains(&response.status)
+            || self.predicate.as_ref().is_some_and(|predicate| predicate.matches(response))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttemptOutcome {
+    Status(u16),
+    Timeout,
+    Connect,
+    /// Name resolution failed; see `DnsError`.
+    Dns,
+    Other,
+}
+
+impl AttemptOutcome {
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        if let Some(request_error) = error.downcast_ref::<crate::error::RequestError>() {
+            if request_error.is_timeout() {
+                return AttemptOutcome::Timeout;
+            }
+            return AttemptOutcome::Other;
+        }
+        
+        if error.chain().any(|cause| cause.is::<crate::error::DnsError>()) {
+            return AttemptOutcome::Dns;
+        }
+        match error.chain().find_map(|cause| cause.downcast_ref::<reqwest::Error>()) {
+            Some(e) if e.is_timeout() => AttemptOutcome::Timeout,
+            Some(e) if e.is_connect() => AttemptOutcome::Connect,
+            _ => AttemptOutcome::Other,
+        }
+    }
+}
+
+impl std::fmt::Display for AttemptOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttemptOutcome::Status(status) => write!(f, "{}", status),
+            AttemptOutcome::Timeout => write!(f, "timeout"),
+            AttemptOutcome::Connect => write!(f, "connect error"),
+            AttemptOutcome::Dns => write!(f, "dns error"),
+            AttemptOutcome::Other => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttemptRecord {
+    pub outcome: AttemptOutcome,
+    pub duration_ms: u64,
+    /// Backoff slept before this attempt started.
+    pub backoff_ms: u64,
+    #[serde(with = "crate::utils::rfc3339")]
+    pub timestamp: std::time::SystemTime,
+}
+
+/// Masks secret query parameters (and userinfo passwords) wherever a URL is
+/// recorded. The request on the wire always uses the real URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlRedactor {
+    params: Vec<String>,
+}
+
+pub const REDACTED: &str = "***";
+
+const DEFAULT_REDACTED_PARAMS: &[&str] = &[
+    "api_key",
+    "apikey",
+    "api-key",
+    "key",
+    "token",
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "auth",
+    "signature",
+    "sig",
+    "x-amz-signature",
+    "x-amz-credential",
+    "x-amz-security-token",
+    "password",
+    "passwd",
+    "pwd",
+    "secret",
+    "client_secret",
+];
+
+impl Default for UrlRedactor {
+    fn default() -> Self {
+        Self {
+            params: DEFAULT_REDACTED_PARAMS.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+impl UrlRedactor {
+    /// A redactor that leaves URLs untouched.
+    pub fn none() -> Self {
+        Self { params: Vec::new() }
+    }
+
+    pub fn with_params<I, S>(params: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            params: params.into_iter().map(|p| p.into().to_ascii_lowercase()).collect(),
+        }
+    }
+
+    pub fn add_param(mut self, param: impl Into<String>) -> Self {
+        self.params.push(param.into().to_ascii_lowercase());
+        self
+    }
+
+    pub fn is_sensitive(&self, param: &str) -> bool {
+        let param = param.to_ascii_lowercase();
+        self.params.contains(&param)
+    }
+
+    pub fn redact(&self, url: &str) -> String {
+        let Ok(mut parsed) = url::Url::parse(url) else {
+            return url.to_string();
+        };
+        
+        if parsed.password().is_some() {
+            let _ = parsed.set_password(Some(REDACTED));
+        }
+        
+        if let Some(query) = parsed.query() {
+            let redacted: Vec<String> = query
+                .split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((key, _)) if self.is_sensitive(&crate::utils::percent_decode(key)) => {
+                        format!("{}={}", key, REDACTED)
+                    }
+                    _ => pair.to_string(),
+                })
+                .collect();
+            parsed.set_query(Some(&redacted.join("&")));
+        }
+        
+        parsed.to_string()
+    }
+
+    /// `redact` with the host shown in Unicode, for error messages; see
+    /// `utils::display_url`.
+    pub fn redact_for_display(&self, url: &str) -> String {
+        crate::utils::display_url(&self.redact(url))
     }
 }
 
@@ -95,7 +1979,8 @@ pub struct ApiError {
     pub details: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum HttpMethod {
     Get,
     Post,
@@ -120,19 +2005,197 @@ impl std::fmt::Display for HttpMethod {
     }
 }
 
-#[derive(Debug, Clone)]
+impl std::str::FromStr for HttpMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_uppercase().as_str() {
+            "GET" => Ok(HttpMethod::Get),
+            "POST" => Ok(HttpMethod::Post),
+            "PUT" => Ok(HttpMethod::Put),
+            "DELETE" => Ok(HttpMethod::Delete),
+            "PATCH" => Ok(HttpMethod::Patch),
+            "HEAD" => Ok(HttpMethod::Head),
+            "OPTIONS" => Ok(HttpMethod::Options),
+            other => anyhow::bail!("Unsupported HTTP method: {}", other),
+        }
+    }
+}
+
+impl HttpMethod {
+    pub fn as_reqwest(&self) -> reqwest::Method {
+        match self {
+            HttpMethod::Get => reqwest::Method::GET,
+            HttpMethod::Post => reqwest::Method::POST,
+            HttpMethod::Put => reqwest::Method::PUT,
+            HttpMethod::Delete => reqwest::Method::DELETE,
+            HttpMethod::Patch => reqwest::Method::PATCH,
+            HttpMethod::Head => reqwest::Method::HEAD,
+            HttpMethod::Options => reqwest::Method::OPTIONS,
+        }
+    }
+
+    /// Methods that change server state: POST, PUT, PATCH and DELETE.
+    pub fn is_write(&self) -> bool {
+        matches!(self, HttpMethod::Post | HttpMethod::Put | HttpMethod::Patch | HttpMethod::Delete)
+    }
+}
+
+/// Methods an endpoint advertised in response to OPTIONS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EndpointCapabilities {
+    Known(std::collections::HashSet<HttpMethod>),
+    /// The server answered 405 or did not list its methods.
+    Unknown,
+}
+
+impl EndpointCapabilities {
+    pub fn is_known(&self) -> bool {
+        matches!(self, EndpointCapabilities::Known(_))
+    }
+
+    /// `true` only when the method was explicitly advertised.
+    pub fn supports(&self, method: HttpMethod) -> bool {
+        match self {
+            EndpointCapabilities::Known(methods) => methods.contains(&method),
+            EndpointCapabilities::Unknown => false,
+        }
+    }
+}
+
+/// One finished request. Timestamps serialize as RFC 3339 UTC strings;
+/// `response_time_ms` comes from a monotonic clock, so wall-clock jumps
+/// cannot make it negative.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RequestStats {
     pub method: HttpMethod,
     pub url: String,
     pub status_code: u16,
     pub response_time_ms: u64,
+    /// Decoded body length, see `ApiResponse::decoded_bytes`.
     pub response_size_bytes: usize,
-    pub timestamp: std::time::SystemTime,
+    /// Bytes read off the connection, see `ApiResponse::wire_bytes`.
+    #[serde(default)]
+    pub wire_bytes: u64,
+    #[serde(with = "crate::utils::rfc3339")]
+    pub started_at: std::time::SystemTime,
+    #[serde(with = "crate::utils::rfc3339")]
+    pub completed_at: std::time::SystemTime,
+    pub target: Option<String>,
+    #[serde(default)]
+    pub local_address: Option<std::net::IpAddr>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub dns_time_ms: Option<u64>,
+}
+
+impl RequestStats {
+    /// Builds stats for a finished request, recording the URL in redacted form.
+    pub fn from_response(
+        method: HttpMethod,
+        url: &str,
+        response: &ApiResponse,
+        redactor: &UrlRedactor,
+    ) -> Self {
+        Self::from_response_at(method, url, response, redactor, std::time::SystemTime::now())
+    }
+
+    /// Like `from_response`, with an explicit completion time.
+    pub fn from_response_at(
+        method: HttpMethod,
+        url: &str,
+        response: &ApiResponse,
+        redactor: &UrlRedactor,
+        completed_at: std::time::SystemTime,
+    ) -> Self {
+        let elapsed = Duration::from_millis(response.total_time_ms.max(response.response_time_ms));
+        
+        Self {
+            method,
+            url: redactor.redact(url),
+            status_code: response.status,
+            response_time_ms: response.response_time_ms,
+            response_size_bytes: response.decoded_bytes as usize,
+            wire_bytes: response.wire_bytes,
+            started_at: completed_at.checked_sub(elapsed).unwrap_or(completed_at),
+            completed_at,
+            target: response.target.clone(),
+            local_address: response.request.as_ref().and_then(|request| request.local_address),
+            request_id: response.request.as_ref().and_then(|request| request.request_id.clone()),
+            dns_time_ms: response.dns_time_ms,
+        }
+    }
+
+    /// Timings as OpenTelemetry span attributes, in seconds:
+    /// `http.request.duration` for the final attempt,
+    /// `http.request.total_duration` across attempts and backoff, and
+    /// `http.dns.duration` when a lookup ran.
+    pub fn as_otel_attributes(&self) -> Vec<(String, f64)> {
+        let total = self.completed_at.duration_since(self.started_at).unwrap_or(Duration::ZERO);
+        let mut attributes = vec![
+            ("http.request.duration".to_string(), self.response_time_ms as f64 / 1000.0),
+            ("http.request.total_duration".to_string(), total.as_secs_f64()),
+        ];
+        if let Some(dns_ms) = self.dns_time_ms {
+            attributes.push(("http.dns.duration".to_string(), dns_ms as f64 / 1000.0));
+        }
+        attributes
+    }
+
+    /// Time since the request completed.
+    pub fn age(&self) -> Duration {
+        self.age_at(std::time::SystemTime::now())
+    }
+
+    /// Age relative to `now`, e.g. a `Clock::system_now` reading.
+    pub fn age_at(&self, now: std::time::SystemTime) -> Duration {
+        now.duration_since(self.completed_at).unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Recorded `RequestStats`, oldest first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestHistory {
+    entries: Vec<RequestStats>,
+}
+
+impl RequestHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, stats: RequestStats) {
+        self.entries.push(stats);
+    }
+
+    pub fn entries(&self) -> &[RequestStats] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Requests that started within `[from, to]`, both ends inclusive.
+    pub fn between(&self, from: std::time::SystemTime, to: std::time::SystemTime) -> Vec<&RequestStats> {
+        self.entries
+            .iter()
+            .filter(|stats| stats.started_at >= from && stats.started_at <= to)
+            .collect()
+    }
+}
+
+pub(crate) fn base64_encode(input: &str) -> String {
+    base64_encode_bytes(input.as_bytes())
 }
 
-fn base64_encode(input: &str) -> String {
+pub(crate) fn base64_encode_bytes(bytes: &[u8]) -> String {
     let mut result = String::new();
-    let bytes = input.as_bytes();
     
     for chunk in bytes.chunks(3) {
         let mut buf = [0u8; 3];
@@ -152,4 +2215,228 @@ fn base64_encode(input: &str) -> String {
     }
     
     result
+}
+
+/// Standard padded base64; `None` on any other character.
+pub(crate) fn base64_decode_bytes(input: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    
+    let input = input.trim_end_matches('=').as_bytes();
+    let mut result = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut n = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            n |= (value(c)? as u32) << (18 - 6 * i);
+        }
+        result.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_set_their_documented_values() {
+        let fast = RequestConfig::preset(Preset::FastFail);
+        assert_eq!(fast.timeout, Some(Duration::from_secs(2)));
+        assert!(fast.retry.is_none());
+        assert!(!fast.coalesce);
+
+        let resilient = RequestConfig::preset(Preset::Resilient);
+        let retry = resilient.retry.as_ref().expect("Resilient retries");
+        assert_eq!(resilient.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(retry.max_retries, 4);
+        assert_eq!(retry.initial_backoff, Duration::from_millis(500));
+        assert_eq!(retry.multiplier, 2.0);
+        assert!(retry.jitter);
+        assert_eq!(retry.max_elapsed, Some(Duration::from_secs(120)));
+        assert!(!resilient.coalesce);
+
+        let bulk = RequestConfig::preset(Preset::Bulk);
+        let retry = bulk.retry.as_ref().expect("Bulk retries");
+        assert_eq!(bulk.timeout, Some(Duration::from_secs(300)));
+        assert_eq!(retry.max_retries, 5);
+        assert_eq!(retry.initial_backoff, Duration::from_secs(2));
+        assert_eq!(retry.retry_statuses, vec![429, 503]);
+        assert!(retry.jitter);
+        assert_eq!(retry.max_elapsed, Some(Duration::from_secs(600)));
+        assert!(bulk.coalesce);
+    }
+
+    #[test]
+    fn later_builder_calls_override_presets() {
+        let config = RequestConfig::preset(Preset::Bulk)
+            .with_timeout(Duration::from_secs(10))
+            .with_coalescing(false)
+            .without_retry();
+        assert_eq!(config.timeout, Some(Duration::from_secs(10)));
+        assert!(!config.coalesce);
+        assert!(config.retry.is_none());
+
+        let config = RequestConfig::preset(Preset::FastFail).with_retry(RetryPolicy::new(1));
+        assert_eq!(config.timeout, Some(Duration::from_secs(2)));
+        assert_eq!(config.retry.map(|retry| retry.max_retries), Some(1));
+    }
+
+    #[cfg(feature = "csv")]
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Row {
+        name: String,
+        city: String,
+        visits: u32,
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn csv_rows_map_by_header_name() -> anyhow::Result<()> {
+        let response = ApiResponse::synthetic(200, "city,name,visits\nOslo,Ada,3\n\"Paris, FR\",Bo,12\n");
+        let rows: Vec<Row> = response.parse_csv()?;
+        assert_eq!(
+            rows,
+            vec![
+                Row { name: "Ada".into(), city: "Oslo".into(), visits: 3 },
+                Row { name: "Bo".into(), city: "Paris, FR".into(), visits: 12 },
+            ]
+        );
+
+        let semicolons = ApiResponse::synthetic(200, "name;city;visits\nAda;Oslo;3\n");
+        assert_eq!(semicolons.parse_csv_with::<Row>(b';')?.len(), 1);
+        assert!(ApiResponse::synthetic(200, "name,city,visits\nAda,Oslo,many\n").parse_csv::<Row>().is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "csv")]
+    #[tokio::test]
+    async fn csv_is_read_in_its_declared_charset() -> anyhow::Result<()> {
+        let server = crate::testing::MockServer::start().await?;
+        server.when(HttpMethod::Get, "/visits.csv").respond(
+            crate::testing::MockResponse::new(200)
+                .with_header("Content-Type", "text/csv; charset=iso-8859-1")
+                .with_body(&b"name;city;visits\nJos\xe9;M\xfcnchen;7\n"[..]),
+        );
+        let response = crate::HttpClient::new().get(&server.url("/visits.csv"), RequestConfig::new()).await?;
+        let rows: Vec<Row> = response.parse_csv_with(b';')?;
+        assert_eq!(rows, vec![Row { name: "José".into(), city: "München".into(), visits: 7 }]);
+        Ok(())
+    }
+
+    #[test]
+    fn describe_lists_the_effective_settings() {
+        let fast = RequestConfig::preset(Preset::FastFail).describe();
+        assert!(fast.contains("timeout: 2s\n"), "{}", fast);
+        assert!(fast.contains("retry: off\n"), "{}", fast);
+        assert!(fast.contains("coalescing: off\n"), "{}", fast);
+
+        let resilient = RequestConfig::preset(Preset::Resilient).describe();
+        assert!(resilient.contains("timeout: 30s\n"), "{}", resilient);
+        assert!(resilient.contains("retry: up to 4 retries, backoff 500ms x2 with jitter"), "{}", resilient);
+        assert!(resilient.contains("retry budget: 120s\n"), "{}", resilient);
+
+        let bulk = RequestConfig::preset(Preset::Bulk)
+            .with_bearer_token("secret-token")
+            .add_header("X-Team", "data")
+            .describe();
+        assert!(bulk.contains("coalescing: on\n"), "{}", bulk);
+        assert!(bulk.contains("on statuses [429, 503]"), "{}", bulk);
+        assert!(bulk.contains("header X-Team: data\n"), "{}", bulk);
+        assert!(!bulk.contains("secret-token"), "{}", bulk);
+    }
+
+    /// Answers every connection with the raw `reply` and closes it.
+    async fn raw_server(reply: &'static str) -> std::net::SocketAddr {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let _ = stream.write_all(reply.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        addr
+    }
+
+    async fn deferred(addr: std::net::SocketAddr, strict: bool) -> anyhow::Result<ApiResponse> {
+        let config = RequestConfig::new().defer_body(true).with_strict_length(strict);
+        crate::HttpClient::new().get(&format!("http://{}/", addr), config).await
+    }
+
+    #[tokio::test]
+    async fn a_body_shorter_than_its_content_length_is_caught() -> anyhow::Result<()> {
+        let addr = raw_server("HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nhello").await;
+
+        let mut response = deferred(addr, true).await?;
+        let error = response.read_body().await.unwrap_err();
+        match error.downcast_ref::<crate::error::RequestError>() {
+            Some(crate::error::RequestError::TruncatedBody { expected, received, partial }) => {
+                assert_eq!((*expected, *received), (100, 5));
+                assert_eq!(partial.bytes_received, 5);
+            }
+            other => panic!("expected TruncatedBody, got {:?}", other),
+        }
+
+        let mut response = deferred(addr, false).await?;
+        assert_eq!(response.read_body().await?, "hello");
+        assert_eq!(response.length_mismatch, Some((100, 5)));
+        assert_eq!(response.wire_bytes, 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_chunked_body_cut_off_mid_chunk_fails() -> anyhow::Result<()> {
+        let addr = raw_server("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\nhello\r\n10\r\nsho").await;
+        for strict in [true, false] {
+            let mut response = deferred(addr, strict).await?;
+            assert!(response.read_body().await.is_err(), "strict_length({}) accepted a cut-off body", strict);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn backoff_grows_up_to_max_delay_without_overflowing() {
+        let retry = RetryPolicy::new(10).with_initial_backoff(Duration::from_millis(100));
+        let delays: Vec<_> = (1..=4).map(|attempt| retry.backoff_for(attempt)).collect();
+        assert_eq!(delays, [100, 200, 400, 800].map(Duration::from_millis));
+        assert_eq!(retry.backoff_for(0), Duration::from_millis(100));
+
+        let capped = retry.clone().with_max_delay(Duration::from_millis(300));
+        assert_eq!(capped.backoff_for(2), Duration::from_millis(200));
+        assert_eq!(capped.backoff_for(3), Duration::from_millis(300));
+        assert_eq!(capped.backoff_for(u32::MAX), Duration::from_millis(300));
+
+        // 10^2000 is infinite as an f64 and far past `Duration::MAX`.
+        let steep = retry.clone().with_multiplier(10.0);
+        assert_eq!(steep.backoff_for(2001), Duration::MAX);
+        assert_eq!(steep.backoff_for(30), Duration::MAX);
+        assert_eq!(steep.with_max_delay(Duration::from_secs(60)).backoff_for(2001), Duration::from_secs(60));
+        assert_eq!(retry.clone().with_initial_backoff(Duration::ZERO).with_multiplier(10.0).backoff_for(2001), Duration::ZERO);
+
+        let jittered = capped.with_jitter(true);
+        for attempt in 1..=5 {
+            let delay = jittered.delay_for(attempt);
+            let nominal = jittered.backoff_for(attempt);
+            assert!(delay >= nominal / 2 && delay <= nominal, "{:?} outside {:?}", delay, nominal);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,331 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+/// One part of a `multipart/*` body. Header names are lower-cased.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartPart {
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    /// Parsed response for `application/http` parts, as in OData `$batch`.
+    pub http: Option<EmbeddedHttpResponse>,
+    /// Sub-parts when the part is itself multipart (e.g. a batch changeset).
+    pub parts: Vec<MultipartPart>,
+}
+
+impl MultipartPart {
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get("content-type").map(String::as_str)
+    }
+
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// An HTTP response embedded in a multipart part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddedHttpResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Reads the `boundary` parameter of a multipart content type.
+pub fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.to_ascii_lowercase().starts_with("multipart/") {
+        return None;
+    }
+
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Splits a multipart body (mixed, byteranges, ...) into its parts. The
+/// preamble and epilogue are ignored and both CRLF and bare LF line endings
+/// are accepted.
+pub fn parse_multipart(content_type: &str, body: &[u8]) -> Result<Vec<MultipartPart>> {
+    let boundary = multipart_boundary(content_type)
+        .with_context(|| format!("No multipart boundary in content type '{}'", content_type))?;
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    let mut parts = Vec::new();
+    let mut cursor = find_delimiter(body, delimiter, 0)
+        .with_context(|| "Multipart body does not contain its boundary")?;
+
+    loop {
+        let after = cursor + delimiter.len();
+        if body[after..].starts_with(b"--") {
+            break;
+        }
+
+        // Skip transport padding up to the end of the delimiter line.
+        let content_start = match find(body, b"\n", after) {
+            Some(newline) => newline + 1,
+            None => break,
+        };
+        let next = find_delimiter(body, delimiter, content_start)
+            .with_context(|| "Multipart body ended without a closing boundary")?;
+
+        let content_end = strip_line_ending(body, content_start, next);
+        parts.push(parse_part(&body[content_start..content_end])?);
+        cursor = next;
+    }
+
+    Ok(parts)
+}
+
+fn parse_part(raw: &[u8]) -> Result<MultipartPart> {
+    let (headers, body) = split_headers(raw);
+    let headers = parse_header_lines(headers);
+
+    let content_type = headers.get("content-type").cloned().unwrap_or_default();
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+
+    let http = if mime == "application/http" {
+        Some(parse_embedded_http(body)?)
+    } else {
+        None
+    };
+    let parts = if mime.starts_with("multipart/") {
+        parse_multipart(&content_type, body)?
+    } else {
+        Vec::new()
+    };
+
+    Ok(MultipartPart {
+        headers,
+        body: body.to_vec(),
+        http,
+        parts,
+    })
+}
+
+fn parse_embedded_http(raw: &[u8]) -> Result<EmbeddedHttpResponse> {
+    let raw = trim_leading_newlines(raw);
+    let line_end = find(raw, b"\n", 0).unwrap_or(raw.len());
+    let status_line = String::from_utf8_lossy(&raw[..line_end]).trim().to_string();
+
+    let mut fields = status_line.splitn(3, ' ');
+    let version = fields.next().unwrap_or("");
+    if !version.starts_with("HTTP/") {
+        anyhow::bail!("Invalid embedded HTTP status line: {}", status_line);
+    }
+    let status = fields
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .with_context(|| format!("Invalid embedded HTTP status line: {}", status_line))?;
+    let status_text = fields.next().unwrap_or("").to_string();
+
+    let rest = if line_end < raw.len() { &raw[line_end + 1..] } else { &[][..] };
+    let (headers, body) = split_headers(rest);
+
+    Ok(EmbeddedHttpResponse {
+        status,
+        status_text,
+        headers: parse_header_lines(headers),
+        body: body.to_vec(),
+    })
+}
+
+/// Splits at the first blank line; a part that starts with a blank line has
+/// no headers.
+fn split_headers(raw: &[u8]) -> (&[u8], &[u8]) {
+    if raw.starts_with(b"\r\n") {
+        return (&[], &raw[2..]);
+    }
+    if raw.starts_with(b"\n") {
+        return (&[], &raw[1..]);
+    }
+
+    let crlf = find(raw, b"\r\n\r\n", 0).map(|i| (i, i + 4));
+    let lf = find(raw, b"\n\n", 0).map(|i| (i, i + 2));
+    let split = match (crlf, lf) {
+        (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+        (a, b) => a.or(b),
+    };
+
+    match split {
+        Some((end, body_start)) => (&raw[..end], &raw[body_start..]),
+        None => (raw, &[]),
+    }
+}
+
+fn parse_header_lines(raw: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(raw)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect()
+}
+
+/// Finds a delimiter that starts a line (or the body).
+fn find_delimiter(body: &[u8], delimiter: &[u8], from: usize) -> Option<usize> {
+    let mut start = from;
+    while let Some(pos) = find(body, delimiter, start) {
+        if pos == 0 || body[pos - 1] == b'\n' {
+            return Some(pos);
+        }
+        start = pos + 1;
+    }
+    None
+}
+
+/// The line break before a delimiter belongs to the delimiter, not the part.
+fn strip_line_ending(body: &[u8], start: usize, delimiter_pos: usize) -> usize {
+    let mut end = delimiter_pos;
+    if end > start && body[end - 1] == b'\n' {
+        end -= 1;
+        if end > start && body[end - 1] == b'\r' {
+            end -= 1;
+        }
+    }
+    end
+}
+
+fn trim_leading_newlines(raw: &[u8]) -> &[u8] {
+    let start = raw.iter().position(|&b| b != b'\r' && b != b'\n').unwrap_or(raw.len());
+    &raw[start..]
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from > haystack.len() || needle.is_empty() {
+        return None;
+    }
+    haystack[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| pos + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+
+    const BATCH: &str = "This preamble is ignored.\r\n\
+--batch_36522ad7\r\n\
+Content-Type: application/http\r\n\
+Content-Transfer-Encoding: binary\r\n\
+\r\n\
+HTTP/1.1 200 OK\r\n\
+Content-Type: application/json\r\n\
+\r\n\
+{\"id\":1,\"name\":\"Milk\"}\r\n\
+--batch_36522ad7\r\n\
+Content-Type: multipart/mixed; boundary=changeset_77162fcd\r\n\
+\r\n\
+--changeset_77162fcd\r\n\
+Content-Type: application/http\r\n\
+Content-ID: 1\r\n\
+\r\n\
+HTTP/1.1 201 Created\r\n\
+Location: /Products(2)\r\n\
+\r\n\
+--changeset_77162fcd\r\n\
+Content-Type: application/http\r\n\
+Content-ID: 2\r\n\
+\r\n\
+HTTP/1.1 204 No Content\r\n\
+\r\n\
+\r\n\
+--changeset_77162fcd--\r\n\
+--batch_36522ad7--\r\n\
+This epilogue is ignored too.\r\n";
+
+    const BYTERANGES: &str = "--THIS_STRING_SEPARATES\r\n\
+Content-Type: text/plain\r\n\
+Content-Range: bytes 0-4/26\r\n\
+\r\n\
+abcde\r\n\
+--THIS_STRING_SEPARATES\r\n\
+Content-Type: text/plain\r\n\
+Content-Range: bytes 20-25/26\r\n\
+\r\n\
+uvwxyz\r\n\
+--THIS_STRING_SEPARATES--\r\n";
+
+    #[tokio::test]
+    async fn odata_batch_responses_nest_changesets_and_http_parts() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/$batch").respond(
+            MockResponse::new(200)
+                .with_header("Content-Type", "multipart/mixed; boundary=\"batch_36522ad7\"")
+                .with_body(BATCH),
+        );
+        let response = HttpClient::new().get(&server.url("/$batch"), RequestConfig::new()).await?;
+
+        let parts = response.parse_multipart()?;
+        assert_eq!(parts.len(), 2);
+        let read = parts[0].http.as_ref().unwrap();
+        assert_eq!((read.status, read.status_text.as_str()), (200, "OK"));
+        assert_eq!(read.headers["content-type"], "application/json");
+        assert_eq!(read.body, b"{\"id\":1,\"name\":\"Milk\"}");
+        assert_eq!(parts[0].headers["content-transfer-encoding"], "binary");
+
+        let changeset = &parts[1];
+        assert!(changeset.http.is_none());
+        let statuses: Vec<(&str, u16)> = changeset
+            .parts
+            .iter()
+            .map(|part| (part.headers["content-id"].as_str(), part.http.as_ref().unwrap().status))
+            .collect();
+        assert_eq!(statuses, [("1", 201), ("2", 204)]);
+        let created = changeset.parts[0].http.as_ref().unwrap();
+        assert_eq!((created.headers["location"].as_str(), created.body.as_slice()), ("/Products(2)", &b""[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn byteranges_parts_keep_their_ranges() -> Result<()> {
+        let parts = parse_multipart("multipart/byteranges; boundary=THIS_STRING_SEPARATES", BYTERANGES.as_bytes())?;
+
+        let ranges: Vec<(&str, String)> = parts
+            .iter()
+            .map(|part| (part.headers["content-range"].as_str(), part.body_text()))
+            .collect();
+        assert_eq!(ranges, [("bytes 0-4/26", "abcde".to_string()), ("bytes 20-25/26", "uvwxyz".to_string())]);
+        assert_eq!(parts[0].content_type(), Some("text/plain"));
+        Ok(())
+    }
+
+    #[test]
+    fn bare_lf_line_endings_parse_the_same() -> Result<()> {
+        let content_type = "multipart/mixed; boundary=\"batch_36522ad7\"";
+        let crlf = parse_multipart(content_type, BATCH.as_bytes())?;
+        let lf = parse_multipart(content_type, BATCH.replace("\r\n", "\n").as_bytes())?;
+
+        assert_eq!(lf.len(), crlf.len());
+        assert_eq!(lf[0].http, crlf[0].http);
+        assert_eq!(lf[1].parts.len(), 2);
+        assert_eq!(lf[1].parts[0].http.as_ref().unwrap().headers["location"], "/Products(2)");
+        Ok(())
+    }
+
+    #[test]
+    fn boundaries_must_be_declared_and_closed() {
+        assert_eq!(multipart_boundary("multipart/mixed; charset=utf-8; Boundary=\"a b\"").as_deref(), Some("a b"));
+        assert_eq!(multipart_boundary("text/plain; boundary=x"), None);
+        assert_eq!(multipart_boundary("multipart/mixed; boundary=\"\""), None);
+
+        let error = parse_multipart("application/json", b"{}").unwrap_err();
+        assert_eq!(error.to_string(), "No multipart boundary in content type 'application/json'");
+        let error = parse_multipart("multipart/mixed; boundary=x", b"--y\r\n\r\nbody\r\n--y--").unwrap_err();
+        assert_eq!(error.to_string(), "Multipart body does not contain its boundary");
+        let error = parse_multipart("multipart/mixed; boundary=x", b"--x\r\n\r\nbody\r\n").unwrap_err();
+        assert_eq!(error.to_string(), "Multipart body ended without a closing boundary");
+        let error = parse_multipart(
+            "multipart/mixed; boundary=x",
+            b"--x\r\nContent-Type: application/http\r\n\r\nnot a status line\r\n--x--",
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Invalid embedded HTTP status line: not a status line");
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::QuotaExceeded;
+
+/// Bytes transferred so far against a client's quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl QuotaUsage {
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+}
+
+/// Cumulative body bytes, uploaded and downloaded, shared by every request
+/// of a client.
+#[derive(Debug)]
+pub(crate) struct TransferQuota {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl TransferQuota {
+    pub(crate) fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn usage(&self) -> QuotaUsage {
+        QuotaUsage {
+            used: self.used.load(Ordering::Relaxed),
+            limit: self.limit,
+        }
+    }
+
+    /// Counts `bytes` that are about to be transferred, or fails without
+    /// counting them if they do not fit.
+    pub(crate) fn reserve(&self, bytes: u64) -> Result<(), QuotaExceeded> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&total| total <= self.limit)
+            })
+            .map(|_| ())
+            .map_err(|used| QuotaExceeded {
+                limit: self.limit,
+                used,
+                requested: bytes,
+            })
+    }
+
+    /// Counts `bytes` that have already arrived, failing if they took the
+    /// total over the limit.
+    pub(crate) fn record(&self, bytes: u64) -> Result<(), QuotaExceeded> {
+        let used = self.used.fetch_add(bytes, Ordering::Relaxed);
+        if used.saturating_add(bytes) > self.limit {
+            return Err(QuotaExceeded {
+                limit: self.limit,
+                used,
+                requested: bytes,
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn reset(&self) {
+        self.used.store(0, Ordering::Relaxed);
+    }
+}
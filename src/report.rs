@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::models::{ApiResponse, HttpMethod, RequestConfig, UrlRedactor};
+use crate::secrets::SecretScanner;
+use crate::utils::format_duration;
+
+/// Request bodies longer than this are cut off in reports.
+pub const DEFAULT_REPORT_BODY_LIMIT: usize = 4096;
+
+/// Headers whose values never appear in a report.
+pub(crate) const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "x-auth-token",
+];
+
+/// One request of a chain, as it should appear in a report.
+#[derive(Debug, Clone)]
+pub struct ChainStep {
+    pub name: String,
+    pub method: HttpMethod,
+    pub url: String,
+    pub request_headers: HashMap<String, String>,
+    pub request_body: Option<String>,
+    pub status: Option<u16>,
+    pub status_text: String,
+    pub elapsed_ms: u64,
+    /// Variables extracted from the response for later steps.
+    pub captured: BTreeMap<String, String>,
+    pub error: Option<String>,
+}
+
+impl ChainStep {
+    pub fn from_response(
+        name: &str,
+        method: HttpMethod,
+        url: &str,
+        config: &RequestConfig,
+        body: Option<&str>,
+        response: &ApiResponse,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            method,
+            url: url.to_string(),
+            request_headers: config.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            request_body: body.map(str::to_string),
+            status: Some(response.status),
+            status_text: response.status_text.clone(),
+            elapsed_ms: response.total_time_ms.max(response.response_time_ms),
+            captured: BTreeMap::new(),
+            error: None,
+        }
+    }
+
+    /// A step that failed before a response arrived.
+    pub fn from_error(
+        name: &str,
+        method: HttpMethod,
+        url: &str,
+        config: &RequestConfig,
+        body: Option<&str>,
+        error: &anyhow::Error,
+        elapsed_ms: u64,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            method,
+            url: url.to_string(),
+            request_headers: config.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            request_body: body.map(str::to_string),
+            status: None,
+            status_text: String::new(),
+            elapsed_ms,
+            captured: BTreeMap::new(),
+            error: Some(format!("{:#}", error)),
+        }
+    }
+
+    pub fn with_captured(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.captured.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.error.is_none() && self.status.is_some_and(|status| (200..300).contains(&status))
+    }
+
+    fn status_label(&self) -> String {
+        let mark = if self.is_success() { "✅" } else { "❌" };
+        match self.status {
+            Some(status) => format!("{} {} {}", mark, status, self.status_text).trim_end().to_string(),
+            None => format!("{} no response", mark),
+        }
+    }
+}
+
+/// The steps of a request chain, renderable as a Markdown report.
+#[derive(Debug, Clone)]
+pub struct ChainResult {
+    pub steps: Vec<ChainStep>,
+    body_limit: usize,
+    redactor: UrlRedactor,
+    secrets: SecretScanner,
+}
+
+impl Default for ChainResult {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChainResult {
+    pub fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            body_limit: DEFAULT_REPORT_BODY_LIMIT,
+            redactor: UrlRedactor::default(),
+            secrets: SecretScanner::default(),
+        }
+    }
+
+    pub fn with_body_limit(mut self, bytes: usize) -> Self {
+        self.body_limit = bytes;
+        self
+    }
+
+    pub fn with_redactor(mut self, redactor: UrlRedactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    /// Replaces the scanner that masks secrets in bodies, errors and
+    /// captured values; `SecretScanner::none()` turns masking off.
+    pub fn with_secret_scanner(mut self, secrets: SecretScanner) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    pub fn push(&mut self, step: ChainStep) {
+        self.steps.push(step);
+    }
+
+    pub fn passed(&self) -> usize {
+        self.steps.iter().filter(|step| step.is_success()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.steps.len() - self.passed()
+    }
+
+    /// A summary table followed by one section per step. Secrets in URLs,
+    /// headers and bodies are masked and no ANSI colors are emitted.
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        let total_ms: u64 = self.steps.iter().map(|step| step.elapsed_ms).sum();
+
+        let _ = writeln!(out, "# Request Chain Report\n");
+        let _ = writeln!(
+            out,
+            "**{} steps:** {} passed, {} failed in {}\n",
+            self.steps.len(),
+            self.passed(),
+            self.failed(),
+            format_duration(total_ms)
+        );
+        let _ = writeln!(out, "| # | Step | Request | Status | Time |");
+        let _ = writeln!(out, "|---|------|---------|--------|------|");
+        for (index, step) in self.steps.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "| {} | {} | `{} {}` | {} | {} |",
+                index + 1,
+                table_cell(&step.name),
+                step.method,
+                table_cell(&self.redactor.redact(&step.url)),
+                step.status_label(),
+                format_duration(step.elapsed_ms)
+            );
+        }
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let _ = writeln!(out, "\n## {}. {}\n", index + 1, step.name);
+            let _ = writeln!(out, "- **Request:** `{} {}`", step.method, self.redactor.redact(&step.url));
+            let _ = writeln!(out, "- **Status:** {}", step.status_label());
+            let _ = writeln!(out, "- **Time:** {}", format_duration(step.elapsed_ms));
+            if let Some(error) = &step.error {
+                let _ = writeln!(out, "- **Error:** {}", self.secrets.redact(error).replace('\n', " "));
+            }
+
+            if !step.request_headers.is_empty() {
+                let _ = writeln!(out, "\n**Request headers**\n");
+                let _ = writeln!(out, "| Header | Value |");
+                let _ = writeln!(out, "|--------|-------|");
+                let mut headers: Vec<_> = step.request_headers.iter().collect();
+                headers.sort();
+                for (name, value) in headers {
+                    let value = if SENSITIVE_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                        crate::models::REDACTED.into()
+                    } else {
+                        self.secrets.redact(value)
+                    };
+                    let _ = writeln!(out, "| {} | {} |", table_cell(name), table_cell(&value));
+                }
+            }
+
+            if let Some(body) = &step.request_body {
+                let _ = writeln!(out, "\n**Request body**\n");
+                let pretty = serde_json::from_str::<serde_json::Value>(body)
+                    .ok()
+                    .and_then(|value| serde_json::to_string_pretty(&value).ok());
+                let is_json = pretty.is_some();
+                let body = pretty.unwrap_or_else(|| body.clone());
+                let body = self.secrets.redact(&body);
+                let (body, truncated) = truncate(&body, self.body_limit);
+                let _ = writeln!(out, "```{}\n{}\n```", if is_json { "json" } else { "" }, body);
+                if truncated {
+                    let _ = writeln!(out, "\n_Body truncated to {} bytes._", self.body_limit);
+                }
+            }
+
+            if !step.captured.is_empty() {
+                let _ = writeln!(out, "\n**Captured variables**\n");
+                let _ = writeln!(out, "| Name | Value |");
+                let _ = writeln!(out, "|------|-------|");
+                for (name, value) in &step.captured {
+                    let _ = writeln!(out, "| {} | {} |", table_cell(name), table_cell(&self.secrets.redact(value)));
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn table_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+/// Cuts `text` to at most `limit` bytes on a character boundary.
+fn truncate(text: &str, limit: usize) -> (&str, bool) {
+    if text.len() <= limit {
+        return (text, false);
+    }
+    let mut end = limit;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    (&text[..end], true)
+}
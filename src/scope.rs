@@ -0,0 +1,133 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::body::Body;
+use crate::client::HttpClient;
+use crate::headers::HeaderList;
+use crate::models::{ApiResponse, HttpMethod, IntoRequestUrl, RequestConfig};
+
+/// Overrides set up in `HttpClient::scoped`.
+#[derive(Debug, Clone, Default)]
+pub struct ScopeDefaults {
+    headers: HeaderList,
+    timeout: Option<Duration>,
+}
+
+impl ScopeDefaults {
+    pub fn set_default_header(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn headers(&self) -> &HeaderList {
+        &self.headers
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Fills in whatever `config` leaves unset; headers match case-insensitively.
+    pub(crate) fn apply(&self, mut config: RequestConfig) -> RequestConfig {
+        for (key, value) in &self.headers {
+            if !config.headers.contains_key(key) {
+                config.headers.insert(key.clone(), value.clone());
+            }
+        }
+        if config.timeout.is_none() {
+            config.timeout = self.timeout;
+        }
+        config
+    }
+}
+
+/// A view of an `HttpClient` whose requests carry extra defaults. The parent
+/// client and other scopes are unaffected. Precedence is `RequestConfig`,
+/// then the scope, then the client's own defaults.
+#[derive(Clone)]
+pub struct ScopedClient<'a> {
+    client: &'a HttpClient,
+    defaults: Arc<ScopeDefaults>,
+}
+
+impl<'a> ScopedClient<'a> {
+    pub(crate) fn new(client: &'a HttpClient, defaults: ScopeDefaults) -> Self {
+        Self {
+            client,
+            defaults: Arc::new(defaults),
+        }
+    }
+
+    /// Narrows this scope further; the new overrides win over the current ones.
+    pub fn scoped<F>(&self, configure: F) -> ScopedClient<'a>
+    where
+        F: FnOnce(&mut ScopeDefaults),
+    {
+        let mut defaults = (*self.defaults).clone();
+        configure(&mut defaults);
+        ScopedClient::new(self.client, defaults)
+    }
+
+    pub fn defaults(&self) -> &ScopeDefaults {
+        &self.defaults
+    }
+
+    pub fn client(&self) -> &'a HttpClient {
+        self.client
+    }
+
+    pub async fn get(&self, url: impl IntoRequestUrl, config: RequestConfig) -> Result<ApiResponse> {
+        self.client.get(url, self.defaults.apply(config)).await
+    }
+
+    pub async fn post(&self, url: impl IntoRequestUrl, data: &str, config: RequestConfig) -> Result<ApiResponse> {
+        self.client.post(url, data, self.defaults.apply(config)).await
+    }
+
+    pub async fn put(&self, url: impl IntoRequestUrl, data: &str, config: RequestConfig) -> Result<ApiResponse> {
+        self.client.put(url, data, self.defaults.apply(config)).await
+    }
+
+    pub async fn post_value(&self, url: impl IntoRequestUrl, value: &Value, config: RequestConfig) -> Result<ApiResponse> {
+        self.client.post_value(url, value, self.defaults.apply(config)).await
+    }
+
+    pub async fn put_value(&self, url: impl IntoRequestUrl, value: &Value, config: RequestConfig) -> Result<ApiResponse> {
+        self.client.put_value(url, value, self.defaults.apply(config)).await
+    }
+
+    pub async fn patch_value(&self, url: impl IntoRequestUrl, value: &Value, config: RequestConfig) -> Result<ApiResponse> {
+        self.client.patch_value(url, value, self.defaults.apply(config)).await
+    }
+
+    pub async fn delete(&self, url: impl IntoRequestUrl, config: RequestConfig) -> Result<ApiResponse> {
+        self.client.delete(url, self.defaults.apply(config)).await
+    }
+
+    pub async fn send_body(
+        &self,
+        method: HttpMethod,
+        url: impl IntoRequestUrl,
+        body: Body,
+        config: RequestConfig,
+    ) -> Result<ApiResponse> {
+        self.client.send_body(method, url, body, self.defaults.apply(config)).await
+    }
+
+    pub async fn request(
+        &self,
+        method: HttpMethod,
+        url: impl IntoRequestUrl,
+        body: Option<&str>,
+        config: RequestConfig,
+    ) -> Result<ApiResponse> {
+        self.client.request(method, url, body, self.defaults.apply(config)).await
+    }
+}
@@ -0,0 +1,474 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Shortest hex run treated as a secret.
+pub const DEFAULT_MIN_HEX_LENGTH: usize = 32;
+/// Shortest base64 run treated as a secret.
+pub const DEFAULT_MIN_BASE64_LENGTH: usize = 40;
+/// Bits of Shannon entropy per character a base64 run needs to count as
+/// random; prose and paths stay well below it.
+const MIN_BASE64_ENTROPY: f64 = 4.0;
+
+/// Keys (`.env` names, JSON keys, header names) whose values are redacted
+/// when, uppercased with `-` read as `_`, they contain one of these.
+const SECRET_NAMES: &[&str] = &[
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "TOKEN",
+    "API_KEY",
+    "APIKEY",
+    "PRIVATE_KEY",
+    "ACCESS_KEY",
+    "CREDENTIAL",
+];
+
+/// Header names that always carry credentials.
+const SECRET_HEADERS: &[&str] = &["AUTHORIZATION", "PROXY_AUTHORIZATION", "COOKIE", "SET_COOKIE"];
+
+/// A kind of secret `SecretScanner` looks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretKind {
+    /// `-----BEGIN ... PRIVATE KEY-----` blocks.
+    PrivateKey,
+    /// AWS access key IDs such as `AKIA...`.
+    AwsKey,
+    /// Three-part `eyJ...` JSON Web Tokens.
+    Jwt,
+    /// Values of `NAME=value` lines whose name looks secret, e.g.
+    /// `DB_PASSWORD=...`; the name is kept.
+    EnvSecret,
+    /// Values of JSON string members whose key looks secret, e.g.
+    /// `"password": "..."` or `"apiKey": "..."`; the key is kept.
+    JsonSecret,
+    /// Values of `Name: value` header lines that carry credentials, e.g.
+    /// `Authorization:` or `X-Api-Key:`; the name is kept.
+    HeaderSecret,
+    /// Long hex strings.
+    Hex,
+    /// Long, high-entropy base64 strings.
+    Base64,
+    /// Tokens starting with a known prefix, e.g. `ghp_` for GitHub.
+    Prefixed { name: String, prefix: String },
+}
+
+impl SecretKind {
+    /// The name used in `«redacted:name»` markers.
+    pub fn label(&self) -> &str {
+        match self {
+            SecretKind::PrivateKey => "private-key",
+            SecretKind::AwsKey => "aws-key",
+            SecretKind::Jwt => "jwt",
+            SecretKind::EnvSecret => "env-secret",
+            SecretKind::JsonSecret => "json-secret",
+            SecretKind::HeaderSecret => "header-secret",
+            SecretKind::Hex => "hex",
+            SecretKind::Base64 => "base64",
+            SecretKind::Prefixed { name, .. } => name,
+        }
+    }
+}
+
+/// Masks secrets in bodies before they reach logs and reports, replacing
+/// each with a marker such as `«redacted:aws-key»`. Responses themselves
+/// are never changed. Clones share one redaction counter.
+#[derive(Debug, Clone)]
+pub struct SecretScanner {
+    kinds: Vec<SecretKind>,
+    min_hex_length: usize,
+    min_base64_length: usize,
+    redactions: Arc<AtomicU64>,
+}
+
+impl Default for SecretScanner {
+    fn default() -> Self {
+        let prefixed = |name: &str, prefix: &str| SecretKind::Prefixed {
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+        };
+        Self::with_kinds(vec![
+            SecretKind::PrivateKey,
+            SecretKind::AwsKey,
+            SecretKind::Jwt,
+            SecretKind::EnvSecret,
+            SecretKind::JsonSecret,
+            SecretKind::HeaderSecret,
+            prefixed("github-token", "ghp_"),
+            prefixed("github-token", "github_pat_"),
+            prefixed("slack-token", "xoxb-"),
+            prefixed("slack-token", "xoxp-"),
+            prefixed("stripe-key", "sk_live_"),
+            SecretKind::Hex,
+            SecretKind::Base64,
+        ])
+    }
+}
+
+impl SecretScanner {
+    /// A scanner that redacts nothing.
+    pub fn none() -> Self {
+        Self::with_kinds(Vec::new())
+    }
+
+    pub fn with_kinds(kinds: Vec<SecretKind>) -> Self {
+        Self {
+            kinds,
+            min_hex_length: DEFAULT_MIN_HEX_LENGTH,
+            min_base64_length: DEFAULT_MIN_BASE64_LENGTH,
+            redactions: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Also redacts tokens starting with `prefix`, marked as `name`.
+    pub fn with_prefix(mut self, name: impl Into<String>, prefix: impl Into<String>) -> Self {
+        self.kinds.push(SecretKind::Prefixed {
+            name: name.into(),
+            prefix: prefix.into(),
+        });
+        self
+    }
+
+    pub fn without(mut self, kind: &SecretKind) -> Self {
+        self.kinds.retain(|existing| existing != kind);
+        self
+    }
+
+    pub fn with_min_lengths(mut self, hex: usize, base64: usize) -> Self {
+        self.min_hex_length = hex;
+        self.min_base64_length = base64;
+        self
+    }
+
+    pub fn kinds(&self) -> &[SecretKind] {
+        &self.kinds
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.kinds.is_empty()
+    }
+
+    /// Secrets replaced so far by this scanner and its clones.
+    pub fn redactions(&self) -> u64 {
+        self.redactions.load(Ordering::Relaxed)
+    }
+
+    /// `text` with every secret found replaced by its marker.
+    pub fn redact<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        let spans = self.find(text);
+        if spans.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        self.redactions.fetch_add(spans.len() as u64, Ordering::Relaxed);
+
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+        for (start, end, label) in spans {
+            out.push_str(&text[last..start]);
+            out.push_str(&format!("«redacted:{}»", label));
+            last = end;
+        }
+        out.push_str(&text[last..]);
+        Cow::Owned(out)
+    }
+
+    /// Byte ranges of the secrets in `text` with their labels, in order and
+    /// without overlaps.
+    fn find<'s>(&'s self, text: &str) -> Vec<(usize, usize, &'s str)> {
+        let mut spans: Vec<(usize, usize, &str)> = Vec::new();
+        let overlaps = |spans: &[(usize, usize, &str)], start: usize, end: usize| {
+            spans.iter().any(|&(s, e, _)| start < e && s < end)
+        };
+
+        if let Some(kind) = self.kinds.iter().find(|kind| **kind == SecretKind::PrivateKey) {
+            spans.extend(private_key_blocks(text).into_iter().map(|(s, e)| (s, e, kind.label())));
+        }
+        for kind in &self.kinds {
+            let values = match kind {
+                SecretKind::EnvSecret => env_secret_values(text),
+                SecretKind::JsonSecret => json_secret_values(text),
+                SecretKind::HeaderSecret => header_secret_values(text),
+                _ => continue,
+            };
+            for (start, end) in values {
+                if !overlaps(&spans, start, end) {
+                    spans.push((start, end, kind.label()));
+                }
+            }
+        }
+        for (start, end) in tokens(text) {
+            if overlaps(&spans, start, end) {
+                continue;
+            }
+            let token = &text[start..end];
+            if let Some(kind) = self.kinds.iter().find(|kind| self.token_matches(kind, token)) {
+                spans.push((start, end, kind.label()));
+            }
+        }
+
+        spans.sort_by_key(|&(start, _, _)| start);
+        spans
+    }
+
+    fn token_matches(&self, kind: &SecretKind, token: &str) -> bool {
+        match kind {
+            SecretKind::PrivateKey | SecretKind::EnvSecret | SecretKind::JsonSecret | SecretKind::HeaderSecret => false,
+            SecretKind::AwsKey => is_aws_key(token),
+            SecretKind::Jwt => is_jwt(token),
+            SecretKind::Hex => {
+                token.len() >= self.min_hex_length
+                    && token.bytes().all(|b| b.is_ascii_hexdigit())
+                    && token.bytes().any(|b| b.is_ascii_digit())
+                    && token.bytes().any(|b| b.is_ascii_alphabetic())
+            }
+            SecretKind::Base64 => token.len() >= self.min_base64_length && is_random_base64(token),
+            SecretKind::Prefixed { prefix, .. } => {
+                token.starts_with(prefix.as_str()) && token.len() >= prefix.len() + 8
+            }
+        }
+    }
+}
+
+/// PEM private key blocks, from `-----BEGIN` to the end of the matching
+/// `-----END ...-----` line, or to the end of `text` if it is cut off.
+fn private_key_blocks(text: &str) -> Vec<(usize, usize)> {
+    let mut blocks = Vec::new();
+    let mut from = 0;
+    while let Some(offset) = text[from..].find("-----BEGIN ") {
+        let start = from + offset;
+        let header_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+        if !text[start..header_end].contains("PRIVATE KEY") {
+            from = header_end;
+            continue;
+        }
+        let end = text[header_end..]
+            .find("-----END ")
+            .and_then(|i| {
+                let footer = header_end + i + "-----END ".len();
+                text[footer..].find("-----").map(|j| footer + j + "-----".len())
+            })
+            .unwrap_or(text.len());
+        blocks.push((start, end));
+        from = end;
+    }
+    blocks
+}
+
+/// Values of `NAME=value` and `export NAME=value` lines whose name
+/// contains a secret-looking word.
+fn env_secret_values(text: &str) -> Vec<(usize, usize)> {
+    let mut values = Vec::new();
+    for line in text.lines() {
+        let content = line.trim_start();
+        let content = content.strip_prefix("export ").unwrap_or(content);
+        if let Some((name, value)) = content.split_once('=') {
+            let name = name.trim();
+            let is_env_name = !name.is_empty()
+                && name.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_');
+            let value = value.trim();
+            if is_env_name && !value.is_empty() && is_secret_name(name) {
+                values.push(offset_in(text, value));
+            }
+        }
+    }
+    values
+}
+
+/// Contents of JSON string members whose key looks secret, e.g. the
+/// `hunter2` in `"password": "hunter2"`.
+fn json_secret_values(text: &str) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut values = Vec::new();
+    let mut index = 0;
+    while let Some((key_start, key_end)) = json_string(bytes, index) {
+        index = key_end + 1;
+        let colon = skip_whitespace(bytes, index);
+        if bytes.get(colon) != Some(&b':') {
+            continue;
+        }
+        let quote = skip_whitespace(bytes, colon + 1);
+        if bytes.get(quote) != Some(&b'"') {
+            continue;
+        }
+        let Some((value_start, value_end)) = json_string(bytes, quote) else {
+            break;
+        };
+        index = value_end + 1;
+        if value_end > value_start && is_secret_name(&text[key_start..key_end]) {
+            values.push((value_start, value_end));
+        }
+    }
+    values
+}
+
+/// The range between the quotes of the next string literal at or after
+/// `from`, skipping escaped quotes.
+fn json_string(bytes: &[u8], from: usize) -> Option<(usize, usize)> {
+    let open = from + bytes[from..].iter().position(|&b| b == b'"')?;
+    let mut index = open + 1;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'\\' => index += 2,
+            b'"' => return Some((open + 1, index)),
+            _ => index += 1,
+        }
+    }
+    None
+}
+
+fn skip_whitespace(bytes: &[u8], from: usize) -> usize {
+    from + bytes[from.min(bytes.len())..].iter().take_while(|b| b.is_ascii_whitespace()).count()
+}
+
+/// Values of `Name: value` lines whose header name carries credentials
+/// (`Authorization`, `Cookie`) or looks secret (`X-Api-Key`).
+fn header_secret_values(text: &str) -> Vec<(usize, usize)> {
+    let mut values = Vec::new();
+    for line in text.lines() {
+        if let Some((name, value)) = line.trim_start().split_once(':') {
+            let is_header_name = !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+            let value = value.trim();
+            if is_header_name && !value.is_empty() && (is_secret_name(name) || is_secret_header(name)) {
+                values.push(offset_in(text, value));
+            }
+        }
+    }
+    values
+}
+
+fn normalize_name(name: &str) -> String {
+    name.to_ascii_uppercase().replace('-', "_")
+}
+
+fn is_secret_name(name: &str) -> bool {
+    let name = normalize_name(name);
+    SECRET_NAMES.iter().any(|word| name.contains(word))
+}
+
+fn is_secret_header(name: &str) -> bool {
+    SECRET_HEADERS.contains(&normalize_name(name).as_str())
+}
+
+/// The byte range of `part`, a subslice of `text`, within it.
+fn offset_in(text: &str, part: &str) -> (usize, usize) {
+    let start = part.as_ptr() as usize - text.as_ptr() as usize;
+    (start, start + part.len())
+}
+
+/// Maximal runs of characters that can make up a key or token, minus a
+/// trailing sentence period. `=` splits tokens so `key=AKIA...` is seen as
+/// two; base64 padding is simply left outside the marker.
+fn tokens(text: &str) -> Vec<(usize, usize)> {
+    let is_token_byte = |b: u8| b.is_ascii_alphanumeric() || b"+/_-.".contains(&b);
+    let bytes = text.as_bytes();
+    let mut runs = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        if !is_token_byte(bytes[index]) {
+            index += 1;
+            continue;
+        }
+        let start = index;
+        while index < bytes.len() && is_token_byte(bytes[index]) {
+            index += 1;
+        }
+        let mut end = index;
+        while end > start && bytes[end - 1] == b'.' {
+            end -= 1;
+        }
+        if end > start {
+            runs.push((start, end));
+        }
+    }
+    runs
+}
+
+fn is_aws_key(token: &str) -> bool {
+    token.len() == 20
+        && ["AKIA", "ASIA", "AIDA", "AROA", "AGPA"].iter().any(|prefix| token.starts_with(prefix))
+        && token.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+}
+
+fn is_jwt(token: &str) -> bool {
+    let parts: Vec<&str> = token.split('.').collect();
+    parts.len() == 3
+        && parts[0].starts_with("eyJ")
+        && parts[1].starts_with("eyJ")
+        && parts.iter().all(|part| {
+            !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+fn is_random_base64(token: &str) -> bool {
+    let is_base64 = token
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"+/-_".contains(&b));
+    is_base64
+        && token.bytes().any(|b| b.is_ascii_uppercase())
+        && token.bytes().any(|b| b.is_ascii_lowercase())
+        && token.bytes().any(|b| b.is_ascii_digit())
+        && shannon_entropy(token) >= MIN_BASE64_ENTROPY
+}
+
+fn shannon_entropy(text: &str) -> f64 {
+    let mut counts = [0usize; 256];
+    for b in text.bytes() {
+        counts[b as usize] += 1;
+    }
+    let len = text.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_lines_keep_their_names() {
+        let text = "HOST=db.local\nexport DB_PASSWORD=hunter2\nAPI_KEY = abc123\n";
+        assert_eq!(
+            SecretScanner::default().redact(text),
+            "HOST=db.local\nexport DB_PASSWORD=«redacted:env-secret»\nAPI_KEY = «redacted:env-secret»\n"
+        );
+    }
+
+    #[test]
+    fn json_members_with_secret_keys_are_masked() {
+        let scanner = SecretScanner::default();
+        let text = r#"{"user": "alice", "password": "hunter2", "apiKey":"k-1", "client_secret" : "s\"q", "token": null}"#;
+        assert_eq!(
+            scanner.redact(text),
+            r#"{"user": "alice", "password": "«redacted:json-secret»", "apiKey":"«redacted:json-secret»", "client_secret" : "«redacted:json-secret»", "token": null}"#
+        );
+        assert_eq!(scanner.redactions(), 3);
+
+        let nested = r#"{"items": ["password", "x"], "auth": {"access_token": "abc"}}"#;
+        assert_eq!(
+            scanner.redact(nested),
+            r#"{"items": ["password", "x"], "auth": {"access_token": "«redacted:json-secret»"}}"#
+        );
+    }
+
+    #[test]
+    fn header_lines_with_credentials_are_masked() {
+        let text = "Content-Type: text/plain\nAuthorization: Bearer abc.def\nx-api-key: k-123\nCookie: session=1\n";
+        assert_eq!(
+            SecretScanner::default().redact(text),
+            "Content-Type: text/plain\nAuthorization: «redacted:header-secret»\nx-api-key: «redacted:header-secret»\nCookie: «redacted:header-secret»\n"
+        );
+    }
+
+    #[test]
+    fn keyed_kinds_can_be_turned_off() {
+        let scanner = SecretScanner::default().without(&SecretKind::JsonSecret).without(&SecretKind::HeaderSecret);
+        let text = "{\"password\": \"hunter2\"}\nAuthorization: Basic dXNlcg";
+        assert_eq!(scanner.redact(text), text);
+    }
+}
@@ -0,0 +1,240 @@
+use futures_util::{Stream, StreamExt};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{Notify, watch};
+
+use crate::error::ClientClosed;
+
+/// How long shutdown waits for cancelled requests to unwind. Deferred
+/// bodies and streams nobody reads stay counted until they are dropped, so
+/// shutdown stops waiting for them here.
+const CANCEL_WAIT: Duration = Duration::from_secs(1);
+
+/// Outcome of `HttpClient::shutdown`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Requests that were running when shutdown began.
+    pub in_flight: usize,
+    /// Of those, how many finished within the grace period.
+    pub completed: usize,
+    /// Of those, how many were cancelled when the grace period ran out.
+    pub cancelled: usize,
+}
+
+/// Tracks running requests so the client can be closed and drained.
+pub(crate) struct Lifecycle {
+    closed: AtomicBool,
+    next_id: AtomicU64,
+    running: Mutex<HashSet<u64>>,
+    finished: Notify,
+    cancel: watch::Sender<bool>,
+    /// Set when shutdown begins, for background tasks.
+    closing: watch::Sender<bool>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            closed: AtomicBool::new(false),
+            next_id: AtomicU64::new(1),
+            running: Mutex::new(HashSet::new()),
+            finished: Notify::new(),
+            cancel: watch::channel(false).0,
+            closing: watch::channel(false).0,
+        }
+    }
+}
+
+impl Lifecycle {
+    pub(crate) fn check_open(&self) -> Result<(), ClientClosed> {
+        if self.closed.load(Ordering::SeqCst) {
+            Err(ClientClosed { cancelled: false })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Registers a new request, or refuses it once shutdown has begun.
+    pub(crate) fn enter(self: &Arc<Self>) -> Result<RequestGuard, ClientClosed> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        // Checked under the lock so shutdown never misses a request.
+        self.check_open()?;
+        running.insert(id);
+        Ok(RequestGuard {
+            lifecycle: Arc::clone(self),
+            id,
+            cancel: self.cancel.subscribe(),
+        })
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Becomes `true` when shutdown begins; closed when the client is dropped.
+    pub(crate) fn closing(&self) -> watch::Receiver<bool> {
+        self.closing.subscribe()
+    }
+
+    pub(crate) async fn shutdown(&self, grace: Duration) -> ShutdownReport {
+        let started: HashSet<u64> = {
+            let running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+            self.closed.store(true, Ordering::SeqCst);
+            self.closing.send_replace(true);
+            running.clone()
+        };
+
+        let remaining = || {
+            let running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+            started.iter().filter(|id| running.contains(id)).count()
+        };
+
+        let drain = || async {
+            loop {
+                let finished = self.finished.notified();
+                if remaining() == 0 {
+                    break;
+                }
+                finished.await;
+            }
+        };
+        let drained = tokio::time::timeout(grace, drain()).await.is_ok();
+
+        let mut cancelled = 0;
+        if !drained {
+            cancelled = remaining();
+            let _ = self.cancel.send(true);
+            // Cancelled requests unwind at their next await point.
+            let _ = tokio::time::timeout(CANCEL_WAIT, drain()).await;
+        }
+
+        ShutdownReport {
+            in_flight: started.len(),
+            completed: started.len() - cancelled,
+            cancelled,
+        }
+    }
+}
+
+/// Marks a request as running until dropped.
+pub(crate) struct RequestGuard {
+    lifecycle: Arc<Lifecycle>,
+    id: u64,
+    cancel: watch::Receiver<bool>,
+}
+
+impl RequestGuard {
+    /// Resolves when shutdown gives up on the request.
+    pub(crate) async fn cancelled(&mut self) {
+        cancelled(&mut self.cancel).await
+    }
+
+    /// For waiting on cancellation without holding the guard, see `cancelled`.
+    pub(crate) fn cancel_signal(&self) -> watch::Receiver<bool> {
+        self.cancel.clone()
+    }
+}
+
+/// Resolves when shutdown cancels the request `cancel` belongs to.
+pub(crate) async fn cancelled(cancel: &mut watch::Receiver<bool>) {
+    if cancel.wait_for(|cancelled| *cancelled).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Keeps a streamed request counted by shutdown until `stream` ends or is
+/// dropped, ending it with `ClientClosed` if shutdown cancels it first.
+pub(crate) fn track_stream<S, T>(stream: S, guard: RequestGuard) -> impl Stream<Item = anyhow::Result<T>>
+where
+    S: Stream<Item = anyhow::Result<T>>,
+{
+    futures_util::stream::unfold((Box::pin(stream), Some(guard)), |(mut stream, guard)| async move {
+        let mut guard = guard?;
+        tokio::select! {
+            biased;
+            _ = guard.cancelled() => Some((Err(ClientClosed { cancelled: true }.into()), (stream, None))),
+            item = stream.next() => item.map(|item| (item, (stream, Some(guard)))),
+        }
+    })
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.lifecycle.running.lock() {
+            running.remove(&self.id);
+        }
+        self.lifecycle.finished.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{ClientClosed, HttpClient, HttpMethod, RequestConfig};
+    use anyhow::Result;
+    use futures_util::StreamExt;
+    use std::time::{Duration, Instant};
+
+    fn is_cancelled(error: &anyhow::Error) -> bool {
+        error
+            .chain()
+            .any(|cause| cause.downcast_ref::<ClientClosed>().is_some_and(|closed| closed.cancelled))
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_deferred_bodies_and_cancels_unread_ones() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/body").respond(MockResponse::text(200, "done"));
+        let client = HttpClient::new();
+        let config = RequestConfig::new().defer_body(true);
+
+        let mut read = client.get(&server.url("/body"), config.clone()).await?;
+        read.read_body().await?;
+        let mut unread = client.get(&server.url("/body"), config).await?;
+
+        let started = Instant::now();
+        let report = client.shutdown(Duration::from_millis(50)).await;
+        assert_eq!((report.in_flight, report.cancelled), (1, 1));
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(is_cancelled(&unread.read_body().await.unwrap_err()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_lets_a_json_array_stream_finish_within_the_grace_period() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/items").respond(
+            MockResponse::new(200)
+                .with_header("Content-Type", "application/json")
+                .chunked(["[1,", "2,", "3]"], Duration::from_millis(50)),
+        );
+        let client = HttpClient::new();
+        let url = server.url("/items");
+        let items = client.get_json_array_stream::<u32>(&url, RequestConfig::new()).await?;
+
+        let (report, items) = tokio::join!(client.shutdown(Duration::from_secs(5)), items.collect::<Vec<_>>());
+        assert_eq!((report.in_flight, report.completed), (1, 1));
+        let items: Vec<u32> = items.into_iter().collect::<Result<_>>()?;
+        assert_eq!(items, [1, 2, 3]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_cancels_a_stalled_json_array_stream() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/items")
+            .respond(MockResponse::new(200).chunked(["[1,", "2]"], Duration::from_secs(10)));
+        let client = HttpClient::new();
+        let url = server.url("/items");
+        let mut items = Box::pin(client.get_json_array_stream::<u32>(&url, RequestConfig::new()).await?);
+
+        let (report, next) = tokio::join!(client.shutdown(Duration::from_millis(50)), items.next());
+        assert_eq!((report.in_flight, report.cancelled), (1, 1));
+        assert!(is_cancelled(&next.unwrap().unwrap_err()));
+        Ok(())
+    }
+}
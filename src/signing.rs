@@ -0,0 +1,163 @@
+use anyhow::Result;
+use reqwest::header::{HeaderName, HeaderValue};
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::headers::HeaderList;
+use crate::models::HttpMethod;
+
+pub type SignFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// A request as it goes on the wire, handed to a `RequestSigner` once its
+/// headers and body are final.
+#[derive(Debug)]
+pub struct SignableRequest<'a> {
+    pub method: HttpMethod,
+    /// The full URL, after base URL resolution and `with_query`.
+    pub url: &'a url::Url,
+    /// Every header that will be sent, `Authorization` and `User-Agent`
+    /// included. Headers added, changed or removed here are sent that way.
+    pub headers: HeaderList,
+    /// `None` for streaming bodies, which cannot be hashed up front.
+    pub body: Option<&'a [u8]>,
+}
+
+impl SignableRequest<'_> {
+    /// The body to hash: empty for requests without one.
+    pub fn payload(&self) -> &[u8] {
+        self.body.unwrap_or_default()
+    }
+}
+
+/// Signs every attempt right before it is sent, retries and re-sent
+/// requests included, e.g. with AWS SigV4. Signing may be async, say to
+/// fetch temporary credentials. Plain closures taking a
+/// `&mut SignableRequest` are signers too.
+pub trait RequestSigner: Send + Sync {
+    fn sign<'a>(&'a self, request: &'a mut SignableRequest<'_>) -> SignFuture<'a>;
+}
+
+impl<F> RequestSigner for F
+where
+    F: Fn(&mut SignableRequest<'_>) -> Result<()> + Send + Sync,
+{
+    fn sign<'a>(&'a self, request: &'a mut SignableRequest<'_>) -> SignFuture<'a> {
+        let signed = self(request);
+        Box::pin(std::future::ready(signed))
+    }
+}
+
+/// Runs `signer` over `request` and writes back the headers it changed.
+/// Untouched headers, repeated ones included, are left as they were.
+pub(crate) async fn sign_request(signer: &dyn RequestSigner, request: &mut reqwest::Request) -> Result<()> {
+    let method = request.method().as_str().parse()?;
+    let before: HeaderList = request
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.as_str(), String::from_utf8_lossy(value.as_bytes())))
+        .collect();
+    let url = request.url().clone();
+    let mut signable = SignableRequest {
+        method,
+        url: &url,
+        headers: before.clone(),
+        body: request.body().and_then(reqwest::Body::as_bytes),
+    };
+    signer.sign(&mut signable).await?;
+    let after = signable.headers;
+    if after == before {
+        return Ok(());
+    }
+
+    after.validate()?;
+    let headers = request.headers_mut();
+    for name in before.keys().filter(|name| !after.contains_key(name)) {
+        headers.remove(name.as_str());
+    }
+    for (name, value) in &after {
+        if before.get(name) != Some(value) {
+            headers.insert(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::models::{RequestConfig, RetryPolicy};
+    use crate::testing::{MockResponse, MockServer};
+    use crate::HttpClient;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Fetches its key asynchronously, like a signer that refreshes
+    /// temporary credentials, then signs the method, path and body.
+    struct AsyncSigner {
+        calls: Arc<AtomicU32>,
+    }
+
+    impl RequestSigner for AsyncSigner {
+        fn sign<'a>(&'a self, request: &'a mut SignableRequest<'_>) -> SignFuture<'a> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let attempt = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+                let digest = crate::utils::sha256(request.payload());
+                let signature = format!("{} {} {:02x}{:02x}", request.method, request.url.path(), digest[0], digest[1]);
+                request.headers.insert("X-Signature", signature);
+                request.headers.insert("X-Attempt", attempt.to_string());
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn async_signers_add_headers_and_sign_every_retry() -> anyhow::Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(crate::HttpMethod::Post, "/orders")
+            .respond_sequence(vec![MockResponse::new(503), MockResponse::new(201)]);
+        let calls = Arc::new(AtomicU32::new(0));
+        let client = HttpClient::new()
+            .with_clock(Arc::new(MockClock::new()))
+            .with_signer(AsyncSigner { calls: Arc::clone(&calls) });
+
+        let config = RequestConfig::new().with_retry(RetryPolicy::new(2));
+        let response = client.post(&server.url("/orders"), "{}", config).await?;
+        assert_eq!(response.status, 201);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let digest = crate::utils::sha256(b"{}");
+        let expected = format!("POST /orders {:02x}{:02x}", digest[0], digest[1]);
+        let requests = server.requests();
+        for (attempt, request) in requests.iter().enumerate() {
+            assert_eq!(request.header("x-signature"), Some(expected.as_str()));
+            assert_eq!(request.header("x-attempt"), Some((attempt + 1).to_string().as_str()));
+        }
+        assert_eq!(requests.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signers_that_change_nothing_leave_the_request_alone() -> anyhow::Result<()> {
+        let mut request = reqwest::Client::new()
+            .get("https://api.example.com/items")
+            .header("Accept", "application/json")
+            .header("Accept", "text/plain")
+            .build()?;
+        let observe = |request: &mut SignableRequest<'_>| {
+            assert_eq!(request.method, HttpMethod::Get);
+            assert_eq!(request.payload(), b"");
+            Ok(())
+        };
+        sign_request(&observe, &mut request).await?;
+        let accept: Vec<_> = request.headers().get_all("accept").iter().collect();
+        assert_eq!(accept, ["application/json", "text/plain"]);
+
+        let failing = |_: &mut SignableRequest<'_>| anyhow::bail!("no credentials");
+        assert!(sign_request(&failing, &mut request).await.is_err());
+        Ok(())
+    }
+}
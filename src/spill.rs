@@ -0,0 +1,284 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A response body kept in a temporary file instead of memory, see
+/// `RequestConfig::with_spill_threshold`. Clones, e.g. in the response
+/// cache, share the file; it is deleted when the last one is dropped.
+#[derive(Debug, Clone)]
+pub struct SpilledBody {
+    file: Arc<TempFile>,
+    len: u64,
+}
+
+impl SpilledBody {
+    pub fn path(&self) -> &Path {
+        &self.file.0
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Loads the whole body into memory.
+    pub fn read(&self) -> Result<Vec<u8>> {
+        std::fs::read(self.path()).with_context(|| format!("Failed to read spilled body {}", self.path().display()))
+    }
+}
+
+/// `ApiResponse`'s `spilled_bytes`: the spilled length, or nothing.
+pub(crate) fn serialize_len<S: serde::Serializer>(spilled: &Option<SpilledBody>, serializer: S) -> Result<S::Ok, S::Error> {
+    use serde::Serialize;
+    spilled.as_ref().map(SpilledBody::len).serialize(serializer)
+}
+
+/// Removed when dropped.
+#[derive(Debug)]
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Writes a body into a new temporary file as it arrives. Dropping it
+/// before `finish` removes the file.
+pub(crate) struct SpillWriter {
+    file: tokio::fs::File,
+    temp: TempFile,
+    len: u64,
+}
+
+impl SpillWriter {
+    /// Creates `rusthttp-body-<uuid>` in `dir`, or the system temp
+    /// directory, readable by the current user only.
+    pub(crate) async fn create(dir: Option<&Path>) -> Result<Self> {
+        let dir = dir.map_or_else(std::env::temp_dir, Path::to_path_buf);
+        let path = dir.join(format!("rusthttp-body-{}", crate::utils::generate_request_id()));
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let file = options
+            .open(&path)
+            .await
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self {
+            file,
+            temp: TempFile(path),
+            len: 0,
+        })
+    }
+
+    pub(crate) async fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.file
+            .write_all(bytes)
+            .await
+            .with_context(|| format!("Failed to write {}", self.temp.0.display()))?;
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) async fn finish(mut self) -> Result<SpilledBody> {
+        self.file
+            .flush()
+            .await
+            .with_context(|| format!("Failed to write {}", self.temp.0.display()))?;
+        Ok(SpilledBody {
+            file: Arc::new(self.temp),
+            len: self.len,
+        })
+    }
+}
+
+/// A body read by `HttpClient`: in memory, or spilled to a file.
+pub(crate) enum ReadBody {
+    Memory(Vec<u8>),
+    Spilled(SpilledBody),
+}
+
+impl ReadBody {
+    pub(crate) fn len(&self) -> u64 {
+        match self {
+            ReadBody::Memory(bytes) => bytes.len() as u64,
+            ReadBody::Spilled(spilled) => spilled.len(),
+        }
+    }
+}
+
+/// `Read` over a buffered or spilled body. The file is opened on the first
+/// read, so failing to open it surfaces as a read error.
+pub(crate) enum BodyReader<'a> {
+    Memory(Cursor<Cow<'a, [u8]>>),
+    File {
+        body: SpilledBody,
+        file: Option<BufReader<File>>,
+    },
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BodyReader::Memory(cursor) => cursor.read(buf),
+            BodyReader::File { body, file } => {
+                let file = match file {
+                    Some(file) => file,
+                    None => file.insert(BufReader::new(File::open(body.path())?)),
+                };
+                file.read(buf)
+            }
+        }
+    }
+}
+
+/// The spilled file as a stream of chunks, for `ApiResponse::into_async_reader`.
+pub(crate) fn file_chunks(body: SpilledBody) -> BoxStream<'static, io::Result<Bytes>> {
+    use tokio::io::AsyncReadExt;
+
+    stream::unfold(Some((body, None)), |state| async move {
+        let (body, file) = state?;
+        let mut file = match file {
+            Some(file) => file,
+            None => match tokio::fs::File::open(body.path()).await {
+                Ok(file) => file,
+                Err(e) => return Some((Err(e), None)),
+            },
+        };
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(n) => {
+                chunk.truncate(n);
+                Some((Ok(Bytes::from(chunk)), Some((body, Some(file)))))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, HttpMethod, RequestConfig};
+    use anyhow::Result;
+    use bytes::Bytes;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    const MB: usize = 1024 * 1024;
+
+    /// A fresh spill directory under the system temp dir, removed on drop.
+    struct SpillDir(PathBuf);
+
+    impl SpillDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("rusthttp-spill-{}", crate::utils::generate_request_id()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn files(&self) -> usize {
+            std::fs::read_dir(&self.0).map_or(0, |entries| entries.count())
+        }
+    }
+
+    impl Drop for SpillDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// `0, 1, ..., 255, 0, 1, ...`, so misplaced bytes show up.
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    /// Peak resident set size of this process, in bytes.
+    #[cfg(target_os = "linux")]
+    fn peak_rss() -> usize {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmHWM:"))
+            .and_then(|kb| kb.trim().trim_end_matches("kB").trim().parse::<usize>().ok())
+            .map_or(0, |kb| kb * 1024)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn a_200_mb_body_spills_without_growing_memory() -> Result<()> {
+        let server = MockServer::start().await?;
+        // Every chunk shares one buffer, so the server holds 1 MB, not 200.
+        let chunk = Bytes::from(pattern(MB));
+        server.when(HttpMethod::Get, "/huge").respond(MockResponse::new(200).chunked(vec![chunk; 200], Duration::ZERO));
+        let dir = SpillDir::new();
+        let config = RequestConfig::new().with_spill_threshold(MB).with_spill_dir(&dir.0);
+
+        let before = peak_rss();
+        assert!(before > 0, "VmHWM missing from /proc/self/status");
+        let response = HttpClient::new().get(&server.url("/huge"), config).await?;
+        let grown = peak_rss().saturating_sub(before);
+        assert!(grown < 64 * MB, "peak RSS grew by {} MB", grown / MB);
+
+        assert!(response.is_spilled());
+        assert!(response.body.is_empty() && response.body_bytes().is_empty());
+        assert_eq!(response.spilled_body().map(|spilled| spilled.len()), Some(200 * MB as u64));
+        assert_eq!(serde_json::to_value(&response)?["spilled_bytes"], 200 * MB);
+
+        let mut reader = response.reader();
+        let mut head = vec![0; MB + 7];
+        reader.read_exact(&mut head)?;
+        assert_eq!(head[..MB], pattern(MB)[..]);
+        assert_eq!(head[MB..], pattern(7)[..]);
+        assert_eq!(std::io::copy(&mut reader, &mut std::io::sink())? as usize, 199 * MB - 7);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn spilled_bodies_read_back_and_are_removed_with_the_last_clone() -> Result<()> {
+        let server = MockServer::start().await?;
+        let body = "spilled text ".repeat(5000);
+        server.when(HttpMethod::Get, "/text").respond(MockResponse::text(200, &body));
+        server.when(HttpMethod::Get, "/small").respond(MockResponse::text(200, "tiny"));
+        let dir = SpillDir::new();
+        let config = RequestConfig::new().with_spill_threshold(1024).with_spill_dir(&dir.0);
+        let client = HttpClient::new();
+
+        let response = client.get(&server.url("/text"), config.clone()).await?;
+        let path = response.spilled_body().map(|spilled| spilled.path().to_path_buf()).expect("spilled");
+        assert!(path.starts_with(&dir.0) && path.exists());
+        assert_eq!(response.text()?, body);
+        assert_eq!(response.bytes()?.as_ref(), body.as_bytes());
+        let mut read = String::new();
+        response.reader().read_to_string(&mut read)?;
+        assert_eq!(read, body);
+
+        let clone = response.clone();
+        drop(response);
+        assert!(path.exists(), "a clone still needs the file");
+        drop(clone);
+        assert!(!path.exists());
+        assert_eq!(dir.files(), 0);
+
+        let small = client.get(&server.url("/small"), config).await?;
+        assert!(!small.is_spilled());
+        assert_eq!(small.body, "tiny");
+        assert_eq!(serde_json::to_value(&small)?.get("spilled_bytes"), None);
+        Ok(())
+    }
+}
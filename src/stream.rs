@@ -0,0 +1,466 @@
+use anyhow::{Context as _, Result};
+use bytes::Bytes;
+use futures_util::ready;
+use futures_util::stream::{self, BoxStream, Fuse, Stream, StreamExt};
+use reqwest::Response;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::client::HttpClient;
+use crate::clock::Clock;
+use crate::error::{PartialResponse, RequestError, TimeoutKind};
+use crate::models::RequestConfig;
+use crate::utils::{expected_body_length, set_query_param};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArrayState {
+    Start,
+    /// After `[`: the first element or `]`.
+    FirstElement,
+    /// After an element: `,` or `]`.
+    AfterElement,
+    /// After `,`: another element.
+    NextElement,
+    InElement,
+    Done,
+}
+
+/// Incremental splitter for a top-level JSON array. Bytes are fed as they
+/// arrive and every complete element is handed back as its raw JSON text.
+#[derive(Debug)]
+pub struct JsonArrayParser {
+    state: ArrayState,
+    element: Vec<u8>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Default for JsonArrayParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JsonArrayParser {
+    pub fn new() -> Self {
+        Self {
+            state: ArrayState::Start,
+            element: Vec::new(),
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let mut elements = Vec::new();
+
+        for &byte in chunk {
+            match self.state {
+                ArrayState::Start => {
+                    if byte == b'[' {
+                        self.state = ArrayState::FirstElement;
+                    } else if !byte.is_ascii_whitespace() {
+                        anyhow::bail!("Expected a top-level JSON array");
+                    }
+                }
+                ArrayState::FirstElement | ArrayState::NextElement => match byte {
+                    _ if byte.is_ascii_whitespace() => {}
+                    b']' if self.state == ArrayState::FirstElement => self.state = ArrayState::Done,
+                    b',' | b']' => anyhow::bail!("Expected a JSON array element, found '{}'", byte as char),
+                    _ => {
+                        self.state = ArrayState::InElement;
+                        self.push_element_byte(byte, &mut elements);
+                    }
+                },
+                ArrayState::AfterElement => match byte {
+                    _ if byte.is_ascii_whitespace() => {}
+                    b',' => self.state = ArrayState::NextElement,
+                    b']' => self.state = ArrayState::Done,
+                    _ => anyhow::bail!("Expected ',' or ']' after a JSON array element, found '{}'", byte as char),
+                },
+                ArrayState::InElement => {
+                    let scalar_end = self.depth == 0
+                        && !self.in_string
+                        && (byte == b',' || byte == b']' || byte.is_ascii_whitespace());
+
+                    if scalar_end {
+                        elements.push(std::mem::take(&mut self.element));
+                        self.state = match byte {
+                            b']' => ArrayState::Done,
+                            b',' => ArrayState::NextElement,
+                            _ => ArrayState::AfterElement,
+                        };
+                    } else {
+                        self.push_element_byte(byte, &mut elements);
+                    }
+                }
+                ArrayState::Done => {
+                    if !byte.is_ascii_whitespace() {
+                        anyhow::bail!("Unexpected data after the end of the JSON array");
+                    }
+                }
+            }
+        }
+
+        Ok(elements)
+    }
+
+    pub fn finish(&self) -> Result<()> {
+        match self.state {
+            ArrayState::Done => Ok(()),
+            _ => anyhow::bail!("JSON array ended before its closing bracket"),
+        }
+    }
+
+    fn push_element_byte(&mut self, byte: u8, elements: &mut Vec<Vec<u8>>) {
+        self.element.push(byte);
+
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if byte == b'\\' {
+                self.escaped = true;
+            } else if byte == b'"' {
+                self.in_string = false;
+            }
+            return;
+        }
+
+        match byte {
+            b'"' => self.in_string = true,
+            b'{' | b'[' => self.depth += 1,
+            b'}' | b']' => {
+                self.depth = self.depth.saturating_sub(1);
+                if self.depth == 0 {
+                    elements.push(std::mem::take(&mut self.element));
+                    self.state = ArrayState::AfterElement;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `AsyncRead` over a stream of body chunks, see
+/// `ApiResponse::into_async_reader`.
+pub(crate) struct ChunkReader {
+    /// Fused: readers keep calling `poll_read` after the end.
+    chunks: Fuse<BoxStream<'static, io::Result<Bytes>>>,
+    current: Bytes,
+}
+
+impl ChunkReader {
+    pub(crate) fn new(chunks: BoxStream<'static, io::Result<Bytes>>) -> Self {
+        Self {
+            chunks: chunks.fuse(),
+            current: Bytes::new(),
+        }
+    }
+}
+
+impl AsyncRead for ChunkReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        while self.current.is_empty() {
+            match ready!(self.chunks.poll_next_unpin(cx)) {
+                Some(Ok(chunk)) => self.current = chunk,
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = self.current.len().min(buf.remaining());
+        buf.put_slice(&self.current.split_to(n));
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// `response.chunk()`, or `None` when nothing arrives within `idle_timeout`.
+pub(crate) async fn next_chunk(
+    response: &mut Response,
+    idle_timeout: Option<Duration>,
+) -> Option<reqwest::Result<Option<bytes::Bytes>>> {
+    match idle_timeout {
+        Some(idle) => tokio::time::timeout(idle, response.chunk()).await.ok(),
+        None => Some(response.chunk().await),
+    }
+}
+
+/// Turns a response whose body is a JSON array into a stream of its elements,
+/// deserializing each one as soon as it has been received in full. The
+/// stream ends with a `Read` timeout when `idle_timeout` passes between
+/// chunks.
+pub(crate) fn json_array_stream<T>(
+    response: Response,
+    start_time: Instant,
+    clock: Arc<dyn Clock>,
+    idle_timeout: Option<Duration>,
+) -> impl Stream<Item = Result<T>>
+where
+    T: DeserializeOwned,
+{
+    let partial = PartialResponse {
+        status: Some(response.status().as_u16()),
+        headers: response
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+            .collect::<HashMap<_, _>>(),
+        ..PartialResponse::default()
+    };
+    let expected_length = expected_body_length(false, response.status().as_u16(), &partial.headers);
+    let state = ArrayStreamState {
+        response: Some(response),
+        parser: JsonArrayParser::new(),
+        ready: VecDeque::new(),
+        partial,
+        start_time,
+        clock,
+        expected_length,
+        idle_timeout,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(raw) = state.ready.pop_front() {
+                let item = serde_json::from_slice::<T>(&raw)
+                    .with_context(|| "Failed to deserialize JSON array element");
+                return Some((item, state));
+            }
+
+            let response = state.response.as_mut()?;
+            let Some(next) = next_chunk(response, state.idle_timeout).await else {
+                state.partial.elapsed_ms = state.elapsed_ms();
+                let err = RequestError::Timeout {
+                    kind: TimeoutKind::Read,
+                    partial: state.partial.clone(),
+                };
+                return Some((Err(err.into()), state.finished()));
+            };
+            match next {
+                Ok(Some(chunk)) => {
+                    state.partial.bytes_received += chunk.len();
+                    match state.parser.feed(&chunk) {
+                        Ok(elements) => state.ready.extend(elements),
+                        Err(e) => return Some((Err(e), state.finished())),
+                    }
+                }
+                Ok(None) => {
+                    state.response = None;
+                    if let Some(err) = state.truncated() {
+                        return Some((Err(err.into()), state.finished()));
+                    }
+                    if let Err(e) = state.parser.finish() {
+                        return Some((Err(e), state));
+                    }
+                }
+                Err(e) if e.is_timeout() => {
+                    state.partial.elapsed_ms = state.elapsed_ms();
+                    let err = RequestError::Timeout {
+                        kind: TimeoutKind::Total,
+                        partial: state.partial.clone(),
+                    };
+                    return Some((Err(err.into()), state.finished()));
+                }
+                Err(e) => {
+                    if let Some(err) = state.truncated() {
+                        return Some((Err(err.into()), state.finished()));
+                    }
+                    let err = anyhow::Error::new(e.without_url()).context("Failed to read response body");
+                    return Some((Err(err), state.finished()));
+                }
+            }
+        }
+    })
+}
+
+struct ArrayStreamState {
+    response: Option<Response>,
+    parser: JsonArrayParser,
+    ready: VecDeque<Vec<u8>>,
+    partial: PartialResponse,
+    start_time: Instant,
+    clock: Arc<dyn Clock>,
+    expected_length: Option<u64>,
+    idle_timeout: Option<Duration>,
+}
+
+impl ArrayStreamState {
+    fn elapsed_ms(&self) -> u64 {
+        self.clock.now().saturating_duration_since(self.start_time).as_millis() as u64
+    }
+
+    fn truncated(&mut self) -> Option<RequestError> {
+        let expected = self.expected_length?;
+        let received = self.partial.bytes_received as u64;
+        (received != expected).then(|| {
+            self.partial.elapsed_ms = self.elapsed_ms();
+            RequestError::TruncatedBody {
+                expected,
+                received,
+                partial: self.partial.clone(),
+            }
+        })
+    }
+
+    fn finished(mut self) -> Self {
+        self.response = None;
+        self.ready.clear();
+        self
+    }
+}
+
+/// How the next page is requested.
+pub(crate) enum PageCursor {
+    Offset { offset: usize, limit: usize },
+    Cursor {
+        param: String,
+        next_field: String,
+        cursor: Option<String>,
+    },
+}
+
+struct PageState<'a, F> {
+    client: &'a HttpClient,
+    url: String,
+    config: RequestConfig,
+    extract_items: F,
+    cursor: PageCursor,
+    ready: VecDeque<Value>,
+    done: bool,
+}
+
+/// Fetches pages on demand and yields their items one by one. Offset paging
+/// stops at the first page with fewer than `limit` items, cursor paging when
+/// the next-cursor field is missing, null or empty. A failed page ends the
+/// stream after yielding its error.
+pub(crate) fn paginate<'a, F>(
+    client: &'a HttpClient,
+    url: &str,
+    cursor: PageCursor,
+    extract_items: F,
+    config: RequestConfig,
+) -> impl Stream<Item = Result<Value>> + 'a
+where
+    F: Fn(&Value) -> Vec<Value> + 'a,
+{
+    let state = PageState {
+        client,
+        url: url.to_string(),
+        config,
+        extract_items,
+        cursor,
+        ready: VecDeque::new(),
+        done: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(item) = state.ready.pop_front() {
+                return Some((Ok(item), state));
+            }
+            if state.done {
+                return None;
+            }
+
+            let page_url = match &state.cursor {
+                PageCursor::Offset { offset, limit } => {
+                    let url = set_query_param(&state.url, "offset", &offset.to_string());
+                    set_query_param(&url, "limit", &limit.to_string())
+                }
+                PageCursor::Cursor { param, cursor: Some(cursor), .. } => set_query_param(&state.url, param, cursor),
+                PageCursor::Cursor { cursor: None, .. } => state.url.clone(),
+            };
+
+            let page = match state.client.get(&page_url, state.config.clone()).await {
+                Ok(response) if response.is_success() => response.as_json_value(),
+                Ok(response) => Err(anyhow::anyhow!(
+                    "Page request returned {} {}",
+                    response.status,
+                    response.status_text
+                )),
+                Err(e) => Err(e),
+            };
+            let page = match page {
+                Ok(page) => page,
+                Err(e) => {
+                    state.done = true;
+                    return Some((Err(e), state));
+                }
+            };
+
+            let items = (state.extract_items)(&page);
+            match &mut state.cursor {
+                PageCursor::Offset { offset, limit } => {
+                    state.done = items.len() < *limit;
+                    *offset += items.len();
+                }
+                PageCursor::Cursor { next_field, cursor, .. } => {
+                    let next = next_field
+                        .split('.')
+                        .try_fold(&page, |value, key| value.get(key))
+                        .and_then(|value| match value {
+                            Value::String(s) => Some(s.clone()),
+                            Value::Number(n) => Some(n.to_string()),
+                            _ => None,
+                        })
+                        .filter(|next| !next.is_empty());
+                    // A server that hands back the same cursor would loop forever.
+                    state.done = next.is_none() || next == *cursor;
+                    *cursor = next;
+                }
+            }
+            state.ready.extend(items);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_in_chunks(input: &[u8], chunks: &[&[u8]]) -> Result<Vec<String>> {
+        let mut parser = JsonArrayParser::new();
+        let mut elements = Vec::new();
+        for chunk in chunks {
+            elements.extend(parser.feed(chunk)?);
+        }
+        parser.finish().with_context(|| String::from_utf8_lossy(input).into_owned())?;
+        Ok(elements.into_iter().map(|element| String::from_utf8(element).unwrap()).collect())
+    }
+
+    #[test]
+    fn elements_split_across_chunks_come_out_whole() -> Result<()> {
+        let input = br#" [ {"a": "x,]\"y}", "n": [1, {"b": "]"}]} , "s\\\"]," ,-1.5e3 ] "#;
+        let expected = [r#"{"a": "x,]\"y}", "n": [1, {"b": "]"}]}"#, r#""s\\\"],""#, "-1.5e3"];
+
+        // Every split point lands somewhere: inside strings, right after a
+        // backslash, and between nesting levels.
+        for split in 0..=input.len() {
+            let (head, tail) = input.split_at(split);
+            assert_eq!(parse_in_chunks(input, &[head, tail])?, expected, "split at {}", split);
+        }
+        let bytes: Vec<&[u8]> = input.chunks(1).collect();
+        assert_eq!(parse_in_chunks(input, &bytes)?, expected);
+        for element in expected {
+            serde_json::from_str::<Value>(element)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_arrays_are_rejected() {
+        for input in ["[1 2]", "[,,1]", "[,1]", "[1,]", "[1,,2]", "[{} {}]", "{}", "[1] 2"] {
+            assert!(parse_in_chunks(input.as_bytes(), &[input.as_bytes()]).is_err(), "{} was accepted", input);
+        }
+        assert!(parse_in_chunks(b"[1,", &[b"[1,"]).is_err());
+        assert_eq!(parse_in_chunks(b" [ ] ", &[b" [ ] "]).unwrap(), Vec::<String>::new());
+    }
+}
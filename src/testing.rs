@@ -0,0 +1,560 @@
+//! An in-process HTTP server for tests, available with the `testing`
+//! feature.
+//!
+//! ```no_run
+//! # async fn demo() -> anyhow::Result<()> {
+//! use RustHTTP::testing::{MockResponse, MockServer};
+//! use RustHTTP::{HttpClient, HttpMethod, RequestConfig};
+//!
+//! let server = MockServer::start().await?;
+//! server
+//!     .when(HttpMethod::Get, "/users/:id")
+//!     .respond_with(|request| MockResponse::json(200, serde_json::json!({ "id": request.param("id") })));
+//!
+//! let response = HttpClient::new().get(&server.url("/users/7"), RequestConfig::new()).await?;
+//! assert_eq!(response.body, r#"{"id":"7"}"#);
+//! assert_eq!(server.received(HttpMethod::Get, "/users/:id"), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::Result;
+use bytes::Bytes;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use crate::fixtures::Fixture;
+use crate::models::HttpMethod;
+
+/// A canned response.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+    /// Wait this long before sending the status line.
+    pub delay: Duration,
+    /// When set, the body is sent as these chunks instead of `body`, with
+    /// `chunk_interval` between them.
+    pub chunks: Option<Vec<Bytes>>,
+    pub chunk_interval: Duration,
+}
+
+impl MockResponse {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Bytes::new(),
+            delay: Duration::ZERO,
+            chunks: None,
+            chunk_interval: Duration::ZERO,
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    pub fn json(status: u16, value: serde_json::Value) -> Self {
+        Self::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(value.to_string())
+    }
+
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn from_fixture(fixture: &Fixture) -> Result<Self> {
+        let mut response = Self::new(fixture.status).with_body(fixture.body_bytes()?);
+        response.headers = fixture.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        Ok(response)
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Streams `chunks` with chunked transfer encoding, pausing `interval`
+    /// before each one.
+    pub fn chunked<I, B>(mut self, chunks: I, interval: Duration) -> Self
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<Bytes>,
+    {
+        self.chunks = Some(chunks.into_iter().map(Into::into).collect());
+        self.chunk_interval = interval;
+        self
+    }
+}
+
+/// A request the server received.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    /// Header names are lowercase.
+    pub headers: HashMap<String, String>,
+    /// Lowercase header names in the order they arrived.
+    pub header_order: Vec<String>,
+    pub body: Bytes,
+    /// The route pattern that matched, if any.
+    pub route: Option<String>,
+    /// Values of `:name` segments in the matched route.
+    pub params: HashMap<String, String>,
+}
+
+impl RecordedRequest {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+type Responder = Arc<dyn Fn(&RecordedRequest) -> MockResponse + Send + Sync>;
+
+enum Reply {
+    Fixed(MockResponse),
+    /// Served in order; the last one repeats.
+    Sequence(Vec<MockResponse>),
+    Dynamic(Responder),
+}
+
+struct Route {
+    method: HttpMethod,
+    pattern: String,
+    reply: Reply,
+    latency: Duration,
+    hits: usize,
+}
+
+impl Route {
+    fn respond(&mut self, request: &RecordedRequest) -> MockResponse {
+        let index = self.hits;
+        self.hits += 1;
+        let mut response = match &self.reply {
+            Reply::Fixed(response) => response.clone(),
+            Reply::Sequence(responses) => responses
+                .get(index)
+                .or(responses.last())
+                .cloned()
+                .unwrap_or_else(|| MockResponse::new(500)),
+            Reply::Dynamic(responder) => responder(request),
+        };
+        response.delay += self.latency;
+        response
+    }
+}
+
+#[derive(Default)]
+struct State {
+    routes: Vec<Route>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// A local HTTP/1.1 server answering from registered routes. Unmatched
+/// requests get a `404`. The server stops when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stop: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// Starts a server on a free port of `127.0.0.1`.
+    pub async fn start() -> Result<Self> {
+        let state = Arc::new(Mutex::new(State::default()));
+        let shared = Arc::clone(&state);
+        let make_service = make_service_fn(move |_| {
+            let state = Arc::clone(&shared);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| handle(Arc::clone(&state), request)))
+            }
+        });
+
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .map_err(|e| anyhow::anyhow!("Failed to bind mock server: {}", e))?
+            .serve(make_service);
+        let addr = server.local_addr();
+        let (stop, stopped) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = stopped.await;
+        }));
+
+        Ok(Self {
+            addr,
+            state,
+            stop: Some(stop),
+        })
+    }
+
+    /// Starts a server answering with every fixture in `dir` (see
+    /// `ApiResponse::to_fixture`), routed by method and URL path. Files are
+    /// read in name order, so when two share a route the later one wins.
+    pub async fn from_fixtures(dir: impl AsRef<std::path::Path>) -> Result<Self> {
+        let fixtures = Fixture::load_dir(dir)?;
+        let server = Self::start().await?;
+        for fixture in fixtures {
+            server.when(fixture.method, &fixture.path()).respond(MockResponse::from_fixture(&fixture)?);
+        }
+        Ok(server)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `http://127.0.0.1:<port>`, no trailing slash.
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url(), path.trim_start_matches('/'))
+    }
+
+    /// Starts a route for `method` and `pattern`, e.g. `/users/:id` or
+    /// `/files/*` (matches any rest of the path). Later routes take
+    /// precedence over earlier ones.
+    pub fn when(&self, method: HttpMethod, pattern: &str) -> RouteBuilder<'_> {
+        RouteBuilder {
+            server: self,
+            method,
+            pattern: pattern.to_string(),
+            latency: Duration::ZERO,
+        }
+    }
+
+    /// Every request received so far, in arrival order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Requests that matched the route registered as `method` `pattern`.
+    pub fn requests_to(&self, method: HttpMethod, pattern: &str) -> Vec<RecordedRequest> {
+        let method = method.to_string();
+        self.lock()
+            .requests
+            .iter()
+            .filter(|request| request.method == method && request.route.as_deref() == Some(pattern))
+            .cloned()
+            .collect()
+    }
+
+    pub fn received(&self, method: HttpMethod, pattern: &str) -> usize {
+        self.requests_to(method, pattern).len()
+    }
+
+    /// Forgets recorded requests and restarts response sequences; routes stay.
+    pub fn reset(&self) {
+        let mut state = self.lock();
+        state.requests.clear();
+        for route in &mut state.routes {
+            route.hits = 0;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+/// Registers a route on a `MockServer`; finish with one of the `respond*`
+/// methods.
+pub struct RouteBuilder<'a> {
+    server: &'a MockServer,
+    method: HttpMethod,
+    pattern: String,
+    latency: Duration,
+}
+
+impl RouteBuilder<'_> {
+    /// Delays every response from this route by `latency`.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn respond(self, response: MockResponse) {
+        self.register(Reply::Fixed(response));
+    }
+
+    /// Answers the first call with the first response, the second with the
+    /// second and so on, repeating the last one afterwards.
+    pub fn respond_sequence<I>(self, responses: I)
+    where
+        I: IntoIterator<Item = MockResponse>,
+    {
+        self.register(Reply::Sequence(responses.into_iter().collect()));
+    }
+
+    pub fn respond_with<F>(self, responder: F)
+    where
+        F: Fn(&RecordedRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        self.register(Reply::Dynamic(Arc::new(responder)));
+    }
+
+    fn register(self, reply: Reply) {
+        self.server.lock().routes.push(Route {
+            method: self.method,
+            pattern: self.pattern,
+            reply,
+            latency: self.latency,
+            hits: 0,
+        });
+    }
+}
+
+async fn handle(state: Arc<Mutex<State>>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let (parts, body) = request.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+    let mut recorded = RecordedRequest {
+        method: parts.method.to_string(),
+        path: parts.uri.path().to_string(),
+        query: parts.uri.query().map(str::to_string),
+        headers: parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        header_order: parts.headers.keys().map(|name| name.to_string()).collect(),
+        body,
+        route: None,
+        params: HashMap::new(),
+    };
+
+    let reply = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let matched = state.routes.iter_mut().rev().find_map(|route| {
+            if !route.method.to_string().eq_ignore_ascii_case(&recorded.method) {
+                return None;
+            }
+            match_route(&route.pattern, &recorded.path).map(|params| (route, params))
+        });
+        let reply = match matched {
+            Some((route, params)) => {
+                recorded.route = Some(route.pattern.clone());
+                recorded.params = params;
+                route.respond(&recorded)
+            }
+            None => MockResponse::text(404, format!("No mock route for {} {}", recorded.method, recorded.path)),
+        };
+        state.requests.push(recorded);
+        reply
+    };
+
+    Ok(build_response(reply).await)
+}
+
+async fn build_response(reply: MockResponse) -> Response<Body> {
+    if !reply.delay.is_zero() {
+        tokio::time::sleep(reply.delay).await;
+    }
+
+    let body = match reply.chunks {
+        Some(chunks) => {
+            let (mut sender, body) = Body::channel();
+            let interval = reply.chunk_interval;
+            tokio::spawn(async move {
+                for chunk in chunks {
+                    tokio::time::sleep(interval).await;
+                    if sender.send_data(chunk).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            body
+        }
+        None => Body::from(reply.body),
+    };
+
+    let mut response = Response::builder().status(reply.status);
+    for (key, value) in &reply.headers {
+        response = response.header(key, value);
+    }
+    response.body(body).unwrap_or_else(|e| {
+        let mut response = Response::new(Body::from(format!("Invalid mock response: {}", e)));
+        *response.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    })
+}
+
+/// Matches `path` against `pattern`, returning the `:name` captures.
+fn match_route(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut segments = path.trim_matches('/').split('/');
+    for expected in pattern.trim_matches('/').split('/') {
+        if expected == "*" {
+            return Some(params);
+        }
+        let actual = segments.next()?;
+        match expected.strip_prefix(':') {
+            Some(name) if !actual.is_empty() => {
+                params.insert(name.to_string(), crate::utils::percent_decode(actual));
+            }
+            Some(_) => return None,
+            None if expected == actual => {}
+            None => return None,
+        }
+    }
+    segments.next().is_none().then_some(params)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HttpClient, RequestConfig};
+    use std::time::Instant;
+
+    #[test]
+    fn match_route_captures_params_and_wildcards() {
+        let params = match_route("/users/:id/posts/:post", "/users/7/posts/a%20b").unwrap();
+        assert_eq!(params["id"], "7");
+        assert_eq!(params["post"], "a b");
+        assert!(match_route("/files/*", "/files/a/b/c").is_some());
+        assert!(match_route("/users/:id", "/users/").is_none());
+        assert!(match_route("/users/:id", "/users/7/extra").is_none());
+        assert!(match_route("/users", "/posts").is_none());
+    }
+
+    #[tokio::test]
+    async fn routes_by_method_and_pattern_with_404_fallback() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/users/:id")
+            .respond_with(|request| MockResponse::json(200, serde_json::json!({ "id": request.param("id") })));
+        server.when(HttpMethod::Delete, "/users/:id").respond(MockResponse::new(204));
+        let client = HttpClient::new();
+
+        let response = client.get(&server.url("/users/7"), RequestConfig::new()).await?;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, r#"{"id":"7"}"#);
+        let response = client.delete(&server.url("/users/7"), RequestConfig::new()).await?;
+        assert_eq!(response.status, 204);
+        let response = client.get(&server.url("/missing"), RequestConfig::new()).await?;
+        assert_eq!(response.status, 404);
+
+        assert_eq!(server.received(HttpMethod::Get, "/users/:id"), 1);
+        assert_eq!(server.received(HttpMethod::Delete, "/users/:id"), 1);
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(server.requests()[2].route, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn later_routes_take_precedence() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/items/*").respond(MockResponse::text(200, "any"));
+        server.when(HttpMethod::Get, "/items/special").respond(MockResponse::text(200, "special"));
+        let client = HttpClient::new();
+        assert_eq!(client.get(&server.url("/items/special"), RequestConfig::new()).await?.body, "special");
+        assert_eq!(client.get(&server.url("/items/other"), RequestConfig::new()).await?.body, "any");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sequence_repeats_its_last_response_until_reset() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/flaky")
+            .respond_sequence([MockResponse::text(503, "down"), MockResponse::text(200, "up")]);
+        let client = HttpClient::new();
+        let mut statuses = Vec::new();
+        for _ in 0..3 {
+            statuses.push(client.get(&server.url("/flaky"), RequestConfig::new()).await?.status);
+        }
+        assert_eq!(statuses, [503, 200, 200]);
+
+        server.reset();
+        assert!(server.requests().is_empty());
+        assert_eq!(client.get(&server.url("/flaky"), RequestConfig::new()).await?.status, 503);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn latency_and_delay_add_up() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/slow")
+            .with_latency(Duration::from_millis(100))
+            .respond(MockResponse::text(200, "ok").with_delay(Duration::from_millis(50)));
+        let started = Instant::now();
+        let response = HttpClient::new().get(&server.url("/slow"), RequestConfig::new()).await?;
+        assert_eq!(response.body, "ok");
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chunked_responses_arrive_whole() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/stream").respond(
+            MockResponse::new(200).chunked(["one,", "two,", "three"], Duration::from_millis(20)),
+        );
+        let started = Instant::now();
+        let response = HttpClient::new().get(&server.url("/stream"), RequestConfig::new()).await?;
+        assert_eq!(response.body, "one,two,three");
+        assert!(!response.headers.contains_key("content-length"));
+        assert_eq!(response.headers.get("transfer-encoding").map(String::as_str), Some("chunked"));
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn captures_method_query_headers_and_body() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Post, "/echo").respond(MockResponse::new(201));
+        let config = RequestConfig::new().add_header("X-Trace", "abc").add_header("X-Second", "2");
+        HttpClient::new()
+            .post(&server.url("/echo?page=2"), r#"{"name":"x"}"#, config)
+            .await?;
+
+        let requests = server.requests_to(HttpMethod::Post, "/echo");
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/echo");
+        assert_eq!(request.query.as_deref(), Some("page=2"));
+        assert_eq!(request.header("X-TRACE"), Some("abc"));
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        let trace = request.header_order.iter().position(|name| name == "x-trace").unwrap();
+        let second = request.header_order.iter().position(|name| name == "x-second").unwrap();
+        assert!(trace < second);
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&request.body)?, serde_json::json!({"name": "x"}));
+        Ok(())
+    }
+}
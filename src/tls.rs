@@ -0,0 +1,552 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+#[cfg(feature = "tls-inspect")]
+use {
+    anyhow::{Context, Result},
+    std::net::SocketAddr,
+    std::sync::{Arc, Mutex},
+};
+
+/// Remaining certificate lifetime below which `TlsInfo::expiring_soon` is set.
+pub const DEFAULT_CERT_EXPIRY_WARNING_DAYS: u64 = 30;
+
+/// Lowest TLS version a client accepts, see `HttpClient::min_tls_version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TlsVersion {
+    V1_2,
+    V1_3,
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsVersion::V1_2 => write!(f, "TLS 1.2"),
+            TlsVersion::V1_3 => write!(f, "TLS 1.3"),
+        }
+    }
+}
+
+impl TlsVersion {
+    /// The name used in `TlsInfo::protocol`, e.g. `TLSv1.3`.
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            TlsVersion::V1_2 => "TLSv1.2",
+            TlsVersion::V1_3 => "TLSv1.3",
+        }
+    }
+
+    #[cfg(feature = "tls-inspect")]
+    fn from_rustls(version: rustls::ProtocolVersion) -> Option<Self> {
+        match version {
+            rustls::ProtocolVersion::TLSv1_3 => Some(TlsVersion::V1_3),
+            rustls::ProtocolVersion::TLSv1_2 => Some(TlsVersion::V1_2),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_reqwest(self) -> reqwest::tls::Version {
+        match self {
+            TlsVersion::V1_2 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::V1_3 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+}
+
+/// Named TLS requirements for `HttpClient::with_tls_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TlsPolicy {
+    /// TLS 1.3 only.
+    Modern,
+    /// TLS 1.2 or newer.
+    Compatible,
+}
+
+impl fmt::Display for TlsPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsPolicy::Modern => write!(f, "Modern ({} only)", self.min_version()),
+            TlsPolicy::Compatible => write!(f, "Compatible ({} or newer)", self.min_version()),
+        }
+    }
+}
+
+impl TlsPolicy {
+    pub fn min_version(&self) -> TlsVersion {
+        match self {
+            TlsPolicy::Modern => TlsVersion::V1_3,
+            TlsPolicy::Compatible => TlsVersion::V1_2,
+        }
+    }
+}
+
+/// Version a server picks when offered TLS 1.2 and 1.3, found with a
+/// handshake that skips certificate checks. `None` when no such handshake
+/// completes, e.g. because the server stops at TLS 1.1.
+#[cfg(feature = "tls-inspect")]
+pub(crate) async fn probe_version(addrs: &[SocketAddr], host: &str) -> Option<TlsVersion> {
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+        .with_no_client_auth();
+    let server_name = rustls::ServerName::try_from(host).ok()?;
+    let tcp = tokio::net::TcpStream::connect(addrs).await.ok()?;
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp)
+        .await
+        .ok()?;
+    TlsVersion::from_rustls(stream.get_ref().1.protocol_version()?)
+}
+
+/// What the server negotiated in an `inspect_handshake`.
+#[cfg(feature = "tls-inspect")]
+pub(crate) struct Handshake {
+    pub protocol: Option<TlsVersion>,
+    pub cipher: Option<String>,
+    pub alpn: Option<String>,
+    /// DER-encoded certificates as the server sent them, leaf first.
+    pub certificates: Vec<Vec<u8>>,
+    pub verify_error: Option<String>,
+}
+
+/// Completes one handshake with `host`, verifying its certificate against
+/// the system roots but finishing even when that fails, so an untrusted
+/// server can still be reported.
+#[cfg(feature = "tls-inspect")]
+pub(crate) async fn inspect_handshake(addrs: &[SocketAddr], host: &str) -> Result<Handshake> {
+    let mut roots = rustls::RootCertStore::empty();
+    // Without a readable system store nothing is trusted, which shows up in
+    // `verify_error` rather than failing the inspection.
+    let native = rustls_native_certs::load_native_certs().unwrap_or_default();
+    roots.add_parsable_certificates(&native.into_iter().map(|certificate| certificate.0).collect::<Vec<_>>());
+    let verifier = Arc::new(RecordingVerifier {
+        inner: rustls::client::WebPkiVerifier::new(roots, None),
+        error: Mutex::new(None),
+    });
+    let mut config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::clone(&verifier) as Arc<dyn rustls::client::ServerCertVerifier>)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    let server_name = rustls::ServerName::try_from(host).with_context(|| format!("Invalid TLS server name: {}", host))?;
+    let tcp = tokio::net::TcpStream::connect(addrs)
+        .await
+        .with_context(|| format!("Failed to connect to {}", host))?;
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp)
+        .await
+        .with_context(|| format!("TLS handshake with {} failed", host))?;
+    let connection = stream.get_ref().1;
+    Ok(Handshake {
+        protocol: connection.protocol_version().and_then(TlsVersion::from_rustls),
+        cipher: connection.negotiated_cipher_suite().map(|suite| format!("{:?}", suite.suite())),
+        alpn: connection.alpn_protocol().map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+        certificates: connection
+            .peer_certificates()
+            .unwrap_or_default()
+            .iter()
+            .map(|certificate| certificate.0.clone())
+            .collect(),
+        verify_error: verifier.error.lock().unwrap_or_else(|e| e.into_inner()).take(),
+    })
+}
+
+/// The rustls error behind `error`, if it failed inside a handshake.
+pub(crate) fn handshake_error<'a>(error: &'a (dyn std::error::Error + 'static)) -> Option<&'a rustls::Error> {
+    let mut cause = Some(error);
+    while let Some(current) = cause {
+        if let Some(handshake) = current.downcast_ref::<rustls::Error>() {
+            return Some(handshake);
+        }
+        // io::Error skips its wrapped error in `source`, and the connector
+        // nests one io::Error inside another, so step into it directly.
+        cause = match current.downcast_ref::<std::io::Error>().and_then(|io| io.get_ref()) {
+            Some(inner) => Some(inner),
+            None => current.source(),
+        };
+    }
+    None
+}
+
+/// Whether the two sides found no common version or cipher suite, as
+/// opposed to failing later, e.g. on an untrusted certificate.
+pub(crate) fn is_negotiation_failure(error: &rustls::Error) -> bool {
+    use rustls::AlertDescription;
+    matches!(
+        error,
+        rustls::Error::PeerIncompatible(_)
+            | rustls::Error::AlertReceived(
+                AlertDescription::ProtocolVersion | AlertDescription::HandshakeFailure | AlertDescription::InsufficientSecurity
+            )
+    )
+}
+
+/// Only used by `probe_version`, which reports what the server offers and
+/// never sends data.
+#[cfg(feature = "tls-inspect")]
+struct AcceptAnyCertificate;
+
+#[cfg(feature = "tls-inspect")]
+impl rustls::client::ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+/// Runs the usual WebPKI checks but accepts the certificate either way,
+/// keeping the reason it would have been rejected. Signatures on the
+/// handshake itself are still checked.
+#[cfg(feature = "tls-inspect")]
+struct RecordingVerifier {
+    inner: rustls::client::WebPkiVerifier,
+    error: Mutex<Option<String>>,
+}
+
+#[cfg(feature = "tls-inspect")]
+impl rustls::client::ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        if let Err(e) = self
+            .inner
+            .verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)
+        {
+            *self.error.lock().unwrap_or_else(|e| e.into_inner()) = Some(e.to_string());
+        }
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+/// Subject, issuer and validity of a server certificate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertificateInfo {
+    /// Distinguished name in certificate order, e.g. `CN=example.com, O=Example`.
+    pub subject: String,
+    pub issuer: String,
+    #[serde(with = "crate::utils::rfc3339")]
+    pub not_before: SystemTime,
+    #[serde(with = "crate::utils::rfc3339")]
+    pub not_after: SystemTime,
+}
+
+impl CertificateInfo {
+    /// Reads a DER-encoded X.509 certificate; `None` if it is malformed.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, certificate, _) = read_tlv(der).filter(|&(tag, _, _)| tag == SEQUENCE)?;
+        let (_, tbs, _) = read_tlv(certificate).filter(|&(tag, _, _)| tag == SEQUENCE)?;
+
+        let mut rest = tbs;
+        let (tag, _, after) = read_tlv(rest)?;
+        // The version is an optional explicit [0] field.
+        if tag == 0xa0 {
+            rest = after;
+        }
+        let (_, _, rest) = read_tlv(rest)?; // serial number
+        let (_, _, rest) = read_tlv(rest)?; // signature algorithm
+        let (_, issuer, rest) = read_tlv(rest)?;
+        let (_, validity, rest) = read_tlv(rest)?;
+        let (_, subject, _) = read_tlv(rest)?;
+
+        let (not_before_tag, not_before, rest) = read_tlv(validity)?;
+        let (not_after_tag, not_after, _) = read_tlv(rest)?;
+        Some(Self {
+            subject: format_name(subject)?,
+            issuer: format_name(issuer)?,
+            not_before: parse_time(not_before_tag, not_before)?,
+            not_after: parse_time(not_after_tag, not_after)?,
+        })
+    }
+
+    /// True when the certificate expires within `window` of `now`, or
+    /// already has.
+    pub fn expires_within(&self, window: Duration, now: SystemTime) -> bool {
+        self.not_after <= now + window
+    }
+
+    pub fn is_self_signed(&self) -> bool {
+        self.subject == self.issuer
+    }
+}
+
+const SEQUENCE: u8 = 0x30;
+
+/// Splits one DER element off `data`: `(tag, contents, rest)`.
+fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&first, data) = data.split_first()?;
+    let (len, data) = if first < 0x80 {
+        (first as usize, data)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || data.len() < count {
+            return None;
+        }
+        let len = data[..count].iter().fold(0usize, |len, &b| (len << 8) | b as usize);
+        (len, &data[count..])
+    };
+    (data.len() >= len).then(|| (tag, &data[..len], &data[len..]))
+}
+
+/// Renders an X.501 `Name` as `CN=..., O=...`.
+fn format_name(mut rdns: &[u8]) -> Option<String> {
+    let mut parts = Vec::new();
+    while !rdns.is_empty() {
+        let (_, mut set, rest) = read_tlv(rdns)?;
+        rdns = rest;
+        while !set.is_empty() {
+            let (_, attribute, rest) = read_tlv(set)?;
+            set = rest;
+            let (_, oid, value) = read_tlv(attribute)?;
+            let (tag, value, _) = read_tlv(value)?;
+            parts.push(format!("{}={}", attribute_name(oid), decode_string(tag, value)));
+        }
+    }
+    Some(parts.join(", "))
+}
+
+fn attribute_name(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress".to_string(),
+        _ => dotted_oid(oid),
+    }
+}
+
+fn dotted_oid(oid: &[u8]) -> String {
+    let Some((&first, rest)) = oid.split_first() else {
+        return String::new();
+    };
+    let mut arcs = vec![(first / 40) as u64, (first % 40) as u64];
+    let mut arc = 0u64;
+    for &b in rest {
+        arc = (arc << 7) | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    arcs.iter().map(u64::to_string).collect::<Vec<_>>().join(".")
+}
+
+fn decode_string(tag: u8, value: &[u8]) -> String {
+    match tag {
+        // BMPString
+        0x1e => char::decode_utf16(value.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])))
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+        // T61String, treated as Latin-1
+        0x14 => value.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(value).into_owned(),
+    }
+}
+
+/// UTCTime (`YYMMDDHHMMSSZ`) or GeneralizedTime (`YYYYMMDDHHMMSSZ`).
+fn parse_time(tag: u8, value: &[u8]) -> Option<SystemTime> {
+    let text = std::str::from_utf8(value).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match tag {
+        0x17 => {
+            let year: u32 = text.get(..2)?.parse().ok()?;
+            (if year < 50 { 2000 + year } else { 1900 + year }, &text[2..])
+        }
+        0x18 => (text.get(..4)?.parse().ok()?, &text[4..]),
+        _ => return None,
+    };
+    let field = |index: usize| rest.get(index * 2..index * 2 + 2);
+    let stamp = format!(
+        "{:04}-{}-{}T{}:{}:{}Z",
+        year,
+        field(0)?,
+        field(1)?,
+        field(2)?,
+        field(3)?,
+        field(4)?
+    );
+    crate::utils::parse_rfc3339(&stamp).ok()
+}
+
+/// Splits `host`, `host:port` or an `https://` URL into host and port.
+#[cfg(feature = "tls-inspect")]
+pub(crate) fn split_target(target: &str) -> Result<(String, u16)> {
+    let with_scheme = if target.contains("://") {
+        target.to_string()
+    } else {
+        format!("https://{}", target)
+    };
+    let url = url::Url::parse(&with_scheme).with_context(|| format!("Invalid TLS target: {}", target))?;
+    let host = url
+        .host_str()
+        .with_context(|| format!("TLS target has no host: {}", target))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    Ok((host, url.port_or_known_default().unwrap_or(443)))
+}
+
+#[cfg(all(test, feature = "tls-inspect"))]
+mod tests {
+    use super::*;
+    use crate::HttpClient;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, IsCa};
+
+    fn params(name: &str, days: i64) -> CertificateParams {
+        let mut params = CertificateParams::new(vec![name.to_string()]);
+        let mut dn = DistinguishedName::new();
+        dn.push(DnType::CommonName, name);
+        params.distinguished_name = dn;
+        let now = time::OffsetDateTime::now_utc();
+        params.not_before = now - time::Duration::days(1);
+        params.not_after = now + time::Duration::days(days);
+        params
+    }
+
+    /// A self-signed certificate for `tls.test` valid for `days` more days,
+    /// as `(chain, key)`.
+    fn self_signed(days: i64) -> (Vec<Vec<u8>>, Vec<u8>) {
+        let certificate = Certificate::from_params(params("tls.test", days)).unwrap();
+        (vec![certificate.serialize_der().unwrap()], certificate.serialize_private_key_der())
+    }
+
+    /// Serves TLS on 127.0.0.1 with `chain` (leaf first), speaking only
+    /// `versions` and offering `http/1.1` over ALPN.
+    async fn tls_server(
+        chain: Vec<Vec<u8>>,
+        key: Vec<u8>,
+        versions: &[&'static rustls::SupportedProtocolVersion],
+    ) -> SocketAddr {
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain.into_iter().map(rustls::Certificate).collect(), rustls::PrivateKey(key))
+            .unwrap();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(mut stream) = acceptor.accept(tcp).await {
+                        let _ = tokio::io::copy(&mut stream, &mut tokio::io::sink()).await;
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    fn client_for(addr: SocketAddr) -> (HttpClient, String) {
+        let client = HttpClient::new().with_resolve("tls.test", &[addr]);
+        (client, format!("tls.test:{}", addr.port()))
+    }
+
+    #[tokio::test]
+    async fn inspect_tls_reports_the_inspected_handshake() -> Result<()> {
+        let (chain, key) = self_signed(5);
+        let (client, target) = client_for(tls_server(chain, key, &[&rustls::version::TLS13]).await);
+
+        let info = client.inspect_tls(&target).await?;
+        assert_eq!(info.protocol.as_deref(), Some("TLSv1.3"));
+        assert!(info.cipher.as_deref().is_some_and(|cipher| cipher.starts_with("TLS13_")), "{:?}", info.cipher);
+        assert_eq!(info.alpn.as_deref(), Some("http/1.1"));
+        assert_eq!(info.sni.as_deref(), Some("tls.test"));
+        let certificate = info.certificate.as_ref().unwrap();
+        assert_eq!(certificate.subject, "CN=tls.test");
+        assert!(certificate.is_self_signed());
+        assert_eq!(info.chain, vec![certificate.clone()]);
+        assert!(info.verify_error.is_some());
+        assert!(info.expiring_soon);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn inspect_tls_returns_the_whole_chain() -> Result<()> {
+        let mut ca_params = params("Test CA", 365);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(ca_params).unwrap();
+        let leaf = Certificate::from_params(params("tls.test", 90)).unwrap();
+        let chain = vec![leaf.serialize_der_with_signer(&ca).unwrap(), ca.serialize_der().unwrap()];
+        let (client, target) = client_for(tls_server(chain, leaf.serialize_private_key_der(), &[&rustls::version::TLS13]).await);
+
+        let info = client.inspect_tls(&target).await?;
+        let subjects: Vec<_> = info.chain.iter().map(|certificate| certificate.subject.as_str()).collect();
+        assert_eq!(subjects, ["CN=tls.test", "CN=Test CA"]);
+        assert_eq!(info.chain[0].issuer, "CN=Test CA");
+        assert!(info.chain[1].is_self_signed());
+        assert!(!info.expiring_soon);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expiry_warning_follows_the_configured_window() -> Result<()> {
+        let (chain, key) = self_signed(5);
+        let (client, target) = client_for(tls_server(chain, key, &[&rustls::version::TLS13]).await);
+
+        let client = client.with_cert_expiry_warning(Duration::from_secs(24 * 60 * 60));
+        assert!(!client.inspect_tls(&target).await?.expiring_soon);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn inspect_tls_reports_tls12_when_that_is_all_the_server_speaks() -> Result<()> {
+        let (chain, key) = self_signed(90);
+        let (client, target) = client_for(tls_server(chain, key, &[&rustls::version::TLS12]).await);
+
+        let info = client.inspect_tls(&target).await?;
+        assert_eq!(info.protocol.as_deref(), Some("TLSv1.2"));
+        assert!(info.cipher.as_deref().is_some_and(|cipher| cipher.starts_with("TLS_ECDHE_")), "{:?}", info.cipher);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls13_only_rejects_a_tls12_server_naming_its_best_version() -> Result<()> {
+        let (chain, key) = self_signed(90);
+        let addr = tls_server(chain, key, &[&rustls::version::TLS12]).await;
+        let url = format!("https://tls.test:{}/", addr.port());
+
+        let client = HttpClient::new().with_resolve("tls.test", &[addr]).tls13_only()?;
+        let error = client.get(url.as_str(), crate::RequestConfig::new()).await.unwrap_err();
+        let violation = error.downcast_ref::<crate::TlsPolicyViolation>().expect("policy violation");
+        assert_eq!(violation.policy, TlsPolicy::Modern);
+        assert_eq!(violation.server_best, Some(TlsVersion::V1_2));
+
+        // TLS 1.2 meets Compatible, so the handshake gets as far as the
+        // untrusted certificate instead.
+        let client = HttpClient::new().with_resolve("tls.test", &[addr]).with_tls_policy(TlsPolicy::Compatible)?;
+        let error = client.get(url.as_str(), crate::RequestConfig::new()).await.unwrap_err();
+        assert!(error.downcast_ref::<crate::TlsPolicyViolation>().is_none(), "{:#}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn only_negotiation_failures_count_against_the_policy() {
+        assert!(is_negotiation_failure(&rustls::Error::AlertReceived(rustls::AlertDescription::ProtocolVersion)));
+        assert!(is_negotiation_failure(&rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::ServerDoesNotSupportTls12Or13
+        )));
+        assert!(!is_negotiation_failure(&rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer)));
+    }
+}
@@ -2,7 +2,12 @@ use anyhow::{Context, Result};
 use colored::*;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::models::{BodyFormat, QueryParams, StatusClass, UrlParts};
+
+/// Indented, colored `json_str`. Key order follows `minify_json`.
 pub fn pretty_print_json(json_str: &str) -> Result<String> {
     let value: Value = serde_json::from_str(json_str)
         .with_context(|| "Invalid JSON format")?;
@@ -13,6 +18,125 @@ pub fn pretty_print_json(json_str: &str) -> Result<String> {
     Ok(colorize_json(&pretty))
 }
 
+/// `json_str` without insignificant whitespace. Object keys keep their
+/// original order only with the `preserve-order` feature; otherwise they
+/// come out sorted.
+pub fn minify_json(json_str: &str) -> Result<String> {
+    let value: Value = serde_json::from_str(json_str)
+        .with_context(|| "Invalid JSON format")?;
+    
+    serde_json::to_string(&value)
+        .with_context(|| "Failed to format JSON")
+}
+
+/// RFC 8785 (JCS) canonical JSON: no whitespace, object keys sorted by
+/// UTF-16 code units, numbers written the way ECMAScript does, and only the
+/// escapes JSON requires. Integers beyond 2^53 lose precision, as they would
+/// in any JCS implementation. Use it to sign a JSON body, and send the same
+/// string (or `Body::CanonicalJson`) so the signature matches the wire.
+pub fn canonicalize_json(value: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => out.push_str(&format_es_number(n.as_f64().unwrap_or(0.0))),
+        Value::String(s) => write_canonical_string(s, out),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_string(key, out);
+                out.push(':');
+                write_canonical(item, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_canonical_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// ECMAScript `Number.prototype.toString` for a finite double: the shortest
+/// round-trip digits, in plain notation for exponents from -7 to 20.
+fn format_es_number(value: f64) -> String {
+    if value == 0.0 || !value.is_finite() {
+        return "0".to_string();
+    }
+    // `{:e}` gives the shortest round-trip digits, e.g. `-1.2345e-7`.
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let mut digits: String = mantissa.chars().filter(char::is_ascii_digit).collect();
+    let k = digits.len() as i32;
+    // Position of the decimal point relative to the start of `digits`.
+    let n = exponent.parse::<i32>().unwrap_or(0) + 1;
+
+    // When the value lies exactly halfway between two shortest candidates,
+    // Rust rounds up but ECMAScript picks the even one. A double's exact
+    // decimal expansion has at most 767 significant digits.
+    let last = digits.as_bytes()[digits.len() - 1];
+    if k > 1 && (last - b'0') % 2 == 1 {
+        let lower = format!("{}{}", &digits[..digits.len() - 1], (last - 1) as char);
+        let exact = format!("{:.767e}", value.abs());
+        let (exact_mantissa, exact_exponent) = exact.split_once('e').unwrap_or((&exact, "0"));
+        let exact_digits: String = exact_mantissa.chars().filter(char::is_ascii_digit).collect();
+        let halfway = format!("{}5", lower);
+        let is_tie = exact_exponent == exponent && exact_digits.trim_end_matches('0') == halfway;
+        if is_tie && format!("{}e{}", lower, n - k).parse::<f64>() == Ok(value.abs()) {
+            digits = lower;
+        }
+    }
+
+    let body = if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat((-n) as usize), digits)
+    } else {
+        let sign = if n > 0 { '+' } else { '-' };
+        match k {
+            1 => format!("{}e{}{}", digits, sign, (n - 1).abs()),
+            _ => format!("{}.{}e{}{}", &digits[..1], &digits[1..], sign, (n - 1).abs()),
+        }
+    };
+    if value < 0.0 { format!("-{}", body) } else { body }
+}
+
 fn colorize_json(json: &str) -> String {
     let mut result = String::new();
     let mut in_string = false;
@@ -23,11 +147,7 @@ fn colorize_json(json: &str) -> String {
         match ch {
             '"' if !escaped => {
                 in_string = !in_string;
-                if in_string {
-                    result.push_str(&ch.to_string().green().to_string());
-                } else {
-                    result.push_str(&ch.to_string().green().to_string());
-                }
+                result.push_str(&ch.to_string().green().to_string());
             }
             '\\' if in_string => {
                 escaped = !escaped;
@@ -88,6 +208,227 @@ fn colorize_json(json: &str) -> String {
     result
 }
 
+const HTML_BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "body", "dd", "details", "div", "dl", "dt",
+    "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6",
+    "head", "header", "hr", "html", "li", "link", "main", "meta", "nav", "ol", "p", "pre",
+    "script", "section", "style", "summary", "table", "tbody", "td", "tfoot", "th", "thead",
+    "title", "tr", "ul",
+];
+
+const HTML_VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is copied verbatim.
+const HTML_RAW_TAGS: &[&str] = &["pre", "script", "style", "textarea"];
+
+#[derive(Debug)]
+enum HtmlToken {
+    Open { name: String, raw: String, void: bool },
+    Close { name: String, raw: String },
+    Text(String),
+    Raw(String),
+    /// Comments, doctype and processing instructions.
+    Other(String),
+}
+
+impl HtmlToken {
+    fn is_block(&self) -> bool {
+        match self {
+            HtmlToken::Open { name, .. } | HtmlToken::Close { name, .. } => {
+                HTML_BLOCK_TAGS.contains(&name.as_str())
+            }
+            HtmlToken::Text(_) => false,
+            HtmlToken::Raw(_) | HtmlToken::Other(_) => true,
+        }
+    }
+}
+
+/// Indents block-level tags of an HTML document two spaces per level. Text
+/// and inline tags stay on their parent's line, and a block that holds only
+/// inline content is kept on one line. This is a display aid, not a
+/// validating formatter: whitespace in text is collapsed, while `pre`,
+/// `script`, `style` and `textarea` contents are left untouched.
+pub fn pretty_print_html(html: &str) -> String {
+    let tokens = tokenize_html(html);
+    let mut output = String::new();
+    let mut stack: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let mut i = 0;
+
+    fn push_line(output: &mut String, depth: usize, line: &str) {
+        let line = line.trim();
+        if !line.is_empty() {
+            output.push_str(&"  ".repeat(depth));
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+
+    while i < tokens.len() {
+        let token = &tokens[i];
+        if !token.is_block() {
+            match token {
+                HtmlToken::Open { raw, .. } | HtmlToken::Close { raw, .. } => line.push_str(raw),
+                HtmlToken::Text(text) => line.push_str(text),
+                _ => {}
+            }
+            i += 1;
+            continue;
+        }
+
+        push_line(&mut output, stack.len(), &line);
+        line.clear();
+
+        match token {
+            HtmlToken::Open { name, raw, void } => {
+                if *void {
+                    push_line(&mut output, stack.len(), raw);
+                } else if let Some(end) = inline_block_end(&tokens, i) {
+                    let mut inline = String::new();
+                    for token in &tokens[i..=end] {
+                        match token {
+                            HtmlToken::Open { raw, .. } | HtmlToken::Close { raw, .. } => inline.push_str(raw),
+                            HtmlToken::Text(text) => inline.push_str(text),
+                            _ => {}
+                        }
+                    }
+                    push_line(&mut output, stack.len(), &inline);
+                    i = end;
+                } else {
+                    push_line(&mut output, stack.len(), raw);
+                    stack.push(name.clone());
+                }
+            }
+            HtmlToken::Close { name, raw } => {
+                if let Some(pos) = stack.iter().rposition(|open| open == name) {
+                    stack.truncate(pos);
+                }
+                push_line(&mut output, stack.len(), raw);
+            }
+            HtmlToken::Raw(content) => {
+                let content = content.trim_matches(|c| c == '\n' || c == '\r');
+                if !content.is_empty() {
+                    output.push_str(content);
+                    output.push('\n');
+                }
+            }
+            HtmlToken::Other(raw) => push_line(&mut output, stack.len(), raw),
+            HtmlToken::Text(_) => {}
+        }
+        i += 1;
+    }
+    push_line(&mut output, stack.len(), &line);
+
+    output
+}
+
+/// Index of the closing tag for the block opened at `start` when everything
+/// in between is inline.
+fn inline_block_end(tokens: &[HtmlToken], start: usize) -> Option<usize> {
+    let HtmlToken::Open { name, .. } = &tokens[start] else {
+        return None;
+    };
+    let mut nested = 0;
+    for (offset, token) in tokens[start + 1..].iter().enumerate() {
+        match token {
+            HtmlToken::Close { name: close, .. } if close == name => {
+                if nested == 0 {
+                    return Some(start + 1 + offset);
+                }
+                nested -= 1;
+            }
+            HtmlToken::Open { name: open, void: false, .. } if open == name => nested += 1,
+            _ if token.is_block() => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn tokenize_html(html: &str) -> Vec<HtmlToken> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(lt) = rest.find('<') else {
+            push_html_text(&mut tokens, rest);
+            break;
+        };
+        push_html_text(&mut tokens, &rest[..lt]);
+        rest = &rest[lt..];
+
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map(|i| i + 3).unwrap_or(rest.len());
+            tokens.push(HtmlToken::Other(rest[..end].to_string()));
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.find('>').map(|i| i + 1).unwrap_or(rest.len());
+        let raw = &rest[..end];
+        rest = &rest[end..];
+
+        if raw.starts_with("<!") || raw.starts_with("<?") {
+            tokens.push(HtmlToken::Other(raw.to_string()));
+            continue;
+        }
+
+        let closing = raw.starts_with("</");
+        let name: String = raw[if closing { 2 } else { 1 }..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if name.is_empty() {
+            push_html_text(&mut tokens, raw);
+            continue;
+        }
+
+        if closing {
+            tokens.push(HtmlToken::Close { name, raw: raw.to_string() });
+            continue;
+        }
+
+        let void = HTML_VOID_TAGS.contains(&name.as_str()) || raw.ends_with("/>");
+        let is_raw = !void && HTML_RAW_TAGS.contains(&name.as_str());
+        tokens.push(HtmlToken::Open { name: name.clone(), raw: raw.to_string(), void });
+
+        if is_raw {
+            let close = format!("</{}", name);
+            let end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+            if !rest[..end].trim().is_empty() {
+                tokens.push(HtmlToken::Raw(rest[..end].to_string()));
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    tokens
+}
+
+fn push_html_text(tokens: &mut Vec<HtmlToken>, text: &str) {
+    if text.trim().is_empty() {
+        // Whitespace between inline elements still separates words.
+        if !text.is_empty() && matches!(tokens.last(), Some(HtmlToken::Text(_)) | Some(HtmlToken::Open { .. }) | Some(HtmlToken::Close { .. })) {
+            tokens.push(HtmlToken::Text(" ".to_string()));
+        }
+        return;
+    }
+
+    let mut collapsed = String::with_capacity(text.len());
+    if text.starts_with(char::is_whitespace) {
+        collapsed.push(' ');
+    }
+    collapsed.push_str(&text.split_whitespace().collect::<Vec<_>>().join(" "));
+    if text.ends_with(char::is_whitespace) {
+        collapsed.push(' ');
+    }
+    tokens.push(HtmlToken::Text(collapsed));
+}
+
 pub fn format_duration(ms: u64) -> String {
     if ms < 1000 {
         format!("{}ms", ms)
@@ -142,8 +483,397 @@ pub fn json_path_extract(json_str: &str, path: &str) -> Result<Value> {
     Ok(current.clone())
 }
 
-pub fn parse_headers_string(headers_str: &str) -> Result<HashMap<String, String>> {
-    let mut headers = HashMap::new();
+/// A defect `repair_json` fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonRepair {
+    pub kind: JsonRepairKind,
+    /// Byte offset of the defect in the input.
+    pub offset: usize,
+    /// Length in bytes of the defect in the input.
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonRepairKind {
+    /// A comma before `}` or `]`, removed.
+    TrailingComma,
+    /// A `// line` or `/* block */` comment, removed.
+    Comment,
+    /// A `'single-quoted'` string, double-quoted.
+    SingleQuotes,
+    /// An object key that is a bare identifier, quoted.
+    UnquotedKey,
+}
+
+impl fmt::Display for JsonRepair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            JsonRepairKind::TrailingComma => "removed trailing comma",
+            JsonRepairKind::Comment => "removed comment",
+            JsonRepairKind::SingleQuotes => "double-quoted single-quoted string",
+            JsonRepairKind::UnquotedKey => "quoted unquoted key",
+        };
+        write!(f, "{} at byte {}", what, self.offset)
+    }
+}
+
+/// Parses almost-valid JSON, fixing trailing commas, comments, single-quoted
+/// strings and unquoted keys, and reports each fix. Strings are copied as
+/// they are, so `"http://x"` or `'/* not a comment */'` survive. Anything
+/// else, such as a missing comma, an unquoted value or an unterminated
+/// string, is not guessed at and fails. Positions in parse errors refer to
+/// the repaired text.
+pub fn repair_json(input: &str) -> Result<(Value, Vec<JsonRepair>)> {
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let offset_of = |index: usize| chars.get(index).map_or(input.len(), |(offset, _)| *offset);
+    let next_is = |index: usize, c: char| chars.get(index).is_some_and(|(_, next)| *next == c);
+
+    let mut out = String::with_capacity(input.len());
+    let mut repairs = Vec::new();
+    // The last token written, ignoring whitespace and comments.
+    let mut last: Option<char> = None;
+    // Output position and input offset of a comma that may turn out to be trailing.
+    let mut comma: Option<(usize, usize)> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let (offset, c) = chars[i];
+        let token = match c {
+            '"' => {
+                let close = string_end(&chars, i)
+                    .with_context(|| format!("Unterminated string at byte {}", offset))?;
+                out.push_str(&input[offset..offset_of(close + 1)]);
+                i = close + 1;
+                '"'
+            }
+            '\'' => {
+                let close = string_end(&chars, i)
+                    .with_context(|| format!("Unterminated string at byte {}", offset))?;
+                out.push('"');
+                let mut j = i + 1;
+                while j < close {
+                    match chars[j].1 {
+                        '\\' if chars[j + 1].1 == '\'' => out.push('\''),
+                        '\\' => {
+                            out.push('\\');
+                            out.push(chars[j + 1].1);
+                        }
+                        '"' => out.push_str("\\\""),
+                        other => out.push(other),
+                    }
+                    j += if chars[j].1 == '\\' { 2 } else { 1 };
+                }
+                out.push('"');
+                repairs.push(JsonRepair { kind: JsonRepairKind::SingleQuotes, offset, len: offset_of(close + 1) - offset });
+                i = close + 1;
+                '"'
+            }
+            '/' if next_is(i + 1, '/') || next_is(i + 1, '*') => {
+                let end = if next_is(i + 1, '/') {
+                    (i + 2..chars.len()).find(|&j| chars[j].1 == '\n').unwrap_or(chars.len())
+                } else {
+                    (i + 2..chars.len())
+                        .find(|&j| chars[j].1 == '*' && next_is(j + 1, '/'))
+                        .with_context(|| format!("Unterminated comment at byte {}", offset))?
+                        + 2
+                };
+                repairs.push(JsonRepair { kind: JsonRepairKind::Comment, offset, len: offset_of(end) - offset });
+                // Keeps the tokens on either side apart.
+                out.push(' ');
+                i = end;
+                continue;
+            }
+            c if c.is_whitespace() => {
+                out.push(c);
+                i += 1;
+                continue;
+            }
+            ',' => {
+                // `[,]` and `[1,,]` are not trailing commas; leave them to fail.
+                comma = match last {
+                    Some('[' | '{' | ',') | None => None,
+                    _ => Some((out.len(), offset)),
+                };
+                out.push(',');
+                i += 1;
+                ','
+            }
+            '}' | ']' => {
+                if let (Some(','), Some((at, comma_offset))) = (last, comma) {
+                    out.remove(at);
+                    repairs.push(JsonRepair { kind: JsonRepairKind::TrailingComma, offset: comma_offset, len: 1 });
+                }
+                out.push(c);
+                i += 1;
+                c
+            }
+            c if c.is_ascii_alphabetic() || c == '_' || c == '$' => {
+                let end = (i..chars.len())
+                    .find(|&j| !(chars[j].1.is_ascii_alphanumeric() || chars[j].1 == '_' || chars[j].1 == '$'))
+                    .unwrap_or(chars.len());
+                let word = &input[offset..offset_of(end)];
+                let colon = (end..chars.len()).find(|&j| !chars[j].1.is_whitespace());
+                let is_key = matches!(last, Some('{' | ',')) && colon.is_some_and(|j| chars[j].1 == ':');
+                if is_key {
+                    out.push('"');
+                    out.push_str(word);
+                    out.push('"');
+                    repairs.push(JsonRepair { kind: JsonRepairKind::UnquotedKey, offset, len: word.len() });
+                } else {
+                    out.push_str(word);
+                }
+                i = end;
+                'a'
+            }
+            other => {
+                out.push(other);
+                i += 1;
+                other
+            }
+        };
+        last = Some(token);
+    }
+
+    let value = serde_json::from_str(&out).with_context(|| "Could not repair JSON")?;
+    Ok((value, repairs))
+}
+
+/// Index of the quote closing the string that opens at `start`.
+fn string_end(chars: &[(usize, char)], start: usize) -> Option<usize> {
+    let quote = chars[start].1;
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i].1 {
+            '\\' => i += 2,
+            c if c == quote => return Some(i),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Deep-merges `overlay` into `base`: objects merge key by key, anything else
+/// (arrays, scalars, or a type mismatch) is replaced by the overlay value.
+pub fn json_merge(base: &str, overlay: &str) -> Result<String> {
+    let mut base: Value = serde_json::from_str(base)
+        .with_context(|| "Invalid base JSON")?;
+    let overlay: Value = serde_json::from_str(overlay)
+        .with_context(|| "Invalid overlay JSON")?;
+    
+    merge_values(&mut base, overlay);
+    
+    serde_json::to_string(&base).with_context(|| "Failed to serialize merged JSON")
+}
+
+pub fn merge_values(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base_map), Value::Object(overlay_map)) => {
+            for (key, value) in overlay_map {
+                match base_map.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base_map.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// How `to_nested_form_with` writes array elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FormArrayStyle {
+    /// `tags[]=a&tags[]=b`, what Rack and PHP expect for scalar arrays.
+    #[default]
+    Brackets,
+    /// `tags[0]=a&tags[1]=b`.
+    Indexed,
+}
+
+/// Flattens a JSON object into PHP/Rails style form pairs:
+/// `{"user": {"name": "x", "tags": ["a", "b"]}}` becomes
+/// `user[name]=x`, `user[tags][]=a`, `user[tags][]=b`.
+///
+/// The bracket syntax cannot express everything JSON can, so a few choices
+/// are fixed:
+/// - Arrays whose elements are objects or arrays are always indexed
+///   (`users[0][name]`), because `users[][name]` is ambiguous once the
+///   objects have different keys. Rack parses these into a hash keyed by
+///   `"0"`, `"1"`, ...
+/// - `null` is sent as an empty value, booleans as `true`/`false`.
+/// - Empty arrays and objects produce no pairs.
+/// - Only a top-level object yields pairs; other values yield none.
+pub fn to_nested_form(value: &Value) -> Vec<(String, String)> {
+    to_nested_form_with(value, FormArrayStyle::Brackets)
+}
+
+pub fn to_nested_form_with(value: &Value, style: FormArrayStyle) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    if let Value::Object(map) = value {
+        for (key, value) in map {
+            push_form_pairs(&mut pairs, key.clone(), value, style);
+        }
+    }
+    pairs
+}
+
+fn push_form_pairs(pairs: &mut Vec<(String, String)>, prefix: String, value: &Value, style: FormArrayStyle) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                push_form_pairs(pairs, format!("{}[{}]", prefix, key), value, style);
+            }
+        }
+        Value::Array(items) => {
+            let nested = items.iter().any(|item| item.is_object() || item.is_array());
+            for (index, item) in items.iter().enumerate() {
+                let key = if style == FormArrayStyle::Brackets && !nested {
+                    format!("{}[]", prefix)
+                } else {
+                    format!("{}[{}]", prefix, index)
+                };
+                push_form_pairs(pairs, key, item, style);
+            }
+        }
+        Value::Null => pairs.push((prefix, String::new())),
+        Value::String(s) => pairs.push((prefix, s.clone())),
+        other => pairs.push((prefix, other.to_string())),
+    }
+}
+
+/// How `to_query_string` writes arrays of scalars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayQueryStyle {
+    /// `ids=1&ids=2`, what most servers expect.
+    #[default]
+    Repeated,
+    /// `ids=1,2`.
+    Comma,
+    /// `ids[]=1&ids[]=2`, for Rails and PHP.
+    Brackets,
+}
+
+/// Encodes a JSON object as a query string (without the `?`), writing
+/// arrays of scalars in `style`. Nested objects become `filter[status]=x`
+/// and arrays of objects or arrays are indexed (`sort[0][field]=name`).
+/// `null` is sent as an empty value and empty arrays are left out; values
+/// other than objects yield an empty string.
+pub fn to_query_string(value: &Value, style: ArrayQueryStyle) -> String {
+    let mut parts = Vec::new();
+    if let Value::Object(map) = value {
+        for (key, value) in map {
+            push_query_parts(&mut parts, encode_query_component(key), value, style);
+        }
+    }
+    parts.join("&")
+}
+
+fn push_query_parts(parts: &mut Vec<String>, key: String, value: &Value, style: ArrayQueryStyle) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map {
+                push_query_parts(parts, format!("{}[{}]", key, encode_query_component(name)), value, style);
+            }
+        }
+        Value::Array(items) if items.iter().any(|item| item.is_object() || item.is_array()) => {
+            for (index, item) in items.iter().enumerate() {
+                push_query_parts(parts, format!("{}[{}]", key, index), item, style);
+            }
+        }
+        Value::Array(items) => match style {
+            ArrayQueryStyle::Repeated | ArrayQueryStyle::Brackets => {
+                let key = if style == ArrayQueryStyle::Brackets { format!("{}[]", key) } else { key };
+                for item in items {
+                    parts.push(format!("{}={}", key, encode_query_component(&query_scalar(item))));
+                }
+            }
+            ArrayQueryStyle::Comma if items.is_empty() => {}
+            ArrayQueryStyle::Comma => {
+                let joined: Vec<String> = items.iter().map(|item| encode_query_component(&query_scalar(item))).collect();
+                parts.push(format!("{}={}", key, joined.join(",")));
+            }
+        },
+        scalar => parts.push(format!("{}={}", key, encode_query_component(&query_scalar(scalar)))),
+    }
+}
+
+fn query_scalar(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn encode_query_component(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// URL-encodes form pairs in order, e.g. the output of `to_nested_form`.
+pub fn encode_form_pairs(pairs: &[(String, String)]) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish()
+}
+
+/// The body length announced by `Content-Length`, or `None` when there is no
+/// header or the response cannot have a body (HEAD, 1xx, 204, 304). reqwest
+/// is built without its decompression features, so the bytes read are the
+/// wire bytes and compare directly even for `Content-Encoding: gzip` bodies.
+pub(crate) fn expected_body_length(head_request: bool, status: u16, headers: &HashMap<String, String>) -> Option<u64> {
+    if head_request || (100..200).contains(&status) || status == 204 || status == 304 {
+        return None;
+    }
+    headers.get("content-length")?.trim().parse().ok()
+}
+
+/// `Accept-Encoding` sent when the client can decode compressed bodies
+/// itself and the request does not set its own.
+pub(crate) const ACCEPT_ENCODING: Option<&str> = if cfg!(feature = "zstd") { Some("zstd") } else { None };
+
+/// Whether the body carries a `Content-Encoding` this client decodes
+/// itself: `zstd` with the `zstd` feature.
+pub(crate) fn is_client_decoded(headers: &HashMap<String, String>) -> bool {
+    cfg!(feature = "zstd")
+        && headers
+            .get("content-encoding")
+            .is_some_and(|encoding| encoding.trim().eq_ignore_ascii_case("zstd"))
+}
+
+/// Decodes a body `is_client_decoded` accepts and, like reqwest's own
+/// decompression, drops the `Content-Encoding` and `Content-Length` headers
+/// that described the encoded bytes. Other bodies are returned unchanged.
+pub(crate) fn decode_content_encoding(headers: &mut HashMap<String, String>, body: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_client_decoded(headers) {
+        return Ok(body);
+    }
+    let decoded = decode_zstd(&body)?;
+    headers.remove("content-encoding");
+    headers.remove("content-length");
+    Ok(decoded)
+}
+
+#[cfg(feature = "zstd")]
+fn decode_zstd(body: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Read;
+    
+    let mut decoded = Vec::with_capacity(body.len() * 4);
+    ruzstd::decoding::StreamingDecoder::new(body)
+        .map_err(|e| anyhow::anyhow!("Failed to decode zstd body: {}", e))?
+        .read_to_end(&mut decoded)
+        .with_context(|| "Failed to decode zstd body")?;
+    Ok(decoded)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decode_zstd(_body: &[u8]) -> Result<Vec<u8>> {
+    anyhow::bail!("Decoding zstd bodies requires the `zstd` feature")
+}
+
+/// Parses `Key: Value` lines, keeping their order. Fails on the first line
+/// without a colon; `parse_headers_string_lenient` skips such lines instead.
+pub fn parse_headers_string(headers_str: &str) -> Result<crate::headers::HeaderList> {
+    let mut headers = crate::headers::HeaderList::new();
     
     for line in headers_str.lines() {
         let line = line.trim();
@@ -164,6 +894,969 @@ pub fn parse_headers_string(headers_str: &str) -> Result<HashMap<String, String>
     Ok(headers)
 }
 
+/// A header line or flag that lenient parsing skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderParseWarning {
+    /// 1-based line number, or flag number for `parse_header_flags`.
+    pub position: usize,
+    pub input: String,
+    pub reason: String,
+}
+
+impl fmt::Display for HeaderParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "header {} skipped ({}): {}", self.position, self.reason, self.input)
+    }
+}
+
+/// Like `parse_headers_string`, but skips lines without a colon or with an
+/// invalid name or value, reporting each with its line number.
+pub fn parse_headers_string_lenient(headers_str: &str) -> (crate::headers::HeaderList, Vec<HeaderParseWarning>) {
+    let mut headers = crate::headers::HeaderList::new();
+    let mut warnings = Vec::new();
+    for (index, line) in headers_str.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_once(':').ok_or("missing ':'").and_then(|(name, value)| checked_header(name, value)) {
+            Ok((name, value)) => {
+                headers.insert(name, value);
+            }
+            Err(reason) => warnings.push(HeaderParseWarning {
+                position: index + 1,
+                input: line.to_string(),
+                reason: reason.to_string(),
+            }),
+        }
+    }
+    (headers, warnings)
+}
+
+/// Headers from curl-style `-H` flags, see `parse_header_flags`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderFlags {
+    pub headers: crate::headers::HeaderList,
+    /// Names given as `Name:`, to leave off the request.
+    pub unset: Vec<String>,
+    pub warnings: Vec<HeaderParseWarning>,
+}
+
+/// Parses `-H` flag values the way curl does: `Name: value` sets a header,
+/// `Name;` sends it with an empty value and `Name:` removes it, defaults
+/// included. Malformed flags are skipped with a warning; later flags win.
+/// Apply the result with `RequestConfig::with_header_flags`.
+pub fn parse_header_flags<I, S>(flags: I) -> HeaderFlags
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut parsed = HeaderFlags::default();
+    for (index, flag) in flags.into_iter().enumerate() {
+        let flag = flag.as_ref().trim();
+        let header = match flag.split_once(':') {
+            Some((name, value)) if value.trim().is_empty() => checked_header(name, "").map(|(name, _)| (name, None)),
+            Some((name, value)) => checked_header(name, value).map(|(name, value)| (name, Some(value))),
+            None => match flag.strip_suffix(';') {
+                Some(name) => checked_header(name, "").map(|(name, value)| (name, Some(value))),
+                None => Err("missing ':'"),
+            },
+        };
+        match header {
+            Ok((name, Some(value))) => {
+                parsed.unset.retain(|unset| !unset.eq_ignore_ascii_case(&name));
+                parsed.headers.insert(name, value);
+            }
+            Ok((name, None)) => {
+                parsed.headers.remove(&name);
+                if !parsed.unset.iter().any(|unset| unset.eq_ignore_ascii_case(&name)) {
+                    parsed.unset.push(name);
+                }
+            }
+            Err(reason) => parsed.warnings.push(HeaderParseWarning {
+                position: index + 1,
+                input: flag.to_string(),
+                reason: reason.to_string(),
+            }),
+        }
+    }
+    parsed
+}
+
+fn checked_header(name: &str, value: &str) -> std::result::Result<(String, String), &'static str> {
+    let (name, value) = (name.trim(), value.trim());
+    if name.is_empty() {
+        return Err("empty name");
+    }
+    reqwest::header::HeaderName::from_bytes(name.as_bytes()).map_err(|_| "invalid name")?;
+    reqwest::header::HeaderValue::from_str(value).map_err(|_| "invalid value")?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// `url::Url::parse`, naming the host when it fails IDNA validation.
+/// Unicode hosts become punycode and Unicode paths are UTF-8
+/// percent-encoded, as sent on the wire.
+pub(crate) fn parse_url(url: &str) -> Result<url::Url> {
+    match url::Url::parse(url) {
+        Err(url::ParseError::IdnaError) => anyhow::bail!(
+            "Invalid URL {}: host {} is not a valid internationalized domain name",
+            url,
+            raw_host(url)
+        ),
+        parsed => parsed.with_context(|| format!("Invalid URL format: {}", url)),
+    }
+}
+
+/// The host of `url` as written, before any IDNA processing.
+fn raw_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    match host.starts_with('[') {
+        true => host.split_inclusive(']').next().unwrap_or(host),
+        false => host.split(':').next().unwrap_or(host),
+    }
+}
+
+/// `host` as sent on the wire: lowercased, with Unicode labels converted to
+/// punycode (`münchen.example` becomes `xn--mnchen-3ya.example`).
+pub fn host_to_ascii(host: &str) -> Result<String> {
+    url::Host::parse(host)
+        .map(|host| host.to_string())
+        .map_err(|e| anyhow::anyhow!("Invalid host {}: {}", host, e))
+}
+
+/// `host` for people to read, with punycode labels shown in Unicode.
+/// Hosts that do not decode are returned unchanged.
+pub fn host_to_unicode(host: &str) -> String {
+    match idna::domain_to_unicode(host) {
+        (unicode, Ok(())) => unicode,
+        (_, Err(_)) => host.to_string(),
+    }
+}
+
+/// Lookup key for a host given by the caller, e.g. to `with_resolve`, so it
+/// matches the punycode form requests carry.
+pub(crate) fn host_key(host: &str) -> String {
+    host_to_ascii(host.trim()).unwrap_or_else(|_| host.trim().to_ascii_lowercase())
+}
+
+/// `url` with its host shown in Unicode, for logs and error messages. The
+/// path and query stay percent-encoded; URLs that do not parse are
+/// returned as is.
+pub fn display_url(url: &str) -> String {
+    let Ok(parsed) = url::Url::parse(url) else {
+        return url.to_string();
+    };
+    match parsed.host() {
+        Some(url::Host::Domain(domain)) if domain.split('.').any(|label| label.starts_with("xn--")) => format!(
+            "{}{}{}",
+            &parsed[..url::Position::BeforeHost],
+            host_to_unicode(domain),
+            &parsed[url::Position::AfterHost..]
+        ),
+        _ => url.to_string(),
+    }
+}
+
+pub fn parse_url_parts(url: &str) -> Result<UrlParts> {
+    let parsed = parse_url(url)?;
+    
+    let host = parsed.host().map(|host| match host {
+        url::Host::Domain(domain) => domain.to_string(),
+        url::Host::Ipv4(addr) => addr.to_string(),
+        url::Host::Ipv6(addr) => addr.to_string(),
+    });
+    
+    let path_segments = parsed
+        .path_segments()
+        .map(|segments| {
+            segments
+                .filter(|segment| !segment.is_empty())
+                .map(percent_decode)
+                .collect()
+        })
+        .unwrap_or_default();
+    
+    // form_urlencoded already treats `+` as a space and keeps repeated keys
+    let pairs = parsed
+        .query_pairs()
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    
+    Ok(UrlParts {
+        scheme: parsed.scheme().to_string(),
+        host,
+        port: parsed.port_or_known_default(),
+        path_segments,
+        query: QueryParams::from_pairs(pairs),
+        fragment: parsed.fragment().map(percent_decode),
+    })
+}
+
+/// Sets `key=value` in the query of `url`, replacing any existing values of
+/// `key`. Works on relative URLs too.
+pub fn set_query_param(url: &str, key: &str, value: &str) -> String {
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(fragment)),
+        None => (url, None),
+    };
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+
+    let mut pairs: Vec<&str> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| percent_decode(pair.split('=').next().unwrap_or("")) != key)
+        .collect();
+    let encoded = encode_form_pairs(&[(key.to_string(), value.to_string())]);
+    pairs.push(&encoded);
+
+    let mut result = format!("{}?{}", path, pairs.join("&"));
+    if let Some(fragment) = fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+    result
+}
+
+/// Decodes `%XX` escapes, leaving `+` untouched as path segments require.
+pub fn percent_decode(input: &str) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(input)).into_owned()
+}
+
+fn percent_decode_bytes(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    
+    decoded
+}
+
+/// Expands an RFC 6570 URI template (levels 1 to 4), e.g.
+/// `/users{/id}{?fields*}`. Missing and `null` variables, empty lists and
+/// empty objects are left out as the RFC specifies; object members expand
+/// in map order, so enable the `preserve-order` feature to keep the order
+/// they were inserted in.
+pub fn expand_uri_template(template: &str, vars: &HashMap<String, Value>) -> Result<String> {
+    expand_template(template, vars, false)
+}
+
+/// Like `expand_uri_template`, but fails listing the variables the
+/// template uses that `vars` leaves undefined (missing or `null`).
+pub fn expand_uri_template_strict(template: &str, vars: &HashMap<String, Value>) -> Result<String> {
+    expand_template(template, vars, true)
+}
+
+/// Operator behaviour from RFC 6570 appendix A.
+struct TemplateOperator {
+    first: &'static str,
+    separator: &'static str,
+    named: bool,
+    if_empty: &'static str,
+    allow_reserved: bool,
+}
+
+impl TemplateOperator {
+    fn parse(expression: &str) -> Result<(Self, &str)> {
+        let operator = |first, separator, named, if_empty, allow_reserved| TemplateOperator {
+            first,
+            separator,
+            named,
+            if_empty,
+            allow_reserved,
+        };
+        let mut chars = expression.chars();
+        let parsed = match chars.next() {
+            Some('+') => operator("", ",", false, "", true),
+            Some('#') => operator("#", ",", false, "", true),
+            Some('.') => operator(".", ".", false, "", false),
+            Some('/') => operator("/", "/", false, "", false),
+            Some(';') => operator(";", ";", true, "", false),
+            Some('?') => operator("?", "&", true, "=", false),
+            Some('&') => operator("&", "&", true, "=", false),
+            Some(c @ ('=' | ',' | '!' | '@' | '|')) => {
+                anyhow::bail!("URI template operator '{}' is reserved for future extensions", c)
+            }
+            _ => return Ok((operator("", ",", false, "", false), expression)),
+        };
+        Ok((parsed, chars.as_str()))
+    }
+}
+
+enum VarModifier {
+    None,
+    Prefix(usize),
+    Explode,
+}
+
+fn parse_varspec(spec: &str) -> Result<(&str, VarModifier)> {
+    let (name, modifier) = if let Some(name) = spec.strip_suffix('*') {
+        (name, VarModifier::Explode)
+    } else if let Some((name, length)) = spec.split_once(':') {
+        let length = Some(length)
+            .filter(|length| length.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|length| length.parse::<usize>().ok())
+            .filter(|length| (1..10_000).contains(length))
+            .with_context(|| format!("Invalid prefix length in URI template variable '{}'", spec))?;
+        (name, VarModifier::Prefix(length))
+    } else {
+        (spec, VarModifier::None)
+    };
+
+    let bytes = name.as_bytes();
+    let mut i = 0;
+    let mut valid = !name.is_empty() && !name.starts_with('.') && !name.ends_with('.') && !name.contains("..");
+    while valid && i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                valid = bytes.len() > i + 2 && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit();
+                i += 3;
+            }
+            b if b.is_ascii_alphanumeric() || b == b'_' || b == b'.' => i += 1,
+            _ => valid = false,
+        }
+    }
+    if !valid {
+        anyhow::bail!("Invalid URI template variable name '{}'", name);
+    }
+    Ok((name, modifier))
+}
+
+fn expand_template(template: &str, vars: &HashMap<String, Value>, strict: bool) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut missing: Vec<&str> = Vec::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            anyhow::bail!("Unmatched '}}' in URI template '{}'", template);
+        }
+        push_template_encoded(&mut out, &rest[..open], true);
+        let close = rest[open..]
+            .find('}')
+            .map(|close| open + close)
+            .with_context(|| format!("Unclosed '{{' in URI template '{}'", template))?;
+        let expression = &rest[open + 1..close];
+        if expression.contains('{') {
+            anyhow::bail!("Nested '{{' in URI template '{}'", template);
+        }
+        expand_expression(&mut out, expression, vars, &mut missing)?;
+        rest = &rest[close + 1..];
+    }
+    push_template_encoded(&mut out, rest, true);
+
+    if strict && !missing.is_empty() {
+        anyhow::bail!("URI template is missing variables: {}", missing.join(", "));
+    }
+    Ok(out)
+}
+
+fn expand_expression<'t>(
+    out: &mut String,
+    expression: &'t str,
+    vars: &HashMap<String, Value>,
+    missing: &mut Vec<&'t str>,
+) -> Result<()> {
+    let (operator, specs) = TemplateOperator::parse(expression)?;
+    if specs.is_empty() {
+        anyhow::bail!("Empty URI template expression '{{{}}}'", expression);
+    }
+
+    let mut first = true;
+    for spec in specs.split(',') {
+        let (name, modifier) = parse_varspec(spec)?;
+        let value = match vars.get(name) {
+            None | Some(Value::Null) => {
+                if !missing.contains(&name) {
+                    missing.push(name);
+                }
+                continue;
+            }
+            Some(Value::Array(items)) if items.iter().all(Value::is_null) => continue,
+            Some(Value::Object(members)) if members.values().all(Value::is_null) => continue,
+            Some(value) => value,
+        };
+
+        out.push_str(if first { operator.first } else { operator.separator });
+        first = false;
+        let encode = |out: &mut String, text: &str| push_template_encoded(out, text, operator.allow_reserved);
+
+        match (value, &modifier) {
+            (Value::Array(_) | Value::Object(_), VarModifier::Prefix(_)) => {
+                anyhow::bail!("Prefix modifier used on composite URI template variable '{}'", name)
+            }
+            (Value::Array(items), VarModifier::Explode) => {
+                for (i, item) in items.iter().filter(|item| !item.is_null()).enumerate() {
+                    if i > 0 {
+                        out.push_str(operator.separator);
+                    }
+                    let item = template_scalar(name, item)?;
+                    if operator.named {
+                        push_named(out, name, &item, &operator, encode);
+                    } else {
+                        encode(out, &item);
+                    }
+                }
+            }
+            (Value::Object(members), VarModifier::Explode) => {
+                let members = members.iter().filter(|(_, value)| !value.is_null());
+                for (i, (key, member)) in members.enumerate() {
+                    if i > 0 {
+                        out.push_str(operator.separator);
+                    }
+                    let member = template_scalar(name, member)?;
+                    if operator.named {
+                        let mut encoded_key = String::new();
+                        encode(&mut encoded_key, key);
+                        push_named(out, &encoded_key, &member, &operator, encode);
+                    } else {
+                        encode(out, key);
+                        out.push('=');
+                        encode(out, &member);
+                    }
+                }
+            }
+            (Value::Array(_) | Value::Object(_), VarModifier::None) => {
+                let parts: Vec<String> = match value {
+                    Value::Array(items) => items
+                        .iter()
+                        .filter(|item| !item.is_null())
+                        .map(|item| template_scalar(name, item))
+                        .collect::<Result<_>>()?,
+                    Value::Object(members) => members
+                        .iter()
+                        .filter(|(_, member)| !member.is_null())
+                        .map(|(key, member)| Ok([key.clone(), template_scalar(name, member)?]))
+                        .collect::<Result<Vec<_>>>()?
+                        .concat(),
+                    _ => unreachable!(),
+                };
+                if operator.named {
+                    out.push_str(name);
+                    out.push('=');
+                }
+                for (i, part) in parts.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    encode(out, part);
+                }
+            }
+            (scalar, modifier) => {
+                let text = template_scalar(name, scalar)?;
+                let text = match modifier {
+                    VarModifier::Prefix(length) => text.chars().take(*length).collect(),
+                    _ => text,
+                };
+                if operator.named {
+                    push_named(out, name, &text, &operator, encode);
+                } else {
+                    encode(out, &text);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `name=value`, or `name` plus the operator's empty marker for an empty
+/// value (`;x` but `?x=`).
+fn push_named(out: &mut String, name: &str, value: &str, operator: &TemplateOperator, encode: impl Fn(&mut String, &str)) {
+    out.push_str(name);
+    if value.is_empty() {
+        out.push_str(operator.if_empty);
+    } else {
+        out.push('=');
+        encode(out, value);
+    }
+}
+
+fn template_scalar(name: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Array(_) | Value::Object(_) => {
+            anyhow::bail!("URI template variable '{}' nests lists or objects", name)
+        }
+        other => Ok(other.to_string()),
+    }
+}
+
+/// Percent-encodes `text` for a URI template: unreserved characters pass,
+/// and with `allow_reserved` so do reserved characters and existing `%XX`
+/// escapes.
+fn push_template_encoded(out: &mut String, text: &str, allow_reserved: bool) {
+    const RESERVED: &[u8] = b":/?#[]@!$&'()*+,;=";
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let escape = b == b'%'
+            && allow_reserved
+            && bytes.len() > i + 2
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit();
+        if escape {
+            out.push_str(&text[i..i + 3]);
+            i += 3;
+            continue;
+        }
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) || allow_reserved && RESERVED.contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+        i += 1;
+    }
+}
+
+/// The filename from a `Content-Disposition` header. The RFC 5987
+/// `filename*=charset'lang'value` form wins over a plain `filename`.
+pub fn content_disposition_filename(header: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+    
+    for (name, value) in disposition_params(header) {
+        if name.eq_ignore_ascii_case("filename*") {
+            extended = decode_ext_value(&value).or(extended);
+        } else if name.eq_ignore_ascii_case("filename") {
+            plain = Some(value);
+        }
+    }
+    
+    extended.or(plain).filter(|name| !name.is_empty())
+}
+
+/// `name=value` parameters after the disposition type, with quoted strings
+/// unquoted and unescaped. Bare tokens without `=` are skipped.
+fn disposition_params(header: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = header.chars().peekable();
+    
+    // Skip the disposition type itself.
+    for c in chars.by_ref() {
+        if c == ';' {
+            break;
+        }
+    }
+    
+    loop {
+        let mut name = String::new();
+        let mut has_value = false;
+        for c in chars.by_ref() {
+            match c {
+                '=' => {
+                    has_value = true;
+                    break;
+                }
+                ';' => break,
+                c => name.push(c),
+            }
+        }
+        if !has_value {
+            if chars.peek().is_none() {
+                break;
+            }
+            continue;
+        }
+        let name = name.trim().to_string();
+        
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+            }
+        } else {
+            value = chars.by_ref().take_while(|&c| c != ';').collect();
+            value = value.trim().to_string();
+        }
+        if !name.is_empty() {
+            params.push((name, value));
+        }
+    }
+    
+    params
+}
+
+/// Decodes an RFC 5987 `charset'language'percent-encoded` value.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.trim();
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+    
+    let encoding = encoding_rs::Encoding::for_label(charset.as_bytes())?;
+    let bytes = percent_decode_bytes(encoded);
+    let (text, _, malformed) = encoding.decode(&bytes);
+    (!malformed).then(|| text.into_owned())
+}
+
+/// A random (version 4) UUID such as `0f8fad5b-d9cb-469f-a165-70867728950e`.
+/// Unique enough for request IDs; not for anything security-related.
+pub fn generate_request_id() -> String {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+    
+    let random = |salt: u64| random_u64(&[count, nanos, salt]);
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&random(1).to_le_bytes());
+    bytes[8..].copy_from_slice(&random(2).to_le_bytes());
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// A random value in `[0, 1)`, good enough for jitter.
+pub(crate) fn random_fraction() -> f64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+    (random_u64(&[nanos]) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn random_u64(seed: &[u64]) -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    // RandomState is seeded randomly per process.
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    for value in seed {
+        hasher.write_u64(*value);
+    }
+    hasher.finish()
+}
+
+/// SHA-256 digest of `data` (FIPS 180-4).
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (chunk, value) in digest.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+/// Guesses the format of a body from its bytes, for responses that arrive
+/// without a `Content-Type`.
+pub fn sniff_body_format(bytes: &[u8]) -> BodyFormat {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    if bytes.starts_with(b"%PDF-") || bytes.contains(&0) {
+        return BodyFormat::Binary;
+    }
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return BodyFormat::Binary;
+    };
+    
+    let text = text.trim_start();
+    if text.starts_with(['{', '[']) && is_valid_json(text) {
+        return BodyFormat::Json;
+    }
+    
+    let head = text.get(..text.len().min(64)).unwrap_or(text).to_ascii_lowercase();
+    if ["<!doctype html", "<html", "<head", "<body"].iter().any(|tag| head.starts_with(tag)) {
+        BodyFormat::Html
+    } else if head.starts_with("<?xml") {
+        BodyFormat::Xml
+    } else {
+        BodyFormat::Text
+    }
+}
+
+/// Decodes a body using the `charset` of its content type, defaulting to UTF-8.
+pub fn decode_body(bytes: &[u8], content_type: &str) -> String {
+    let encoding = content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| encoding_rs::Encoding::for_label(value.trim().trim_matches('"').as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+/// Formats a timestamp as RFC 3339 UTC with millisecond precision, e.g.
+/// `2024-05-01T12:30:00.250Z`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let (secs, millis) = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_millis()),
+        Err(e) => {
+            let d = e.duration();
+            let millis = d.subsec_millis();
+            if millis == 0 {
+                (-(d.as_secs() as i64), 0)
+            } else {
+                (-(d.as_secs() as i64) - 1, 1000 - millis)
+            }
+        }
+    };
+    
+    let days = secs.div_euclid(86_400);
+    let secs_of_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        (secs_of_day % 3600) / 60,
+        secs_of_day % 60,
+        millis
+    )
+}
+
+/// Parses RFC 3339 timestamps with an optional fraction and a `Z` or
+/// `+HH:MM` offset.
+pub fn parse_rfc3339(input: &str) -> Result<SystemTime> {
+    let invalid = || anyhow::anyhow!("Invalid RFC 3339 timestamp: {}", input);
+    let bytes = input.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':' || bytes[16] != b':'
+    {
+        return Err(invalid());
+    }
+    
+    let number = |range: std::ops::Range<usize>| -> Result<i64> {
+        input.get(range).and_then(|s| s.parse().ok()).ok_or_else(invalid)
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    
+    let mut rest = &input[19..];
+    let mut nanos: u32 = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return Err(invalid());
+        }
+        let padded = format!("{:0<9}", &fraction[..digits.min(9)]);
+        nanos = padded.parse().map_err(|_| invalid())?;
+        rest = &fraction[digits..];
+    }
+    
+    // Matched on bytes: slicing `rest` could split a multibyte character.
+    let offset_secs = match rest.as_bytes() {
+        b"Z" | b"z" => 0,
+        [sign @ (b'+' | b'-'), h1, h2, b':', m1, m2] if [h1, h2, m1, m2].iter().all(|b| b.is_ascii_digit()) => {
+            let digits = |tens: u8, ones: u8| i64::from(tens - b'0') * 10 + i64::from(ones - b'0');
+            let offset = digits(*h1, *h2) * 3600 + digits(*m1, *m2) * 60;
+            if *sign == b'-' { -offset } else { offset }
+        }
+        _ => return Err(invalid()),
+    };
+    
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset_secs;
+    let time = if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nanos)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + Duration::from_nanos(nanos as u64)
+    };
+    
+    Ok(time)
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Formats a time as an HTTP date (IMF-fixdate), e.g.
+/// `Sun, 06 Nov 1994 08:49:37 GMT`. Sub-second precision is dropped.
+pub fn format_http_date(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs_f64().ceil() as i64),
+    };
+    let days = secs.div_euclid(86_400);
+    let secs_of_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs_of_day / 3600,
+        (secs_of_day % 3600) / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Parses an HTTP date in any of the three formats RFC 9110 requires
+/// recipients to accept: IMF-fixdate, RFC 850 and asctime.
+pub fn parse_http_date(input: &str) -> Result<SystemTime> {
+    let invalid = || anyhow::anyhow!("Invalid HTTP date: {}", input);
+    let month_number = |name: &str| -> Result<i64> {
+        MONTHS
+            .iter()
+            .position(|month| month.eq_ignore_ascii_case(name))
+            .map(|index| index as i64 + 1)
+            .ok_or_else(invalid)
+    };
+    let number = |text: &str| -> Result<i64> { text.parse().map_err(|_| invalid()) };
+    
+    let fields: Vec<&str> = input.split_whitespace().collect();
+    let (year, month, day, clock) = match fields.as_slice() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        [_, day, month, year, clock, "GMT"] if year.len() == 4 => {
+            (number(year)?, month_number(month)?, number(day)?, *clock)
+        }
+        // Sunday, 06-Nov-94 08:49:37 GMT
+        [_, date, clock, "GMT"] => {
+            let mut parts = date.split('-');
+            let (Some(day), Some(month), Some(year), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(invalid());
+            };
+            let year = number(year)?;
+            // Two-digit years more than 50 years ahead belong to the last century.
+            let year = if year < 50 { 2000 + year } else if year < 100 { 1900 + year } else { year };
+            (year, month_number(month)?, number(day)?, *clock)
+        }
+        // Sun Nov  6 08:49:37 1994
+        [_, month, day, clock, year] => (number(year)?, month_number(month)?, number(day)?, *clock),
+        _ => return Err(invalid()),
+    };
+    
+    let mut time = clock.split(':');
+    let (Some(hour), Some(minute), Some(second), None) = (time.next(), time.next(), time.next(), time.next()) else {
+        return Err(invalid());
+    };
+    let (hour, minute, second) = (number(hour)?, number(minute)?, number(second)?);
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second;
+    Ok(if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    })
+}
+
+// Howard Hinnant's days-from-civil algorithms.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Serde adapter storing `SystemTime` as an RFC 3339 string, for use with
+/// `#[serde(with = "crate::utils::rfc3339")]`.
+pub mod rfc3339 {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::SystemTime;
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format_rfc3339(*time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        super::parse_rfc3339(&value).map_err(serde::de::Error::custom)
+    }
+}
+
 pub fn status_message(status_code: u16) -> String {
     match status_code {
         200 => "OK".to_string(),
@@ -182,11 +1875,379 @@ pub fn status_message(status_code: u16) -> String {
 
 pub fn status_indicator(status_code: u16) -> String {
     let status_str = status_code.to_string();
-    match status_code {
-        200..=299 => status_str.green().bold().to_string(),
-        300..=399 => status_str.yellow().bold().to_string(),
-        400..=499 => status_str.red().bold().to_string(),
-        500..=599 => status_str.red().bold().on_white().to_string(),
-        _ => status_str.white().to_string(),
+    match StatusClass::from_status(status_code) {
+        StatusClass::Success => status_str.green().bold().to_string(),
+        StatusClass::Redirect => status_str.yellow().bold().to_string(),
+        StatusClass::ClientError => status_str.red().bold().to_string(),
+        StatusClass::ServerError => status_str.red().bold().on_white().to_string(),
+        StatusClass::Informational | StatusClass::Unknown => status_str.white().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_convert_between_unicode_and_punycode() -> Result<()> {
+        assert_eq!(host_to_ascii("München.Example")?, "xn--mnchen-3ya.example");
+        assert_eq!(host_to_ascii("日本.example")?, "xn--wgv71a.example");
+        assert_eq!(host_to_ascii("☕.example")?, "xn--53h.example");
+        assert_eq!(host_to_unicode("xn--mnchen-3ya.example"), "münchen.example");
+        assert_eq!(host_to_unicode("xn--53h.example"), "☕.example");
+        assert_eq!(host_key(" MÜNCHEN.example "), "xn--mnchen-3ya.example");
+        Ok(())
+    }
+
+    #[test]
+    fn display_url_shows_unicode_host_and_keeps_path_encoded() {
+        assert_eq!(
+            display_url("https://xn--wgv71a.example/%E5%86%99?q=1"),
+            "https://日本.example/%E5%86%99?q=1"
+        );
+        assert_eq!(display_url("https://api.example.com/a"), "https://api.example.com/a");
+        assert_eq!(display_url("not a url"), "not a url");
+    }
+
+    #[test]
+    fn lenient_header_parsing_skips_bad_lines_with_warnings() {
+        let (headers, warnings) = parse_headers_string_lenient("Accept: text/html\ngarbage\n\nBad Name: x\nX-Ok: 1");
+        assert_eq!(headers.get("accept").map(String::as_str), Some("text/html"));
+        assert_eq!(headers.get("x-ok").map(String::as_str), Some("1"));
+        assert_eq!(headers.len(), 2);
+        let positions: Vec<usize> = warnings.iter().map(|warning| warning.position).collect();
+        assert_eq!(positions, [2, 4]);
+        assert_eq!(warnings[0].reason, "missing ':'");
+        assert!(parse_headers_string("Accept: text/html\ngarbage").is_err());
+    }
+
+    #[test]
+    fn header_flags_follow_curl() {
+        let flags = parse_header_flags(["X-Trace: 1", "X-Empty;", "User-Agent:", "no colon", "X-Trace: 2", "X-Empty:"]);
+        assert_eq!(flags.headers.get("x-trace").map(String::as_str), Some("2"));
+        assert!(flags.headers.get("x-empty").is_none());
+        assert_eq!(flags.unset, ["User-Agent", "X-Empty"]);
+        assert_eq!(flags.warnings.len(), 1);
+        assert_eq!(flags.warnings[0].position, 4);
+
+        let flags = parse_header_flags(["User-Agent:", "user-agent:", "User-Agent: custom"]);
+        assert!(flags.unset.is_empty());
+        assert_eq!(flags.headers.get("user-agent").map(String::as_str), Some("custom"));
+    }
+
+    #[test]
+    fn parse_rfc3339_handles_offsets_and_fractions() -> Result<()> {
+        let base = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(parse_rfc3339("2023-11-14T22:13:20Z")?, base);
+        assert_eq!(parse_rfc3339("2023-11-15T00:13:20+02:00")?, base);
+        assert_eq!(parse_rfc3339("2023-11-14T21:13:20-01:00")?, base);
+        assert_eq!(parse_rfc3339("2023-11-14T22:13:20.5z")?, base + Duration::from_millis(500));
+        assert!(parse_rfc3339("2023-11-14T22:13:20+2:000").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rfc3339_rejects_multibyte_input_without_panicking() {
+        for input in [
+            "2023-11-14T22:13:20+0é00",
+            "2023-11-14T22:13:20+é:00",
+            "2023-11-14T22:13:20€:00",
+            "2023-11-14T22:13:20.5日本",
+            "2023-11-14T22:13:2日Z",
+            "２０２３-11-14T22:13:20Z",
+            "2023-11-14T22:13:20😀",
+        ] {
+            assert!(parse_rfc3339(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_url_names_the_invalid_host() {
+        let error = parse_url("https://xn--a.example/path").unwrap_err().to_string();
+        assert!(error.contains("host xn--a.example"), "{}", error);
+    }
+
+    #[cfg(feature = "preserve-order")]
+    #[test]
+    fn json_helpers_keep_key_order() -> Result<()> {
+        let original = r#"{"zebra":1,"apple":{"mango":2,"banana":3},"list":[{"b":1,"a":2}],"middle":null}"#;
+        assert_eq!(minify_json(original)?, original);
+
+        let pretty = pretty_print_json(original)?;
+        let positions: Vec<usize> = ["zebra", "apple", "mango", "banana", "list", "\"b\"", "\"a\"", "middle"]
+            .iter()
+            .map(|key| pretty.find(key).expect("key missing"))
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{}", pretty);
+
+        let value = crate::ApiResponse::synthetic(200, original).as_json_value()?;
+        let keys: Vec<&str> = value.as_object().map(|object| object.keys().map(String::as_str).collect()).unwrap_or_default();
+        assert_eq!(keys, ["zebra", "apple", "list", "middle"]);
+        assert_eq!(serde_json::to_string(&value)?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn disposition_params_skip_bare_tokens() {
+        assert_eq!(
+            content_disposition_filename("attachment; inline; filename=\"a.txt\"").as_deref(),
+            Some("a.txt")
+        );
+        assert_eq!(content_disposition_filename("attachment; foo; filename=b.txt; bar").as_deref(), Some("b.txt"));
+        assert_eq!(
+            content_disposition_filename("attachment;; filename=c.txt; filename*=UTF-8''%C3%A9.txt").as_deref(),
+            Some("é.txt")
+        );
+        assert_eq!(content_disposition_filename("attachment; inline"), None);
+        assert_eq!(content_disposition_filename("attachment; =x; filename=d.txt").as_deref(), Some("d.txt"));
+    }
+
+    #[test]
+    fn sha256_matches_fips_180_vectors() {
+        let hex = |data: &[u8]| sha256(data).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        assert_eq!(hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            hex(&vec![b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    /// The variables of RFC 6570 section 3.2.1.
+    fn rfc_6570_vars() -> HashMap<String, Value> {
+        let vars = serde_json::json!({
+            "count": ["one", "two", "three"],
+            "dom": ["example", "com"],
+            "dub": "me/too",
+            "hello": "Hello World!",
+            "half": "50%",
+            "var": "value",
+            "who": "fred",
+            "base": "http://example.com/home/",
+            "path": "/foo/bar",
+            "list": ["red", "green", "blue"],
+            "keys": {"semi": ";", "dot": ".", "comma": ","},
+            "v": "6",
+            "x": "1024",
+            "y": "768",
+            "empty": "",
+            "empty_keys": {},
+            "undef": null
+        });
+        serde_json::from_value(vars).unwrap()
+    }
+
+    #[test]
+    fn uri_templates_expand_the_rfc_6570_examples() -> Result<()> {
+        let vars = rfc_6570_vars();
+        let examples = [
+            // 3.2.2 simple string expansion
+            ("{var}", "value"),
+            ("{hello}", "Hello%20World%21"),
+            ("{half}", "50%25"),
+            ("O{empty}X", "OX"),
+            ("O{undef}X", "OX"),
+            ("{x,y}", "1024,768"),
+            ("{x,hello,y}", "1024,Hello%20World%21,768"),
+            ("?{x,empty}", "?1024,"),
+            ("?{x,undef}", "?1024"),
+            ("?{undef,y}", "?768"),
+            ("{var:3}", "val"),
+            ("{var:30}", "value"),
+            ("{list}", "red,green,blue"),
+            ("{list*}", "red,green,blue"),
+            // 3.2.3 reserved expansion
+            ("{+var}", "value"),
+            ("{+hello}", "Hello%20World!"),
+            ("{+half}", "50%25"),
+            ("{base}index", "http%3A%2F%2Fexample.com%2Fhome%2Findex"),
+            ("{+base}index", "http://example.com/home/index"),
+            ("O{+empty}X", "OX"),
+            ("O{+undef}X", "OX"),
+            ("{+path}/here", "/foo/bar/here"),
+            ("here?ref={+path}", "here?ref=/foo/bar"),
+            ("up{+path}{var}/here", "up/foo/barvalue/here"),
+            ("{+x,hello,y}", "1024,Hello%20World!,768"),
+            ("{+path,x}/here", "/foo/bar,1024/here"),
+            ("{+path:6}/here", "/foo/b/here"),
+            ("{+list}", "red,green,blue"),
+            ("{+list*}", "red,green,blue"),
+            // 3.2.4 fragment expansion
+            ("{#var}", "#value"),
+            ("{#hello}", "#Hello%20World!"),
+            ("{#half}", "#50%25"),
+            ("foo{#empty}", "foo#"),
+            ("foo{#undef}", "foo"),
+            ("{#x,hello,y}", "#1024,Hello%20World!,768"),
+            ("{#path,x}/here", "#/foo/bar,1024/here"),
+            ("{#path:6}/here", "#/foo/b/here"),
+            ("{#list}", "#red,green,blue"),
+            ("{#list*}", "#red,green,blue"),
+            // 3.2.5 label expansion
+            ("{.who}", ".fred"),
+            ("{.who,who}", ".fred.fred"),
+            ("{.half,who}", ".50%25.fred"),
+            ("www{.dom*}", "www.example.com"),
+            ("X{.var}", "X.value"),
+            ("X{.empty}", "X."),
+            ("X{.undef}", "X"),
+            ("X{.var:3}", "X.val"),
+            ("X{.list}", "X.red,green,blue"),
+            ("X{.list*}", "X.red.green.blue"),
+            ("X{.empty_keys}", "X"),
+            ("X{.empty_keys*}", "X"),
+            // 3.2.6 path segment expansion
+            ("{/who}", "/fred"),
+            ("{/who,who}", "/fred/fred"),
+            ("{/half,who}", "/50%25/fred"),
+            ("{/who,dub}", "/fred/me%2Ftoo"),
+            ("{/var}", "/value"),
+            ("{/var,empty}", "/value/"),
+            ("{/var,undef}", "/value"),
+            ("{/var,x}/here", "/value/1024/here"),
+            ("{/var:1,var}", "/v/value"),
+            ("{/list}", "/red,green,blue"),
+            ("{/list*}", "/red/green/blue"),
+            ("{/list*,path:4}", "/red/green/blue/%2Ffoo"),
+            // 3.2.7 path-style parameter expansion
+            ("{;who}", ";who=fred"),
+            ("{;half}", ";half=50%25"),
+            ("{;empty}", ";empty"),
+            ("{;v,empty,who}", ";v=6;empty;who=fred"),
+            ("{;v,bar,who}", ";v=6;who=fred"),
+            ("{;x,y}", ";x=1024;y=768"),
+            ("{;x,y,empty}", ";x=1024;y=768;empty"),
+            ("{;x,y,undef}", ";x=1024;y=768"),
+            ("{;hello:5}", ";hello=Hello"),
+            ("{;list}", ";list=red,green,blue"),
+            ("{;list*}", ";list=red;list=green;list=blue"),
+            // 3.2.8 form-style query expansion
+            ("{?who}", "?who=fred"),
+            ("{?half}", "?half=50%25"),
+            ("{?x,y}", "?x=1024&y=768"),
+            ("{?x,y,empty}", "?x=1024&y=768&empty="),
+            ("{?x,y,undef}", "?x=1024&y=768"),
+            ("{?var:3}", "?var=val"),
+            ("{?list}", "?list=red,green,blue"),
+            ("{?list*}", "?list=red&list=green&list=blue"),
+            // 3.2.9 form-style query continuation
+            ("{&who}", "&who=fred"),
+            ("{&half}", "&half=50%25"),
+            ("?fixed=yes{&x}", "?fixed=yes&x=1024"),
+            ("{&x,y,empty}", "&x=1024&y=768&empty="),
+            ("{&var:3}", "&var=val"),
+            ("{&list}", "&list=red,green,blue"),
+            ("{&list*}", "&list=red&list=green&list=blue"),
+        ];
+        for (template, expected) in examples {
+            assert_eq!(expand_uri_template(template, &vars)?, expected, "{}", template);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn uri_template_objects_expand_in_map_order() -> Result<()> {
+        let vars = rfc_6570_vars();
+        // The RFC's order is insertion order; without `preserve-order` keys sort.
+        let examples = [
+            ("{keys}", "semi,%3B,dot,.,comma,%2C", "comma,%2C,dot,.,semi,%3B"),
+            ("{keys*}", "semi=%3B,dot=.,comma=%2C", "comma=%2C,dot=.,semi=%3B"),
+            ("{+keys}", "semi,;,dot,.,comma,,", "comma,,,dot,.,semi,;"),
+            ("{+keys*}", "semi=;,dot=.,comma=,", "comma=,,dot=.,semi=;"),
+            ("{#keys}", "#semi,;,dot,.,comma,,", "#comma,,,dot,.,semi,;"),
+            ("{#keys*}", "#semi=;,dot=.,comma=,", "#comma=,,dot=.,semi=;"),
+            ("X{.keys}", "X.semi,%3B,dot,.,comma,%2C", "X.comma,%2C,dot,.,semi,%3B"),
+            ("X{.keys*}", "X.semi=%3B.dot=..comma=%2C", "X.comma=%2C.dot=..semi=%3B"),
+            ("{/keys}", "/semi,%3B,dot,.,comma,%2C", "/comma,%2C,dot,.,semi,%3B"),
+            ("{/keys*}", "/semi=%3B/dot=./comma=%2C", "/comma=%2C/dot=./semi=%3B"),
+            ("{;keys}", ";keys=semi,%3B,dot,.,comma,%2C", ";keys=comma,%2C,dot,.,semi,%3B"),
+            ("{;keys*}", ";semi=%3B;dot=.;comma=%2C", ";comma=%2C;dot=.;semi=%3B"),
+            ("{?keys}", "?keys=semi,%3B,dot,.,comma,%2C", "?keys=comma,%2C,dot,.,semi,%3B"),
+            ("{?keys*}", "?semi=%3B&dot=.&comma=%2C", "?comma=%2C&dot=.&semi=%3B"),
+            ("{&keys}", "&keys=semi,%3B,dot,.,comma,%2C", "&keys=comma,%2C,dot,.,semi,%3B"),
+            ("{&keys*}", "&semi=%3B&dot=.&comma=%2C", "&comma=%2C&dot=.&semi=%3B"),
+        ];
+        for (template, inserted, sorted) in examples {
+            let expected = if cfg!(feature = "preserve-order") { inserted } else { sorted };
+            assert_eq!(expand_uri_template(template, &vars)?, expected, "{}", template);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn strict_uri_templates_name_the_undefined_variables() -> Result<()> {
+        let vars = rfc_6570_vars();
+        assert_eq!(expand_uri_template_strict("{/var,empty}{?list*}{&empty_keys*}", &vars)?, "/value/?list=red&list=green&list=blue");
+        let error = expand_uri_template_strict("{/who,undef}{?bar,x,undef}", &vars).unwrap_err();
+        assert_eq!(error.to_string(), "URI template is missing variables: undef, bar");
+        assert_eq!(expand_uri_template("{/who,undef}{?bar,x,undef}", &vars)?, "/fred?x=1024");
+
+        for invalid in ["{var", "var}", "{}", "{=var}", "{var:0}", "{list:3}", "{a..b}"] {
+            assert!(expand_uri_template(invalid, &vars).is_err(), "{}", invalid);
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn canonical_json_matches_rfc_8785() -> Result<()> {
+        // Section 3.2.2, with the numbers written as in the RFC.
+        let input: Value = serde_json::from_str(
+            r#"{
+                "numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+                "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+                "literals": [null, true, false]
+            }"#,
+        )?;
+        assert_eq!(
+            canonicalize_json(&input),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+
+        // Section 3.2.3: keys sort by UTF-16 code units, so the emoji's
+        // surrogate pair comes before U+FB33.
+        let input: Value = serde_json::from_str(
+            r#"{"\u20ac": 1, "\r": 2, "\ufb33": 3, "1": 4, "\ud83d\ude00": 5, "\u0080": 6, "\u00f6": 7}"#,
+        )?;
+        assert_eq!(canonicalize_json(&input), "{\"\\r\":2,\"1\":4,\"\u{80}\":6,\"ö\":7,\"€\":1,\"😀\":5,\"\u{fb33}\":3}");
+        Ok(())
+    }
+
+    #[test]
+    fn numbers_format_like_ecmascript() {
+        // RFC 8785 appendix B, by IEEE 754 bit pattern.
+        let vectors = [
+            (0x0000000000000000_u64, "0"),
+            (0x8000000000000000, "0"),
+            (0x0000000000000001, "5e-324"),
+            (0x8000000000000001, "-5e-324"),
+            (0x7fefffffffffffff, "1.7976931348623157e+308"),
+            (0xffefffffffffffff, "-1.7976931348623157e+308"),
+            (0x4340000000000000, "9007199254740992"),
+            (0xc340000000000000, "-9007199254740992"),
+            (0x4430000000000000, "295147905179352830000"),
+            (0x44b52d02c7e14af5, "9.999999999999997e+22"),
+            (0x44b52d02c7e14af6, "1e+23"),
+            (0x44b52d02c7e14af7, "1.0000000000000001e+23"),
+            (0x444b1ae4d6e2ef4e, "999999999999999700000"),
+            (0x444b1ae4d6e2ef4f, "999999999999999900000"),
+            (0x444b1ae4d6e2ef50, "1e+21"),
+            (0x3eb0c6f7a0b5ed8c, "9.999999999999997e-7"),
+            (0x3eb0c6f7a0b5ed8d, "0.000001"),
+            (0x41b3de4355555553, "333333333.3333332"),
+            (0x41b3de4355555554, "333333333.33333325"),
+            (0x41b3de4355555555, "333333333.3333333"),
+            (0x41b3de4355555556, "333333333.3333334"),
+            (0x41b3de4355555557, "333333333.33333343"),
+            (0xbecbf647612f3696, "-0.0000033333333333333333"),
+            // Halfway between two shortest candidates; ECMAScript rounds to even.
+            (0x43143ff3c1cb0959, "1424953923781206.2"),
+        ];
+        for (bits, expected) in vectors {
+            assert_eq!(format_es_number(f64::from_bits(bits)), expected, "{:016x}", bits);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::models::RequestConfig;
+
+/// Prefix that marks process environment variables as template variables,
+/// e.g. `RUSTHTTP_VAR_base_url=...` defines `base_url`.
+pub const ENV_VAR_PREFIX: &str = "RUSTHTTP_VAR_";
+
+/// One layer of template variables. Layers passed to `resolve_vars` later in
+/// the list override earlier ones.
+#[derive(Debug, Clone)]
+pub enum VarSource {
+    /// A JSON object file such as `vars.json` or `vars.prod.json`.
+    File(PathBuf),
+    /// Process environment variables starting with the given prefix.
+    Env { prefix: String },
+    /// `key=value` pairs, typically from repeated `--var` flags.
+    Cli(Vec<String>),
+    /// An in-memory layer with a name for the provenance report.
+    Map {
+        name: String,
+        vars: HashMap<String, String>,
+    },
+}
+
+impl VarSource {
+    /// The standard precedence, lowest first: `vars.json`, `vars.<env>.json`,
+    /// `RUSTHTTP_VAR_*` environment variables, then `--var` flags. Missing
+    /// files are skipped.
+    pub fn layered(dir: impl AsRef<Path>, environment: Option<&str>, cli_vars: Vec<String>) -> Vec<VarSource> {
+        let dir = dir.as_ref();
+        let mut sources = Vec::new();
+
+        let base = dir.join("vars.json");
+        if base.exists() {
+            sources.push(VarSource::File(base));
+        }
+        if let Some(environment) = environment {
+            let overlay = dir.join(format!("vars.{}.json", environment));
+            if overlay.exists() {
+                sources.push(VarSource::File(overlay));
+            }
+        }
+        sources.push(VarSource::Env {
+            prefix: ENV_VAR_PREFIX.to_string(),
+        });
+        sources.push(VarSource::Cli(cli_vars));
+
+        sources
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            VarSource::File(path) => path.display().to_string(),
+            VarSource::Env { prefix } => format!("env:{}*", prefix),
+            VarSource::Cli(_) => "cli".to_string(),
+            VarSource::Map { name, .. } => name.clone(),
+        }
+    }
+
+    fn load(&self) -> Result<Vec<(String, String)>> {
+        match self {
+            VarSource::File(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read variables file {}", path.display()))?;
+                let value: Value = serde_json::from_str(&content)
+                    .with_context(|| format!("Invalid JSON in {}", path.display()))?;
+                let Value::Object(map) = value else {
+                    anyhow::bail!("Variables file {} must contain a JSON object", path.display());
+                };
+                Ok(map
+                    .into_iter()
+                    .map(|(key, value)| match value {
+                        Value::String(s) => (key, s),
+                        other => (key, other.to_string()),
+                    })
+                    .collect())
+            }
+            VarSource::Env { prefix } => Ok(std::env::vars()
+                .filter_map(|(key, value)| {
+                    key.strip_prefix(prefix.as_str())
+                        .filter(|name| !name.is_empty())
+                        .map(|name| (name.to_string(), value))
+                })
+                .collect()),
+            VarSource::Cli(pairs) => pairs
+                .iter()
+                .map(|pair| {
+                    pair.split_once('=')
+                        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+                        .with_context(|| format!("Invalid --var '{}', expected key=value", pair))
+                })
+                .collect(),
+            VarSource::Map { vars, .. } => {
+                Ok(vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            }
+        }
+    }
+}
+
+/// A variable's final value, the layer that supplied it and the layers whose
+/// definitions it overrode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedVar {
+    pub value: String,
+    pub source: String,
+    pub overridden: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvedVars {
+    vars: BTreeMap<String, ResolvedVar>,
+}
+
+pub fn resolve_vars(sources: &[VarSource]) -> Result<ResolvedVars> {
+    let mut resolved = ResolvedVars::default();
+
+    for source in sources {
+        let name = source.name();
+        for (key, value) in source.load()? {
+            match resolved.vars.get_mut(&key) {
+                Some(existing) => {
+                    let previous = std::mem::replace(&mut existing.source, name.clone());
+                    existing.overridden.push(previous);
+                    existing.value = value;
+                }
+                None => {
+                    resolved.vars.insert(
+                        key,
+                        ResolvedVar {
+                            value,
+                            source: name.clone(),
+                            overridden: Vec::new(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+impl ResolvedVars {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(|var| var.value.as_str())
+    }
+
+    pub fn source_of(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(|var| var.source.as_str())
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &ResolvedVar)> {
+        self.vars.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// Replaces `{{name}}` placeholders; unknown variables are an error.
+    pub fn substitute(&self, template: &str) -> Result<String> {
+        let mut output = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find("{{") {
+            output.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let end = after
+                .find("}}")
+                .with_context(|| format!("Unclosed placeholder in '{}'", template))?;
+            let name = after[..end].trim();
+            let value = self
+                .get(name)
+                .with_context(|| format!("Undefined variable '{}'", name))?;
+            output.push_str(value);
+            rest = &after[end + 2..];
+        }
+        output.push_str(rest);
+
+        Ok(output)
+    }
+
+    /// Substitutes variables into every header value of `config`.
+    pub fn apply_to_config(&self, config: &RequestConfig) -> Result<RequestConfig> {
+        let mut rendered = config.clone();
+        for value in rendered.headers.values_mut() {
+            *value = self.substitute(value)?;
+        }
+        Ok(rendered)
+    }
+}
+
+impl fmt::Display for ResolvedVars {
+    /// One line per variable: `name = value (from layer, overrides a, b)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, var) in &self.vars {
+            write!(f, "{} = {} (from {}", name, var.value, var.source)?;
+            if !var.overridden.is_empty() {
+                write!(f, ", overrides {}", var.overridden.join(", "))?;
+            }
+            writeln!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding `vars.json` and `vars.prod.json`.
+    fn var_files() -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("rusthttp-vars-{}", crate::utils::generate_request_id()));
+        std::fs::create_dir(&dir)?;
+        std::fs::write(
+            dir.join("vars.json"),
+            r#"{"base_url": "https://staging.example.com", "region": "eu", "token": "base", "retries": 3}"#,
+        )?;
+        std::fs::write(dir.join("vars.prod.json"), r#"{"base_url": "https://api.example.com", "token": "prod"}"#)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn layers_override_in_precedence_order() -> Result<()> {
+        // The environment layer reads the process environment, so check in
+        // a child that starts with the variable set.
+        if std::env::var_os("RUSTHTTP_VAR_token").is_none() {
+            let status = std::process::Command::new(std::env::current_exe()?)
+                .args(["--exact", "vars::tests::layers_override_in_precedence_order"])
+                .env("RUSTHTTP_VAR_token", "env")
+                .env("RUSTHTTP_VAR_region", "us")
+                .status()?;
+            assert!(status.success());
+            return Ok(());
+        }
+
+        let dir = var_files()?;
+        let sources = VarSource::layered(&dir, Some("prod"), vec!["token=cli=1".to_string()]);
+        let resolved = resolve_vars(&sources);
+        std::fs::remove_dir_all(&dir)?;
+        let resolved = resolved?;
+
+        let base = dir.join("vars.json").display().to_string();
+        let prod = dir.join("vars.prod.json").display().to_string();
+        let layers: Vec<String> = sources.iter().map(VarSource::name).collect();
+        assert_eq!(layers, [base.clone(), prod.clone(), "env:RUSTHTTP_VAR_*".to_string(), "cli".to_string()]);
+
+        assert_eq!(resolved.get("base_url"), Some("https://api.example.com"));
+        assert_eq!(resolved.source_of("base_url"), Some(prod.as_str()));
+        assert_eq!((resolved.get("region"), resolved.source_of("region")), (Some("us"), Some("env:RUSTHTTP_VAR_*")));
+        assert_eq!((resolved.get("retries"), resolved.source_of("retries")), (Some("3"), Some(base.as_str())));
+        let token = resolved.entries().find(|(name, _)| *name == "token").unwrap().1;
+        assert_eq!(
+            token,
+            &ResolvedVar {
+                value: "cli=1".to_string(),
+                source: "cli".to_string(),
+                overridden: vec![base.clone(), prod.clone(), "env:RUSTHTTP_VAR_*".to_string()],
+            }
+        );
+        assert!(resolved.to_string().contains(&format!(
+            "token = cli=1 (from cli, overrides {}, {}, env:RUSTHTTP_VAR_*)\n",
+            base, prod
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_files_are_skipped_and_bad_ones_fail() -> Result<()> {
+        let dir = var_files()?;
+        let staging: Vec<String> = VarSource::layered(&dir, Some("staging"), Vec::new()).iter().map(VarSource::name).collect();
+        std::fs::write(dir.join("vars.json"), "[1, 2]")?;
+        let not_an_object = resolve_vars(&[VarSource::File(dir.join("vars.json"))]);
+        std::fs::write(dir.join("vars.json"), "{")?;
+        let invalid = resolve_vars(&[VarSource::File(dir.join("vars.json"))]);
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(staging[1..], ["env:RUSTHTTP_VAR_*", "cli"]);
+        assert!(not_an_object.unwrap_err().to_string().ends_with("vars.json must contain a JSON object"));
+        assert!(invalid.unwrap_err().to_string().starts_with("Invalid JSON in "));
+        let error = resolve_vars(&[VarSource::Cli(vec!["token".to_string()])]).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid --var 'token', expected key=value");
+        Ok(())
+    }
+
+    #[test]
+    fn placeholders_are_substituted_into_headers() -> Result<()> {
+        let resolved = resolve_vars(&[VarSource::Map {
+            name: "test".to_string(),
+            vars: HashMap::from([("token".to_string(), "abc".to_string()), ("tenant".to_string(), "t1".to_string())]),
+        }])?;
+
+        assert_eq!(resolved.substitute("Bearer {{ token }} for {{tenant}}")?, "Bearer abc for t1");
+        assert_eq!(resolved.substitute("no placeholders")?, "no placeholders");
+        assert_eq!(resolved.substitute("{{missing}}").unwrap_err().to_string(), "Undefined variable 'missing'");
+        assert_eq!(resolved.substitute("{{token").unwrap_err().to_string(), "Unclosed placeholder in '{{token'");
+
+        let config = RequestConfig::new()
+            .add_header("Authorization", "Bearer {{token}}")
+            .add_header("X-Tenant", "{{tenant}}");
+        let rendered = resolved.apply_to_config(&config)?;
+        assert_eq!(rendered.headers.get("authorization").map(String::as_str), Some("Bearer abc"));
+        assert_eq!(rendered.headers.get("x-tenant").map(String::as_str), Some("t1"));
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use url::Url;
+
+use crate::error::{RequestError, WarningEscalated};
+use crate::headers::{HeaderList, MediaType};
+use crate::models::{ApiResponse, HttpMethod};
+use crate::report::SENSITIVE_HEADERS;
+
+/// Query parameters that carry bearer tokens when an API accepts them there.
+const TOKEN_QUERY_PARAMS: &[&str] = &[
+    "access_token",
+    "id_token",
+    "refresh_token",
+    "auth_token",
+    "bearer_token",
+    "bearer",
+    "token",
+];
+
+/// What a `RequestWarning` is about. The `as_str` codes are stable and safe
+/// to match on in scripts and logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningCode {
+    /// Credential headers or URL userinfo sent over plain HTTP to a host
+    /// other than loopback.
+    CredentialsOverHttp,
+    /// Basic auth, which is only encoded, sent without TLS.
+    BasicAuthWithoutTls,
+    /// A bearer token in the query string, where it ends up in server and
+    /// proxy logs.
+    TokenInQuery,
+    /// `RequestConfig::with_ssl_verification(false)` for a public host.
+    TlsVerificationDisabled,
+    /// A GET or HEAD with a body, which many servers and proxies drop.
+    GetWithBody,
+    /// The response's `Content-Type` is not one the `Accept` header asked for.
+    AcceptMismatch,
+}
+
+impl WarningCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WarningCode::CredentialsOverHttp => "credentials-over-http",
+            WarningCode::BasicAuthWithoutTls => "basic-auth-without-tls",
+            WarningCode::TokenInQuery => "token-in-query",
+            WarningCode::TlsVerificationDisabled => "tls-verification-disabled",
+            WarningCode::GetWithBody => "get-with-body",
+            WarningCode::AcceptMismatch => "accept-mismatch",
+        }
+    }
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A request that went out but probably should not have gone out like that.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestWarning {
+    pub code: WarningCode,
+    pub message: String,
+}
+
+impl RequestWarning {
+    fn new(code: WarningCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RequestWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.code, self.message)
+    }
+}
+
+/// Warnings about a request before it is sent. `headers` are the ones that
+/// go out with it, client defaults included.
+pub(crate) fn check_request(
+    method: HttpMethod,
+    url: &str,
+    headers: &HeaderList,
+    has_body: bool,
+    verify_ssl: bool,
+) -> Vec<RequestWarning> {
+    let mut warnings = Vec::new();
+    let Ok(url) = Url::parse(url) else {
+        return warnings;
+    };
+    let host = url.host_str().unwrap_or_default().trim_matches(['[', ']']);
+
+    if url.scheme() == "http" && !is_loopback(host) {
+        let basic = headers
+            .get("authorization")
+            .is_some_and(|value| value.trim_start().to_ascii_lowercase().starts_with("basic "));
+        let credentials: Vec<&str> = SENSITIVE_HEADERS
+            .iter()
+            .copied()
+            .filter(|name| *name != "set-cookie" && headers.contains_key(name))
+            .collect();
+        if basic {
+            warnings.push(RequestWarning::new(
+                WarningCode::BasicAuthWithoutTls,
+                format!("Basic credentials sent to {} over plain HTTP can be read by anyone on the path", host),
+            ));
+        } else if !credentials.is_empty() {
+            warnings.push(RequestWarning::new(
+                WarningCode::CredentialsOverHttp,
+                format!("{} sent to {} over plain HTTP", credentials.join(", "), host),
+            ));
+        } else if !url.username().is_empty() || url.password().is_some() {
+            warnings.push(RequestWarning::new(
+                WarningCode::CredentialsOverHttp,
+                format!("URL credentials sent to {} over plain HTTP", host),
+            ));
+        }
+    }
+
+    for (name, value) in url.query_pairs() {
+        let named = TOKEN_QUERY_PARAMS.iter().any(|param| name.eq_ignore_ascii_case(param));
+        if named || looks_like_bearer_token(&value) {
+            warnings.push(RequestWarning::new(
+                WarningCode::TokenInQuery,
+                format!("Query parameter {:?} carries a token; send it in the Authorization header", name),
+            ));
+            break;
+        }
+    }
+
+    if !verify_ssl && url.scheme() == "https" && is_public(host) {
+        warnings.push(RequestWarning::new(
+            WarningCode::TlsVerificationDisabled,
+            format!("Certificate verification is off for public host {}", host),
+        ));
+    }
+
+    if has_body && matches!(method, HttpMethod::Get | HttpMethod::Head) {
+        warnings.push(RequestWarning::new(
+            WarningCode::GetWithBody,
+            format!("{} request has a body, which servers and proxies may ignore", method),
+        ));
+    }
+
+    warnings
+}
+
+/// Warns when the response's format is not one the request's `Accept`
+/// header allows. Empty bodies and responses without a `Content-Type` pass.
+pub(crate) fn check_response(headers: &HeaderList, response: &ApiResponse) -> Option<RequestWarning> {
+    let accept = headers.get("accept")?;
+    if response.content_type_missing || response.raw_body.is_empty() && response.spilled.is_none() && !response.has_pending_body() {
+        return None;
+    }
+    let received = MediaType::parse(&response.content_type)?;
+    let accepted = accept
+        .split(',')
+        .filter_map(MediaType::parse)
+        .filter(|range| range.param("q").and_then(|q| q.trim().parse::<f32>().ok()) != Some(0.0))
+        .any(|range| media_range_matches(&range.essence, &received.essence));
+    (!accepted).then(|| {
+        RequestWarning::new(
+            WarningCode::AcceptMismatch,
+            format!("Asked for {} but the response is {}", accept.trim(), received.essence),
+        )
+    })
+}
+
+/// `*/*`, `type/*` and exact matches, plus structured suffixes: a request
+/// for `application/json` accepts `application/problem+json`.
+fn media_range_matches(range: &str, essence: &str) -> bool {
+    let (Some((range_type, range_subtype)), Some((kind, subtype))) = (range.split_once('/'), essence.split_once('/'))
+    else {
+        return false;
+    };
+    range == "*/*"
+        || range_type == kind
+            && (range_subtype == "*"
+                || range_subtype == subtype
+                || subtype.rsplit_once('+').is_some_and(|(_, suffix)| suffix == range_subtype))
+}
+
+/// `Bearer …` values and JWTs.
+fn looks_like_bearer_token(value: &str) -> bool {
+    let value = value.trim();
+    if value.len() > 7 && value.get(..7).is_some_and(|prefix| prefix.eq_ignore_ascii_case("bearer ")) {
+        return true;
+    }
+    let segments: Vec<&str> = value.split('.').collect();
+    value.starts_with("eyJ")
+        && segments.len() == 3
+        && segments.iter().all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        })
+}
+
+fn is_loopback(host: &str) -> bool {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback(),
+        Err(_) => host.eq_ignore_ascii_case("localhost") || host.to_ascii_lowercase().ends_with(".localhost"),
+    }
+}
+
+/// Hosts reachable from the internet: neither loopback nor private,
+/// link-local or `.local` / `.internal` names.
+fn is_public(host: &str) -> bool {
+    if is_loopback(host) {
+        return false;
+    }
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => !(ip.is_private() || ip.is_link_local() || ip.is_unspecified()),
+        Ok(IpAddr::V6(ip)) => {
+            let first = ip.segments()[0];
+            // fc00::/7 unique local and fe80::/10 link-local.
+            !(ip.is_unspecified() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80)
+        }
+        Err(_) => {
+            let host = host.to_ascii_lowercase();
+            host.contains('.') && !host.ends_with(".local") && !host.ends_with(".internal")
+        }
+    }
+}
+
+/// The warnings an error from a request carries: those of a
+/// `RequestError`'s partial response, or of `WarningEscalated`.
+pub fn error_warnings(error: &anyhow::Error) -> &[RequestWarning] {
+    if let Some(escalated) = error.downcast_ref::<WarningEscalated>() {
+        return &escalated.warnings;
+    }
+    error
+        .downcast_ref::<RequestError>()
+        .map_or(&[], |error| &error.partial().warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockResponse, MockServer};
+    use crate::{HttpClient, RequestConfig};
+    use anyhow::Result;
+    use std::time::Duration;
+
+    fn codes(method: HttpMethod, url: &str, headers: &[(&str, &str)], has_body: bool, verify_ssl: bool) -> Vec<WarningCode> {
+        let headers: HeaderList = headers.iter().copied().collect();
+        check_request(method, url, &headers, has_body, verify_ssl)
+            .into_iter()
+            .map(|warning| warning.code)
+            .collect()
+    }
+
+    #[test]
+    fn each_request_warning_is_raised() {
+        use WarningCode::*;
+        let get = HttpMethod::Get;
+        assert_eq!(codes(get, "http://api.example.com/", &[("X-Api-Key", "k")], false, true), [CredentialsOverHttp]);
+        assert_eq!(codes(get, "http://user:pw@api.example.com/", &[], false, true), [CredentialsOverHttp]);
+        assert_eq!(
+            codes(get, "http://api.example.com/", &[("Authorization", "Basic dTpw")], false, true),
+            [BasicAuthWithoutTls]
+        );
+        assert_eq!(codes(get, "https://api.example.com/?access_token=abc", &[], false, true), [TokenInQuery]);
+        assert_eq!(
+            codes(get, "https://api.example.com/?q=eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiIxIn0.c2ln", &[], false, true),
+            [TokenInQuery]
+        );
+        assert_eq!(codes(get, "https://api.example.com/", &[], false, false), [TlsVerificationDisabled]);
+        assert_eq!(codes(get, "https://api.example.com/", &[], true, true), [GetWithBody]);
+        assert_eq!(codes(HttpMethod::Head, "https://api.example.com/", &[], true, true), [GetWithBody]);
+    }
+
+    #[test]
+    fn clean_and_local_requests_raise_nothing() {
+        let auth = [("Authorization", "Bearer abc")];
+        assert!(codes(HttpMethod::Get, "https://api.example.com/users?page=2", &auth, false, true).is_empty());
+        assert!(codes(HttpMethod::Post, "https://api.example.com/users", &auth, true, true).is_empty());
+        assert!(codes(HttpMethod::Get, "http://localhost:8080/", &auth, false, true).is_empty());
+        assert!(codes(HttpMethod::Get, "http://[::1]:8080/", &[("Authorization", "Basic dTpw")], false, true).is_empty());
+        for private in ["https://10.0.0.5/", "https://192.168.1.1/", "https://build.internal/", "https://[fd00::1]/"] {
+            assert!(codes(HttpMethod::Get, private, &[], false, false).is_empty(), "{private}");
+        }
+    }
+
+    #[tokio::test]
+    async fn accept_mismatches_are_reported_on_the_response() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/page")
+            .respond(MockResponse::new(200).with_header("Content-Type", "text/html").with_body("<p>hi</p>"));
+        server
+            .when(HttpMethod::Get, "/problem")
+            .respond(MockResponse::new(400).with_header("Content-Type", "application/problem+json").with_body("{}"));
+        let client = HttpClient::new();
+        let json = || RequestConfig::new().add_header("Accept", "application/json, text/html;q=0");
+
+        let page = client.get(&server.url("/page"), json()).await?;
+        assert_eq!(
+            page.warnings,
+            [RequestWarning::new(
+                WarningCode::AcceptMismatch,
+                "Asked for application/json, text/html;q=0 but the response is text/html"
+            )]
+        );
+        let problem = client.get(&server.url("/problem"), json()).await?;
+        assert!(problem.warnings.is_empty());
+        let any = client.get(&server.url("/page"), RequestConfig::new().add_header("Accept", "text/*")).await?;
+        assert!(any.warnings.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn escalated_codes_fail_the_request() -> Result<()> {
+        let server = MockServer::start().await?;
+        server.when(HttpMethod::Get, "/me").respond(MockResponse::text(200, "ok"));
+        let client = HttpClient::new().escalate_warnings([WarningCode::TokenInQuery, WarningCode::AcceptMismatch]);
+
+        let error = client.get(&server.url("/me?access_token=abc"), RequestConfig::new()).await.unwrap_err();
+        assert_eq!(server.requests().len(), 0);
+        assert_eq!(
+            error.to_string(),
+            "Request refused by the warning policy: [token-in-query] Query parameter \"access_token\" carries a token; send it in the Authorization header"
+        );
+        assert_eq!(error_warnings(&error)[0].code, WarningCode::TokenInQuery);
+
+        let config = RequestConfig::new().add_header("Accept", "application/json");
+        let error = client.get(&server.url("/me"), config).await.unwrap_err();
+        assert_eq!(server.requests().len(), 1);
+        let escalated = error.downcast_ref::<WarningEscalated>().unwrap();
+        assert_eq!(escalated.escalated[0].code, WarningCode::AcceptMismatch);
+
+        let lenient = HttpClient::new().escalate_warnings([WarningCode::GetWithBody]);
+        let response = lenient.get(&server.url("/me?token=abc"), RequestConfig::new()).await?;
+        let codes: Vec<WarningCode> = response.warnings.iter().map(|warning| warning.code).collect();
+        assert_eq!(codes, [WarningCode::TokenInQuery]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn timed_out_requests_carry_their_warnings() -> Result<()> {
+        let server = MockServer::start().await?;
+        server
+            .when(HttpMethod::Get, "/slow")
+            .respond(MockResponse::text(200, "late").with_delay(Duration::from_millis(500)));
+
+        let config = RequestConfig::new().with_timeout(Duration::from_millis(100));
+        let error = HttpClient::new().get(&server.url("/slow?access_token=abc"), config).await.unwrap_err();
+        assert!(error.downcast_ref::<RequestError>().is_some(), "{error:?}");
+        let codes: Vec<WarningCode> = error_warnings(&error).iter().map(|warning| warning.code).collect();
+        assert_eq!(codes, [WarningCode::TokenInQuery]);
+        Ok(())
+    }
+}
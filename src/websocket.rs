@@ -0,0 +1,321 @@
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::Message as Frame;
+
+/// Messages buffered in each direction before `send` waits and the socket
+/// stops being read.
+pub const DEFAULT_WS_CHANNEL_CAPACITY: usize = 16;
+/// Interval between keepalive pings.
+pub const DEFAULT_WS_PING_INTERVAL_SECS: u64 = 30;
+
+/// A WebSocket data message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Settings for `WsConnection::connect`.
+#[derive(Debug, Clone)]
+pub struct WsOptions {
+    capacity: usize,
+    ping_interval: Option<Duration>,
+    pong_timeout: Duration,
+    headers: HashMap<String, String>,
+}
+
+impl Default for WsOptions {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_WS_CHANNEL_CAPACITY,
+            ping_interval: Some(Duration::from_secs(DEFAULT_WS_PING_INTERVAL_SECS)),
+            pong_timeout: Duration::from_secs(DEFAULT_WS_PING_INTERVAL_SECS),
+            headers: HashMap::new(),
+        }
+    }
+}
+
+impl WsOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Pings the server every `interval`; the connection fails when no pong
+    /// arrives within the pong timeout of a ping.
+    pub fn with_ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = Some(interval);
+        self
+    }
+
+    pub fn with_pong_timeout(mut self, timeout: Duration) -> Self {
+        self.pong_timeout = timeout;
+        self
+    }
+
+    pub fn without_keepalive(mut self) -> Self {
+        self.ping_interval = None;
+        self
+    }
+
+    /// Adds a header to the opening handshake, e.g. `Authorization`.
+    pub fn add_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// An open WebSocket. Both directions go through bounded queues: `send`
+/// waits while the outgoing one is full, and the socket is not read while
+/// received messages wait for `recv`, so neither side buffers without limit.
+/// Pings from the server are answered automatically.
+#[derive(Debug)]
+pub struct WsConnection {
+    outgoing: mpsc::Sender<Message>,
+    incoming: mpsc::Receiver<Result<Message>>,
+    task: JoinHandle<()>,
+}
+
+impl WsConnection {
+    /// Opens a `ws://` or `wss://` connection.
+    pub async fn connect(url: &str, options: WsOptions) -> Result<Self> {
+        let mut request = url
+            .into_client_request()
+            .with_context(|| format!("Invalid WebSocket URL: {}", url))?;
+        for (key, value) in &options.headers {
+            let name = HeaderName::from_bytes(key.as_bytes())
+                .with_context(|| format!("Invalid header name: {}", key))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for header {}", key))?;
+            request.headers_mut().insert(name, value);
+        }
+
+        let (socket, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .with_context(|| format!("Failed to open WebSocket to {}", url))?;
+
+        let (outgoing, outgoing_rx) = mpsc::channel(options.capacity);
+        let (incoming_tx, incoming) = mpsc::channel(options.capacity);
+        let task = tokio::spawn(run(socket, outgoing_rx, incoming_tx, options));
+        Ok(Self { outgoing, incoming, task })
+    }
+
+    /// Queues `message`, waiting while the outgoing queue is full.
+    pub async fn send(&self, message: Message) -> Result<()> {
+        self.outgoing
+            .send(message)
+            .await
+            .map_err(|_| anyhow::anyhow!("WebSocket connection is closed"))
+    }
+
+    /// The next message; `None` once the connection has closed. A failure
+    /// is delivered once as an `Err` before that.
+    pub async fn recv(&mut self) -> Option<Result<Message>> {
+        self.incoming.recv().await
+    }
+
+    /// Sends a close frame after the queued messages and waits for the
+    /// connection to shut down.
+    pub async fn close(self) -> Result<()> {
+        drop(self.outgoing);
+        self.task.await.context("WebSocket task failed")
+    }
+}
+
+async fn run<S>(
+    mut socket: tokio_tungstenite::WebSocketStream<S>,
+    mut outgoing: mpsc::Receiver<Message>,
+    incoming: mpsc::Sender<Result<Message>>,
+    options: WsOptions,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut ping = options.ping_interval.map(|interval| {
+        let mut ticker = tokio::time::interval_at(Instant::now() + interval, interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker
+    });
+    // When the oldest unanswered ping was sent.
+    let mut awaiting_pong: Option<Instant> = None;
+    // A received message waiting for room in `incoming`.
+    let mut pending: Option<Result<Message>> = None;
+
+    let failure = loop {
+        tokio::select! {
+            message = outgoing.recv() => {
+                let Some(message) = message else {
+                    let _ = socket.close(None).await;
+                    break None;
+                };
+                let frame = match message {
+                    Message::Text(text) => Frame::text(text),
+                    Message::Binary(data) => Frame::binary(data),
+                };
+                if let Err(e) = socket.send(frame).await {
+                    break Some(anyhow::Error::new(e).context("Failed to send WebSocket message"));
+                }
+            }
+            permit = incoming.reserve(), if pending.is_some() => {
+                match permit {
+                    Ok(permit) => permit.send(pending.take().expect("pending message checked by the guard")),
+                    // Nobody is listening; keep sending until the sender goes away.
+                    Err(_) => pending = None,
+                }
+            }
+            frame = socket.next(), if pending.is_none() => {
+                match frame {
+                    Some(Ok(Frame::Text(text))) => pending = Some(Ok(Message::Text(text.to_string()))),
+                    Some(Ok(Frame::Binary(data))) => pending = Some(Ok(Message::Binary(data.to_vec()))),
+                    Some(Ok(Frame::Pong(_))) => awaiting_pong = None,
+                    // Pings are answered by the protocol layer.
+                    Some(Ok(Frame::Ping(_) | Frame::Frame(_))) => {}
+                    Some(Ok(Frame::Close(_))) | None => break None,
+                    Some(Err(e)) => break Some(anyhow::Error::new(e).context("WebSocket connection failed")),
+                }
+            }
+            _ = async { ping.as_mut().expect("ping interval checked by the guard").tick().await }, if ping.is_some() => {
+                if let Some(sent) = awaiting_pong
+                    && sent.elapsed() >= options.pong_timeout
+                {
+                    break Some(anyhow::anyhow!(
+                        "WebSocket keepalive failed: no pong within {:?}",
+                        options.pong_timeout
+                    ));
+                }
+                awaiting_pong.get_or_insert_with(Instant::now);
+                if let Err(e) = socket.send(Frame::Ping(Default::default())).await {
+                    break Some(anyhow::Error::new(e).context("Failed to send WebSocket ping"));
+                }
+            }
+        }
+    };
+
+    // Deliver what was already received, then the failure, if anyone listens.
+    if let Some(message) = pending {
+        let _ = incoming.send(message).await;
+    }
+    if let Some(error) = failure {
+        let _ = incoming.send(Err(error)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::WebSocketStream;
+
+    /// Accepts one WebSocket connection and hands it to `serve`.
+    async fn server<F, Fut>(serve: F) -> Result<SocketAddr>
+    where
+        F: FnOnce(WebSocketStream<tokio::net::TcpStream>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            if let Ok((tcp, _)) = listener.accept().await
+                && let Ok(socket) = tokio_tungstenite::accept_async(tcp).await
+            {
+                serve(socket).await;
+            }
+        });
+        Ok(addr)
+    }
+
+    async fn echo(mut socket: WebSocketStream<tokio::net::TcpStream>) {
+        while let Some(Ok(frame)) = socket.next().await {
+            if (frame.is_text() || frame.is_binary()) && socket.send(frame).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn binary_and_text_messages_echo_back() -> Result<()> {
+        let addr = server(echo).await?;
+        let mut ws = WsConnection::connect(&format!("ws://{}/", addr), WsOptions::new()).await?;
+
+        let binary = Message::Binary(vec![0, 1, 2, 0xfe, 0xff]);
+        ws.send(binary.clone()).await?;
+        ws.send(Message::Text("héllo".to_string())).await?;
+        assert_eq!(ws.recv().await.transpose()?, Some(binary));
+        assert_eq!(ws.recv().await.transpose()?, Some(Message::Text("héllo".to_string())));
+        ws.close().await
+    }
+
+    #[tokio::test]
+    async fn an_unread_connection_stops_the_sender() -> Result<()> {
+        const MESSAGES: usize = 400;
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&sent);
+        let addr = server(move |mut socket| async move {
+            for _ in 0..MESSAGES {
+                if socket.send(Frame::binary(vec![7u8; 64 * 1024])).await.is_err() {
+                    return;
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            let _ = socket.close(None).await;
+        })
+        .await?;
+        let mut ws = WsConnection::connect(&format!("ws://{}/", addr), WsOptions::new().with_capacity(2)).await?;
+
+        // 25 MB do not fit in the socket buffers and two queued messages.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let stalled_at = sent.load(Ordering::SeqCst);
+        assert!(stalled_at < MESSAGES, "the server sent everything without the client reading");
+
+        let mut received = 0;
+        while let Some(message) = ws.recv().await {
+            assert_eq!(message?, Message::Binary(vec![7u8; 64 * 1024]));
+            received += 1;
+        }
+        assert_eq!(received, MESSAGES);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_missing_pong_fails_the_connection() -> Result<()> {
+        let options = WsOptions::new()
+            .with_ping_interval(Duration::from_millis(50))
+            .with_pong_timeout(Duration::from_millis(100));
+
+        // Echo servers read the socket, so pings get answered.
+        let addr = server(echo).await?;
+        let mut ws = WsConnection::connect(&format!("ws://{}/", addr), options.clone()).await?;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        ws.send(Message::Text("still here".to_string())).await?;
+        assert_eq!(ws.recv().await.transpose()?, Some(Message::Text("still here".to_string())));
+
+        // This one never reads, so it never answers.
+        let addr = server(|socket| async move {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            drop(socket);
+        })
+        .await?;
+        let mut ws = WsConnection::connect(&format!("ws://{}/", addr), options).await?;
+        let error = tokio::time::timeout(Duration::from_secs(2), ws.recv())
+            .await?
+            .context("closed without an error")?
+            .unwrap_err();
+        assert!(error.to_string().contains("no pong within 100ms"), "{:#}", error);
+        assert!(ws.recv().await.is_none());
+        Ok(())
+    }
+}